@@ -2,7 +2,8 @@ MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
   /* Map for STM32DISCOVERY */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  /* The last 2K page is kept for settings, see src/storage.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 254K
   RAM : ORIGIN = 0x20000000, LENGTH = 40K
 }
 
@@ -0,0 +1,343 @@
+/// Attitude and heading estimation fusing the gyroscope, accelerometer and
+/// magnetometer with Mahony's complementary filter.  The gyroscope follows
+/// quick turns smoothly, while gravity and the magnetic field pull the
+/// estimate back to stop it drifting.
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::{Angle, Point2D, Quaternion, Vector3};
+
+/// How hard the accelerometer and magnetometer pull the estimate, per
+/// second.  Higher follows them more closely, noise and all.
+pub const DEFAULT_KP: f32 = 1.0;
+
+/// How quickly a steady disagreement is put down to gyroscope bias
+pub const DEFAULT_KI: f32 = 0.05;
+
+/// Longest gap between updates that's integrated, so a stall in updates
+/// doesn't spin the estimate
+const MAX_DT: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ahrs {
+    /// Rotation from the board's axes to the earth's, with x toward magnetic
+    /// north, y west and z up
+    orientation: Quaternion,
+    /// Estimated gyroscope bias, in radians per second
+    bias: Vector3,
+    kp: f32,
+    ki: f32,
+    /// Whether the estimate has been set from the first readings
+    started: bool,
+}
+
+impl Default for Ahrs {
+    fn default() -> Self {
+        Self::new(DEFAULT_KP, DEFAULT_KI)
+    }
+}
+
+impl Ahrs {
+    pub fn new(kp: f32, ki: f32) -> Self {
+        Self {
+            orientation: Quaternion::IDENTITY,
+            bias: Vector3::ZERO,
+            kp,
+            ki,
+            started: false,
+        }
+    }
+
+    /// Whether there have been readings to start the estimate from
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    pub fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    /// Gyroscope bias in radians per second about each axis
+    pub fn bias(&self) -> Vector3 {
+        self.bias
+    }
+
+    /// Fold in a gyroscope reading in radians per second, taken over the
+    /// last `dt` seconds, with accelerometer and magnetometer readings in
+    /// any units.  A zero accelerometer or magnetometer reading is left out.
+    pub fn update(&mut self, gyro: Vector3, accel: Vector3, mag: Vector3, dt: f32) {
+        if !self.started {
+            if let Some(orientation) = Self::from_readings(accel, mag) {
+                self.orientation = orientation;
+                self.started = true;
+            }
+            return;
+        }
+
+        let q = self.orientation;
+        let mut error = Vector3::ZERO;
+
+        // Which way gravity and the field should be, given the estimate,
+        // against which way they are
+        if let Some(up) = accel.normalized() {
+            let expected = q.conjugate().rotate(Vector3::new(0.0, 0.0, 1.0));
+            error = error + up.cross(expected);
+        }
+        if let Some(field) = mag.normalized() {
+            // Only the field's dip and the estimate's heading are trusted,
+            // so it can't disturb the tilt
+            let earth = q.rotate(field);
+            let horizontal = (earth.x * earth.x + earth.y * earth.y).sqrt();
+            let reference = Vector3::new(horizontal, 0.0, earth.z);
+            let expected = q.conjugate().rotate(reference);
+            error = error + field.cross(expected);
+        }
+
+        let dt = dt.clamp(0.0, MAX_DT);
+        if self.ki > 0.0 {
+            self.bias = self.bias - error * (self.ki * dt);
+        }
+        let rate = gyro - self.bias + error * self.kp;
+
+        let change = q * Quaternion::new(0.0, rate.x, rate.y, rate.z);
+        self.orientation = Quaternion::new(
+            q.w + change.w * 0.5 * dt,
+            q.x + change.x * 0.5 * dt,
+            q.y + change.y * 0.5 * dt,
+            q.z + change.z * 0.5 * dt,
+        )
+        .normalized();
+    }
+
+    /// The orientation from gravity and the field alone, or `None` if either
+    /// reading is zero or they're parallel
+    pub fn from_readings(accel: Vector3, mag: Vector3) -> Option<Quaternion> {
+        let up = accel.normalized()?;
+        let west = up.cross(mag).normalized()?;
+        let north = west.cross(up);
+
+        // The earth's axes in the board's frame are the rows of the
+        // rotation from the board's axes to the earth's
+        let rows = [
+            [north.x, north.y, north.z],
+            [west.x, west.y, west.z],
+            [up.x, up.y, up.z],
+        ];
+        Some(from_rotation(rows))
+    }
+
+    /// Bearing from the front of the board toward magnetic north, in the same
+    /// sense as `Compass::bearing_north` so either can be used
+    pub fn bearing_north(&self) -> Angle {
+        let north = self
+            .orientation
+            .conjugate()
+            .rotate(Vector3::new(1.0, 0.0, 0.0));
+        -Point2D::new(north.x, north.y).bearing_north()
+    }
+}
+
+/// Quaternion of a rotation matrix given by rows, after Shepperd
+fn from_rotation(m: [[f32; 3]; 3]) -> Quaternion {
+    let trace = m[0][0] + m[1][1] + m[2][2];
+
+    let q = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        Quaternion::new(
+            0.25 * s,
+            (m[2][1] - m[1][2]) / s,
+            (m[0][2] - m[2][0]) / s,
+            (m[1][0] - m[0][1]) / s,
+        )
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+        let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+        Quaternion::new(
+            (m[2][1] - m[1][2]) / s,
+            0.25 * s,
+            (m[0][1] + m[1][0]) / s,
+            (m[0][2] + m[2][0]) / s,
+        )
+    } else if m[1][1] > m[2][2] {
+        let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+        Quaternion::new(
+            (m[0][2] - m[2][0]) / s,
+            (m[0][1] + m[1][0]) / s,
+            0.25 * s,
+            (m[1][2] + m[2][1]) / s,
+        )
+    } else {
+        let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+        Quaternion::new(
+            (m[1][0] - m[0][1]) / s,
+            (m[0][2] + m[2][0]) / s,
+            (m[1][2] + m[2][1]) / s,
+            0.25 * s,
+        )
+    };
+    q.normalized()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+
+    /// The field in the earth's frame, dipping 66° as it does in Britain
+    fn field() -> Vector3 {
+        let dip = 66.0f32.to_radians();
+        Vector3::new(dip.cos(), 0.0, -dip.sin()) * 50.0
+    }
+
+    fn attitude(roll: f32, pitch: f32, yaw: f32) -> Quaternion {
+        Quaternion::from_euler(
+            Angle::from_degrees(roll),
+            Angle::from_degrees(pitch),
+            Angle::from_degrees(yaw),
+        )
+    }
+
+    /// Accelerometer and magnetometer readings with the board at an attitude
+    fn readings(attitude: Quaternion) -> (Vector3, Vector3) {
+        let to_board = attitude.conjugate();
+        (to_board.rotate(UP * 9.81), to_board.rotate(field()))
+    }
+
+    /// Degrees of rotation between two attitudes
+    fn separation(a: Quaternion, b: Quaternion) -> f32 {
+        let dot = a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z;
+        2.0 * dot.abs().min(1.0).acos().to_degrees()
+    }
+
+    fn started_at(attitude: Quaternion) -> Ahrs {
+        let (accel, mag) = readings(attitude);
+        let mut ahrs = Ahrs::default();
+        ahrs.update(Vector3::ZERO, accel, mag, 0.01);
+        ahrs
+    }
+
+    #[test]
+    fn starts_from_the_first_readings() {
+        let mut ahrs = Ahrs::default();
+        assert!(!ahrs.is_started());
+        // Nothing to start from without gravity
+        ahrs.update(Vector3::ZERO, Vector3::ZERO, field(), 0.01);
+        assert!(!ahrs.is_started());
+
+        for truth in [
+            Quaternion::IDENTITY,
+            attitude(0.0, 0.0, 90.0),
+            attitude(20.0, -10.0, 200.0),
+            attitude(-35.0, 50.0, 310.0),
+        ] {
+            let ahrs = started_at(truth);
+            assert!(ahrs.is_started());
+            assert!(separation(ahrs.orientation(), truth) < 0.1);
+        }
+    }
+
+    #[test]
+    fn bearing_matches_the_compass() {
+        for yaw in [0.0, 30.0, 90.0, 180.0, 275.0] {
+            // Level, it's what the compass makes of the field, whichever way
+            // the board faces
+            let (_, mag) = readings(attitude(0.0, 0.0, yaw));
+            let compass = -Point2D::new(mag.x, mag.y).bearing_north();
+            let bearing = started_at(attitude(0.0, 0.0, yaw)).bearing_north();
+            assert!(bearing.difference(compass).degrees().abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn converges_to_a_static_attitude() {
+        let mut ahrs = started_at(Quaternion::IDENTITY);
+        let truth = attitude(20.0, -15.0, 60.0);
+        let (accel, mag) = readings(truth);
+
+        let mut separations = [0.0; 3];
+        let mut elapsed = 0;
+        for (separation_at, seconds) in separations.iter_mut().zip([1, 10, 120]) {
+            while elapsed < seconds * 100 {
+                ahrs.update(Vector3::ZERO, accel, mag, 0.01);
+                elapsed += 1;
+            }
+            *separation_at = separation(ahrs.orientation(), truth);
+        }
+        assert!(separations[0] < 80.0);
+        assert!(separations[1] < separations[0]);
+        assert!(separations[2] < 0.5, "{:?}", separations);
+    }
+
+    #[test]
+    fn tilt_converges_before_heading() {
+        // Gravity is trusted more than the field, which only sets the heading
+        let mut ahrs = started_at(Quaternion::IDENTITY);
+        let truth = attitude(20.0, -15.0, 0.0);
+        let (accel, mag) = readings(truth);
+        for _ in 0..1_000 {
+            ahrs.update(Vector3::ZERO, accel, mag, 0.01);
+        }
+        let up = ahrs.orientation().conjugate().rotate(UP);
+        let angle = up.dot(accel.normalized().unwrap()).min(1.0).acos();
+        assert!(angle.to_degrees() < 1.0);
+    }
+
+    #[test]
+    fn gyro_only_integration() {
+        // With no accelerometer or magnetometer it just follows the gyro
+        let start = attitude(10.0, 5.0, 0.0);
+        let mut ahrs = started_at(start);
+        let rate = Vector3::new(0.0, 0.0, 10.0f32.to_radians());
+        for _ in 0..900 {
+            ahrs.update(rate, Vector3::ZERO, Vector3::ZERO, 0.01);
+        }
+
+        let turned = Quaternion::from_axis_angle(UP, Angle::from_degrees(90.0));
+        assert!(separation(ahrs.orientation(), start * turned) < 0.5);
+    }
+
+    #[test]
+    fn gyro_only_about_each_axis() {
+        for axis in [
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ] {
+            let mut ahrs = started_at(Quaternion::IDENTITY);
+            for _ in 0..100 {
+                ahrs.update(axis * 0.5, Vector3::ZERO, Vector3::ZERO, 0.02);
+            }
+            let expected = Quaternion::from_axis_angle(axis, Angle::from_radians(1.0));
+            assert!(separation(ahrs.orientation(), expected) < 0.1);
+        }
+    }
+
+    #[test]
+    fn long_gaps_are_limited() {
+        let rate = Vector3::new(0.0, 0.0, 0.2);
+        let mut stalled = started_at(Quaternion::IDENTITY);
+        stalled.update(rate, Vector3::ZERO, Vector3::ZERO, 10.0);
+        let mut limited = started_at(Quaternion::IDENTITY);
+        limited.update(rate, Vector3::ZERO, Vector3::ZERO, MAX_DT);
+        assert_eq!(stalled.orientation(), limited.orientation());
+    }
+
+    #[test]
+    fn learns_the_gyro_bias() {
+        let truth = attitude(5.0, 5.0, 45.0);
+        let (accel, mag) = readings(truth);
+        let offset = Vector3::new(0.01, -0.02, 0.015);
+        let mut ahrs = started_at(truth);
+        for _ in 0..30_000 {
+            ahrs.update(offset, accel, mag, 0.01);
+        }
+
+        let error = ahrs.bias() - offset;
+        assert!(error.magnitude() < 0.002, "{:?}", ahrs.bias());
+        assert!(separation(ahrs.orientation(), truth) < 0.5);
+    }
+}
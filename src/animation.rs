@@ -0,0 +1,301 @@
+/// Frame generation for status animations on the LED ring
+use core::mem;
+
+use crate::leds::LedId;
+use crate::stepper::CircularDirection;
+
+/// Milliseconds between animation frames
+pub const FRAME_PERIOD_MS: u64 = 20;
+
+/// Number of LEDs on the ring
+const RING_SIZE: usize = LedId::CLOCKWISE.len();
+
+/// Frames each spinner position is held for
+const SPINNER_TICKS: u32 = 5;
+/// Frames each chase position is held for
+const CHASE_TICKS: u32 = 8;
+/// Frames for a full breath in and out
+const BREATH_TICKS: u32 = 100;
+/// Frames of each breath the LEDs are dark for
+const BREATH_OUT_TICKS: u32 = 25;
+/// Frames a blink is on (and then off) for
+const BLINK_TICKS: u32 = 15;
+/// Frames between repeats of a blink code
+const BLINK_PAUSE_TICKS: u32 = 75;
+
+/// Blink code shown when the compass produces a bearing that can't be displayed
+pub const BLINK_INVALID_BEARING: u8 = 2;
+/// Blink codes for faults, which stay up until something else changes the
+/// pattern: the turntable stalled, homing didn't find the index, and the
+/// startup discovery didn't see the turntable turn
+pub const BLINK_STALL: u8 = 3;
+pub const BLINK_HOMING_FAILED: u8 = 4;
+pub const BLINK_DISCOVERY_FAILED: u8 = 5;
+
+/// The state of every LED on the ring.  Bit N is the Nth LED clockwise from
+/// north, following `LedId::CLOCKWISE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Frame(u8);
+
+impl Frame {
+    pub fn empty() -> Self {
+        Frame(0)
+    }
+
+    pub fn all() -> Self {
+        Frame(0xff)
+    }
+
+    /// A frame with only the LED at the given clockwise position lit
+    pub fn single(index: usize) -> Self {
+        Frame::empty().with(index)
+    }
+
+    /// Return a copy of this frame with the LED at the given clockwise
+    /// position lit.  Positions wrap around the ring.
+    pub fn with(self, index: usize) -> Self {
+        Frame(self.0 | 1 << (index % RING_SIZE))
+    }
+
+    pub fn is_on(&self, index: usize) -> bool {
+        index < RING_SIZE && self.0 & (1 << index) != 0
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+}
+
+impl From<LedId> for Frame {
+    fn from(led_id: LedId) -> Self {
+        let index = LedId::CLOCKWISE
+            .iter()
+            .position(|id| *id == led_id)
+            .unwrap_or(0);
+        Frame::single(index)
+    }
+}
+
+/// Status patterns that can be shown on the ring
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// All LEDs off
+    Off,
+    /// A single LED circling clockwise, used while calibrating
+    Spinner,
+    /// The given LEDs lit, going dark briefly every couple of seconds, used
+    /// while idle
+    Breathing(Frame),
+    /// A pair of LEDs moving in the direction of rotation
+    Chase(CircularDirection),
+    /// The north LED blinking the given number of times, then pausing
+    BlinkCode(u8),
+}
+
+impl Pattern {
+    /// Generate the frame for this pattern at the given tick.  This is pure,
+    /// so the same pattern and tick always produce the same frame.
+    pub fn frame(&self, tick: u32) -> Frame {
+        match self {
+            Pattern::Off => Frame::empty(),
+            Pattern::Spinner => Frame::single(((tick / SPINNER_TICKS) % RING_SIZE as u32) as usize),
+            Pattern::Breathing(leds) => {
+                if breath_pulse(tick) {
+                    *leds
+                } else {
+                    Frame::empty()
+                }
+            }
+            Pattern::Chase(direction) => {
+                let step = ((tick / CHASE_TICKS) % RING_SIZE as u32) as usize;
+                match direction {
+                    CircularDirection::CW => Frame::single(step).with(step + RING_SIZE - 1),
+                    CircularDirection::CCW => {
+                        let head = RING_SIZE - step;
+                        Frame::single(head).with(head + 1)
+                    }
+                }
+            }
+            Pattern::BlinkCode(count) => {
+                let count = *count as u32;
+                let cycle = count * BLINK_TICKS * 2 + BLINK_PAUSE_TICKS;
+                let position = tick % cycle;
+                let blinking = position < count * BLINK_TICKS * 2;
+
+                if blinking && position % (BLINK_TICKS * 2) < BLINK_TICKS {
+                    Frame::single(0)
+                } else {
+                    Frame::empty()
+                }
+            }
+        }
+    }
+}
+
+/// Whether a breathing LED should be lit at the given tick.  LEDs can only be
+/// on or off, and dimming them in software flickers at any frame rate the
+/// task can afford, so a breath is a short dark spell at the end of each one.
+fn breath_pulse(tick: u32) -> bool {
+    tick % BREATH_TICKS < BREATH_TICKS - BREATH_OUT_TICKS
+}
+
+/// Steps through the frames of the current pattern, one per call to
+/// `next_frame`.
+pub struct Animator {
+    pattern: Pattern,
+    tick: u32,
+}
+
+impl Animator {
+    pub fn new(pattern: Pattern) -> Self {
+        Self { pattern, tick: 0 }
+    }
+
+    pub fn pattern(&self) -> Pattern {
+        self.pattern
+    }
+
+    /// Change the displayed pattern.  The animation only restarts if the kind
+    /// of pattern changes so that e.g. a breathing LED can be moved without
+    /// interrupting the breath.
+    pub fn set_pattern(&mut self, pattern: Pattern) {
+        if mem::discriminant(&pattern) != mem::discriminant(&self.pattern) {
+            self.tick = 0;
+        }
+        self.pattern = pattern;
+    }
+
+    /// Whether the current pattern can be replaced by a bearing display
+    pub fn is_idle(&self) -> bool {
+        matches!(
+            self.pattern,
+            Pattern::Off | Pattern::Breathing(_) | Pattern::BlinkCode(BLINK_INVALID_BEARING)
+        )
+    }
+
+    /// Generate the next frame of the current pattern
+    pub fn next_frame(&mut self) -> Frame {
+        let frame = self.pattern.frame(self.tick);
+        self.tick = self.tick.wrapping_add(1);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The distinct frames a pattern steps through, sampling every `every`
+    /// ticks
+    fn sampled(pattern: Pattern, every: u32) -> [u8; 16] {
+        let mut frames = [0; 16];
+        for (i, frame) in frames.iter_mut().enumerate() {
+            *frame = pattern.frame(i as u32 * every).bits();
+        }
+        frames
+    }
+
+    #[test]
+    fn spinner_goes_round_clockwise() {
+        assert_eq!(
+            sampled(Pattern::Spinner, SPINNER_TICKS),
+            [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128]
+        );
+        // Each position is held
+        assert!((0..SPINNER_TICKS).all(|tick| Pattern::Spinner.frame(tick) == Frame::single(0)));
+    }
+
+    #[test]
+    fn chase_follows_the_direction() {
+        assert_eq!(
+            sampled(Pattern::Chase(CircularDirection::CW), CHASE_TICKS)[..8],
+            [0x81, 0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0xc0]
+        );
+        assert_eq!(
+            sampled(Pattern::Chase(CircularDirection::CCW), CHASE_TICKS)[..8],
+            [0x03, 0x81, 0xc0, 0x60, 0x30, 0x18, 0x0c, 0x06]
+        );
+    }
+
+    #[test]
+    fn breathing_is_mostly_lit() {
+        let leds = Frame::single(2);
+        let pattern = Pattern::Breathing(leds);
+        let lit = (0..BREATH_TICKS)
+            .filter(|tick| pattern.frame(*tick) == leds)
+            .count() as u32;
+        assert_eq!(lit, BREATH_TICKS - BREATH_OUT_TICKS);
+        // One dark spell per breath, not a flicker
+        let changes = (1..BREATH_TICKS * 2)
+            .filter(|tick| pattern.frame(*tick) != pattern.frame(tick - 1))
+            .count();
+        assert_eq!(changes, 3);
+        assert_eq!(Pattern::Breathing(Frame::empty()).frame(0), Frame::empty());
+    }
+
+    #[test]
+    fn blink_code_blinks_then_pauses() {
+        let pattern = Pattern::BlinkCode(BLINK_INVALID_BEARING);
+        assert_eq!(
+            sampled(pattern, BLINK_TICKS)[..9],
+            [1, 0, 1, 0, 0, 0, 0, 0, 0]
+        );
+        let cycle = 2 * BLINK_TICKS * 2 + BLINK_PAUSE_TICKS;
+        assert_eq!(pattern.frame(cycle), Frame::single(0));
+        assert_eq!(pattern.frame(cycle - 1), Frame::empty());
+    }
+
+    #[test]
+    fn off_is_dark() {
+        assert!((0..100).all(|tick| Pattern::Off.frame(tick) == Frame::empty()));
+    }
+
+    #[test]
+    fn animator_keeps_its_place_for_the_same_kind_of_pattern() {
+        let mut animator = Animator::new(Pattern::Breathing(Frame::single(0)));
+        for _ in 0..10 {
+            animator.next_frame();
+        }
+        animator.set_pattern(Pattern::Breathing(Frame::single(1)));
+        assert_eq!(animator.next_frame(), Frame::single(1));
+        assert_eq!(animator.tick, 11);
+
+        animator.set_pattern(Pattern::Spinner);
+        assert_eq!(animator.tick, 0);
+        assert!(!animator.is_idle());
+    }
+
+    #[test]
+    fn faults_stay_up() {
+        let mut animator = Animator::new(Pattern::BlinkCode(BLINK_INVALID_BEARING));
+        assert!(animator.is_idle());
+        for code in [BLINK_STALL, BLINK_HOMING_FAILED, BLINK_DISCOVERY_FAILED] {
+            animator.set_pattern(Pattern::BlinkCode(code));
+            assert!(!animator.is_idle());
+        }
+    }
+
+    #[test]
+    fn blink_codes_blink_their_count() {
+        for code in [
+            BLINK_INVALID_BEARING,
+            BLINK_STALL,
+            BLINK_HOMING_FAILED,
+            BLINK_DISCOVERY_FAILED,
+        ] {
+            let pattern = Pattern::BlinkCode(code);
+            let cycle = code as u32 * BLINK_TICKS * 2 + BLINK_PAUSE_TICKS;
+            let blinks = (0..cycle)
+                .filter(|tick| tick % BLINK_TICKS == 0 && pattern.frame(*tick).is_on(0))
+                .count();
+            assert_eq!(blinks, code as usize);
+        }
+    }
+
+    #[test]
+    fn frames_wrap_around_the_ring() {
+        assert_eq!(Frame::single(RING_SIZE), Frame::single(0));
+        assert!(!Frame::all().is_on(RING_SIZE));
+        assert_eq!(Frame::from(LedId::CLOCKWISE[3]), Frame::single(3));
+    }
+}
@@ -0,0 +1,263 @@
+/// Backlash in the gear train between the stepper and the turntable
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::Angle;
+use crate::stepper::CircularDirection;
+
+/// Motor degrees to turn one way before measuring, to be sure the gears are
+/// pressed together on that side
+pub const MEASUREMENT_PRELOAD: f32 = 90.0;
+
+/// Compass degrees of movement taken as the turntable starting to move,
+/// clear of compass noise
+pub const MEASUREMENT_DETECT: f32 = 2.0;
+
+/// Compass degrees moved after that to work out the gear ratio
+pub const MEASUREMENT_SPAN: f32 = 20.0;
+
+/// Motor degrees to turn without the compass moving before giving up
+pub const MEASUREMENT_MAX_TRAVEL: f32 = 3600.0;
+
+/// Full steps per second while measuring, slow so the compass keeps up
+pub const MEASUREMENT_RATE: u32 = 20;
+
+/// The play between the motor and the turntable.  Turning the motor first
+/// takes up any play in that direction before the turntable moves, so a
+/// reversal costs the full backlash in extra motor travel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GearPlay {
+    /// Motor degrees of play
+    backlash: f32,
+    /// Where in the play the motor is, from 0 (pressed anticlockwise) to
+    /// `backlash` (pressed clockwise)
+    position: f32,
+}
+
+impl GearPlay {
+    /// Starts pressed clockwise, as if the last move was clockwise
+    pub fn new(backlash: f32) -> Self {
+        let backlash = backlash.max(0.0);
+
+        Self {
+            backlash,
+            position: backlash,
+        }
+    }
+
+    pub fn backlash(&self) -> f32 {
+        self.backlash
+    }
+
+    /// Turn the motor by some degrees, clockwise positive, returning how far
+    /// the turntable side moves
+    pub fn turn(&mut self, degrees: f32) -> f32 {
+        let previous = self.position;
+        self.position = (self.position + degrees).clamp(0.0, self.backlash);
+
+        degrees - (self.position - previous)
+    }
+
+    /// Extra motor degrees needed before the turntable moves in a direction
+    pub fn take_up(&self, direction: CircularDirection) -> f32 {
+        match direction {
+            CircularDirection::CW => self.backlash - self.position,
+            CircularDirection::CCW => self.position,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeasurementAction {
+    /// Run the motor this way at this many full steps per second
+    Run(CircularDirection, u32),
+    /// Stop, the backlash is this many motor degrees
+    Done(f32),
+    /// Stop, the compass never saw the turntable move
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Preloading {
+        from: f32,
+    },
+    Reversed {
+        from: f32,
+        heading: Angle,
+    },
+    Moving {
+        from: f32,
+        heading: Angle,
+        detected: f32,
+    },
+    Done(f32),
+    Failed,
+}
+
+/// Estimates backlash by turning one way, reversing and timing how much
+/// motor travel it takes for the compass to see the turntable move.  The gear
+/// ratio is measured from a little more travel, so the motor travel for the
+/// few degrees needed to see movement over compass noise can be taken off.
+/// Positions are raw motor degrees, without any backlash compensation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BacklashMeasurement {
+    state: State,
+}
+
+impl BacklashMeasurement {
+    pub fn new(position: f32) -> Self {
+        Self {
+            state: State::Preloading { from: position },
+        }
+    }
+
+    pub fn update(&mut self, position: f32, heading: Angle) -> MeasurementAction {
+        self.state = match self.state {
+            State::Preloading { from } if position - from >= MEASUREMENT_PRELOAD => {
+                State::Reversed {
+                    from: position,
+                    heading,
+                }
+            }
+            State::Reversed {
+                from,
+                heading: start,
+            } if moved(start, heading) >= MEASUREMENT_DETECT => State::Moving {
+                from,
+                heading: start,
+                detected: from - position,
+            },
+            State::Reversed { from, .. } if from - position > MEASUREMENT_MAX_TRAVEL => {
+                State::Failed
+            }
+            State::Moving {
+                from,
+                heading: start,
+                detected,
+            } if moved(start, heading) >= MEASUREMENT_DETECT + MEASUREMENT_SPAN => {
+                let ratio = (from - position - detected) / MEASUREMENT_SPAN;
+                State::Done((detected - MEASUREMENT_DETECT * ratio).max(0.0))
+            }
+            State::Moving { from, .. } if from - position > MEASUREMENT_MAX_TRAVEL => State::Failed,
+            state => state,
+        };
+
+        match self.state {
+            State::Preloading { .. } => {
+                MeasurementAction::Run(CircularDirection::CW, MEASUREMENT_RATE)
+            }
+            State::Reversed { .. } | State::Moving { .. } => {
+                MeasurementAction::Run(CircularDirection::CCW, MEASUREMENT_RATE)
+            }
+            State::Done(backlash) => MeasurementAction::Done(backlash),
+            State::Failed => MeasurementAction::Failed,
+        }
+    }
+}
+
+fn moved(from: Angle, to: Angle) -> f32 {
+    from.difference(to).degrees().abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_direction_moves_add_no_slack() {
+        let mut play = GearPlay::new(10.0);
+        assert_eq!(play.take_up(CircularDirection::CW), 0.0);
+        assert_eq!(play.turn(30.0), 30.0);
+        assert_eq!(play.turn(5.0), 5.0);
+        assert_eq!(play.take_up(CircularDirection::CW), 0.0);
+        assert_eq!(play.take_up(CircularDirection::CCW), 10.0);
+    }
+
+    #[test]
+    fn reversals_take_up_the_slack() {
+        let mut play = GearPlay::new(10.0);
+        // The first 10° back only takes up the play
+        assert_eq!(play.turn(-4.0), 0.0);
+        assert_eq!(play.take_up(CircularDirection::CCW), 6.0);
+        assert_eq!(play.turn(-16.0), -10.0);
+        assert_eq!(play.take_up(CircularDirection::CCW), 0.0);
+        assert_eq!(play.turn(-5.0), -5.0);
+
+        // And again on the way back
+        assert_eq!(play.take_up(CircularDirection::CW), 10.0);
+        assert_eq!(play.turn(25.0), 15.0);
+        assert_eq!(play.turn(-25.0), -15.0);
+    }
+
+    #[test]
+    fn small_reversals_stay_in_the_play() {
+        let mut play = GearPlay::new(10.0);
+        for _ in 0..10 {
+            assert_eq!(play.turn(-3.0), 0.0);
+            assert_eq!(play.turn(3.0), 0.0);
+        }
+        assert_eq!(play.turn(1.0), 1.0);
+    }
+
+    #[test]
+    fn no_backlash() {
+        let mut play = GearPlay::new(-5.0);
+        assert_eq!(play.backlash(), 0.0);
+        assert_eq!(play.turn(-12.0), -12.0);
+        assert_eq!(play.turn(7.0), 7.0);
+    }
+
+    /// Run a measurement against a turntable geared down by `ratio` with
+    /// `backlash` motor degrees of play, moving the motor a degree per update
+    fn measure(backlash: f32, ratio: f32) -> MeasurementAction {
+        let mut play = GearPlay::new(backlash);
+        // Start pressed anticlockwise so the preload has play to take up
+        play.turn(-backlash);
+        let mut position = 0.0;
+        let mut turntable = 100.0;
+        let mut measurement = BacklashMeasurement::new(position);
+        for _ in 0..10_000 {
+            let heading = Angle::from_degrees(turntable);
+            let step = match measurement.update(position, heading) {
+                MeasurementAction::Run(CircularDirection::CW, _) => 1.0,
+                MeasurementAction::Run(CircularDirection::CCW, _) => -1.0,
+                result => return result,
+            };
+            position += step;
+            turntable += play.turn(step) * ratio;
+        }
+
+        panic!("measurement didn't finish");
+    }
+
+    #[test]
+    fn measures_the_backlash() {
+        for (backlash, ratio) in [(30.0, 0.5), (12.0, 0.25), (45.0, 1.0)] {
+            match measure(backlash, ratio) {
+                MeasurementAction::Done(measured) => {
+                    assert!(
+                        (measured - backlash).abs() <= 2.0,
+                        "{} {}",
+                        measured,
+                        backlash
+                    )
+                }
+                result => panic!("{:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn measures_no_backlash() {
+        match measure(0.0, 0.5) {
+            MeasurementAction::Done(measured) => assert!(measured <= 2.0),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn fails_if_the_turntable_never_moves() {
+        assert_eq!(measure(10.0, 0.0), MeasurementAction::Failed);
+    }
+}
@@ -0,0 +1,54 @@
+/// The blue user button on the STM32F3DISCOVERY
+use stm32f3xx_hal::gpio::gpioa::PA0;
+use stm32f3xx_hal::gpio::{gpioa, Edge, Input};
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::syscfg::SysCfg;
+
+use crate::Error;
+
+/// Milliseconds after a press during which contact bounce is ignored
+pub const DEBOUNCE_MS: u64 = 250;
+
+/*
+ * Pinout:
+ *   PA0 -> User button, pulled down on the board and high while pressed
+ */
+pub struct UserButton {
+    pin: PA0<Input>,
+    last_press: Option<u64>,
+}
+
+impl UserButton {
+    pub fn new<Pa0Mode>(
+        pa0: PA0<Pa0Mode>,
+        moder: &mut gpioa::MODER,
+        pupdr: &mut gpioa::PUPDR,
+        syscfg: &mut SysCfg,
+        exti: &mut pac::EXTI,
+    ) -> Result<Self, Error> {
+        let mut pin = pa0.into_floating_input(moder, pupdr);
+        syscfg.select_exti_interrupt_source(&pin);
+        pin.trigger_on_edge(exti, Edge::Rising);
+        pin.enable_interrupt(exti);
+
+        Ok(Self {
+            pin,
+            last_press: None,
+        })
+    }
+
+    /// Acknowledge the EXTI0 interrupt, returning whether it was a fresh
+    /// press rather than bounce from the last one
+    pub fn pressed(&mut self, now_ms: u64) -> bool {
+        self.pin.clear_interrupt();
+
+        let bounce = self
+            .last_press
+            .is_some_and(|last| now_ms.saturating_sub(last) < DEBOUNCE_MS);
+        if bounce {
+            return false;
+        }
+        self.last_press = Some(now_ms);
+        true
+    }
+}
@@ -0,0 +1,133 @@
+/// Tracking of how far the turntable has turned in total, so the cables
+/// through its base aren't wound up
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::Angle;
+
+/// Default degrees the turntable may turn either way from where it started
+pub const DEFAULT_LIMIT: f32 = 540.0;
+
+/// Total rotation of the turntable, unwrapped from its heading
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CableWrap {
+    last_heading: Option<Angle>,
+    total: f32,
+}
+
+impl CableWrap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow a new heading, which should be less than half a turn from the
+    /// last one, returning the total rotation
+    pub fn update(&mut self, heading: Angle) -> f32 {
+        if let Some(last) = self.last_heading {
+            self.total += last.difference(heading).degrees();
+        }
+        self.last_heading = Some(heading);
+
+        self.total
+    }
+
+    /// Degrees turned since startup, clockwise positive
+    pub fn total(&self) -> f32 {
+        self.total
+    }
+}
+
+/// The rotation in degrees to correct a heading error (clockwise positive,
+/// within ±180°) given the total rotation so far.  This is the short way
+/// round unless that would take the total past ±`limit`, in which case it's
+/// the long way round.  If both would, whichever unwinds the most.
+pub fn rotation(total: f32, error: f32, limit: f32) -> f32 {
+    if (total + error).abs() <= limit {
+        return error;
+    }
+
+    let long = if error > 0.0 {
+        error - 360.0
+    } else {
+        error + 360.0
+    };
+    if (total + long).abs() < (total + error).abs() {
+        long
+    } else {
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_way_within_the_limit() {
+        assert_eq!(rotation(0.0, 90.0, DEFAULT_LIMIT), 90.0);
+        assert_eq!(rotation(0.0, -170.0, DEFAULT_LIMIT), -170.0);
+        assert_eq!(rotation(400.0, 100.0, DEFAULT_LIMIT), 100.0);
+        assert_eq!(rotation(-400.0, -100.0, DEFAULT_LIMIT), -100.0);
+    }
+
+    #[test]
+    fn up_to_the_limit() {
+        // Landing exactly on the limit is allowed
+        assert_eq!(rotation(450.0, 90.0, DEFAULT_LIMIT), 90.0);
+        assert_eq!(rotation(-450.0, -90.0, DEFAULT_LIMIT), -90.0);
+        assert_eq!(rotation(DEFAULT_LIMIT, 0.0, DEFAULT_LIMIT), 0.0);
+    }
+
+    #[test]
+    fn long_way_past_the_limit() {
+        assert_eq!(rotation(500.0, 90.0, DEFAULT_LIMIT), -270.0);
+        assert_eq!(rotation(-500.0, -90.0, DEFAULT_LIMIT), 270.0);
+        // Unwinding is always the short way
+        assert_eq!(rotation(500.0, -90.0, DEFAULT_LIMIT), -90.0);
+        assert_eq!(rotation(-500.0, 90.0, DEFAULT_LIMIT), 90.0);
+    }
+
+    #[test]
+    fn past_the_limit_either_way() {
+        // Both ways go past a tight limit, so it's whichever ends up nearer
+        // the start
+        let limit = 100.0;
+        assert_eq!(rotation(150.0, 10.0, limit), 10.0);
+        assert_eq!(rotation(150.0, 100.0, limit), -260.0);
+        assert_eq!(rotation(-150.0, -100.0, limit), 260.0);
+    }
+
+    #[test]
+    fn accumulates_turns() {
+        let mut wrap = CableWrap::new();
+        assert_eq!(wrap.total(), 0.0);
+        // The first heading is where it starts
+        assert_eq!(wrap.update(Angle::from_degrees(350.0)), 0.0);
+
+        // Two and a half turns clockwise, through north each time
+        let mut heading = 350.0;
+        for _ in 0..90 {
+            heading = (heading + 10.0) % 360.0;
+            wrap.update(Angle::from_degrees(heading));
+        }
+        assert!((wrap.total() - 900.0).abs() < 1e-3);
+        assert!((rotation(wrap.total(), 10.0, DEFAULT_LIMIT) + 350.0).abs() < 1e-3);
+
+        // And back past the start counterclockwise
+        for _ in 0..117 {
+            heading = (heading + 350.0) % 360.0;
+            wrap.update(Angle::from_degrees(heading));
+        }
+        assert!((wrap.total() + 270.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn heading_noise_cancels_out() {
+        let mut wrap = CableWrap::new();
+        for i in 0..100 {
+            let jitter = if i % 2 == 0 { 3.0 } else { -3.0 };
+            wrap.update(Angle::from_degrees(jitter + 360.0));
+        }
+        assert!(wrap.total().abs() <= 6.0);
+    }
+}
@@ -0,0 +1,504 @@
+/// Calibration of how the turntable responds to the stepper, found with the
+/// compass and kept in flash between resets
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::Angle;
+use crate::stepper::CircularDirection;
+use crate::tuning::Gains;
+use crate::Error;
+
+/// Marks the start of a stored calibration, "ORNT"
+const MAGIC: u32 = 0x4f52_4e54;
+
+/// Bumped when the stored layout changes, so an old calibration is ignored
+/// rather than misread
+const VERSION: u32 = 3;
+
+/// Words in a stored calibration, including the magic, version and checksum
+pub const RECORD_WORDS: usize = 9;
+
+/// Longest the discovery runs the motor for
+pub const DISCOVERY_RUN_MS: u64 = 2_000;
+
+/// Compass degrees after which the discovery stops early, well short of the
+/// half turn between compass readings that would hide the direction
+pub const DISCOVERY_TURN: f32 = 90.0;
+
+/// Milliseconds to keep watching the compass after stopping, so a lagging
+/// reading is still counted
+pub const DISCOVERY_SETTLE_MS: u64 = 500;
+
+/// Least compass degrees the turntable has to turn to trust the direction
+pub const DISCOVERY_MIN_TURN: f32 = 5.0;
+
+/// Full steps per second while counting revolutions, slow enough for the
+/// compass to follow a direct drive
+pub const REVOLUTION_RATE: u32 = 100;
+
+/// Compass degrees turned before counting starts, so taking up backlash and
+/// getting going aren't counted
+pub const REVOLUTION_SPIN_UP: f32 = 10.0;
+
+/// Milliseconds the turntable may take to turn `REVOLUTION_PROGRESS` compass
+/// degrees before giving up on it
+pub const REVOLUTION_TIMEOUT_MS: u64 = 30_000;
+pub const REVOLUTION_PROGRESS: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Calibration {
+    /// Which way the motor turns to increase the heading, or `None` before
+    /// it's been discovered
+    pub increasing: Option<CircularDirection>,
+    /// Rough turntable degrees per second at the stepper's normal rate
+    pub turn_rate: Option<f32>,
+    /// Full motor steps per turntable revolution, through the whole drive
+    /// train
+    pub steps_per_revolution: Option<f32>,
+    /// Gains for the azimuth controller, from relay feedback tuning
+    pub gains: Option<Gains>,
+}
+
+impl Calibration {
+    /// Which way to turn the motor for a positive heading error.  Until
+    /// discovered, this is what the turntable was first built with.
+    pub fn positive(&self) -> CircularDirection {
+        self.increasing.unwrap_or(CircularDirection::CCW)
+    }
+
+    /// Full motor steps per compass degree, once measured
+    pub fn steps_per_degree(&self) -> Option<f32> {
+        self.steps_per_revolution.map(|steps| steps / 360.0)
+    }
+
+    /// Turntable degrees for `motor` degrees of a motor with the given full
+    /// steps per revolution, once the steps per revolution are known
+    pub fn turntable_degrees(&self, motor: f32, motor_steps: u32) -> Option<f32> {
+        let steps = self.steps_per_revolution.filter(|steps| *steps > 0.0)?;
+        Some(motor * motor_steps as f32 / steps)
+    }
+
+    /// Motor degrees for `turntable` degrees of the turntable, the other way
+    /// to `turntable_degrees`
+    pub fn motor_degrees(&self, turntable: f32, motor_steps: u32) -> Option<f32> {
+        let steps = self.steps_per_revolution.filter(|steps| *steps > 0.0)?;
+        Some(turntable * steps / motor_steps as f32)
+    }
+
+    /// Degrees per second the turntable turns at with the motor stepping at
+    /// `rate` full steps per second, once the steps per revolution are known
+    pub fn turntable_speed(&self, rate: u32) -> Option<f32> {
+        let steps = self.steps_per_revolution.filter(|steps| *steps > 0.0)?;
+        Some(rate as f32 * 360.0 / steps)
+    }
+
+    /// Rough degrees per second the turntable should turn at with the motor
+    /// stepping at `rate` full steps per second and its shaft turning at
+    /// `motor_speed` degrees per second.  Before the steps per revolution are
+    /// measured this is the speed discovery saw, and before that the motor's
+    /// own, as if it drove the turntable directly.
+    pub fn expected_speed(&self, rate: u32, motor_speed: f32) -> f32 {
+        self.turntable_speed(rate)
+            .or(self.turn_rate.map(f32::abs))
+            .unwrap_or(motor_speed)
+    }
+
+    /// Degrees per second the heading changes by with a motor of the given
+    /// full steps per revolution turning at `velocity` degrees per second
+    /// clockwise, once the direction and steps per revolution are known
+    pub fn heading_rate(&self, velocity: f32, motor_steps: u32) -> Option<f32> {
+        let rate = self.turntable_degrees(velocity, motor_steps)?;
+        match self.increasing? {
+            CircularDirection::CW => Some(rate),
+            CircularDirection::CCW => Some(-rate),
+        }
+    }
+
+    /// The words stored in flash
+    pub fn to_words(&self) -> [u32; RECORD_WORDS] {
+        let increasing = match self.increasing {
+            None => 0,
+            Some(CircularDirection::CW) => 1,
+            Some(CircularDirection::CCW) => 2,
+        };
+        let gains = self.gains;
+
+        let mut words = [
+            MAGIC,
+            VERSION,
+            increasing,
+            to_word(self.turn_rate),
+            to_word(self.steps_per_revolution),
+            to_word(gains.map(|gains| gains.kp)),
+            to_word(gains.map(|gains| gains.ki)),
+            to_word(gains.map(|gains| gains.kd)),
+            0,
+        ];
+        words[RECORD_WORDS - 1] = checksum(&words[..RECORD_WORDS - 1]);
+        words
+    }
+
+    /// Read back words stored in flash.  Erased flash, or a calibration
+    /// stored by another version, is a `Parse` error.
+    pub fn from_words(words: &[u32; RECORD_WORDS]) -> Result<Self, Error> {
+        if words[0] != MAGIC || words[1] != VERSION {
+            return Err(Error::Parse);
+        }
+        if words[RECORD_WORDS - 1] != checksum(&words[..RECORD_WORDS - 1]) {
+            return Err(Error::Checksum);
+        }
+
+        let increasing = match words[2] {
+            0 => None,
+            1 => Some(CircularDirection::CW),
+            2 => Some(CircularDirection::CCW),
+            _ => return Err(Error::Parse),
+        };
+        let gains = match (
+            from_word(words[5]),
+            from_word(words[6]),
+            from_word(words[7]),
+        ) {
+            (Some(kp), Some(ki), Some(kd)) => Some(Gains { kp, ki, kd }),
+            _ => None,
+        };
+
+        Ok(Self {
+            increasing,
+            turn_rate: from_word(words[3]),
+            steps_per_revolution: from_word(words[4]),
+            gains,
+        })
+    }
+}
+
+/// Missing values are stored as NaN
+fn to_word(value: Option<f32>) -> u32 {
+    value.unwrap_or(f32::NAN).to_bits()
+}
+
+fn from_word(word: u32) -> Option<f32> {
+    Some(f32::from_bits(word)).filter(|value| value.is_finite())
+}
+
+fn checksum(words: &[u32]) -> u32 {
+    !words
+        .iter()
+        .fold(0u32, |sum, word| sum.rotate_left(1).wrapping_add(*word))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiscoveryAction {
+    /// Run the motor this way at its normal rate
+    Run(CircularDirection),
+    /// Stop the motor and keep watching the compass
+    Stop,
+    /// Stop, the motor turns the heading up this way at about this many
+    /// degrees per second
+    Done(CircularDirection, f32),
+    /// Stop, the compass didn't see the turntable turn
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Running,
+    /// Stopped after running for this many milliseconds
+    Settling(u64),
+    Done(CircularDirection, f32),
+    Failed,
+}
+
+/// Finds which way the motor turns the turntable by running it clockwise
+/// briefly and watching which way the compass heading goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionDiscovery {
+    state: State,
+    started: u64,
+    last_heading: Angle,
+    /// Compass degrees turned so far, clockwise positive
+    turned: f32,
+}
+
+impl DirectionDiscovery {
+    pub fn new(now_ms: u64, heading: Angle) -> Self {
+        Self {
+            state: State::Running,
+            started: now_ms,
+            last_heading: heading,
+            turned: 0.0,
+        }
+    }
+
+    /// Move on given the time and compass heading, which should change by
+    /// less than half a turn between updates
+    pub fn update(&mut self, now_ms: u64, heading: Angle) -> DiscoveryAction {
+        self.turned += self.last_heading.difference(heading).degrees();
+        self.last_heading = heading;
+        let elapsed = now_ms.saturating_sub(self.started);
+
+        self.state = match self.state {
+            State::Running
+                if elapsed >= DISCOVERY_RUN_MS || self.turned.abs() >= DISCOVERY_TURN =>
+            {
+                State::Settling(elapsed)
+            }
+            State::Settling(ran) if elapsed >= ran + DISCOVERY_SETTLE_MS => {
+                if self.turned.abs() < DISCOVERY_MIN_TURN || ran == 0 {
+                    State::Failed
+                } else {
+                    let increasing = if self.turned > 0.0 {
+                        CircularDirection::CW
+                    } else {
+                        CircularDirection::CCW
+                    };
+                    State::Done(increasing, self.turned.abs() * 1000.0 / ran as f32)
+                }
+            }
+            state => state,
+        };
+
+        match self.state {
+            State::Running => DiscoveryAction::Run(CircularDirection::CW),
+            State::Settling(_) => DiscoveryAction::Stop,
+            State::Done(increasing, rate) => DiscoveryAction::Done(increasing, rate),
+            State::Failed => DiscoveryAction::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevolutionAction {
+    /// Run the motor this way at this many full steps per second
+    Run(CircularDirection, u32),
+    /// Keep running, a revolution took this many full steps
+    Counted(f32),
+    /// Stop, the last revolution took this many full steps
+    Done(f32),
+    /// Stop, the compass stopped seeing the turntable turn
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RevolutionState {
+    Running,
+    Done(f32),
+    Failed,
+}
+
+/// Counts the full steps for each of some whole revolutions of the
+/// turntable, seen by the compass, at a steady step rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RevolutionMeasurement {
+    state: RevolutionState,
+    direction: CircularDirection,
+    revolutions: u32,
+    started: u64,
+    last_heading: Angle,
+    /// Compass degrees turned so far, clockwise positive
+    turned: f32,
+    last_steps: f32,
+    /// Revolution marks passed, the first being the end of the spin up
+    marks: u32,
+    /// Steps at the last mark
+    mark_steps: f32,
+    /// When the turntable last made progress, and how far it had turned
+    progress: (u64, f32),
+}
+
+impl RevolutionMeasurement {
+    pub fn new(
+        now_ms: u64,
+        heading: Angle,
+        direction: CircularDirection,
+        revolutions: u32,
+    ) -> Self {
+        Self {
+            state: RevolutionState::Running,
+            direction,
+            revolutions: revolutions.max(1),
+            started: now_ms,
+            last_heading: heading,
+            turned: 0.0,
+            last_steps: 0.0,
+            marks: 0,
+            mark_steps: 0.0,
+            progress: (now_ms, 0.0),
+        }
+    }
+
+    /// Move on given the time and compass heading, which should change by
+    /// less than half a turn between updates
+    pub fn update(&mut self, now_ms: u64, heading: Angle) -> RevolutionAction {
+        let elapsed = now_ms.saturating_sub(self.started);
+        let steps = REVOLUTION_RATE as f32 * elapsed as f32 / 1000.0;
+        // The direction the heading goes doesn't matter, only how far
+        let previous = self.turned.abs();
+        self.turned += self.last_heading.difference(heading).degrees();
+        self.last_heading = heading;
+        let turned = self.turned.abs();
+
+        // Where between the updates the turntable passed the mark, which
+        // keeps the count from depending on when the compass was read
+        let mark = REVOLUTION_SPIN_UP + self.marks as f32 * 360.0;
+        let mut counted = None;
+        if self.state == RevolutionState::Running && previous < mark && turned >= mark {
+            let passed = (mark - previous) / (turned - previous);
+            let at = self.last_steps + (steps - self.last_steps) * passed;
+            if self.marks > 0 {
+                counted = Some(at - self.mark_steps);
+            }
+            self.marks += 1;
+            self.mark_steps = at;
+        }
+        self.last_steps = steps;
+
+        if turned >= self.progress.1 + REVOLUTION_PROGRESS {
+            self.progress = (now_ms, turned);
+        }
+
+        self.state = match (self.state, counted) {
+            (RevolutionState::Running, Some(counted)) if self.marks > self.revolutions => {
+                RevolutionState::Done(counted)
+            }
+            (RevolutionState::Running, _)
+                if now_ms.saturating_sub(self.progress.0) > REVOLUTION_TIMEOUT_MS =>
+            {
+                RevolutionState::Failed
+            }
+            (state, _) => state,
+        };
+
+        match (self.state, counted) {
+            (RevolutionState::Running, Some(counted)) => RevolutionAction::Counted(counted),
+            (RevolutionState::Running, None) => {
+                RevolutionAction::Run(self.direction, REVOLUTION_RATE)
+            }
+            (RevolutionState::Done(counted), _) => RevolutionAction::Done(counted),
+            (RevolutionState::Failed, _) => RevolutionAction::Failed,
+        }
+    }
+}
+
+/// Running statistics of the steps counted for each revolution, to show how
+/// repeatable the measurement is
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RevolutionStats {
+    count: u32,
+    mean: f32,
+    /// Sum of squared differences from the mean
+    m2: f32,
+    min: f32,
+    max: f32,
+}
+
+impl RevolutionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, steps: f32) {
+        if self.count == 0 {
+            self.min = steps;
+            self.max = steps;
+        }
+        self.count += 1;
+        let delta = steps - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (steps - self.mean);
+        self.min = self.min.min(steps);
+        self.max = self.max.max(steps);
+    }
+
+    /// Revolutions counted so far
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        Some(self.mean).filter(|_| self.count > 0)
+    }
+
+    /// Sample standard deviation, once there are two revolutions
+    pub fn std_dev(&self) -> Option<f32> {
+        Some(self.m2 / (self.count as f32 - 1.0))
+            .filter(|_| self.count > 1)
+            .map(|variance| variance.sqrt())
+    }
+
+    /// Difference between the most and fewest steps counted
+    pub fn range(&self) -> Option<f32> {
+        Some(self.max - self.min).filter(|_| self.count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibrated(steps_per_revolution: f32, increasing: CircularDirection) -> Calibration {
+        Calibration {
+            increasing: Some(increasing),
+            steps_per_revolution: Some(steps_per_revolution),
+            ..Calibration::default()
+        }
+    }
+
+    #[test]
+    fn turntable_speeds() {
+        let calibration = calibrated(7_200.0, CircularDirection::CW);
+        assert_eq!(calibration.turntable_speed(200), Some(10.0));
+        assert_eq!(Calibration::default().turntable_speed(200), None);
+    }
+
+    #[test]
+    fn expected_speeds() {
+        // Measured steps per revolution first, then discovery's speed, then
+        // the motor's
+        let calibration = Calibration {
+            turn_rate: Some(-4.0),
+            ..calibrated(7_200.0, CircularDirection::CW)
+        };
+        assert_eq!(calibration.expected_speed(200, 360.0), 10.0);
+        let discovered = Calibration {
+            steps_per_revolution: None,
+            ..calibration
+        };
+        assert_eq!(discovered.expected_speed(200, 360.0), 4.0);
+        assert_eq!(Calibration::default().expected_speed(200, 360.0), 360.0);
+    }
+
+    #[test]
+    fn turntable_degrees_for_each_motor() {
+        // The same turntable, driven through the same steps per revolution,
+        // whichever motor
+        let calibration = calibrated(7_200.0, CircularDirection::CW);
+        assert_eq!(calibration.turntable_degrees(360.0, 200), Some(10.0));
+        assert_eq!(calibration.turntable_degrees(360.0, 2048), Some(102.4));
+        assert_eq!(Calibration::default().turntable_degrees(360.0, 200), None);
+        assert_eq!(calibration.motor_degrees(10.0, 200), Some(360.0));
+        assert_eq!(Calibration::default().motor_degrees(10.0, 200), None);
+    }
+
+    #[test]
+    fn heading_rates() {
+        let cw = calibrated(7_200.0, CircularDirection::CW);
+        let ccw = calibrated(7_200.0, CircularDirection::CCW);
+        // A 200 step motor at a revolution every 10s, and a 28BYJ-48 at the
+        // same step rate
+        assert_eq!(cw.heading_rate(36.0, 200), Some(1.0));
+        assert!((cw.heading_rate(3.515625, 2048).unwrap() - 1.0).abs() < 1e-6);
+        assert_eq!(ccw.heading_rate(36.0, 200), Some(-1.0));
+        assert_eq!(ccw.heading_rate(-36.0, 200), Some(1.0));
+
+        let undiscovered = Calibration {
+            increasing: None,
+            ..cw
+        };
+        assert_eq!(undiscovered.heading_rate(36.0, 200), None);
+        assert_eq!(
+            calibrated(0.0, CircularDirection::CW).heading_rate(36.0, 200),
+            None
+        );
+    }
+}
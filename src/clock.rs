@@ -0,0 +1,241 @@
+/// Struct for real time clock configuration and control
+use cortex_m::asm;
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::rcc::{self, Enable};
+use stm32f3xx_hal::time::rate::*;
+
+use crate::datetime::{Date, DateTime, Time};
+use crate::Error;
+
+/// Prescalers giving a 1Hz calendar clock from the 32.768kHz LSE
+const PREDIV_S: u16 = 255;
+const PREDIV_A: u8 = 127;
+
+/// How long to give the LSE to start.  The datasheet gives 2s as typical.
+const LSE_STARTUP_TIMEOUT_MS: u32 = 3000;
+
+/// How long to wait for the calendar to enter init mode or resynchronise,
+/// which take a couple of RTC clock cycles
+const RTC_TIMEOUT_MS: u32 = 10;
+
+/// The RTC only counts years within a century, and its leap years are the
+/// ones divisible by four, so it counts from 2000
+const EPOCH_YEAR: u16 = 2000;
+
+/// Keeps UTC time on the RTC.  Requires the 32.768kHz crystal (X3) to be
+/// fitted to the board.  The RTC is in the backup domain, so once set it
+/// keeps its time through resets.
+pub struct Clock {
+    rtc: pac::RTC,
+    clocks: rcc::Clocks,
+}
+
+impl Clock {
+    /// Start the RTC from the LSE, or pick up the one already running if the
+    /// calendar was set before a reset
+    pub fn new(
+        rtc: pac::RTC,
+        apb1: &mut rcc::APB1,
+        // Only taken so nothing else can touch the backup domain
+        _bdcr: &mut rcc::BDCR,
+        pwr: &mut pac::PWR,
+        clocks: rcc::Clocks,
+    ) -> Result<Self, Error> {
+        // The backup domain is write protected until DBP is set
+        pac::PWR::enable(apb1);
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+        // Safe as the BDCR proxy gives us the backup domain control register,
+        // which the HAL only lets itself at
+        let bdcr = unsafe { &(*pac::RCC::ptr()).bdcr };
+        let running = {
+            let control = bdcr.read();
+            rtc.isr.read().inits().bit_is_set()
+                && control.rtcen().bit_is_set()
+                && control.rtcsel().is_lse()
+                && control.lserdy().bit_is_set()
+        };
+
+        let mut clock = Self { rtc, clocks };
+        if !running {
+            // The clock source can only be chosen after a backup domain reset
+            bdcr.modify(|_, w| w.bdrst().enabled());
+            bdcr.modify(|_, w| w.bdrst().disabled());
+            bdcr.modify(|_, w| w.lseon().on().lsebyp().not_bypassed());
+            wait_until(&clocks, LSE_STARTUP_TIMEOUT_MS, || {
+                bdcr.read().lserdy().bit_is_set()
+            })?;
+            bdcr.modify(|_, w| w.rtcsel().lse().rtcen().enabled());
+
+            clock.modify(|rtc| {
+                rtc.cr.modify(|_, w| w.fmt().twenty_four_hour());
+                rtc.prer.write(|w| {
+                    w.prediv_s().bits(PREDIV_S);
+                    w.prediv_a().bits(PREDIV_A)
+                });
+            })?;
+        }
+
+        // The calendar's shadow registers aren't valid until they've been
+        // resynchronised since the reset
+        clock.rtc.isr.modify(|_, w| w.rsf().clear_bit());
+        let rtc = &clock.rtc;
+        wait_until(&clocks, RTC_TIMEOUT_MS, || {
+            rtc.isr.read().rsf().bit_is_set()
+        })?;
+
+        Ok(clock)
+    }
+
+    /// The current UTC time
+    pub fn now(&mut self) -> Result<DateTime, Error> {
+        // Reading the time holds the date until it's read too
+        let time = self.rtc.tr.read();
+        let date = self.rtc.dr.read();
+
+        Ok(DateTime::new(
+            Date::new(
+                EPOCH_YEAR + from_bcd(date.yt().bits(), date.yu().bits()) as u16,
+                from_bcd(date.mt().bit() as u8, date.mu().bits()),
+                from_bcd(date.dt().bits(), date.du().bits()),
+            )?,
+            Time::new(
+                from_bcd(time.ht().bits(), time.hu().bits()),
+                from_bcd(time.mnt().bits(), time.mnu().bits()),
+                from_bcd(time.st().bits(), time.su().bits()),
+                0,
+            )?,
+        ))
+    }
+
+    /// Set the current UTC time
+    pub fn set(&mut self, datetime: &DateTime) -> Result<(), Error> {
+        let (date, time) = (datetime.date, datetime.time);
+        if !(EPOCH_YEAR..EPOCH_YEAR + 100).contains(&date.year) || time.second > 59 {
+            return Err(Error::InvalidDateTime);
+        }
+
+        let (yt, yu) = to_bcd((date.year - EPOCH_YEAR) as u8);
+        let (mt, mu) = to_bcd(date.month);
+        let (dt, du) = to_bcd(date.day);
+        let (ht, hu) = to_bcd(time.hour);
+        let (mnt, mnu) = to_bcd(time.minute);
+        let (st, su) = to_bcd(time.second);
+        let weekday = weekday(&date);
+
+        self.modify(|rtc| {
+            rtc.dr.write(|w| {
+                w.yt().bits(yt);
+                w.yu().bits(yu);
+                w.mt().bit(mt > 0);
+                w.mu().bits(mu);
+                w.dt().bits(dt);
+                w.du().bits(du);
+                // Safe as it's always 1 to 7
+                unsafe { w.wdu().bits(weekday) }
+            });
+            rtc.tr.write(|w| {
+                w.pm().clear_bit();
+                w.ht().bits(ht);
+                w.hu().bits(hu);
+                w.mnt().bits(mnt);
+                w.mnu().bits(mnu);
+                w.st().bits(st);
+                w.su().bits(su)
+            });
+        })
+    }
+
+    /// Change the calendar's settings in init mode, with its write protection
+    /// lifted
+    fn modify(&mut self, change: impl FnOnce(&pac::RTC)) -> Result<(), Error> {
+        let rtc = &self.rtc;
+        rtc.wpr.write(|w| w.key().bits(0xca));
+        rtc.wpr.write(|w| w.key().bits(0x53));
+
+        rtc.isr.modify(|_, w| w.init().init_mode());
+        let result = wait_until(&self.clocks, RTC_TIMEOUT_MS, || {
+            rtc.isr.read().initf().bit_is_set()
+        })
+        .map(|_| change(rtc));
+
+        rtc.isr.modify(|_, w| w.init().free_running_mode());
+        // Any other key locks it again
+        rtc.wpr.write(|w| w.key().bits(0xff));
+
+        result
+    }
+}
+
+/// Poll for something to happen about every millisecond, giving up after
+/// the given time
+fn wait_until(
+    clocks: &rcc::Clocks,
+    timeout_ms: u32,
+    mut ready: impl FnMut() -> bool,
+) -> Result<(), Error> {
+    let cycles_per_ms = clocks.sysclk().integer() / 1000;
+    for _ in 0..timeout_ms {
+        if ready() {
+            return Ok(());
+        }
+        asm::delay(cycles_per_ms);
+    }
+
+    if ready() {
+        Ok(())
+    } else {
+        Err(Error::Hardware)
+    }
+}
+
+/// Split a value below 100 into its tens and units
+fn to_bcd(value: u8) -> (u8, u8) {
+    (value / 10, value % 10)
+}
+
+fn from_bcd(tens: u8, units: u8) -> u8 {
+    tens * 10 + units
+}
+
+/// Day of the week from 1 for Monday to 7 for Sunday, as the RTC counts them
+fn weekday(date: &Date) -> u8 {
+    let midnight = DateTime::new(
+        *date,
+        Time {
+            hour: 0,
+            minute: 0,
+            second: 0,
+            millisecond: 0,
+        },
+    );
+    // Julian day numbers start on a Monday
+    let day = (midnight.julian_day() + 0.5) as u32;
+    (day % 7) as u8 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bcd_round_trip() {
+        for value in 0..100 {
+            let (tens, units) = to_bcd(value);
+            assert!(tens < 10 && units < 10);
+            assert_eq!(from_bcd(tens, units), value);
+        }
+        assert_eq!(to_bcd(59), (5, 9));
+    }
+
+    #[test]
+    fn weekdays() {
+        let day = |year, month, day| weekday(&Date::new(year, month, day).unwrap());
+        assert_eq!(day(2000, 1, 1), 6);
+        assert_eq!(day(2024, 2, 29), 4);
+        assert_eq!(day(2024, 6, 21), 5);
+        assert_eq!(day(2024, 6, 23), 7);
+        assert_eq!(day(2024, 6, 24), 1);
+        assert_eq!(day(2099, 12, 31), 4);
+    }
+}
@@ -4,28 +4,75 @@ use panic_semihosting as _;
 use accelerometer::vector::I32x3;
 use lsm303agr::interface::I2cInterface;
 use lsm303agr::mode::MagContinuous;
-use lsm303agr::{Lsm303agr, MagOutputDataRate};
-use stm32f3xx_hal::gpio::{gpiob, OpenDrain, AF4};
+use lsm303agr::{AccelOutputDataRate, Lsm303agr, MagOutputDataRate};
+use stm32f3xx_hal::gpio::{gpioa, gpiob, OpenDrain, AF4};
 use stm32f3xx_hal::i2c;
 use stm32f3xx_hal::pac;
 use stm32f3xx_hal::prelude::*;
 use stm32f3xx_hal::rcc;
 
-use crate::geo::Point2D;
+use crate::geo::{Angle, Point2D, Vector3};
+use crate::lsm303dlhc::{BoardRevision, Lsm303dlhc};
+use crate::magnetometer::{ExternalMagnetometer, Magnetometer};
+use crate::Error;
 
-pub type Lsm303 = Lsm303agr<
-    I2cInterface<i2c::I2c<pac::I2C1, (gpiob::PB6<AF4<OpenDrain>>, gpiob::PB7<AF4<OpenDrain>>)>>,
-    MagContinuous,
->;
+pub type CompassI2c = i2c::I2c<pac::I2C1, (gpiob::PB6<AF4<OpenDrain>>, gpiob::PB7<AF4<OpenDrain>>)>;
 
-/// STMF3DISCOVERY Rev E is currently unsupported by stm32f3-discovery crate
-/// so I'm implementing an lsm303agr driver based on the PR here:
-/// https://github.com/rubberduck203/stm32f3-discovery/pull/43
-pub struct Compass {
-    lsm303: Lsm303,
+pub type Lsm303 = Lsm303agr<I2cInterface<CompassI2c>, MagContinuous>;
+
+pub type ExternalI2c =
+    i2c::I2c<pac::I2C2, (gpioa::PA9<AF4<OpenDrain>>, gpioa::PA10<AF4<OpenDrain>>)>;
+
+/// The compass as the device uses it, with any external magnetometer on
+/// I2C2
+pub type DeviceCompass = Compass<ExternalMagnetometer<ExternalI2c>>;
+
+/// Slow enough for a long run of cable up a mast
+pub const EXTERNAL_I2C_FREQUENCY_HZ: u32 = 100_000;
+
+/// The compass chip on whichever revision of the board this is
+pub enum OnBoardCompass {
+    /// STMF3DISCOVERY Rev E is currently unsupported by stm32f3-discovery
+    /// crate so I'm implementing an lsm303agr driver based on the PR here:
+    /// https://github.com/rubberduck203/stm32f3-discovery/pull/43
+    Lsm303agr(Lsm303),
+    Lsm303dlhc(Lsm303dlhc<CompassI2c>),
+}
+
+impl OnBoardCompass {
+    /// Acceleration in mg
+    pub fn acceleration(&mut self) -> Result<I32x3, Error> {
+        match self {
+            OnBoardCompass::Lsm303agr(lsm303) => {
+                let reading = lsm303.accel_data().map_err(|_| Error::Hardware)?;
+                Ok(I32x3::new(reading.x, reading.y, reading.z))
+            }
+            OnBoardCompass::Lsm303dlhc(lsm303) => lsm303.acceleration(),
+        }
+    }
 }
 
-impl Compass {
+impl Magnetometer for OnBoardCompass {
+    fn magnetic_field(&mut self) -> Result<I32x3, Error> {
+        match self {
+            OnBoardCompass::Lsm303agr(lsm303) => {
+                let reading = lsm303.mag_data().map_err(|_| Error::Hardware)?;
+                Ok(I32x3::new(reading.x, reading.y, reading.z))
+            }
+            OnBoardCompass::Lsm303dlhc(lsm303) => lsm303.magnetic_field(),
+        }
+    }
+}
+
+/// The board's accelerometer and magnetometer, whichever revision it is,
+/// and optionally a magnetometer elsewhere to read the field from instead
+pub struct Compass<M> {
+    lsm303: OnBoardCompass,
+    revision: BoardRevision,
+    external: Option<M>,
+}
+
+impl<M: Magnetometer> Compass<M> {
     pub fn new<Pb6Mode, Pb7Mode>(
         pb6: gpiob::PB6<Pb6Mode>,
         pb7: gpiob::PB7<Pb7Mode>,
@@ -35,7 +82,7 @@ impl Compass {
         i2c1: pac::I2C1,
         clocks: rcc::Clocks,
         advanced_periph_bus: &mut rcc::APB1,
-    ) -> Result<Self, i2c::Error> {
+    ) -> Result<Self, Error> {
         /*
          * Pinout:
          * PB6 -> SCL (clock)
@@ -50,33 +97,118 @@ impl Compass {
         //let sda = pb7.into_open_drain_output(moder, otyper);
         let scl = pb6.into_af4_open_drain(moder, otyper, afl);
         let sda = pb7.into_af4_open_drain(moder, otyper, afl);
-        let i2ci = i2c::I2c::new(i2c1, (scl, sda), 400_000.Hz(), clocks, advanced_periph_bus);
-        let mut lsm303agr = Lsm303agr::new_with_i2c(i2ci);
-        lsm303agr.init().unwrap();
-        lsm303agr.set_mag_odr(MagOutputDataRate::Hz20).unwrap();
-
-        match lsm303agr.into_mag_continuous() {
-            Ok(lsm303) => Ok(Self { lsm303 }),
-            Err(_e) => Err(i2c::Error::Nack),
+        let mut i2ci = i2c::I2c::new(i2c1, (scl, sda), 400_000.Hz(), clocks, advanced_periph_bus);
+
+        // Boards before Rev E have the LSM303DLHC at the same addresses
+        let revision = BoardRevision::detect(&mut i2ci)?;
+        let lsm303 = match revision {
+            BoardRevision::RevE => {
+                let mut lsm303agr = Lsm303agr::new_with_i2c(i2ci);
+                lsm303agr.init().map_err(|_| Error::Hardware)?;
+                lsm303agr
+                    .set_mag_odr(MagOutputDataRate::Hz20)
+                    .map_err(|_| Error::Hardware)?;
+                lsm303agr
+                    .set_accel_odr(AccelOutputDataRate::Hz50)
+                    .map_err(|_| Error::Hardware)?;
+                let lsm303 = lsm303agr
+                    .into_mag_continuous()
+                    .map_err(|_| Error::Hardware)?;
+                OnBoardCompass::Lsm303agr(lsm303)
+            }
+            BoardRevision::Earlier => OnBoardCompass::Lsm303dlhc(Lsm303dlhc::new(i2ci)?),
+        };
+
+        Ok(Self {
+            lsm303,
+            revision,
+            external: None,
+        })
+    }
+
+    /// Read the field from a magnetometer away from the board rather than
+    /// the board's own
+    pub fn with_external(mut self, magnetometer: M) -> Self {
+        self.external = Some(magnetometer);
+        self
+    }
+
+    pub fn external(&self) -> Option<&M> {
+        self.external.as_ref()
+    }
+
+    /// Which revision of the board the compass chip shows this is
+    pub fn revision(&self) -> BoardRevision {
+        self.revision
+    }
+
+    /// Reading returned in nT (nanotesla), from the external magnetometer
+    /// when there is one
+    pub fn mag_raw(&mut self) -> Result<I32x3, Error> {
+        match &mut self.external {
+            Some(magnetometer) => magnetometer.magnetic_field(),
+            None => self.lsm303.magnetic_field(),
         }
     }
 
-    /// Reading returned in nT (nanotesla)
-    pub fn mag_raw(&mut self) -> Result<I32x3, i2c::Error> {
-        let reading = self.lsm303.mag_data().unwrap();
-        Ok(I32x3::new(reading.x, reading.y, reading.z))
+    /// Reading returned in mg
+    pub fn accel_raw(&mut self) -> Result<I32x3, Error> {
+        self.lsm303.acceleration()
+    }
+
+    /// Tilt of the front of the device above the horizontal, from the
+    /// accelerometer's reading of gravity.  Only accurate while the device
+    /// isn't accelerating.
+    pub fn elevation(&mut self) -> Result<Angle, Error> {
+        let up = Vector3::from(self.accel_raw()?);
+        Vector3::new(1.0, 0.0, 0.0)
+            .elevation(up)
+            .ok_or(Error::InvalidAngle)
     }
 
-    /// Reading returned in nT (nanotesla)
-    pub fn bearing_north(&mut self) -> f32 {
-        let reading = self.lsm303.mag_data().unwrap();
+    /// Bearing from the front of the device toward magnetic north
+    pub fn bearing_north(&mut self) -> Result<Angle, Error> {
+        let reading = self.mag_raw()?;
         let point = Point2D::new(reading.x as f32, reading.y as f32);
         // Flip
-        point.bearing_north() * -1.0
+        Ok(-point.bearing_north())
     }
 
-    /// Consume the Compass and return the underlying lsm303agr
-    pub fn into_lsm303agr(self) -> Lsm303 {
+    /// Consume the Compass and return the underlying chip's driver
+    pub fn into_lsm303(self) -> OnBoardCompass {
         self.lsm303
     }
 }
+
+/*
+ * Pinout:
+ *   PA9  -> I2C2 SCL (to the external magnetometer)
+ *   PA10 -> I2C2 SDA
+ *
+ * These are fixed: I2C1 is the board's own sensor bus, and PF0, the only
+ * other I2C2 SDA pin, is the Discovery's clock input.
+ */
+/// Find a magnetometer on the external bus, whichever of the supported chips
+/// it is
+pub fn external_magnetometer<Pa9Mode, Pa10Mode>(
+    pa9: gpioa::PA9<Pa9Mode>,
+    pa10: gpioa::PA10<Pa10Mode>,
+    moder: &mut gpioa::MODER,
+    otyper: &mut gpioa::OTYPER,
+    afh: &mut gpioa::AFRH,
+    i2c2: pac::I2C2,
+    clocks: rcc::Clocks,
+    advanced_periph_bus: &mut rcc::APB1,
+) -> Result<ExternalMagnetometer<ExternalI2c>, Error> {
+    let scl = pa9.into_af_open_drain(moder, otyper, afh);
+    let sda = pa10.into_af_open_drain(moder, otyper, afh);
+    let i2c = i2c::I2c::new(
+        i2c2,
+        (scl, sda),
+        EXTERNAL_I2C_FREQUENCY_HZ.Hz(),
+        clocks,
+        advanced_periph_bus,
+    );
+
+    ExternalMagnetometer::detect(i2c)
+}
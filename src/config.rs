@@ -0,0 +1,106 @@
+/// Runtime configuration of the device
+use crate::cable_wrap;
+use crate::calibration::Calibration;
+use crate::estimator::EstimatorConfig;
+use crate::geo::sgp4::Sgp4;
+use crate::geo::{Angle, GeoPoint};
+use crate::gps::Fix;
+use crate::homing::HomingConfig;
+use crate::stall::StallConfig;
+use crate::stepper::CircularDirection;
+use crate::target::Target;
+
+/// Default milliseconds between heading sentences
+pub const DEFAULT_HEADING_OUTPUT_PERIOD_MS: u32 = 1000;
+
+/// Fewest milliseconds between heading sentences.  A full set takes about
+/// 150ms to send at 4800 baud.
+pub const MIN_HEADING_OUTPUT_PERIOD_MS: u32 = 200;
+
+/// Way the elevation stepper tilts the reference build down
+pub const DEFAULT_ELEVATION_DOWN: CircularDirection = CircularDirection::CCW;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// Magnetic declination at the device's location, east is positive, or
+    /// `None` to use the variation the GPS reports
+    pub declination: Option<Angle>,
+    /// Compass deviation remaining after calibration, east is positive
+    pub deviation: Angle,
+    /// Milliseconds between NMEA heading sentences, or 0 to disable them
+    pub heading_output_period_ms: u32,
+    /// Where the device is, used when there's no GPS fix
+    pub location: Option<GeoPoint>,
+    /// What the device should point at
+    pub target: Target,
+    /// Orbit of the satellite to track when the target is a satellite
+    pub satellite: Option<Sgp4>,
+    /// Whether an elevation stepper is fitted and should be driven
+    pub elevation_axis: bool,
+    /// Which way the elevation stepper turns to tilt the device down.  This
+    /// depends on which side of the tilt axis the motor is mounted.
+    pub elevation_down: CircularDirection,
+    /// Most degrees the turntable may turn either way from where it started
+    /// before it has to unwind, or infinity for no limit
+    pub cable_wrap_limit: f32,
+    /// Motor degrees of play in the gears to the turntable
+    pub backlash: f32,
+    /// When to give up on a turntable that isn't turning
+    pub stall: StallConfig,
+    /// How to find the index switch
+    pub homing: HomingConfig,
+    /// How the turntable responds to the stepper, kept in flash
+    pub calibration: Calibration,
+    /// How far to trust the stepper and compass in estimating the heading
+    pub estimator: EstimatorConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            declination: None,
+            deviation: Angle::ZERO,
+            heading_output_period_ms: DEFAULT_HEADING_OUTPUT_PERIOD_MS,
+            location: None,
+            target: Target::North,
+            satellite: None,
+            elevation_axis: false,
+            elevation_down: DEFAULT_ELEVATION_DOWN,
+            cable_wrap_limit: cable_wrap::DEFAULT_LIMIT,
+            backlash: 0.0,
+            stall: StallConfig::default(),
+            homing: HomingConfig::default(),
+            calibration: Calibration::default(),
+            estimator: EstimatorConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The declination to correct headings by: the one set, else the GPS's,
+    /// else none
+    pub fn variation(&self, fix: &Fix) -> Angle {
+        self.declination
+            .or(fix.magnetic_variation)
+            .unwrap_or(Angle::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variation_prefers_the_set_declination() {
+        let mut fix = Fix::default();
+        let mut config = Config::default();
+        assert_eq!(config.variation(&fix), Angle::ZERO);
+
+        fix.magnetic_variation = Some(Angle::from_degrees(-3.1));
+        assert_eq!(config.variation(&fix), Angle::from_degrees(-3.1));
+
+        config.declination = Some(Angle::from_degrees(12.5));
+        assert_eq!(config.variation(&fix), Angle::from_degrees(12.5));
+        assert_eq!(config.variation(&Fix::default()), Angle::from_degrees(12.5));
+    }
+}
@@ -0,0 +1,319 @@
+/// Line based command console on USART3
+use core::fmt::{self, Write};
+use core::str;
+
+use stm32f3xx_hal::gpio::{gpioc, PushPull, AF7};
+use stm32f3xx_hal::nb;
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::rcc;
+use stm32f3xx_hal::serial::{Event, Serial};
+
+use crate::config::MIN_HEADING_OUTPUT_PERIOD_MS;
+use crate::datetime::DateTime;
+use crate::geo::astro::Equatorial;
+use crate::geo::sgp4::{TleLine1, TleLine2};
+use crate::geo::{Angle, GeoPoint};
+use crate::stepper::{CircularDirection, DriverKind};
+use crate::target::Target;
+use crate::Error;
+
+pub const BAUD_RATE: u32 = 115_200;
+
+/// Longest command line accepted
+pub const MAX_LINE_LENGTH: usize = 96;
+
+/// Most turntable revolutions `calibrate steps` will count in one go
+pub const MAX_REVOLUTIONS: u32 = 10;
+
+pub type ConsoleSerial =
+    Serial<pac::USART3, (gpioc::PC10<AF7<PushPull>>, gpioc::PC11<AF7<PushPull>>)>;
+
+/// A command entered on the console
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// `time <YYYY-MM-DDTHH:MM:SSZ>`: set the clock
+    SetTime(DateTime),
+    /// `location <lat>, <lon>`: set the stored location
+    SetLocation(GeoPoint),
+    /// `target <north|sun|satellite>` or `target radec <ra> <dec>`: choose
+    /// what to point at
+    SetTarget(Target),
+    /// `declination <degrees>` or `declination gps`: set the magnetic
+    /// declination, east positive, or use the variation the GPS reports
+    SetDeclination(Option<Angle>),
+    /// `deviation <degrees>`: set the compass deviation left after
+    /// calibration, east positive
+    SetDeviation(Angle),
+    /// `output <milliseconds>` or `output off`: how often to send NMEA
+    /// heading sentences
+    SetHeadingOutputPeriod(u32),
+    /// `elevation <on|off>`: drive the elevation stepper or not
+    SetElevationAxis(bool),
+    /// `elevation down <cw|ccw>`: which way the elevation stepper turns to
+    /// tilt the device down
+    SetElevationDown(CircularDirection),
+    /// `driver <stepdir|unipolar>`: choose which stepper turns the turntable
+    SetAzimuthDriver(DriverKind),
+    /// `backlash <degrees>`: set the motor degrees of play in the gears
+    SetBacklash(f32),
+    /// `backlash measure`: measure the play in the gears with the compass
+    MeasureBacklash,
+    /// `home`: find the index switch and zero the step counter there
+    Home,
+    /// `calibrate direction`: find which way the stepper turns the heading
+    CalibrateDirection,
+    /// `calibrate steps [revolutions]`: count the steps for some whole
+    /// turns of the turntable, one by default
+    CalibrateSteps(u32),
+    /// `tune`: oscillate around the target to find the controller's gains
+    Tune,
+    /// `status`: print the current state
+    Status,
+    /// The first line of a two-line element set, pasted as is
+    TleLine1(TleLine1),
+    /// The second line of a two-line element set, which makes the satellite
+    /// the target
+    TleLine2(TleLine2),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let line = line.trim();
+        let (name, args) = match line.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (line, ""),
+        };
+
+        match (name, args) {
+            ("time", args) => DateTime::parse(args).map(Command::SetTime),
+            ("location", args) => GeoPoint::parse(args).map(Command::SetLocation),
+            ("target", "north") => Ok(Command::SetTarget(Target::North)),
+            ("target", "sun") => Ok(Command::SetTarget(Target::Sun)),
+            ("target", "satellite") => Ok(Command::SetTarget(Target::Satellite)),
+            ("target", args) => match args.strip_prefix("radec ") {
+                Some(coordinates) => Equatorial::parse(coordinates)
+                    .map(|coordinates| Command::SetTarget(Target::Celestial(coordinates))),
+                None => Err(Error::Parse),
+            },
+            ("declination", "gps") => Ok(Command::SetDeclination(None)),
+            ("declination", args) => {
+                parse_correction(args).map(|d| Command::SetDeclination(Some(d)))
+            }
+            ("deviation", args) => parse_correction(args).map(Command::SetDeviation),
+            ("output", "off") => Ok(Command::SetHeadingOutputPeriod(0)),
+            ("output", args) => match args.parse() {
+                Ok(period) if period >= MIN_HEADING_OUTPUT_PERIOD_MS => {
+                    Ok(Command::SetHeadingOutputPeriod(period))
+                }
+                _ => Err(Error::Parse),
+            },
+            ("elevation", "on") => Ok(Command::SetElevationAxis(true)),
+            ("elevation", "off") => Ok(Command::SetElevationAxis(false)),
+            ("elevation", "down cw") => Ok(Command::SetElevationDown(CircularDirection::CW)),
+            ("elevation", "down ccw") => Ok(Command::SetElevationDown(CircularDirection::CCW)),
+            ("driver", "stepdir") => Ok(Command::SetAzimuthDriver(DriverKind::StepDir)),
+            ("driver", "unipolar") => Ok(Command::SetAzimuthDriver(DriverKind::Unipolar)),
+            ("backlash", "measure") => Ok(Command::MeasureBacklash),
+            ("backlash", args) => match args.parse::<f32>() {
+                Ok(backlash) if backlash >= 0.0 => Ok(Command::SetBacklash(backlash)),
+                _ => Err(Error::Parse),
+            },
+            ("home", "") => Ok(Command::Home),
+            ("tune", "") => Ok(Command::Tune),
+            ("calibrate", "direction") => Ok(Command::CalibrateDirection),
+            ("calibrate", "steps") => Ok(Command::CalibrateSteps(1)),
+            ("calibrate", args) => match args.strip_prefix("steps ").map(|n| n.trim().parse()) {
+                Some(Ok(revolutions)) if (1..=MAX_REVOLUTIONS).contains(&revolutions) => {
+                    Ok(Command::CalibrateSteps(revolutions))
+                }
+                _ => Err(Error::Parse),
+            },
+            ("status", "") => Ok(Command::Status),
+            ("1", _) => TleLine1::parse(line).map(Command::TleLine1),
+            ("2", _) => TleLine2::parse(line).map(Command::TleLine2),
+            _ => Err(Error::Parse),
+        }
+    }
+}
+
+/// Parse a heading correction in degrees, east positive
+fn parse_correction(args: &str) -> Result<Angle, Error> {
+    match args.parse::<f32>() {
+        Ok(degrees) if (-180.0..=180.0).contains(&degrees) => Ok(Angle::from_degrees(degrees)),
+        _ => Err(Error::Parse),
+    }
+}
+
+/*
+ * Pinout:
+ *   PC10 -> USART3 TX
+ *   PC11 -> USART3 RX
+ */
+pub struct Console {
+    serial: ConsoleSerial,
+    line: [u8; MAX_LINE_LENGTH],
+    length: usize,
+    overflowed: bool,
+}
+
+impl Console {
+    pub fn new<Pc10Mode, Pc11Mode>(
+        pc10: gpioc::PC10<Pc10Mode>,
+        pc11: gpioc::PC11<Pc11Mode>,
+        moder: &mut gpioc::MODER,
+        otyper: &mut gpioc::OTYPER,
+        afh: &mut gpioc::AFRH,
+        usart3: pac::USART3,
+        clocks: rcc::Clocks,
+        advanced_periph_bus: &mut rcc::APB1,
+    ) -> Result<Self, Error> {
+        let tx = pc10.into_af_push_pull(moder, otyper, afh);
+        let rx = pc11.into_af_push_pull(moder, otyper, afh);
+        let mut serial = Serial::new(
+            usart3,
+            (tx, rx),
+            BAUD_RATE.Bd(),
+            clocks,
+            advanced_periph_bus,
+        );
+        serial.enable_interrupt(Event::ReceiveDataRegisterNotEmpty);
+
+        Ok(Self {
+            serial,
+            line: [0; MAX_LINE_LENGTH],
+            length: 0,
+            overflowed: false,
+        })
+    }
+
+    /// Read a received byte, returning the parsed command once a line is
+    /// complete.  Intended to be called from the USART3 interrupt.
+    pub fn read(&mut self) -> Option<Result<Command, Error>> {
+        let byte = match self.serial.read() {
+            Ok(byte) => byte,
+            Err(nb::Error::WouldBlock) => return None,
+            Err(nb::Error::Other(_)) => {
+                self.overflowed = true;
+                return None;
+            }
+        };
+
+        match byte {
+            b'\r' | b'\n' => {
+                let (length, overflowed) = (self.length, self.overflowed);
+                self.length = 0;
+                self.overflowed = false;
+
+                if overflowed {
+                    Some(Err(Error::Overflow))
+                } else if length == 0 {
+                    None
+                } else {
+                    Some(
+                        str::from_utf8(&self.line[..length])
+                            .map_err(|_| Error::Parse)
+                            .and_then(Command::parse),
+                    )
+                }
+            }
+            _ if self.length == MAX_LINE_LENGTH => {
+                self.overflowed = true;
+                None
+            }
+            _ => {
+                self.line[self.length] = byte;
+                self.length += 1;
+                None
+            }
+        }
+    }
+
+    /// Write a line of output, blocking until it's sent
+    pub fn write_line(&mut self, args: fmt::Arguments) {
+        self.serial.write_fmt(args).ok();
+        self.serial.write_str("\r\n").ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heading_corrections() {
+        assert_eq!(
+            Command::parse("declination -12.5").ok(),
+            Some(Command::SetDeclination(Some(Angle::from_degrees(-12.5))))
+        );
+        assert_eq!(
+            Command::parse("declination gps").ok(),
+            Some(Command::SetDeclination(None))
+        );
+        assert_eq!(
+            Command::parse(" deviation  1.5 ").ok(),
+            Some(Command::SetDeviation(Angle::from_degrees(1.5)))
+        );
+        for line in [
+            "declination",
+            "declination east",
+            "declination 181",
+            "declination NaN",
+            "deviation -180.5",
+            "deviation inf",
+        ]
+        .iter()
+        {
+            assert!(Command::parse(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn heading_output_period() {
+        assert_eq!(
+            Command::parse("output 500").ok(),
+            Some(Command::SetHeadingOutputPeriod(500))
+        );
+        assert_eq!(
+            Command::parse("output off").ok(),
+            Some(Command::SetHeadingOutputPeriod(0))
+        );
+        let fastest = Command::parse("output 200").ok();
+        assert_eq!(
+            fastest,
+            Some(Command::SetHeadingOutputPeriod(
+                MIN_HEADING_OUTPUT_PERIOD_MS
+            ))
+        );
+        for line in [
+            "output",
+            "output 199",
+            "output 0",
+            "output -1",
+            "output 1.5",
+        ]
+        .iter()
+        {
+            assert!(Command::parse(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn elevation_axis() {
+        assert_eq!(
+            Command::parse("elevation on").ok(),
+            Some(Command::SetElevationAxis(true))
+        );
+        assert_eq!(
+            Command::parse("elevation down cw").ok(),
+            Some(Command::SetElevationDown(CircularDirection::CW))
+        );
+        assert_eq!(
+            Command::parse("elevation down ccw").ok(),
+            Some(Command::SetElevationDown(CircularDirection::CCW))
+        );
+        for line in ["elevation", "elevation down", "elevation down up"].iter() {
+            assert!(Command::parse(line).is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+/// UTC date and time values shared by the GPS, clock and astronomy code
+use core::fmt;
+use core::iter;
+use core::str::FromStr;
+
+use crate::Error;
+
+/// A calendar date in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn new(year: u16, month: u8, day: u8) -> Result<Self, Error> {
+        if month == 0 || month > 12 || day == 0 || day > days_in_month(year, month) {
+            return Err(Error::InvalidDateTime);
+        }
+
+        Ok(Self { year, month, day })
+    }
+}
+
+/// A time of day in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millisecond: u16,
+}
+
+impl Time {
+    pub fn new(hour: u8, minute: u8, second: u8, millisecond: u16) -> Result<Self, Error> {
+        // Allow a leap second
+        if hour > 23 || minute > 59 || second > 60 || millisecond > 999 {
+            return Err(Error::InvalidDateTime);
+        }
+
+        Ok(Self {
+            hour,
+            minute,
+            second,
+            millisecond,
+        })
+    }
+
+    /// Seconds since midnight
+    pub fn seconds_of_day(&self) -> f64 {
+        self.hour as f64 * 3600.0
+            + self.minute as f64 * 60.0
+            + self.second as f64
+            + self.millisecond as f64 / 1000.0
+    }
+}
+
+/// A point in time in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub date: Date,
+    pub time: Time,
+}
+
+impl DateTime {
+    pub fn new(date: Date, time: Time) -> Self {
+        Self { date, time }
+    }
+
+    /// Parse an ISO 8601 UTC date and time, e.g. `2024-06-21T12:30:00Z`.  The
+    /// trailing `Z` and fractional seconds are optional.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        let s = s.strip_suffix('Z').unwrap_or(s);
+        let (date, time) = s.split_once(['T', ' ']).ok_or(Error::Parse)?;
+
+        let mut date_parts = date.split('-');
+        let year = parse_field(date_parts.next())?;
+        let month = parse_field(date_parts.next())?;
+        let day = parse_field(date_parts.next())?;
+        if date_parts.next().is_some() {
+            return Err(Error::Parse);
+        }
+
+        let (time, fraction) = match time.split_once('.') {
+            Some((time, fraction)) => (time, Some(fraction)),
+            None => (time, None),
+        };
+        let mut time_parts = time.split(':');
+        let hour = parse_field(time_parts.next())?;
+        let minute = parse_field(time_parts.next())?;
+        let second = parse_field(time_parts.next())?;
+        if time_parts.next().is_some() {
+            return Err(Error::Parse);
+        }
+        let millisecond = match fraction {
+            Some(fraction) => parse_milliseconds(fraction)?,
+            None => 0,
+        };
+
+        Ok(DateTime::new(
+            Date::new(year, month, day)?,
+            Time::new(hour, minute, second, millisecond)?,
+        ))
+    }
+
+    /// The Julian day number, including the fraction of the day
+    pub fn julian_day(&self) -> f64 {
+        let (mut year, mut month) = (self.date.year as i32, self.date.month as i32);
+        if month <= 2 {
+            year -= 1;
+            month += 12;
+        }
+
+        // Gregorian calendar correction
+        let century = year / 100;
+        let correction = 2 - century + century / 4;
+
+        let days = (1461 * (year + 4716)) / 4 + (306_001 * (month + 1)) / 10_000;
+
+        days as f64 + self.date.day as f64 + correction as f64 - 1524.5
+            + self.time.seconds_of_day() / 86_400.0
+    }
+
+    /// The date and time of a Julian day, to the nearest millisecond
+    pub fn from_julian_day(julian_day: f64) -> Result<Self, Error> {
+        if !julian_day.is_finite() || julian_day < 2_299_160.5 {
+            return Err(Error::InvalidDateTime);
+        }
+
+        // Split at midnight rather than noon
+        let milliseconds = ((julian_day + 0.5) * MILLISECONDS_PER_DAY as f64).round() as i64;
+        let days = milliseconds / MILLISECONDS_PER_DAY;
+        let millisecond_of_day = milliseconds % MILLISECONDS_PER_DAY;
+
+        let alpha = (days * 100 - 186_721_625) / 3_652_425;
+        let a = days + 1 + alpha - alpha / 4;
+        let b = a + 1524;
+        let c = (b * 100 - 12_210) / 36_525;
+        let d = (c * 36_525) / 100;
+        let e = ((b - d) * 10_000) / 306_001;
+
+        let day = b - d - (e * 306_001) / 10_000;
+        let month = if e < 14 { e - 1 } else { e - 13 };
+        let year = if month > 2 { c - 4716 } else { c - 4715 };
+        if year > u16::MAX as i64 {
+            return Err(Error::InvalidDateTime);
+        }
+
+        let second_of_day = millisecond_of_day / 1000;
+        Ok(DateTime::new(
+            Date::new(year as u16, month as u8, day as u8)?,
+            Time::new(
+                (second_of_day / 3600) as u8,
+                (second_of_day / 60 % 60) as u8,
+                (second_of_day % 60) as u8,
+                (millisecond_of_day % 1000) as u16,
+            )?,
+        ))
+    }
+}
+
+const MILLISECONDS_PER_DAY: i64 = 86_400_000;
+
+impl FromStr for DateTime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DateTime::parse(s)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.date.year,
+            self.date.month,
+            self.date.day,
+            self.time.hour,
+            self.time.minute,
+            self.time.second,
+            self.time.millisecond
+        )
+    }
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_field<T: FromStr>(field: Option<&str>) -> Result<T, Error> {
+    match field {
+        Some(field) if !field.is_empty() && field.bytes().all(|b| b.is_ascii_digit()) => {
+            field.parse().map_err(|_| Error::Parse)
+        }
+        _ => Err(Error::Parse),
+    }
+}
+
+/// Parse the digits after the decimal point of a seconds value as
+/// milliseconds, padding or truncating to three digits
+pub(crate) fn parse_milliseconds(fraction: &str) -> Result<u16, Error> {
+    if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Parse);
+    }
+
+    Ok(fraction
+        .bytes()
+        .chain(iter::repeat(b'0'))
+        .take(3)
+        .fold(0, |ms, digit| ms * 10 + (digit - b'0') as u16))
+}
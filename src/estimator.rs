@@ -0,0 +1,302 @@
+/// Heading estimation with a small Kalman filter.  The stepper's commanded
+/// rate predicts how the heading moves between compass readings, and the
+/// compass corrects it, trusted less while the motor runs and disturbs it.
+/// Along with the heading, the filter learns how far the turntable's real
+/// speed is off the commanded one, e.g. from a slightly wrong gear ratio.
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::Angle;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EstimatorConfig {
+    /// Variance in degrees² per second the heading wanders by on its own
+    pub heading_noise: f32,
+    /// Variance per second the speed error wanders by
+    pub scale_noise: f32,
+    /// Variance in degrees² per second the heading wanders by while the
+    /// turntable turns at a speed that isn't known, before calibration
+    pub turning_noise: f32,
+    /// Standard deviation of compass headings in degrees, still and with
+    /// the motor running
+    pub compass_noise: f32,
+    pub compass_noise_moving: f32,
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        Self {
+            heading_noise: 0.5,
+            scale_noise: 0.001,
+            turning_noise: 400.0,
+            compass_noise: 2.0,
+            compass_noise_moving: 8.0,
+        }
+    }
+}
+
+/// Variance of the first guesses: the first compass heading, and the
+/// commanded speed being right to within about 30%
+const INITIAL_SCALE_VARIANCE: f32 = 0.1;
+
+/// Compass headings further than this many standard deviations from the
+/// estimate are taken to be glitches and ignored
+const GATE: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeadingEstimator {
+    /// Degrees clockwise from magnetic north, not wrapped so it can be
+    /// followed through whole turns
+    heading: f32,
+    /// Fraction the turntable's speed is off the commanded speed
+    scale: f32,
+    /// Covariance of the heading and speed error
+    covariance: [[f32; 2]; 2],
+    started: bool,
+}
+
+impl Default for HeadingEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeadingEstimator {
+    pub fn new() -> Self {
+        Self {
+            heading: 0.0,
+            scale: 0.0,
+            covariance: [[0.0; 2]; 2],
+            started: false,
+        }
+    }
+
+    /// The estimated heading, once there's been a compass heading
+    pub fn heading(&self) -> Option<Angle> {
+        Some(Angle::from_degrees(self.heading).normalized()).filter(|_| self.started)
+    }
+
+    /// Variance of the estimated heading in degrees²
+    pub fn variance(&self) -> f32 {
+        self.covariance[0][0]
+    }
+
+    /// Fraction the turntable's speed is off the commanded speed
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Move the estimate on by `dt` seconds of the turntable being commanded
+    /// to turn at `rate` degrees per second, clockwise positive, or at some
+    /// unknown rate for `None`
+    pub fn predict(&mut self, config: &EstimatorConfig, rate: Option<f32>, dt: f32) {
+        if !self.started || dt <= 0.0 {
+            return;
+        }
+        let rate = match rate {
+            Some(rate) => rate,
+            None => {
+                // All that's known is the heading is getting less certain
+                self.covariance[0][0] += config.turning_noise * dt;
+                return;
+            }
+        };
+
+        // The speed error only matters while moving, so how the heading
+        // depends on it grows with the commanded travel
+        let travel = rate * dt;
+        self.heading += travel * (1.0 + self.scale);
+
+        let f = [[1.0, travel], [0.0, 1.0]];
+        let p = self.covariance;
+        let fp = [
+            [
+                f[0][0] * p[0][0] + f[0][1] * p[1][0],
+                f[0][0] * p[0][1] + f[0][1] * p[1][1],
+            ],
+            [p[1][0], p[1][1]],
+        ];
+        self.covariance = [
+            [
+                fp[0][0] * f[0][0] + fp[0][1] * f[0][1] + config.heading_noise * dt,
+                fp[0][1],
+            ],
+            [
+                fp[1][0] * f[0][0] + fp[1][1] * f[0][1],
+                fp[1][1] + config.scale_noise * dt,
+            ],
+        ];
+    }
+
+    /// Correct the estimate with a compass heading.  Returns whether it was
+    /// used, as one far off the estimate is dropped.
+    pub fn update(&mut self, config: &EstimatorConfig, heading: Angle, moving: bool) -> bool {
+        let noise = if moving {
+            config.compass_noise_moving
+        } else {
+            config.compass_noise
+        };
+        let r = noise * noise;
+
+        if !self.started {
+            self.heading = heading.normalized().degrees();
+            self.scale = 0.0;
+            self.covariance = [[r, 0.0], [0.0, INITIAL_SCALE_VARIANCE]];
+            self.started = true;
+            return true;
+        }
+
+        let p = self.covariance;
+        let innovation = Angle::from_degrees(self.heading)
+            .difference(heading)
+            .degrees();
+        let s = p[0][0] + r;
+        if innovation * innovation > GATE * GATE * s {
+            return false;
+        }
+
+        let gain = [p[0][0] / s, p[1][0] / s];
+        self.heading += gain[0] * innovation;
+        self.scale += gain[1] * innovation;
+        self.covariance = [
+            [(1.0 - gain[0]) * p[0][0], (1.0 - gain[0]) * p[0][1]],
+            [p[1][0] - gain[1] * p[0][0], p[1][1] - gain[1] * p[0][1]],
+        ];
+
+        // Keep the unwrapped heading from growing without bound
+        if self.heading.abs() > 3600.0 {
+            self.heading = Angle::from_degrees(self.heading).normalized().degrees();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.1;
+
+    /// Pseudo-random compass noise of up to ±`amplitude` degrees
+    fn noise(i: u32, amplitude: f32) -> f32 {
+        let hash = i.wrapping_mul(2_654_435_761) >> 16;
+        (hash % 2001) as f32 / 1000.0 * amplitude - amplitude
+    }
+
+    fn error(estimator: &HeadingEstimator, truth: f32) -> f32 {
+        estimator
+            .heading()
+            .unwrap()
+            .difference(Angle::from_degrees(truth))
+            .degrees()
+            .abs()
+    }
+
+    #[test]
+    fn starts_from_the_first_heading() {
+        let config = EstimatorConfig::default();
+        let mut estimator = HeadingEstimator::new();
+        assert!(estimator.heading().is_none());
+        // Nothing to move on before then
+        estimator.predict(&config, Some(10.0), DT);
+        assert!(estimator.heading().is_none());
+
+        assert!(estimator.update(&config, Angle::from_degrees(370.0), false));
+        assert!(error(&estimator, 10.0) < 1e-4);
+        assert_eq!(
+            estimator.variance(),
+            config.compass_noise * config.compass_noise
+        );
+    }
+
+    #[test]
+    fn converges_while_still() {
+        let config = EstimatorConfig::default();
+        let mut estimator = HeadingEstimator::new();
+        estimator.update(&config, Angle::from_degrees(123.0 + noise(0, 3.0)), false);
+
+        let mut variances = [0.0; 100];
+        for (i, variance) in variances.iter_mut().enumerate() {
+            estimator.predict(&config, Some(0.0), DT);
+            let heading = Angle::from_degrees(123.0 + noise(i as u32 + 1, 3.0));
+            assert!(estimator.update(&config, heading, false));
+            *variance = estimator.variance();
+        }
+
+        assert!(error(&estimator, 123.0) < 0.5);
+        // The variance shrinks with each reading, to well under a reading's
+        assert!(variances.windows(2).take(10).all(|w| w[1] < w[0]));
+        assert!(variances[99] < 0.25 * config.compass_noise * config.compass_noise);
+    }
+
+    #[test]
+    fn learns_the_speed_error() {
+        let config = EstimatorConfig::default();
+        let mut estimator = HeadingEstimator::new();
+        let commanded = 20.0;
+        let mut truth = 300.0;
+        estimator.update(&config, Angle::from_degrees(truth), false);
+
+        // Through several turns, and past north each time
+        for i in 0..1_000 {
+            truth += commanded * 1.1 * DT;
+            estimator.predict(&config, Some(commanded), DT);
+            let heading = Angle::from_degrees(truth + noise(i, 8.0));
+            estimator.update(&config, heading, true);
+        }
+
+        assert!(
+            (estimator.scale() - 0.1).abs() < 0.02,
+            "{}",
+            estimator.scale()
+        );
+        assert!(error(&estimator, truth) < 2.0);
+    }
+
+    #[test]
+    fn predicts_between_readings() {
+        let config = EstimatorConfig::default();
+        let mut estimator = HeadingEstimator::new();
+        estimator.update(&config, Angle::from_degrees(350.0), false);
+        let before = estimator.variance();
+
+        for _ in 0..10 {
+            estimator.predict(&config, Some(-5.0), DT);
+        }
+        assert!(error(&estimator, 345.0) < 1e-3);
+        // Less certain after moving without readings
+        assert!(estimator.variance() > before);
+    }
+
+    #[test]
+    fn unknown_speed_just_adds_uncertainty() {
+        let config = EstimatorConfig::default();
+        let mut estimator = HeadingEstimator::new();
+        estimator.update(&config, Angle::from_degrees(45.0), false);
+        let before = estimator.variance();
+
+        estimator.predict(&config, None, 0.5);
+        assert!(error(&estimator, 45.0) < 1e-4);
+        assert!((estimator.variance() - before - config.turning_noise * 0.5).abs() < 1e-3);
+
+        // So the next reading moves it most of the way, even with the motor
+        // disturbing the compass
+        assert!(estimator.update(&config, Angle::from_degrees(75.0), true));
+        assert!(error(&estimator, 75.0) < 10.0);
+    }
+
+    #[test]
+    fn ignores_glitches() {
+        let config = EstimatorConfig::default();
+        let mut estimator = HeadingEstimator::new();
+        for _ in 0..20 {
+            estimator.predict(&config, Some(0.0), DT);
+            estimator.update(&config, Angle::from_degrees(200.0), false);
+        }
+
+        assert!(!estimator.update(&config, Angle::from_degrees(290.0), false));
+        assert!(error(&estimator, 200.0) < 1e-3);
+        // Still follows small changes
+        assert!(estimator.update(&config, Angle::from_degrees(202.0), false));
+    }
+}
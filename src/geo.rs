@@ -1,7 +1,25 @@
 /// Utils and objects for geography and coordinate work
+use core::fmt;
+use core::ops::{Add, Neg, Sub};
+
 #[allow(unused_imports)]
 use num_traits::float::Float;
 
+use crate::Error;
+
+pub mod astro;
+pub mod geodesic;
+pub mod rotation;
+pub mod satellite;
+pub mod sgp4;
+pub mod solar;
+pub mod vector;
+
+pub use astro::Horizontal;
+pub use geodesic::GeoPoint;
+pub use rotation::{Matrix3, Quaternion};
+pub use vector::Vector3;
+
 /// A 2-dimensional point with x and y coordinates
 pub struct Point2D {
     pub x: f32,
@@ -14,7 +32,7 @@ impl Point2D {
     }
 
     /// Find the bearing from this point to the given point
-    pub fn bearing(&self, point: Point2D) -> f32 {
+    pub fn bearing(&self, point: Point2D) -> Angle {
         let lng_a = self.x.to_radians();
         let lat_a = self.y.to_radians();
         let lng_b = point.x.to_radians();
@@ -24,11 +42,361 @@ impl Point2D {
         let s = lat_b.cos() * delta_lng.sin();
         let c = lat_a.cos() * lat_b.sin() - lat_a.sin() * lat_b.cos() * delta_lng.cos();
 
-        f32::atan2(s, c).to_degrees()
+        Angle::from_radians(f32::atan2(s, c))
     }
 
     /// Find the bearing from this point to north
-    pub fn bearing_north(&self) -> f32 {
-        f32::atan2(self.x, self.y).to_degrees()
+    pub fn bearing_north(&self) -> Angle {
+        Angle::from_radians(f32::atan2(self.x, self.y))
+    }
+}
+
+/// An angle in degrees.  It holds whatever it was made from, so `degrees`
+/// can be outside [0, 360) and `signed` and `difference` give [-180, 180),
+/// but adding, subtracting and negating wrap the result to [0, 360).  Angles
+/// are equal when they point the same way, so 360° equals 0°.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Angle(f32);
+
+impl Angle {
+    pub const ZERO: Angle = Angle(0.0);
+
+    pub fn from_degrees(degrees: f32) -> Self {
+        Angle(degrees)
+    }
+
+    pub fn from_radians(radians: f32) -> Self {
+        Angle(radians.to_degrees())
+    }
+
+    /// The center of the given point on a compass rose
+    pub fn from_compass_point(rose: &CompassRose, point: usize) -> Self {
+        rose.sector_center(point)
+    }
+
+    pub fn degrees(self) -> f32 {
+        self.0
+    }
+
+    pub fn radians(self) -> f32 {
+        self.0.to_radians()
+    }
+
+    pub fn is_finite(self) -> bool {
+        self.0.is_finite()
+    }
+
+    /// The same angle wrapped to [0, 360)
+    pub fn normalized(self) -> Self {
+        Angle(normalize_degrees(self.0))
+    }
+
+    /// The same angle wrapped to [-180, 180)
+    pub fn signed(self) -> Self {
+        let degrees = normalize_degrees(self.0);
+        if degrees >= 180.0 {
+            Angle(degrees - 360.0)
+        } else {
+            Angle(degrees)
+        }
+    }
+
+    /// The shortest signed rotation from this angle to the given one, in
+    /// [-180, 180).  Positive is clockwise.
+    pub fn difference(self, to: Angle) -> Self {
+        Angle(to.0 - self.0).signed()
+    }
+
+    /// The point on the given compass rose this angle falls in
+    pub fn compass_point(self, rose: &CompassRose) -> Result<usize, Error> {
+        rose.sector(self)
+    }
+}
+
+impl PartialEq for Angle {
+    fn eq(&self, other: &Angle) -> bool {
+        normalize_degrees(self.0) == normalize_degrees(other.0)
+    }
+}
+
+impl fmt::Display for Angle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}°", self.0)
+    }
+}
+
+impl Add for Angle {
+    type Output = Angle;
+
+    fn add(self, other: Angle) -> Angle {
+        Angle(self.0 + other.0).normalized()
+    }
+}
+
+impl Sub for Angle {
+    type Output = Angle;
+
+    fn sub(self, other: Angle) -> Angle {
+        Angle(self.0 - other.0).normalized()
+    }
+}
+
+impl Neg for Angle {
+    type Output = Angle;
+
+    fn neg(self) -> Angle {
+        Angle(-self.0).normalized()
+    }
+}
+
+/// Number of points on a compass rose
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RosePoints {
+    Four,
+    Eight,
+    Sixteen,
+}
+
+impl RosePoints {
+    pub fn count(&self) -> usize {
+        match self {
+            RosePoints::Four => 4,
+            RosePoints::Eight => 8,
+            RosePoints::Sixteen => 16,
+        }
+    }
+}
+
+/// Maps angles onto the sectors of a compass rose.  Sector 0 is centered on
+/// the offset (north by default) and sectors are numbered clockwise.
+#[derive(Debug, Clone, Copy)]
+pub struct CompassRose {
+    points: RosePoints,
+    offset: Angle,
+}
+
+impl CompassRose {
+    pub fn new(points: RosePoints) -> Self {
+        Self {
+            points,
+            offset: Angle::ZERO,
+        }
+    }
+
+    /// Rotate the rose so sector 0 is centered on the given angle
+    pub fn with_offset(mut self, offset: Angle) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn points(&self) -> RosePoints {
+        self.points
+    }
+
+    /// Width of a single sector in degrees
+    pub fn sector_width(&self) -> f32 {
+        360.0 / self.points.count() as f32
+    }
+
+    /// Find the sector the given angle falls in.  Any finite angle is
+    /// accepted and normalised first.
+    pub fn sector(&self, angle: Angle) -> Result<usize, Error> {
+        if !angle.is_finite() || !self.offset.is_finite() {
+            return Err(Error::InvalidAngle);
+        }
+
+        let width = self.sector_width();
+        let shifted = normalize_degrees(angle.0 - self.offset.0 + width / 2.0);
+        let sector = (shifted / width).floor() as usize;
+
+        // Guard against float rounding right below 360
+        Ok(sector % self.points.count())
+    }
+
+    /// The angle at the center of the given sector
+    pub fn sector_center(&self, sector: usize) -> Angle {
+        self.offset + Angle(sector as f32 * self.sector_width())
+    }
+}
+
+/// Normalise an angle in degrees to [0, 360)
+pub fn normalize_degrees(angle: f32) -> f32 {
+    let wrapped = angle % 360.0;
+    let wrapped = if wrapped < 0.0 {
+        wrapped + 360.0
+    } else {
+        wrapped
+    };
+
+    // Adding 360 to a tiny negative number can round up to 360
+    if wrapped >= 360.0 {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-3;
+
+    /// Angles spread around and beyond the circle, including either side of
+    /// the wrap
+    fn angles() -> impl Iterator<Item = f32> {
+        (-1440..=1440)
+            .map(|half| half as f32 * 0.5)
+            .chain([-0.001, 0.001, 359.999, 360.001, -359.999, 179.999, 180.001])
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < EPSILON
+    }
+
+    #[test]
+    fn normalized_is_in_range() {
+        for degrees in angles() {
+            let normalized = Angle::from_degrees(degrees).normalized().degrees();
+            assert!(
+                (0.0..360.0).contains(&normalized),
+                "{} -> {}",
+                degrees,
+                normalized
+            );
+            let signed = Angle::from_degrees(degrees).signed().degrees();
+            assert!(
+                (-180.0..180.0).contains(&signed),
+                "{} -> {}",
+                degrees,
+                signed
+            );
+        }
+        assert_eq!(normalize_degrees(-1e-9), 0.0);
+    }
+
+    #[test]
+    fn equal_when_pointing_the_same_way() {
+        assert_eq!(Angle::from_degrees(360.0), Angle::ZERO);
+        assert_eq!(Angle::from_degrees(-90.0), Angle::from_degrees(270.0));
+        assert_eq!(Angle::from_degrees(720.0 + 45.0), Angle::from_degrees(45.0));
+        assert_ne!(Angle::from_degrees(1.0), Angle::ZERO);
+        assert_ne!(Angle::from_degrees(f32::NAN), Angle::from_degrees(f32::NAN));
+    }
+
+    #[test]
+    fn difference_across_north() {
+        let difference = |from: f32, to: f32| {
+            Angle::from_degrees(from)
+                .difference(Angle::from_degrees(to))
+                .degrees()
+        };
+        assert!(close(difference(359.0, 1.0), 2.0));
+        assert!(close(difference(1.0, 359.0), -2.0));
+        assert!(close(difference(0.0, 359.999), -0.001));
+        assert!(close(difference(359.999, 0.0), 0.001));
+        assert!(close(difference(-10.0, 10.0), 20.0));
+        assert!(close(difference(710.0, 10.0), 20.0));
+        assert!(close(difference(0.0, 180.0), -180.0));
+    }
+
+    #[test]
+    fn difference_is_the_shortest_rotation() {
+        for from in angles() {
+            for to in [0.0, 0.5, 90.0, 179.5, 180.5, 270.0, 359.5] {
+                let from = Angle::from_degrees(from);
+                let to = Angle::from_degrees(to);
+                let difference = from.difference(to);
+                assert!(difference.degrees().abs() <= 180.0);
+                // Turning by the difference arrives at the target
+                let arrived = (from + difference).difference(to).degrees();
+                assert!(close(arrived, 0.0), "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn add_and_sub_round_trip() {
+        for a in angles() {
+            for b in [-725.0, -180.0, -0.5, 0.0, 0.5, 90.0, 359.9, 1000.0] {
+                let a = Angle::from_degrees(a);
+                let b = Angle::from_degrees(b);
+                let round_trip = (a + b) - b;
+                assert!(close(round_trip.difference(a).degrees(), 0.0));
+                assert!((0.0..360.0).contains(&(a + b).degrees()));
+                assert!((0.0..360.0).contains(&(a - b).degrees()));
+                assert!(close((a - b).difference(a + (-b)).degrees(), 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn negation_mirrors_about_north() {
+        assert_eq!(-Angle::from_degrees(90.0), Angle::from_degrees(270.0));
+        assert_eq!(-Angle::ZERO, Angle::ZERO);
+        assert_eq!((-Angle::from_degrees(-30.0)).degrees(), 30.0);
+    }
+
+    #[test]
+    fn compass_rose_sectors() {
+        let rose = CompassRose::new(RosePoints::Eight);
+        assert_eq!(rose.sector(Angle::from_degrees(0.0)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(22.4)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(22.5)).unwrap(), 1);
+        assert_eq!(rose.sector(Angle::from_degrees(337.5)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(-45.0)).unwrap(), 7);
+        assert!(rose.sector(Angle::from_degrees(f32::INFINITY)).is_err());
+        assert_eq!(rose.sector_center(7), Angle::from_degrees(315.0));
+    }
+
+    #[test]
+    fn four_and_sixteen_point_roses() {
+        let rose = CompassRose::new(RosePoints::Four);
+        assert_eq!(rose.sector(Angle::from_degrees(44.9)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(45.0)).unwrap(), 1);
+        assert_eq!(rose.sector(Angle::from_degrees(180.0)).unwrap(), 2);
+        assert_eq!(rose.sector(Angle::from_degrees(314.9)).unwrap(), 3);
+        assert_eq!(rose.sector(Angle::from_degrees(315.0)).unwrap(), 0);
+        assert_eq!(rose.sector_center(3), Angle::from_degrees(270.0));
+
+        let rose = CompassRose::new(RosePoints::Sixteen);
+        assert_eq!(rose.sector(Angle::from_degrees(11.2)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(11.25)).unwrap(), 1);
+        assert_eq!(rose.sector(Angle::from_degrees(348.75)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(-11.25)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(-11.3)).unwrap(), 15);
+        assert_eq!(rose.sector(Angle::from_degrees(720.0 + 90.0)).unwrap(), 4);
+        assert_eq!(rose.sector_center(15), Angle::from_degrees(337.5));
+    }
+
+    #[test]
+    fn compass_rose_offset() {
+        let rose = CompassRose::new(RosePoints::Eight).with_offset(Angle::from_degrees(10.0));
+        assert_eq!(rose.sector(Angle::from_degrees(32.4)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(32.5)).unwrap(), 1);
+        assert_eq!(rose.sector(Angle::from_degrees(347.5)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(347.4)).unwrap(), 7);
+        assert_eq!(rose.sector_center(1), Angle::from_degrees(55.0));
+
+        // Negative offsets wrap the centers back into [0, 360)
+        let rose = CompassRose::new(RosePoints::Four).with_offset(Angle::from_degrees(-90.0));
+        assert_eq!(rose.sector(Angle::from_degrees(270.0)).unwrap(), 0);
+        assert_eq!(rose.sector(Angle::from_degrees(0.0)).unwrap(), 1);
+        assert_eq!(rose.sector_center(0), Angle::from_degrees(270.0));
+    }
+
+    #[test]
+    fn compass_rose_rejects_nan() {
+        let rose = CompassRose::new(RosePoints::Eight);
+        assert!(matches!(
+            rose.sector(Angle::from_degrees(f32::NAN)),
+            Err(Error::InvalidAngle)
+        ));
+
+        let rose = rose.with_offset(Angle::from_degrees(f32::NAN));
+        assert!(matches!(
+            rose.sector(Angle::from_degrees(0.0)),
+            Err(Error::InvalidAngle)
+        ));
     }
 }
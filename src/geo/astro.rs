@@ -0,0 +1,310 @@
+/// Shared definitions and coordinate transforms for astronomical
+/// calculations.  Formulae are from Meeus, Astronomical Algorithms.  Nutation,
+/// aberration and refraction are ignored, which is far finer than we can
+/// point.
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::datetime::DateTime;
+use crate::geo::geodesic::{parse_sexagesimal, DEGREE_SEPARATORS};
+use crate::geo::{Angle, GeoPoint};
+use crate::Error;
+
+/// Julian day of the J2000.0 epoch
+pub const J2000: f64 = 2_451_545.0;
+
+/// Days in a Julian century
+pub const DAYS_PER_CENTURY: f64 = 36_525.0;
+
+/// A position in the local sky
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Horizontal {
+    /// Clockwise from true north
+    pub azimuth: Angle,
+    /// Above the horizon
+    pub altitude: Angle,
+}
+
+impl Horizontal {
+    pub fn is_above_horizon(&self) -> bool {
+        self.altitude.signed().degrees() > 0.0
+    }
+}
+
+/// A position on the celestial sphere, in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Equatorial {
+    pub right_ascension: f64,
+    pub declination: f64,
+}
+
+impl Equatorial {
+    pub fn new(right_ascension: f64, declination: f64) -> Result<Self, Error> {
+        if !right_ascension.is_finite() || !(-90.0..=90.0).contains(&declination) {
+            return Err(Error::InvalidCoordinate);
+        }
+
+        Ok(Self {
+            right_ascension: normalize_degrees(right_ascension),
+            declination,
+        })
+    }
+
+    /// Parse a right ascension in hours and a declination in degrees, e.g.
+    /// `10h08m22.3s +11°58'02"` or `10.1395, 11.9672`.  Without a comma the
+    /// declination must be signed.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        let index = match s.find(',') {
+            Some(index) => index,
+            None => s.rfind(['+', '-']).ok_or(Error::Parse)?,
+        };
+        let (right_ascension, declination) = (&s[..index], s[index..].trim_start_matches(','));
+
+        let hours = parse_sexagesimal(right_ascension, "hms:")?;
+        if !(0.0..24.0).contains(&hours) {
+            return Err(Error::InvalidCoordinate);
+        }
+        let declination = parse_sexagesimal(declination, DEGREE_SEPARATORS)?;
+
+        Equatorial::new(hours * 15.0, declination)
+    }
+
+    /// Precess coordinates referred to the J2000.0 equinox to the equinox of
+    /// the given date
+    pub fn precess_from_j2000(&self, datetime: &DateTime) -> Self {
+        let t = julian_centuries(datetime);
+
+        let zeta = arcseconds(2306.2181 * t + 0.30188 * t * t + 0.017998 * t * t * t);
+        let z = arcseconds(2306.2181 * t + 1.09468 * t * t + 0.018203 * t * t * t);
+        let theta = arcseconds(2004.3109 * t - 0.42665 * t * t - 0.041833 * t * t * t);
+
+        let ra = self.right_ascension.to_radians();
+        let dec = self.declination.to_radians();
+        let a = dec.cos() * (ra + zeta).sin();
+        let b = theta.cos() * dec.cos() * (ra + zeta).cos() - theta.sin() * dec.sin();
+        let c = theta.sin() * dec.cos() * (ra + zeta).cos() + theta.cos() * dec.sin();
+
+        Self {
+            right_ascension: normalize_degrees((a.atan2(b) + z).to_degrees()),
+            declination: c.clamp(-1.0, 1.0).asin().to_degrees(),
+        }
+    }
+
+    /// Where these coordinates (referred to the equinox of date) appear in the
+    /// sky at the given time and location
+    pub fn to_horizontal(&self, datetime: &DateTime, location: &GeoPoint) -> Horizontal {
+        let hour_angle =
+            (local_sidereal_time(datetime, location) - self.right_ascension).to_radians();
+        let latitude = location.lat().to_radians();
+        let dec = self.declination.to_radians();
+
+        // Meeus measures azimuth from the south
+        let azimuth = hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * latitude.sin() - dec.tan() * latitude.cos());
+        let altitude = (latitude.sin() * dec.sin() + latitude.cos() * dec.cos() * hour_angle.cos())
+            .clamp(-1.0, 1.0)
+            .asin();
+
+        Horizontal {
+            azimuth: Angle::from_degrees(normalize_degrees(azimuth.to_degrees() + 180.0) as f32),
+            altitude: Angle::from_degrees(altitude.to_degrees() as f32),
+        }
+    }
+}
+
+/// Mean sidereal time at Greenwich in degrees
+pub fn greenwich_sidereal_time(datetime: &DateTime) -> f64 {
+    greenwich_sidereal_time_at(datetime.julian_day())
+}
+
+pub(crate) fn greenwich_sidereal_time_at(jd: f64) -> f64 {
+    let t = (jd - J2000) / DAYS_PER_CENTURY;
+
+    normalize_degrees(
+        280.460_618_37 + 360.985_647_366_29 * (jd - J2000) + 0.000_387_933 * t * t
+            - t * t * t / 38_710_000.0,
+    )
+}
+
+/// Mean sidereal time at the given location in degrees
+pub fn local_sidereal_time(datetime: &DateTime, location: &GeoPoint) -> f64 {
+    normalize_degrees(greenwich_sidereal_time(datetime) + location.lon())
+}
+
+fn arcseconds(value: f64) -> f64 {
+    (value / 3600.0).to_radians()
+}
+
+/// Julian centuries since J2000.0
+pub fn julian_centuries(datetime: &DateTime) -> f64 {
+    (datetime.julian_day() - J2000) / DAYS_PER_CENTURY
+}
+
+/// Wrap an angle in degrees to [0, 360) at full precision
+pub fn normalize_degrees(degrees: f64) -> f64 {
+    let wrapped = degrees % 360.0;
+    if wrapped < 0.0 {
+        wrapped + 360.0
+    } else {
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(datetime: &str) -> DateTime {
+        DateTime::parse(datetime).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn sidereal_time() {
+        // Meeus examples 12.a and 12.b: 13h10m46.3668s and 8h34m57.0896s
+        assert_close(
+            greenwich_sidereal_time(&at("1987-04-10T00:00:00Z")),
+            (13.0 + 10.0 / 60.0 + 46.3668 / 3600.0) * 15.0,
+            1e-5,
+        );
+        assert_close(
+            greenwich_sidereal_time(&at("1987-04-10T19:21:00Z")),
+            (8.0 + 34.0 / 60.0 + 57.0896 / 3600.0) * 15.0,
+            1e-5,
+        );
+
+        let washington = GeoPoint::new(38.921389, -77.065556).unwrap();
+        assert_close(
+            local_sidereal_time(&at("1987-04-10T19:21:00Z"), &washington),
+            128.737_873 - 77.065_556,
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn venus_from_the_naval_observatory() {
+        // Meeus example 13.b: azimuth 68.0337° from the south and altitude
+        // 15.1249°.  Meeus uses apparent sidereal time, which is 0.001° off
+        // the mean.
+        let venus = Equatorial::new(347.319_337_5, -6.719_892).unwrap();
+        let observatory = GeoPoint::new(38.921389, -77.065556).unwrap();
+        let position = venus.to_horizontal(&at("1987-04-10T19:21:00Z"), &observatory);
+
+        assert_close(position.azimuth.degrees() as f64, 68.0337 + 180.0, 0.005);
+        assert_close(position.altitude.degrees() as f64, 15.1249, 0.005);
+        assert!(position.is_above_horizon());
+    }
+
+    #[test]
+    fn celestial_pole_is_at_the_latitude() {
+        let pole = Equatorial::new(0.0, 90.0).unwrap();
+        for (datetime, lat, lon) in [
+            ("2024-06-21T00:00:00Z", 51.4779, 0.0),
+            ("2024-01-01T06:30:00Z", 37.7749, -122.4194),
+            ("2030-09-15T18:00:00Z", 0.5, 100.0),
+        ]
+        .iter()
+        {
+            let location = GeoPoint::new(*lat, *lon).unwrap();
+            let position = pole.to_horizontal(&at(datetime), &location);
+            assert_close(position.altitude.degrees() as f64, *lat, 1e-4);
+            let azimuth = position.azimuth.signed().degrees() as f64;
+            assert_close(azimuth, 0.0, 1e-3);
+        }
+    }
+
+    #[test]
+    fn transit_and_setting() {
+        // A star on the meridian is due south at its highest, and one on the
+        // celestial equator sets due west
+        let location = GeoPoint::new(40.0, -105.0).unwrap();
+        let datetime = at("2024-03-01T04:00:00Z");
+        let lst = local_sidereal_time(&datetime, &location);
+
+        let transiting = Equatorial::new(lst, 10.0).unwrap();
+        let position = transiting.to_horizontal(&datetime, &location);
+        assert_close(position.azimuth.degrees() as f64, 180.0, 1e-3);
+        assert_close(position.altitude.degrees() as f64, 60.0, 1e-3);
+
+        let setting = Equatorial::new(lst - 90.0, 0.0).unwrap();
+        let position = setting.to_horizontal(&datetime, &location);
+        assert_close(position.azimuth.degrees() as f64, 270.0, 1e-3);
+        assert_close(position.altitude.degrees() as f64, 0.0, 1e-3);
+        assert!(!setting
+            .to_horizontal(&at("2024-03-01T04:10:00Z"), &location)
+            .is_above_horizon());
+    }
+
+    #[test]
+    fn precession() {
+        // Meeus example 21.b, θ Persei to 2028 November 13.19, without the
+        // proper motion the example adds
+        let theta_persei = Equatorial::new(41.054_061, 49.227_749).unwrap();
+        let of_date = theta_persei.precess_from_j2000(&at("2028-11-13T04:33:36Z"));
+        assert_close(of_date.right_ascension, 41.547_214, 1e-4);
+        assert_close(of_date.declination, 49.348_483, 1e-4);
+
+        // Nothing to do at the epoch
+        let at_epoch = theta_persei.precess_from_j2000(&at("2000-01-01T12:00:00Z"));
+        assert_close(at_epoch.right_ascension, theta_persei.right_ascension, 1e-9);
+        assert_close(at_epoch.declination, theta_persei.declination, 1e-9);
+    }
+
+    #[test]
+    fn parse_coordinates() {
+        // Regulus
+        let regulus = Equatorial::parse("10h08m22.3s +11°58'02\"").unwrap();
+        assert_close(regulus.right_ascension, 152.092_917, 1e-6);
+        assert_close(regulus.declination, 11.967_222, 1e-6);
+        let decimal = Equatorial::parse("10.1395, 11.9672").unwrap();
+        assert_close(decimal.right_ascension, 152.0925, 1e-9);
+        assert_close(decimal.declination, 11.9672, 1e-9);
+
+        let south = Equatorial::parse("6:45:08.9 -16:42:58").unwrap();
+        assert_close(
+            south.declination,
+            -(16.0 + 42.0 / 60.0 + 58.0 / 3600.0),
+            1e-9,
+        );
+
+        for s in [
+            "10h08m22.3s 11°58'02\"",
+            "24h00m00s +10",
+            "10h60m00s +10",
+            "10h08m22.3s +91",
+            "",
+        ]
+        .iter()
+        {
+            assert!(Equatorial::parse(s).is_err(), "{:?} parsed", s);
+        }
+        assert!(matches!(
+            Equatorial::new(f64::NAN, 0.0),
+            Err(Error::InvalidCoordinate)
+        ));
+        assert_close(
+            Equatorial::new(-15.0, 0.0).unwrap().right_ascension,
+            345.0,
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_degrees(0.0), 0.0);
+        assert_eq!(normalize_degrees(360.0), 0.0);
+        assert_eq!(normalize_degrees(-90.0), 270.0);
+        assert_eq!(normalize_degrees(725.0), 5.0);
+    }
+}
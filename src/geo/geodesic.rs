@@ -0,0 +1,557 @@
+/// Geodesic calculations on real-world latitude and longitude coordinates
+use core::str::FromStr;
+
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::{Angle, Point2D};
+use crate::Error;
+
+/// Mean radius of the earth in meters, used for spherical calculations
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// WGS84 ellipsoid semi-major axis in meters
+const WGS84_A: f64 = 6_378_137.0;
+/// WGS84 ellipsoid flattening
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// Maximum iterations before Vincenty's formula is considered non-convergent,
+/// which happens for nearly antipodal points
+const VINCENTY_ITERATIONS: usize = 200;
+
+/// A location on the earth in decimal degrees.  North and east are positive.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GeoPoint {
+    lat: f64,
+    lon: f64,
+}
+
+impl GeoPoint {
+    /// Create a point, validating the latitude is within ±90 and longitude
+    /// within ±180 degrees
+    pub fn new(lat: f64, lon: f64) -> Result<Self, Error> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(Error::InvalidCoordinate);
+        }
+
+        Ok(Self { lat, lon })
+    }
+
+    fn from_radians(lat: f64, lon: f64) -> Self {
+        Self {
+            lat: lat.to_degrees(),
+            lon: normalize_longitude(lon.to_degrees()),
+        }
+    }
+
+    pub fn lat(&self) -> f64 {
+        self.lat
+    }
+
+    pub fn lon(&self) -> f64 {
+        self.lon
+    }
+
+    /// Great-circle distance in meters using the haversine formula on a
+    /// spherical earth
+    pub fn haversine_distance(&self, other: &GeoPoint) -> f64 {
+        let lat_a = self.lat.to_radians();
+        let lat_b = other.lat.to_radians();
+        let delta_lat = lat_b - lat_a;
+        let delta_lon = (other.lon - self.lon).to_radians();
+
+        let h = (delta_lat / 2.0).sin().powi(2)
+            + lat_a.cos() * lat_b.cos() * (delta_lon / 2.0).sin().powi(2);
+
+        2.0 * EARTH_RADIUS_M * h.sqrt().min(1.0).asin()
+    }
+
+    /// Distance in meters on the WGS84 ellipsoid using Vincenty's inverse
+    /// formula.  Accurate to within a millimeter, but fails to converge for
+    /// nearly antipodal points.
+    pub fn vincenty_distance(&self, other: &GeoPoint) -> Result<f64, Error> {
+        let b = (1.0 - WGS84_F) * WGS84_A;
+        let l = (other.lon - self.lon).to_radians();
+        let u1 = ((1.0 - WGS84_F) * self.lat.to_radians().tan()).atan();
+        let u2 = ((1.0 - WGS84_F) * other.lat.to_radians().tan()).atan();
+        let (sin_u1, cos_u1) = u1.sin_cos();
+        let (sin_u2, cos_u2) = u2.sin_cos();
+
+        let mut lambda = l;
+        for _ in 0..VINCENTY_ITERATIONS {
+            let (sin_lambda, cos_lambda) = lambda.sin_cos();
+            let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+                + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+            .sqrt();
+            if sin_sigma == 0.0 {
+                // Coincident points
+                return Ok(0.0);
+            }
+
+            let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+            let sigma = sin_sigma.atan2(cos_sigma);
+            let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+            let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+            // Both points on the equator
+            let cos_2sigma_m = if cos_sq_alpha != 0.0 {
+                cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+            } else {
+                0.0
+            };
+            let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+
+            let previous = lambda;
+            lambda = l
+                + (1.0 - c)
+                    * WGS84_F
+                    * sin_alpha
+                    * (sigma
+                        + c * sin_sigma
+                            * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+
+            if (lambda - previous).abs() < 1e-12 {
+                let u_sq = cos_sq_alpha * (WGS84_A.powi(2) - b.powi(2)) / b.powi(2);
+                let big_a = 1.0
+                    + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+                let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+                let delta_sigma = big_b
+                    * sin_sigma
+                    * (cos_2sigma_m
+                        + big_b / 4.0
+                            * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                                - big_b / 6.0
+                                    * cos_2sigma_m
+                                    * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                    * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+
+                return Ok(b * big_a * (sigma - delta_sigma));
+            }
+        }
+
+        Err(Error::NoConvergence)
+    }
+
+    /// Initial great-circle bearing from this point toward the given point
+    pub fn bearing(&self, other: &GeoPoint) -> Angle {
+        let lat_a = self.lat.to_radians();
+        let lat_b = other.lat.to_radians();
+        let delta_lon = (other.lon - self.lon).to_radians();
+
+        let s = lat_b.cos() * delta_lon.sin();
+        let c = lat_a.cos() * lat_b.sin() - lat_a.sin() * lat_b.cos() * delta_lon.cos();
+
+        Angle::from_degrees(s.atan2(c).to_degrees() as f32).normalized()
+    }
+
+    /// The point reached by travelling the given distance in meters along a
+    /// great circle starting at the given bearing
+    pub fn destination(&self, bearing: Angle, distance: f64) -> GeoPoint {
+        let lat = self.lat.to_radians();
+        let lon = self.lon.to_radians();
+        let theta = (bearing.degrees() as f64).to_radians();
+        let delta = distance / EARTH_RADIUS_M;
+
+        let lat_dest = (lat.sin() * delta.cos() + lat.cos() * delta.sin() * theta.cos()).asin();
+        let lon_dest = lon
+            + (theta.sin() * delta.sin() * lat.cos())
+                .atan2(delta.cos() - lat.sin() * lat_dest.sin());
+
+        GeoPoint::from_radians(lat_dest, lon_dest)
+    }
+
+    /// The point halfway along the great circle between this and the given
+    /// point
+    pub fn midpoint(&self, other: &GeoPoint) -> GeoPoint {
+        let lat_a = self.lat.to_radians();
+        let lat_b = other.lat.to_radians();
+        let lon_a = self.lon.to_radians();
+        let delta_lon = (other.lon - self.lon).to_radians();
+
+        let bx = lat_b.cos() * delta_lon.cos();
+        let by = lat_b.cos() * delta_lon.sin();
+        let lat = (lat_a.sin() + lat_b.sin()).atan2(((lat_a.cos() + bx).powi(2) + by * by).sqrt());
+        let lon = lon_a + by.atan2(lat_a.cos() + bx);
+
+        GeoPoint::from_radians(lat, lon)
+    }
+
+    /// Parse a latitude and longitude pair, e.g. `37.7749, -122.4194`,
+    /// `37°46'29.6"N 122°25'9.8"W` or `N 37 46.5 W 122 25`.  Each coordinate
+    /// may be decimal degrees, degrees and decimal minutes, or degrees,
+    /// minutes and seconds.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        let (lat, lon) = match s.find(',') {
+            Some(index) => (&s[..index], &s[index + 1..]),
+            None => {
+                // Without a comma the latitude must end in its hemisphere,
+                // or lead with it and the longitude with its own
+                let index = if s.starts_with(['N', 'S']) {
+                    s.find(['E', 'W'])
+                } else {
+                    s.find(['N', 'S']).map(|index| index + 1)
+                };
+                let index = index.ok_or(Error::Parse)?;
+                (&s[..index], &s[index..])
+            }
+        };
+
+        let lat = parse_coordinate(lat, Axis::Latitude)?;
+        let lon = parse_coordinate(lon, Axis::Longitude)?;
+
+        GeoPoint::new(lat, lon)
+    }
+}
+
+impl FromStr for GeoPoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GeoPoint::parse(s)
+    }
+}
+
+impl From<GeoPoint> for Point2D {
+    fn from(point: GeoPoint) -> Self {
+        Point2D::new(point.lon as f32, point.lat as f32)
+    }
+}
+
+/// Wrap a longitude in degrees to [-180, 180)
+fn normalize_longitude(lon: f64) -> f64 {
+    let wrapped = (lon + 180.0) % 360.0;
+    if wrapped < 0.0 {
+        wrapped + 180.0
+    } else {
+        wrapped - 180.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Axis {
+    Latitude,
+    Longitude,
+}
+
+/// Parse a single latitude in decimal degrees from any of the supported
+/// formats, e.g. `-33.8688`, `33 52.128 S` or `33°52'7.68"S`
+pub fn parse_latitude(s: &str) -> Result<f64, Error> {
+    parse_coordinate(s, Axis::Latitude)
+}
+
+/// Parse a single longitude in decimal degrees from any of the supported
+/// formats, e.g. `151.2093`, `E 151 12.558` or `151°12'33.48"E`
+pub fn parse_longitude(s: &str) -> Result<f64, Error> {
+    parse_coordinate(s, Axis::Longitude)
+}
+
+fn parse_coordinate(s: &str, axis: Axis) -> Result<f64, Error> {
+    let mut s = s.trim();
+
+    // Hemisphere may lead or trail the numbers
+    let mut sign = 1.0;
+    let hemisphere = match (s.chars().next(), s.chars().last()) {
+        (Some(c), _) if c.is_ascii_alphabetic() => {
+            s = &s[1..];
+            Some(c)
+        }
+        (_, Some(c)) if c.is_ascii_alphabetic() => {
+            s = &s[..s.len() - 1];
+            Some(c)
+        }
+        _ => None,
+    };
+    if let Some(hemisphere) = hemisphere {
+        sign = match (hemisphere.to_ascii_uppercase(), axis) {
+            ('N', Axis::Latitude) | ('E', Axis::Longitude) => 1.0,
+            ('S', Axis::Latitude) | ('W', Axis::Longitude) => -1.0,
+            _ => return Err(Error::Parse),
+        };
+    }
+
+    let s = s.trim();
+    // A hemisphere and a sign are ambiguous
+    if hemisphere.is_some() && s.starts_with(['+', '-']) {
+        return Err(Error::Parse);
+    }
+
+    let degrees = sign * parse_sexagesimal(s, DEGREE_SEPARATORS)?;
+    let limit = match axis {
+        Axis::Latitude => 90.0,
+        Axis::Longitude => 180.0,
+    };
+    if !degrees.is_finite() || degrees.abs() > limit {
+        return Err(Error::InvalidCoordinate);
+    }
+
+    Ok(degrees)
+}
+
+/// Characters separating degrees, minutes and seconds
+pub(crate) const DEGREE_SEPARATORS: &str = "°'\"′″:";
+
+/// Parse a signed value made of a whole part, then optional minutes and
+/// seconds split by whitespace or any of the given separators, e.g.
+/// `-33 52 7.68` or `10h08m22.3s`.  Only the last part may have a fraction.
+pub(crate) fn parse_sexagesimal(s: &str, separators: &str) -> Result<f64, Error> {
+    let s = s.trim();
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let mut parts = [0.0; 3];
+    let mut count = 0;
+    let mut fractional = false;
+    for part in s
+        .split(|c: char| c.is_whitespace() || separators.contains(c))
+        .filter(|part| !part.is_empty())
+    {
+        if count == parts.len() || fractional || part.starts_with(['+', '-']) {
+            return Err(Error::Parse);
+        }
+        parts[count] = f64::from_str(part).map_err(|_| Error::Parse)?;
+        fractional = part.contains('.');
+        count += 1;
+    }
+    if count == 0 || parts[1] >= 60.0 || parts[2] >= 60.0 {
+        return Err(Error::Parse);
+    }
+
+    Ok(sign * (parts[0] + parts[1] / 60.0 + parts[2] / 3600.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, lon: f64) -> GeoPoint {
+        GeoPoint::new(lat, lon).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn haversine_known_distances() {
+        // A quarter of the way round the equator, and pole to pole
+        let quarter = core::f64::consts::FRAC_PI_2 * EARTH_RADIUS_M;
+        assert_close(
+            point(0.0, 0.0).haversine_distance(&point(0.0, 90.0)),
+            quarter,
+            1e-6,
+        );
+        assert_close(
+            point(90.0, 0.0).haversine_distance(&point(-90.0, 0.0)),
+            2.0 * quarter,
+            1e-6,
+        );
+        assert_eq!(
+            point(12.0, 34.0).haversine_distance(&point(12.0, 34.0)),
+            0.0
+        );
+
+        // Big Ben to the Statue of Liberty
+        let london = point(51.5007, -0.1246);
+        let new_york = point(40.6892, -74.0445);
+        assert_close(london.haversine_distance(&new_york), 5_574_840.0, 100.0);
+        assert_eq!(
+            london.haversine_distance(&new_york),
+            new_york.haversine_distance(&london)
+        );
+    }
+
+    #[test]
+    fn vincenty_known_distances() {
+        // Flinders Peak to Buninyong, Vincenty's own example
+        let flinders = GeoPoint::parse("37°57'3.72030\"S 144°25'29.52440\"E").unwrap();
+        let buninyong = GeoPoint::parse("37°39'10.15610\"S 143°55'35.38390\"E").unwrap();
+        assert_close(
+            flinders.vincenty_distance(&buninyong).unwrap(),
+            54_972.271,
+            1e-3,
+        );
+
+        // A degree of longitude along the equator and a quarter meridian
+        assert_close(
+            point(0.0, 0.0).vincenty_distance(&point(0.0, 1.0)).unwrap(),
+            111_319.491,
+            1e-3,
+        );
+        assert_close(
+            point(0.0, 0.0)
+                .vincenty_distance(&point(90.0, 0.0))
+                .unwrap(),
+            10_001_965.729,
+            1e-3,
+        );
+        assert_eq!(
+            point(12.0, 34.0)
+                .vincenty_distance(&point(12.0, 34.0))
+                .unwrap(),
+            0.0
+        );
+    }
+
+    #[test]
+    fn vincenty_fails_near_antipodes() {
+        assert!(matches!(
+            point(0.0, 0.0).vincenty_distance(&point(0.5, 179.7)),
+            Err(Error::NoConvergence)
+        ));
+        assert!(matches!(
+            point(0.0, 0.0).vincenty_distance(&point(0.0, 180.0)),
+            Err(Error::NoConvergence)
+        ));
+    }
+
+    #[test]
+    fn bearings() {
+        let origin = point(0.0, 0.0);
+        assert_close(origin.bearing(&point(1.0, 0.0)).degrees() as f64, 0.0, 1e-4);
+        assert_close(
+            origin.bearing(&point(0.0, 1.0)).degrees() as f64,
+            90.0,
+            1e-4,
+        );
+        assert_close(
+            origin.bearing(&point(-1.0, 0.0)).degrees() as f64,
+            180.0,
+            1e-4,
+        );
+        assert_close(
+            origin.bearing(&point(0.0, -1.0)).degrees() as f64,
+            270.0,
+            1e-4,
+        );
+    }
+
+    #[test]
+    fn destination_and_back() {
+        let start = point(51.5007, -0.1246);
+        let end = point(40.6892, -74.0445);
+        let distance = start.haversine_distance(&end);
+        let reached = start.destination(start.bearing(&end), distance);
+        // The bearing is only single precision
+        assert!(reached.haversine_distance(&end) < 50.0);
+
+        // Due east along the equator, across the antimeridian
+        let reached = point(0.0, 179.0).destination(Angle::from_degrees(90.0), 2.0 * 111_195.08);
+        assert_close(reached.lat(), 0.0, 1e-6);
+        assert_close(reached.lon(), -179.0, 1e-4);
+    }
+
+    #[test]
+    fn midpoints() {
+        let middle = point(0.0, 10.0).midpoint(&point(0.0, 30.0));
+        assert_close(middle.lat(), 0.0, 1e-9);
+        assert_close(middle.lon(), 20.0, 1e-9);
+
+        let a = point(51.5007, -0.1246);
+        let b = point(40.6892, -74.0445);
+        let middle = a.midpoint(&b);
+        assert_close(
+            middle.haversine_distance(&a),
+            middle.haversine_distance(&b),
+            1e-3,
+        );
+        assert_close(
+            a.haversine_distance(&middle),
+            a.haversine_distance(&b) / 2.0,
+            1e-3,
+        );
+
+        // The short way across the antimeridian
+        let middle = point(0.0, 170.0).midpoint(&point(0.0, -170.0));
+        assert_close(middle.lon().abs(), 180.0, 1e-9);
+    }
+
+    #[test]
+    fn range_is_validated() {
+        assert!(matches!(
+            GeoPoint::new(90.5, 0.0),
+            Err(Error::InvalidCoordinate)
+        ));
+        assert!(matches!(
+            GeoPoint::new(0.0, -180.5),
+            Err(Error::InvalidCoordinate)
+        ));
+        assert!(matches!(
+            GeoPoint::new(f64::NAN, 0.0),
+            Err(Error::InvalidCoordinate)
+        ));
+        assert!(GeoPoint::new(-90.0, 180.0).is_ok());
+    }
+
+    #[test]
+    fn parse_formats() {
+        let sydney = point(-33.8688, 151.2093);
+        for s in [
+            "-33.8688, 151.2093",
+            "-33.8688,151.2093",
+            "33.8688 S, 151.2093 E",
+            "33 52.128 S, E 151 12.558",
+            "33°52'7.68\"S 151°12'33.48\"E",
+            "33°52′7.68″S, 151°12′33.48″E",
+            "s33:52:7.68, 151:12:33.48e",
+            "S 33 52.128 E 151 12.558",
+            "S33°52'7.68\" E151°12'33.48\"",
+        ]
+        .iter()
+        {
+            let parsed: GeoPoint = s.parse().unwrap();
+            assert_close(parsed.lat(), sydney.lat(), 1e-6);
+            assert_close(parsed.lon(), sydney.lon(), 1e-6);
+        }
+        assert_close(parse_latitude("+12 30").unwrap(), 12.5, 1e-12);
+        assert_close(parse_longitude("W 0 0 36").unwrap(), -0.01, 1e-12);
+
+        let san_francisco = GeoPoint::parse("N 37 46.5 W 122 25").unwrap();
+        assert_close(san_francisco.lat(), 37.775, 1e-9);
+        assert_close(san_francisco.lon(), -122.416_666_666_666_67, 1e-9);
+    }
+
+    #[test]
+    fn parse_rejects_bad_coordinates() {
+        for s in [
+            "",
+            "12.5",
+            "12 34",
+            // A leading hemisphere for only one coordinate
+            "N 12 34",
+            "N 12 34 W",
+            // A hemisphere and a sign
+            "-12 N, 34 E",
+            "N +12, 34 E",
+            // Out of range
+            "91, 0",
+            "0, 180 0 1",
+            // Minutes and seconds of 60 or more
+            "12 60 N, 34 E",
+            "12 30 60, 34",
+            // A fraction before the last part, and too many parts
+            "12.5 30, 34",
+            "12 30 15 1, 34",
+            // The wrong hemisphere for the axis
+            "12 E, 34 N",
+            "12 X, 34",
+            "twelve, 34",
+        ]
+        .iter()
+        {
+            assert!(GeoPoint::parse(s).is_err(), "{:?} parsed", s);
+        }
+        assert!(matches!(
+            parse_latitude("91"),
+            Err(Error::InvalidCoordinate)
+        ));
+        assert!(matches!(parse_latitude("12 60"), Err(Error::Parse)));
+    }
+}
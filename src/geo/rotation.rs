@@ -0,0 +1,379 @@
+/// Rotation matrices and quaternions
+use core::ops::Mul;
+
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::vector::Vector3;
+use crate::geo::Angle;
+
+/// A 3x3 matrix stored by rows, generally used as a rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix3 {
+    pub rows: [[f32; 3]; 3],
+}
+
+impl Matrix3 {
+    pub const IDENTITY: Matrix3 = Matrix3 {
+        rows: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    };
+
+    pub fn new(rows: [[f32; 3]; 3]) -> Self {
+        Self { rows }
+    }
+
+    /// Rotation about the x axis (roll)
+    pub fn rotation_x(angle: Angle) -> Self {
+        let (s, c) = angle.radians().sin_cos();
+        Matrix3::new([[1.0, 0.0, 0.0], [0.0, c, -s], [0.0, s, c]])
+    }
+
+    /// Rotation about the y axis (pitch)
+    pub fn rotation_y(angle: Angle) -> Self {
+        let (s, c) = angle.radians().sin_cos();
+        Matrix3::new([[c, 0.0, s], [0.0, 1.0, 0.0], [-s, 0.0, c]])
+    }
+
+    /// Rotation about the z axis (yaw)
+    pub fn rotation_z(angle: Angle) -> Self {
+        let (s, c) = angle.radians().sin_cos();
+        Matrix3::new([[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    /// Rotation from roll, pitch and yaw, applied in that order (Z-Y-X)
+    pub fn from_euler(roll: Angle, pitch: Angle, yaw: Angle) -> Self {
+        Matrix3::rotation_z(yaw) * Matrix3::rotation_y(pitch) * Matrix3::rotation_x(roll)
+    }
+
+    pub fn transpose(&self) -> Self {
+        let r = &self.rows;
+        Matrix3::new([
+            [r[0][0], r[1][0], r[2][0]],
+            [r[0][1], r[1][1], r[2][1]],
+            [r[0][2], r[1][2], r[2][2]],
+        ])
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let r = &self.rows;
+        r[0][0] * (r[1][1] * r[2][2] - r[1][2] * r[2][1])
+            - r[0][1] * (r[1][0] * r[2][2] - r[1][2] * r[2][0])
+            + r[0][2] * (r[1][0] * r[2][1] - r[1][1] * r[2][0])
+    }
+}
+
+impl Mul<Vector3> for Matrix3 {
+    type Output = Vector3;
+
+    fn mul(self, v: Vector3) -> Vector3 {
+        let r = &self.rows;
+        Vector3::new(
+            r[0][0] * v.x + r[0][1] * v.y + r[0][2] * v.z,
+            r[1][0] * v.x + r[1][1] * v.y + r[1][2] * v.z,
+            r[2][0] * v.x + r[2][1] * v.y + r[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, other: Matrix3) -> Matrix3 {
+        let mut rows = [[0.0; 3]; 3];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = (0..3).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+            }
+        }
+        Matrix3::new(rows)
+    }
+}
+
+impl From<Quaternion> for Matrix3 {
+    fn from(q: Quaternion) -> Self {
+        let Quaternion { w, x, y, z } = q;
+        Matrix3::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ])
+    }
+}
+
+/// A quaternion, generally used as a unit rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion::IDENTITY
+    }
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+
+    /// Rotation by the given angle about the given axis.  A zero axis gives
+    /// no rotation.
+    pub fn from_axis_angle(axis: Vector3, angle: Angle) -> Self {
+        match axis.normalized() {
+            Some(axis) => {
+                let (s, c) = (angle.radians() / 2.0).sin_cos();
+                Quaternion::new(c, axis.x * s, axis.y * s, axis.z * s)
+            }
+            None => Quaternion::IDENTITY,
+        }
+    }
+
+    /// Rotation from roll, pitch and yaw, applied in that order (Z-Y-X)
+    pub fn from_euler(roll: Angle, pitch: Angle, yaw: Angle) -> Self {
+        let (sr, cr) = (roll.radians() / 2.0).sin_cos();
+        let (sp, cp) = (pitch.radians() / 2.0).sin_cos();
+        let (sy, cy) = (yaw.radians() / 2.0).sin_cos();
+
+        Quaternion::new(
+            cr * cp * cy + sr * sp * sy,
+            sr * cp * cy - cr * sp * sy,
+            cr * sp * cy + sr * cp * sy,
+            cr * cp * sy - sr * sp * cy,
+        )
+    }
+
+    /// The shortest rotation taking the direction of `from` to that of `to`
+    pub fn between(from: Vector3, to: Vector3) -> Self {
+        let (from, to) = match (from.normalized(), to.normalized()) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Quaternion::IDENTITY,
+        };
+
+        let dot = from.dot(to);
+        if dot < -0.999_999 {
+            // Opposite directions, so rotate half a turn about any
+            // perpendicular axis
+            let mut axis = Vector3::new(1.0, 0.0, 0.0).cross(from);
+            if axis.magnitude() < 1e-6 {
+                axis = Vector3::new(0.0, 1.0, 0.0).cross(from);
+            }
+            return Quaternion::from_axis_angle(axis, Angle::from_degrees(180.0));
+        }
+
+        let axis = from.cross(to);
+        Quaternion::new(1.0 + dot, axis.x, axis.y, axis.z).normalized()
+    }
+
+    /// Roll, pitch and yaw of this rotation (Z-Y-X)
+    pub fn to_euler(&self) -> (Angle, Angle, Angle) {
+        let Quaternion { w, x, y, z } = *self;
+
+        let roll = f32::atan2(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+        let yaw = f32::atan2(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+
+        (
+            Angle::from_radians(roll),
+            Angle::from_radians(pitch),
+            Angle::from_radians(yaw),
+        )
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn norm(&self) -> f32 {
+        (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    /// Scale to unit length.  A zero quaternion becomes the identity.
+    pub fn normalized(&self) -> Self {
+        let norm = self.norm();
+        if norm > 0.0 && norm.is_finite() {
+            Quaternion::new(self.w / norm, self.x / norm, self.y / norm, self.z / norm)
+        } else {
+            Quaternion::IDENTITY
+        }
+    }
+
+    /// Rotate the given vector by this (unit) quaternion
+    pub fn rotate(&self, v: Vector3) -> Vector3 {
+        let u = Vector3::new(self.x, self.y, self.z);
+        let t = u.cross(v) * 2.0;
+        v + t * self.w + u.cross(t)
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// Hamilton product, i.e. the rotation `other` followed by `self`
+    fn mul(self, other: Quaternion) -> Quaternion {
+        let (a, b) = (self, other);
+        Quaternion::new(
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).magnitude() < EPSILON
+    }
+
+    fn close_matrix(a: Matrix3, b: Matrix3) -> bool {
+        (0..3).all(|i| (0..3).all(|j| (a.rows[i][j] - b.rows[i][j]).abs() < EPSILON))
+    }
+
+    /// The same rotation, as q and -q are
+    fn same_rotation(a: Quaternion, b: Quaternion) -> bool {
+        let dot = a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z;
+        (dot.abs() - 1.0).abs() < EPSILON
+    }
+
+    fn degrees(d: f32) -> Angle {
+        Angle::from_degrees(d)
+    }
+
+    /// A spread of orientations away from gimbal lock
+    fn orientations() -> impl Iterator<Item = (f32, f32, f32)> {
+        (-4..=4).flat_map(|r| {
+            (-2..=2).flat_map(move |p| {
+                (-4..=4).map(move |y| (r as f32 * 40.0, p as f32 * 35.0, y as f32 * 42.0))
+            })
+        })
+    }
+
+    #[test]
+    fn elementary_rotations() {
+        let x = Vector3::new(1.0, 0.0, 0.0);
+        let y = Vector3::new(0.0, 1.0, 0.0);
+        let z = Vector3::new(0.0, 0.0, 1.0);
+        assert!(close(Matrix3::rotation_z(degrees(90.0)) * x, y));
+        assert!(close(Matrix3::rotation_x(degrees(90.0)) * y, z));
+        assert!(close(Matrix3::rotation_y(degrees(90.0)) * z, x));
+
+        let q = Quaternion::from_axis_angle(z, degrees(90.0));
+        assert!(close(q.rotate(x), y));
+        assert_eq!(
+            Quaternion::from_axis_angle(Vector3::ZERO, degrees(90.0)),
+            Quaternion::IDENTITY
+        );
+    }
+
+    #[test]
+    fn matrices_are_rotations() {
+        for (roll, pitch, yaw) in orientations() {
+            let m = Matrix3::from_euler(degrees(roll), degrees(pitch), degrees(yaw));
+            assert!((m.determinant() - 1.0).abs() < EPSILON);
+            assert!(close_matrix(m * m.transpose(), Matrix3::IDENTITY));
+        }
+    }
+
+    #[test]
+    fn quaternion_matrix_round_trip() {
+        let v = Vector3::new(0.3, -1.2, 2.5);
+        for (roll, pitch, yaw) in orientations() {
+            let q = Quaternion::from_euler(degrees(roll), degrees(pitch), degrees(yaw));
+            let m = Matrix3::from_euler(degrees(roll), degrees(pitch), degrees(yaw));
+            assert!((q.norm() - 1.0).abs() < EPSILON);
+            assert!(close_matrix(Matrix3::from(q), m));
+            assert!(close(q.rotate(v), m * v));
+            // Undone by the conjugate and the transpose
+            assert!(close(q.conjugate().rotate(q.rotate(v)), v));
+            assert!(close(m.transpose() * (m * v), v));
+        }
+    }
+
+    #[test]
+    fn euler_round_trip() {
+        for (roll, pitch, yaw) in orientations() {
+            let q = Quaternion::from_euler(degrees(roll), degrees(pitch), degrees(yaw));
+            let (r, p, y) = q.to_euler();
+            let back = Quaternion::from_euler(r, p, y);
+            assert!(same_rotation(q, back), "{} {} {}", roll, pitch, yaw);
+            assert!(r.difference(degrees(roll)).degrees().abs() < 0.01);
+            assert!(p.difference(degrees(pitch)).degrees().abs() < 0.01);
+            assert!(y.difference(degrees(yaw)).degrees().abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn products_compose_rotations() {
+        let a = Quaternion::from_euler(degrees(10.0), degrees(20.0), degrees(30.0));
+        let b = Quaternion::from_euler(degrees(-50.0), degrees(5.0), degrees(120.0));
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert!(close((a * b).rotate(v), a.rotate(b.rotate(v))));
+        assert!(close_matrix(
+            Matrix3::from(a * b),
+            Matrix3::from(a) * Matrix3::from(b)
+        ));
+        assert!(same_rotation(a * a.conjugate(), Quaternion::IDENTITY));
+    }
+
+    #[test]
+    fn between_directions() {
+        let pairs = [
+            (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+            (Vector3::new(0.0, 0.0, 2.0), Vector3::new(1.0, 1.0, 1.0)),
+            (Vector3::new(1.0, 0.0, 0.0), Vector3::new(-3.0, 0.0, 0.0)),
+            (Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, 1.0)),
+        ];
+        for (from, to) in pairs.iter() {
+            let q = Quaternion::between(*from, *to);
+            assert!((q.norm() - 1.0).abs() < EPSILON);
+            assert!(close(
+                q.rotate(from.normalized().unwrap()),
+                to.normalized().unwrap()
+            ));
+        }
+        assert_eq!(
+            Quaternion::between(Vector3::ZERO, Vector3::new(1.0, 0.0, 0.0)),
+            Quaternion::IDENTITY
+        );
+    }
+
+    #[test]
+    fn normalisation() {
+        let q = Quaternion::new(2.0, 0.0, 0.0, 0.0).normalized();
+        assert_eq!(q, Quaternion::IDENTITY);
+        let q = Quaternion::new(1.0, 1.0, 1.0, 1.0).normalized();
+        assert!((q.norm() - 1.0).abs() < 1e-6);
+        assert_eq!(
+            Quaternion::new(0.0, 0.0, 0.0, 0.0).normalized(),
+            Quaternion::IDENTITY
+        );
+    }
+}
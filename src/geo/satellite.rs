@@ -0,0 +1,389 @@
+/// Satellite look angles and pass prediction from an SGP4 orbit
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::datetime::DateTime;
+use crate::geo::astro::greenwich_sidereal_time_at;
+use crate::geo::sgp4::Sgp4;
+use crate::geo::{Angle, GeoPoint, Horizontal};
+use crate::Error;
+
+/// WGS-84 ellipsoid for the observer's position
+const EQUATORIAL_RADIUS_KM: f64 = 6378.137;
+const FLATTENING: f64 = 1.0 / 298.257_223_563;
+
+/// Interval between elevation samples while searching for a pass.  Passes
+/// shorter than this may be missed.
+pub const SEARCH_STEP_MINUTES: f64 = 1.0;
+
+/// How far ahead to search before giving up
+pub const SEARCH_LIMIT_DAYS: f64 = 2.0;
+
+/// Bisection steps used to find the rise, set and culmination times to within
+/// a second
+const REFINE_ITERATIONS: usize = 6;
+const CULMINATION_ITERATIONS: usize = 12;
+
+/// Where the satellite appears in the sky at the given time and location
+pub fn look_angles(
+    satellite: &Sgp4,
+    datetime: &DateTime,
+    location: &GeoPoint,
+) -> Result<Horizontal, Error> {
+    look_angles_at(satellite, datetime.julian_day(), location)
+}
+
+fn look_angles_at(
+    satellite: &Sgp4,
+    julian_day: f64,
+    location: &GeoPoint,
+) -> Result<Horizontal, Error> {
+    let state = satellite.propagate((julian_day - satellite.epoch()) * 1440.0)?;
+
+    // Rotate from the inertial TEME frame to earth fixed coordinates
+    let theta = greenwich_sidereal_time_at(julian_day).to_radians();
+    let [x, y, z] = state.position;
+    let satellite = [
+        theta.cos() * x + theta.sin() * y,
+        -theta.sin() * x + theta.cos() * y,
+        z,
+    ];
+
+    let (sin_lat, cos_lat) = (
+        location.lat().to_radians().sin(),
+        location.lat().to_radians().cos(),
+    );
+    let (sin_lon, cos_lon) = (
+        location.lon().to_radians().sin(),
+        location.lon().to_radians().cos(),
+    );
+    let e2 = FLATTENING * (2.0 - FLATTENING);
+    let n = EQUATORIAL_RADIUS_KM / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+    let observer = [
+        n * cos_lat * cos_lon,
+        n * cos_lat * sin_lon,
+        n * (1.0 - e2) * sin_lat,
+    ];
+
+    let range = [
+        satellite[0] - observer[0],
+        satellite[1] - observer[1],
+        satellite[2] - observer[2],
+    ];
+    let east = -sin_lon * range[0] + cos_lon * range[1];
+    let north = -sin_lat * cos_lon * range[0] - sin_lat * sin_lon * range[1] + cos_lat * range[2];
+    let up = cos_lat * cos_lon * range[0] + cos_lat * sin_lon * range[1] + sin_lat * range[2];
+
+    Ok(Horizontal {
+        azimuth: Angle::from_radians(east.atan2(north) as f32).normalized(),
+        altitude: Angle::from_radians(up.atan2((east * east + north * north).sqrt()) as f32),
+    })
+}
+
+fn elevation_at(satellite: &Sgp4, julian_day: f64, location: &GeoPoint) -> Result<f32, Error> {
+    Ok(look_angles_at(satellite, julian_day, location)?
+        .altitude
+        .degrees())
+}
+
+/// A period when the satellite is above the horizon
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pass {
+    pub rise: DateTime,
+    pub rise_azimuth: Angle,
+    /// Highest point of the pass
+    pub culmination: DateTime,
+    pub max_elevation: Angle,
+    pub set: DateTime,
+    pub set_azimuth: Angle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchStatus {
+    Searching,
+    Found(Pass),
+    /// No pass within the search limit
+    NotFound,
+}
+
+/// A search for the next pass that can be run a few samples at a time, so
+/// the minutes to days of propagation it takes never hold up other tasks.  A
+/// pass in progress when the search starts is returned with the start time as
+/// its rise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PassSearch {
+    start: f64,
+    time: f64,
+    previous_elevation: Option<f32>,
+    rise: Option<f64>,
+    culmination: f64,
+    max_elevation: f32,
+}
+
+impl PassSearch {
+    pub fn new(from: &DateTime) -> Self {
+        let start = from.julian_day();
+
+        Self {
+            start,
+            time: start,
+            previous_elevation: None,
+            rise: None,
+            culmination: start,
+            max_elevation: 0.0,
+        }
+    }
+
+    /// Take up to `samples` steps through the search
+    pub fn step(
+        &mut self,
+        satellite: &Sgp4,
+        location: &GeoPoint,
+        samples: usize,
+    ) -> Result<SearchStatus, Error> {
+        let step = SEARCH_STEP_MINUTES / 1440.0;
+
+        for _ in 0..samples {
+            if self.rise.is_none() && self.time > self.start + SEARCH_LIMIT_DAYS {
+                return Ok(SearchStatus::NotFound);
+            }
+
+            let elevation = elevation_at(satellite, self.time, location)?;
+            match self.rise {
+                None if elevation >= 0.0 => {
+                    let rise = match self.previous_elevation {
+                        Some(_) => refine(satellite, location, self.time - step, self.time)?,
+                        None => self.time,
+                    };
+                    self.rise = Some(rise);
+                    self.culmination = self.time;
+                    self.max_elevation = elevation;
+                }
+                None => {}
+                Some(rise) if elevation < 0.0 => {
+                    let set = refine(satellite, location, self.time - step, self.time)?;
+                    let culmination = refine_culmination(
+                        satellite,
+                        location,
+                        (self.culmination - step).max(rise),
+                        (self.culmination + step).min(set),
+                    )?;
+                    let pass = Pass {
+                        rise: DateTime::from_julian_day(rise)?,
+                        rise_azimuth: look_angles_at(satellite, rise, location)?.azimuth,
+                        culmination: DateTime::from_julian_day(culmination)?,
+                        max_elevation: Angle::from_degrees(elevation_at(
+                            satellite,
+                            culmination,
+                            location,
+                        )?),
+                        set: DateTime::from_julian_day(set)?,
+                        set_azimuth: look_angles_at(satellite, set, location)?.azimuth,
+                    };
+
+                    return Ok(SearchStatus::Found(pass));
+                }
+                Some(_) if elevation > self.max_elevation => {
+                    self.culmination = self.time;
+                    self.max_elevation = elevation;
+                }
+                Some(_) => {}
+            }
+
+            self.previous_elevation = Some(elevation);
+            self.time += step;
+        }
+
+        Ok(SearchStatus::Searching)
+    }
+}
+
+/// Find when the elevation crosses the horizon between two times on either
+/// side of it
+fn refine(satellite: &Sgp4, location: &GeoPoint, mut from: f64, mut to: f64) -> Result<f64, Error> {
+    let rising = elevation_at(satellite, from, location)? < 0.0;
+
+    for _ in 0..REFINE_ITERATIONS {
+        let middle = (from + to) / 2.0;
+        if (elevation_at(satellite, middle, location)? < 0.0) == rising {
+            from = middle;
+        } else {
+            to = middle;
+        }
+    }
+
+    Ok((from + to) / 2.0)
+}
+
+/// Find the highest point of a pass by ternary search between two times either
+/// side of it
+fn refine_culmination(
+    satellite: &Sgp4,
+    location: &GeoPoint,
+    mut from: f64,
+    mut to: f64,
+) -> Result<f64, Error> {
+    for _ in 0..CULMINATION_ITERATIONS {
+        let third = (to - from) / 3.0;
+        if elevation_at(satellite, from + third, location)?
+            < elevation_at(satellite, to - third, location)?
+        {
+            from += third;
+        } else {
+            to -= third;
+        }
+    }
+
+    Ok((from + to) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::sgp4::{TleLine1, TleLine2};
+
+    /// The verification element set from Spacetrack Report #3
+    const LINE1: &str = "1 88888U          80275.98708465  .00073094  13844-3  66816-4 0    87";
+    const LINE2: &str = "2 88888  72.8435 115.9689 0086731  52.6988 110.5714 16.05824518  1058";
+
+    fn satellite() -> Sgp4 {
+        Sgp4::new(
+            &TleLine1::parse(LINE1).unwrap(),
+            &TleLine2::parse(LINE2).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn assert_angle(actual: Angle, expected: f32, tolerance: f32) {
+        let difference = actual.difference(Angle::from_degrees(expected)).degrees();
+        assert!(
+            difference.abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    fn search(from: &DateTime, location: &GeoPoint) -> SearchStatus {
+        let satellite = satellite();
+        let mut search = PassSearch::new(from);
+        loop {
+            match search.step(&satellite, location, 100).unwrap() {
+                SearchStatus::Searching => {}
+                status => return status,
+            }
+        }
+    }
+
+    #[test]
+    fn look_angles_at_epoch() {
+        // From the verification state vectors, rotated to earth fixed by the
+        // mean sidereal time and seen from the WGS-84 ellipsoid
+        let satellite = satellite();
+        let epoch = satellite.epoch();
+        let cases = [
+            (epoch, 18.0, -72.0, 224.7699, 29.1248),
+            (epoch, 10.0, -80.0, 43.7461, 15.8175),
+            (epoch + 0.25, 21.3, -157.9, 211.6068, 5.3001),
+        ];
+        for (julian_day, lat, lon, azimuth, altitude) in cases.iter() {
+            let location = GeoPoint::new(*lat, *lon).unwrap();
+            let look = look_angles_at(&satellite, *julian_day, &location).unwrap();
+            assert_angle(look.azimuth, *azimuth, 0.01);
+            assert_angle(look.altitude, *altitude, 0.01);
+        }
+
+        // The same to within the satellite's movement over the rounding of
+        // the date and time
+        let location = GeoPoint::new(18.0, -72.0).unwrap();
+        let datetime = DateTime::from_julian_day(epoch).unwrap();
+        let look = look_angles(&satellite, &datetime, &location).unwrap();
+        assert_angle(look.azimuth, 224.7699, 0.1);
+        assert_angle(look.altitude, 29.1248, 0.1);
+    }
+
+    #[test]
+    fn finds_the_next_pass() {
+        let satellite = satellite();
+        let location = GeoPoint::new(21.3, -157.9).unwrap();
+        let from = DateTime::from_julian_day(satellite.epoch()).unwrap();
+        let pass = match search(&from, &location) {
+            SearchStatus::Found(pass) => pass,
+            status => panic!("{:?}", status),
+        };
+
+        // Rises after the search starts, then culminates, then sets, all
+        // within a low orbit's few minutes
+        let rise = pass.rise.julian_day();
+        let culmination = pass.culmination.julian_day();
+        let set = pass.set.julian_day();
+        assert!(from.julian_day() < rise);
+        assert!(rise < culmination && culmination < set);
+        assert!((set - rise) * 1440.0 < 20.0);
+
+        // On the horizon at each end, to within the second the times are
+        // rounded to
+        let rise_look = look_angles(&satellite, &pass.rise, &location).unwrap();
+        let set_look = look_angles(&satellite, &pass.set, &location).unwrap();
+        assert_angle(rise_look.altitude, 0.0, 0.1);
+        assert_angle(set_look.altitude, 0.0, 0.1);
+        assert_angle(pass.rise_azimuth, rise_look.azimuth.degrees(), 0.1);
+        assert_angle(pass.set_azimuth, set_look.azimuth.degrees(), 0.1);
+
+        // And highest at the culmination
+        let mut minute = rise;
+        while minute < set {
+            let altitude = elevation_at(&satellite, minute, &location).unwrap();
+            assert!(altitude <= pass.max_elevation.degrees() + 0.01);
+            minute += 1.0 / 1440.0;
+        }
+    }
+
+    #[test]
+    fn searches_on_from_the_last_pass() {
+        // The satellite is above the horizon six hours after the epoch, an
+        // orbit after the first pass
+        let satellite = satellite();
+        let location = GeoPoint::new(21.3, -157.9).unwrap();
+        let from = DateTime::from_julian_day(satellite.epoch()).unwrap();
+        let first = match search(&from, &location) {
+            SearchStatus::Found(pass) => pass,
+            status => panic!("{:?}", status),
+        };
+        assert!(first.set.julian_day() < satellite.epoch() + 0.25);
+
+        let second = match search(&first.set, &location) {
+            SearchStatus::Found(pass) => pass,
+            status => panic!("{:?}", status),
+        };
+        assert!(second.rise.julian_day() < satellite.epoch() + 0.25);
+        assert!(second.set.julian_day() > satellite.epoch() + 0.25);
+        assert!(second.max_elevation.degrees() >= 5.3);
+    }
+
+    #[test]
+    fn a_pass_in_progress_rises_at_the_start() {
+        let satellite = satellite();
+        let location = GeoPoint::new(18.0, -72.0).unwrap();
+        let from = DateTime::from_julian_day(satellite.epoch()).unwrap();
+        match search(&from, &location) {
+            SearchStatus::Found(pass) => {
+                assert_eq!(pass.rise, from);
+                assert!(pass.set.julian_day() > from.julian_day());
+            }
+            status => panic!("{:?}", status),
+        }
+    }
+
+    #[test]
+    fn deep_space_orbits_are_refused() {
+        // Two revolutions a day, beyond what near earth SGP4 covers
+        let high = "2 88888  72.8435 115.9689 0086731  52.6988 110.5714  2.00563170  1052";
+        let satellite = Sgp4::new(
+            &TleLine1::parse(LINE1).unwrap(),
+            &TleLine2::parse(high).unwrap(),
+        );
+        assert!(matches!(satellite, Err(Error::Propagation)));
+    }
+}
@@ -0,0 +1,697 @@
+/// SGP4 orbit propagation from NORAD two-line element sets, following
+/// Vallado et al., "Revisiting Spacetrack Report #3" (AIAA 2006-6753).  Only
+/// near-earth orbits (periods under 225 minutes) are supported; the deep-space
+/// SDP4 extensions are not implemented.
+use core::f64::consts::PI;
+
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::datetime::{Date, DateTime, Time};
+use crate::Error;
+
+/// WGS-72 constants the element sets are generated with
+const EARTH_RADIUS_KM: f64 = 6378.135;
+const MU: f64 = 398_600.8;
+const J2: f64 = 0.001_082_616;
+const J3: f64 = -0.000_002_538_81;
+const J4: f64 = -0.000_001_655_97;
+const J3_OVER_J2: f64 = J3 / J2;
+const TWO_THIRDS: f64 = 2.0 / 3.0;
+const TWO_PI: f64 = 2.0 * PI;
+const MINUTES_PER_DAY: f64 = 1440.0;
+
+/// Orbits with longer periods need the deep-space model
+const DEEP_SPACE_PERIOD_MINUTES: f64 = 225.0;
+
+/// Length of each element set line
+pub const TLE_LINE_LENGTH: usize = 69;
+
+/// Sqrt of mu in earth radii^3 per minute^2
+fn xke() -> f64 {
+    60.0 / (EARTH_RADIUS_KM * EARTH_RADIUS_KM * EARTH_RADIUS_KM / MU).sqrt()
+}
+
+/// The first line of an element set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TleLine1 {
+    pub catalog_number: u32,
+    /// Julian day of the epoch
+    pub epoch: f64,
+    /// Drag term in inverse earth radii
+    pub bstar: f64,
+}
+
+impl TleLine1 {
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let line = validate_line(line, b'1')?;
+
+        let year: u16 = parse_number(&line[18..20])?;
+        let year = if year < 57 { year + 2000 } else { year + 1900 };
+        let day_of_year: f64 = parse_number(&line[20..32])?;
+        if !(1.0..367.0).contains(&day_of_year) {
+            return Err(Error::InvalidDateTime);
+        }
+        let new_year = DateTime::new(Date::new(year, 1, 1)?, Time::new(0, 0, 0, 0)?);
+
+        Ok(Self {
+            catalog_number: parse_number(&line[2..7])?,
+            epoch: new_year.julian_day() + day_of_year - 1.0,
+            bstar: parse_exponent(&line[53..61])?,
+        })
+    }
+}
+
+/// The second line of an element set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TleLine2 {
+    pub catalog_number: u32,
+    /// Inclination in degrees
+    pub inclination: f64,
+    /// Right ascension of the ascending node in degrees
+    pub right_ascension: f64,
+    pub eccentricity: f64,
+    /// Argument of perigee in degrees
+    pub argument_of_perigee: f64,
+    /// Mean anomaly in degrees
+    pub mean_anomaly: f64,
+    /// Mean motion in revolutions per day
+    pub mean_motion: f64,
+}
+
+impl TleLine2 {
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let line = validate_line(line, b'2')?;
+
+        let eccentricity: u32 = parse_number(&line[26..33])?;
+
+        Ok(Self {
+            catalog_number: parse_number(&line[2..7])?,
+            inclination: parse_number(&line[8..16])?,
+            right_ascension: parse_number(&line[17..25])?,
+            eccentricity: eccentricity as f64 / 10_000_000.0,
+            argument_of_perigee: parse_number(&line[34..42])?,
+            mean_anomaly: parse_number(&line[43..51])?,
+            mean_motion: parse_number(&line[52..63])?,
+        })
+    }
+}
+
+/// Check the length, line number and checksum of an element set line
+fn validate_line(line: &str, number: u8) -> Result<&str, Error> {
+    let line = line.trim_end();
+    let bytes = line.as_bytes();
+    if bytes.len() != TLE_LINE_LENGTH || bytes[0] != number || bytes[1] != b' ' {
+        return Err(Error::Parse);
+    }
+
+    let sum = bytes[..TLE_LINE_LENGTH - 1]
+        .iter()
+        .map(|&b| match b {
+            b'0'..=b'9' => (b - b'0') as u32,
+            b'-' => 1,
+            _ => 0,
+        })
+        .sum::<u32>();
+    if (sum % 10) as u8 + b'0' != bytes[TLE_LINE_LENGTH - 1] {
+        return Err(Error::Checksum);
+    }
+
+    Ok(line)
+}
+
+fn parse_number<T: core::str::FromStr>(field: &str) -> Result<T, Error> {
+    field.trim().parse().map_err(|_| Error::Parse)
+}
+
+/// Parse a field with an assumed leading decimal point and a power of ten
+/// exponent, e.g. ` 28098-4` for 0.28098e-4
+fn parse_exponent(field: &str) -> Result<f64, Error> {
+    let field = field.trim();
+    if field.len() < 3 {
+        return Err(Error::Parse);
+    }
+    let (mantissa, exponent) = field.split_at(field.len() - 2);
+    let (sign, digits) = match mantissa.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, mantissa.trim_start_matches('+')),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Parse);
+    }
+
+    let mantissa: f64 = parse_number(digits)?;
+    let exponent: i32 = parse_number(exponent)?;
+
+    Ok(sign * mantissa / 10f64.powi(digits.len() as i32) * 10f64.powi(exponent))
+}
+
+/// Position and velocity in the true equator, mean equinox (TEME) frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateVector {
+    /// Kilometres
+    pub position: [f64; 3],
+    /// Kilometres per second
+    pub velocity: [f64; 3],
+}
+
+/// A satellite's orbit, initialised from an element set and ready to be
+/// propagated to any time near its epoch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sgp4 {
+    catalog_number: u32,
+    epoch: f64,
+
+    bstar: f64,
+    eccentricity: f64,
+    inclination: f64,
+    right_ascension: f64,
+    argument_of_perigee: f64,
+    mean_anomaly: f64,
+    mean_motion: f64,
+
+    simple: bool,
+    aycof: f64,
+    con41: f64,
+    cc1: f64,
+    cc4: f64,
+    cc5: f64,
+    d2: f64,
+    d3: f64,
+    d4: f64,
+    delmo: f64,
+    eta: f64,
+    argpdot: f64,
+    omgcof: f64,
+    sinmao: f64,
+    t2cof: f64,
+    t3cof: f64,
+    t4cof: f64,
+    t5cof: f64,
+    x1mth2: f64,
+    x7thm1: f64,
+    mdot: f64,
+    nodedot: f64,
+    xlcof: f64,
+    xmcof: f64,
+    nodecf: f64,
+}
+
+impl Sgp4 {
+    pub fn new(line1: &TleLine1, line2: &TleLine2) -> Result<Self, Error> {
+        if line1.catalog_number != line2.catalog_number {
+            return Err(Error::Parse);
+        }
+        if !(0.0..1.0).contains(&line2.eccentricity) || line2.mean_motion <= 0.0 {
+            return Err(Error::Propagation);
+        }
+
+        let xke = xke();
+        let ecco = line2.eccentricity;
+        let inclo = line2.inclination.to_radians();
+        let argpo = line2.argument_of_perigee.to_radians();
+        let mo = line2.mean_anomaly.to_radians();
+        let no_kozai = line2.mean_motion * TWO_PI / MINUTES_PER_DAY;
+        let bstar = line1.bstar;
+
+        // Recover the original mean motion and semi-major axis from the Kozai
+        // mean motion in the element set
+        let eccsq = ecco * ecco;
+        let omeosq = 1.0 - eccsq;
+        let rteosq = omeosq.sqrt();
+        let cosio = inclo.cos();
+        let cosio2 = cosio * cosio;
+        let ak = (xke / no_kozai).powf(TWO_THIRDS);
+        let d1 = 0.75 * J2 * (3.0 * cosio2 - 1.0) / (rteosq * omeosq);
+        let del = d1 / (ak * ak);
+        let adel = ak * (1.0 - del * del - del * (1.0 / 3.0 + 134.0 * del * del / 81.0));
+        let del = d1 / (adel * adel);
+        let no = no_kozai / (1.0 + del);
+
+        if TWO_PI / no >= DEEP_SPACE_PERIOD_MINUTES {
+            return Err(Error::Propagation);
+        }
+
+        let ao = (xke / no).powf(TWO_THIRDS);
+        let sinio = inclo.sin();
+        let po = ao * omeosq;
+        let con42 = 1.0 - 5.0 * cosio2;
+        let con41 = -con42 - cosio2 - cosio2;
+        let posq = po * po;
+        let rp = ao * (1.0 - ecco);
+
+        // Perigees below 220 km use a truncated drag model
+        let simple = rp < 220.0 / EARTH_RADIUS_KM + 1.0;
+
+        // Atmospheric density parameters, adjusted for low perigees
+        let mut sfour = 78.0 / EARTH_RADIUS_KM + 1.0;
+        let mut qzms24 = ((120.0 - 78.0) / EARTH_RADIUS_KM).powi(4);
+        let perigee = (rp - 1.0) * EARTH_RADIUS_KM;
+        if perigee < 156.0 {
+            sfour = if perigee < 98.0 { 20.0 } else { perigee - 78.0 };
+            qzms24 = ((120.0 - sfour) / EARTH_RADIUS_KM).powi(4);
+            sfour = sfour / EARTH_RADIUS_KM + 1.0;
+        }
+
+        let pinvsq = 1.0 / posq;
+        let tsi = 1.0 / (ao - sfour);
+        let eta = ao * ecco * tsi;
+        let etasq = eta * eta;
+        let eeta = ecco * eta;
+        let psisq = (1.0 - etasq).abs();
+        let coef = qzms24 * tsi.powi(4);
+        let coef1 = coef / psisq.powf(3.5);
+        let cc2 = coef1
+            * no
+            * (ao * (1.0 + 1.5 * etasq + eeta * (4.0 + etasq))
+                + 0.375 * J2 * tsi / psisq * con41 * (8.0 + 3.0 * etasq * (8.0 + etasq)));
+        let cc1 = bstar * cc2;
+        let cc3 = if ecco > 1.0e-4 {
+            -2.0 * coef * tsi * J3_OVER_J2 * no * sinio / ecco
+        } else {
+            0.0
+        };
+        let x1mth2 = 1.0 - cosio2;
+        let cc4 = 2.0
+            * no
+            * coef1
+            * ao
+            * omeosq
+            * (eta * (2.0 + 0.5 * etasq) + ecco * (0.5 + 2.0 * etasq)
+                - J2 * tsi / (ao * psisq)
+                    * (-3.0 * con41 * (1.0 - 2.0 * eeta + etasq * (1.5 - 0.5 * eeta))
+                        + 0.75
+                            * x1mth2
+                            * (2.0 * etasq - eeta * (1.0 + etasq))
+                            * (2.0 * argpo).cos()));
+        let cc5 = 2.0 * coef1 * ao * omeosq * (1.0 + 2.75 * (etasq + eeta) + eeta * etasq);
+
+        // Secular rates from the zonal harmonics
+        let cosio4 = cosio2 * cosio2;
+        let temp1 = 1.5 * J2 * pinvsq * no;
+        let temp2 = 0.5 * temp1 * J2 * pinvsq;
+        let temp3 = -0.46875 * J4 * pinvsq * pinvsq * no;
+        let mdot = no
+            + 0.5 * temp1 * rteosq * con41
+            + 0.0625 * temp2 * rteosq * (13.0 - 78.0 * cosio2 + 137.0 * cosio4);
+        let argpdot = -0.5 * temp1 * con42
+            + 0.0625 * temp2 * (7.0 - 114.0 * cosio2 + 395.0 * cosio4)
+            + temp3 * (3.0 - 36.0 * cosio2 + 49.0 * cosio4);
+        let xhdot1 = -temp1 * cosio;
+        let nodedot = xhdot1
+            + (0.5 * temp2 * (4.0 - 19.0 * cosio2) + 2.0 * temp3 * (3.0 - 7.0 * cosio2)) * cosio;
+
+        let omgcof = bstar * cc3 * argpo.cos();
+        let xmcof = if ecco > 1.0e-4 {
+            -TWO_THIRDS * coef * bstar / eeta
+        } else {
+            0.0
+        };
+        let nodecf = 3.5 * omeosq * xhdot1 * cc1;
+        let t2cof = 1.5 * cc1;
+
+        // Avoid dividing by zero for an inclination of 180 degrees
+        let xlcof = -0.25 * J3_OVER_J2 * sinio * (3.0 + 5.0 * cosio)
+            / if (cosio + 1.0).abs() > 1.5e-12 {
+                1.0 + cosio
+            } else {
+                1.5e-12
+            };
+        let aycof = -0.5 * J3_OVER_J2 * sinio;
+        let delmo = (1.0 + eta * mo.cos()).powi(3);
+
+        let (mut d2, mut d3, mut d4) = (0.0, 0.0, 0.0);
+        let (mut t3cof, mut t4cof, mut t5cof) = (0.0, 0.0, 0.0);
+        if !simple {
+            let cc1sq = cc1 * cc1;
+            d2 = 4.0 * ao * tsi * cc1sq;
+            let temp = d2 * tsi * cc1 / 3.0;
+            d3 = (17.0 * ao + sfour) * temp;
+            d4 = 0.5 * temp * ao * tsi * (221.0 * ao + 31.0 * sfour) * cc1;
+            t3cof = d2 + 2.0 * cc1sq;
+            t4cof = 0.25 * (3.0 * d3 + cc1 * (12.0 * d2 + 10.0 * cc1sq));
+            t5cof = 0.2
+                * (3.0 * d4 + 12.0 * cc1 * d3 + 6.0 * d2 * d2 + 15.0 * cc1sq * (2.0 * d2 + cc1sq));
+        }
+
+        Ok(Self {
+            catalog_number: line1.catalog_number,
+            epoch: line1.epoch,
+            bstar,
+            eccentricity: ecco,
+            inclination: inclo,
+            right_ascension: line2.right_ascension.to_radians(),
+            argument_of_perigee: argpo,
+            mean_anomaly: mo,
+            mean_motion: no,
+            simple,
+            aycof,
+            con41,
+            cc1,
+            cc4,
+            cc5,
+            d2,
+            d3,
+            d4,
+            delmo,
+            eta,
+            argpdot,
+            omgcof,
+            sinmao: mo.sin(),
+            t2cof,
+            t3cof,
+            t4cof,
+            t5cof,
+            x1mth2,
+            x7thm1: 7.0 * cosio2 - 1.0,
+            mdot,
+            nodedot,
+            xlcof,
+            xmcof,
+            nodecf,
+        })
+    }
+
+    pub fn catalog_number(&self) -> u32 {
+        self.catalog_number
+    }
+
+    /// Julian day of the element set epoch
+    pub fn epoch(&self) -> f64 {
+        self.epoch
+    }
+
+    /// Position and velocity at the given time
+    pub fn state_at(&self, datetime: &DateTime) -> Result<StateVector, Error> {
+        self.propagate((datetime.julian_day() - self.epoch) * MINUTES_PER_DAY)
+    }
+
+    /// Position and velocity a number of minutes after the epoch
+    pub fn propagate(&self, minutes: f64) -> Result<StateVector, Error> {
+        let xke = xke();
+        let t = minutes;
+
+        // Secular gravity and atmospheric drag
+        let xmdf = self.mean_anomaly + self.mdot * t;
+        let argpdf = self.argument_of_perigee + self.argpdot * t;
+        let nodedf = self.right_ascension + self.nodedot * t;
+        let t2 = t * t;
+        let nodem = nodedf + self.nodecf * t2;
+        let mut argpm = argpdf;
+        let mut mm = xmdf;
+        let mut tempa = 1.0 - self.cc1 * t;
+        let mut tempe = self.bstar * self.cc4 * t;
+        let mut templ = self.t2cof * t2;
+
+        if !self.simple {
+            let delomg = self.omgcof * t;
+            let delm = self.xmcof * ((1.0 + self.eta * xmdf.cos()).powi(3) - self.delmo);
+            let temp = delomg + delm;
+            mm = xmdf + temp;
+            argpm = argpdf - temp;
+            let t3 = t2 * t;
+            let t4 = t3 * t;
+            tempa = tempa - self.d2 * t2 - self.d3 * t3 - self.d4 * t4;
+            tempe += self.bstar * self.cc5 * (mm.sin() - self.sinmao);
+            templ += self.t3cof * t3 + t4 * (self.t4cof + t * self.t5cof);
+        }
+
+        let am = (xke / self.mean_motion).powf(TWO_THIRDS) * tempa * tempa;
+        let nm = xke / am.powf(1.5);
+        let em = self.eccentricity - tempe;
+        if !(-0.001..1.0).contains(&em) || !am.is_finite() {
+            return Err(Error::Propagation);
+        }
+        let em = em.max(1.0e-6);
+
+        mm += self.mean_motion * templ;
+        let xlm = mm + argpm + nodem;
+        let nodem = nodem % TWO_PI;
+        let argpm = argpm % TWO_PI;
+        let xlm = xlm % TWO_PI;
+        let mm = (xlm - argpm - nodem) % TWO_PI;
+
+        // Long period periodics
+        let axnl = em * argpm.cos();
+        let temp = 1.0 / (am * (1.0 - em * em));
+        let aynl = em * argpm.sin() + temp * self.aycof;
+        let xl = mm + argpm + nodem + temp * self.xlcof * axnl;
+
+        // Solve Kepler's equation
+        let u = (xl - nodem) % TWO_PI;
+        let mut eo1 = u;
+        let (mut sineo1, mut coseo1) = (0.0, 0.0);
+        for _ in 0..10 {
+            sineo1 = eo1.sin();
+            coseo1 = eo1.cos();
+            let step =
+                (u - aynl * coseo1 + axnl * sineo1 - eo1) / (1.0 - coseo1 * axnl - sineo1 * aynl);
+            let step = step.clamp(-0.95, 0.95);
+            eo1 += step;
+            if step.abs() < 1.0e-12 {
+                break;
+            }
+        }
+
+        // Short period periodics
+        let ecose = axnl * coseo1 + aynl * sineo1;
+        let esine = axnl * sineo1 - aynl * coseo1;
+        let el2 = axnl * axnl + aynl * aynl;
+        let pl = am * (1.0 - el2);
+        if pl < 0.0 {
+            return Err(Error::Propagation);
+        }
+
+        let rl = am * (1.0 - ecose);
+        let rdotl = am.sqrt() * esine / rl;
+        let rvdotl = pl.sqrt() / rl;
+        let betal = (1.0 - el2).sqrt();
+        let temp = esine / (1.0 + betal);
+        let sinu = am / rl * (sineo1 - aynl - axnl * temp);
+        let cosu = am / rl * (coseo1 - axnl + aynl * temp);
+        let su = sinu.atan2(cosu);
+        let sin2u = (cosu + cosu) * sinu;
+        let cos2u = 1.0 - 2.0 * sinu * sinu;
+        let temp = 1.0 / pl;
+        let temp1 = 0.5 * J2 * temp;
+        let temp2 = temp1 * temp;
+
+        let (sinim, cosim) = (self.inclination.sin(), self.inclination.cos());
+        let mrt = rl * (1.0 - 1.5 * temp2 * betal * self.con41) + 0.5 * temp1 * self.x1mth2 * cos2u;
+        let su = su - 0.25 * temp2 * self.x7thm1 * sin2u;
+        let xnode = nodem + 1.5 * temp2 * cosim * sin2u;
+        let xinc = self.inclination + 1.5 * temp2 * cosim * sinim * cos2u;
+        let mvt = rdotl - nm * temp1 * self.x1mth2 * sin2u / xke;
+        let rvdot = rvdotl + nm * temp1 * (self.x1mth2 * cos2u + 1.5 * self.con41) / xke;
+
+        // The satellite has decayed
+        if mrt < 1.0 {
+            return Err(Error::Propagation);
+        }
+
+        // Orientation vectors
+        let (sinsu, cossu) = (su.sin(), su.cos());
+        let (snod, cnod) = (xnode.sin(), xnode.cos());
+        let (sini, cosi) = (xinc.sin(), xinc.cos());
+        let xmx = -snod * cosi;
+        let xmy = cnod * cosi;
+        let u = [
+            xmx * sinsu + cnod * cossu,
+            xmy * sinsu + snod * cossu,
+            sini * sinsu,
+        ];
+        let v = [
+            xmx * cossu - cnod * sinsu,
+            xmy * cossu - snod * sinsu,
+            sini * cossu,
+        ];
+
+        let velocity_scale = EARTH_RADIUS_KM * xke / 60.0;
+        let mut state = StateVector {
+            position: [0.0; 3],
+            velocity: [0.0; 3],
+        };
+        for i in 0..3 {
+            state.position[i] = mrt * u[i] * EARTH_RADIUS_KM;
+            state.velocity[i] = (mvt * u[i] + rvdot * v[i]) * velocity_scale;
+        }
+
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The verification element set from Spacetrack Report #3, as Vallado
+    /// republished it with checksums
+    const LINE1: &str = "1 88888U          80275.98708465  .00073094  13844-3  66816-4 0    87";
+    const LINE2: &str = "2 88888  72.8435 115.9689 0086731  52.6988 110.5714 16.05824518  1058";
+
+    fn satellite() -> Sgp4 {
+        Sgp4::new(
+            &TleLine1::parse(LINE1).unwrap(),
+            &TleLine2::parse(LINE2).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3], tolerance: f64) {
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            assert!(
+                (actual - expected).abs() <= tolerance,
+                "{:?} is not within {} of {:?}",
+                actual,
+                tolerance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn parse_elements() {
+        let line1 = TleLine1::parse(LINE1).unwrap();
+        assert_eq!(line1.catalog_number, 88888);
+        // 1980 day 275.98708465
+        assert!((line1.epoch - 2_444_514.48708465).abs() < 1e-8);
+        assert!((line1.bstar - 0.66816e-4).abs() < 1e-12);
+
+        let line2 = TleLine2::parse(LINE2).unwrap();
+        assert_eq!(line2.catalog_number, 88888);
+        assert_eq!(line2.inclination, 72.8435);
+        assert_eq!(line2.right_ascension, 115.9689);
+        assert_eq!(line2.eccentricity, 0.0086731);
+        assert_eq!(line2.argument_of_perigee, 52.6988);
+        assert_eq!(line2.mean_anomaly, 110.5714);
+        assert_eq!(line2.mean_motion, 16.05824518);
+    }
+
+    #[test]
+    fn exponent_fields() {
+        assert_eq!(parse_exponent(" 13844-3").unwrap(), 0.13844e-3);
+        assert_eq!(parse_exponent("-11606-4").unwrap(), -0.11606e-4);
+        assert_eq!(parse_exponent(" 00000-0").unwrap(), 0.0);
+        assert!((parse_exponent("+12345+1").unwrap() - 1.2345).abs() < 1e-12);
+        assert!(parse_exponent("  -").is_err());
+        assert!(parse_exponent(" 1x844-3").is_err());
+    }
+
+    #[test]
+    fn bad_lines() {
+        // A changed digit breaks the checksum
+        let corrupted = "1 88888U          80275.98708466  .00073094  13844-3  66816-4 0    87";
+        assert!(matches!(TleLine1::parse(corrupted), Err(Error::Checksum)));
+        // Short, or the wrong line
+        assert!(matches!(TleLine1::parse(&LINE1[..68]), Err(Error::Parse)));
+        assert!(matches!(TleLine1::parse(LINE2), Err(Error::Parse)));
+        assert!(matches!(TleLine2::parse(LINE1), Err(Error::Parse)));
+        // Trailing whitespace and line endings are fine
+        let padded = TleLine1::parse(
+            "1 88888U          80275.98708465  .00073094  13844-3  66816-4 0    87 \r\n",
+        );
+        assert!(padded.is_ok());
+    }
+
+    #[test]
+    fn lines_must_be_the_same_satellite() {
+        let other = "2 88887  72.8435 115.9689 0086731  52.6988 110.5714 16.05824518  1057";
+        assert!(matches!(
+            Sgp4::new(
+                &TleLine1::parse(LINE1).unwrap(),
+                &TleLine2::parse(other).unwrap()
+            ),
+            Err(Error::Parse)
+        ));
+    }
+
+    #[test]
+    fn deep_space_orbits_are_refused() {
+        // Two revolutions a day, like a GPS satellite
+        let high = "2 88888  72.8435 115.9689 0086731  52.6988 110.5714  2.00563170  1052";
+        assert!(matches!(
+            Sgp4::new(
+                &TleLine1::parse(LINE1).unwrap(),
+                &TleLine2::parse(high).unwrap()
+            ),
+            Err(Error::Propagation)
+        ));
+    }
+
+    #[test]
+    fn vallado_verification_vector_at_epoch() {
+        // Vallado's published state for the improved SGP4
+        let state = satellite().propagate(0.0).unwrap();
+        assert_close(
+            state.position,
+            [2328.96975262, -5995.22051338, 1719.97297192],
+            1e-6,
+        );
+        assert_close(
+            state.velocity,
+            [2.912073281, -0.983417956, -7.090816210],
+            1e-9,
+        );
+    }
+
+    #[test]
+    fn spacetrack_report_verification_vectors() {
+        // Spacetrack Report #3's results at 6 hour steps, which Vallado's
+        // corrections move by a few meters
+        let expected = [
+            (
+                0.0,
+                [2328.97048951, -5995.22076416, 1719.97067261],
+                [2.91207230, -0.98341546, -7.09081703],
+            ),
+            (
+                360.0,
+                [2456.10705566, -6071.93853760, 1222.89727783],
+                [2.67938992, -0.44829041, -7.22879231],
+            ),
+            (
+                720.0,
+                [2567.56195068, -6112.50384522, 713.96397400],
+                [2.44024599, 0.09810869, -7.31995916],
+            ),
+            (
+                1080.0,
+                [2663.09078980, -6115.48229980, 196.39640427],
+                [2.19611958, 0.65241995, -7.36282432],
+            ),
+            (
+                1440.0,
+                [2742.55133057, -6079.67144775, -326.38095856],
+                [1.94850229, 1.21106251, -7.35619372],
+            ),
+        ];
+
+        let satellite = satellite();
+        for (minutes, position, velocity) in expected.iter() {
+            let state = satellite.propagate(*minutes).unwrap();
+            assert_close(state.position, *position, 0.02);
+            assert_close(state.velocity, *velocity, 2e-5);
+        }
+    }
+
+    #[test]
+    fn propagate_to_a_time() {
+        let satellite = satellite();
+        assert_eq!(satellite.catalog_number(), 88888);
+
+        // Six hours after the epoch of 1980-10-01T23:41:24.113760
+        let datetime = DateTime::parse("1980-10-02T05:41:24.114Z").unwrap();
+        let state = satellite.state_at(&datetime).unwrap();
+        let expected = satellite.propagate(360.0).unwrap();
+        // Within the quarter millisecond the time is rounded by
+        assert_close(state.position, expected.position, 0.01);
+        assert_close(state.velocity, expected.velocity, 1e-5);
+
+        // Near-earth orbits stay about a radius out
+        for minutes in (0..10).map(|i| i as f64 * 997.0 - 3000.0) {
+            let state = satellite.propagate(minutes).unwrap();
+            let radius = state.position.iter().map(|x| x * x).sum::<f64>().sqrt();
+            assert!((6400.0..7000.0).contains(&radius), "{}", radius);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+/// Position of the sun using the NOAA solar calculator algorithm, accurate to
+/// around a minute of arc for dates between 1901 and 2099
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::datetime::DateTime;
+use crate::geo::astro::{self, Horizontal};
+use crate::geo::{Angle, GeoPoint};
+
+/// The sun's position in the sky at the given time and location
+pub fn sun_position(datetime: &DateTime, location: &GeoPoint) -> Horizontal {
+    let t = astro::julian_centuries(datetime);
+
+    let mean_longitude =
+        astro::normalize_degrees(280.466_46 + t * (36_000.769_83 + t * 0.000_303_2));
+    let mean_anomaly = 357.529_11 + t * (35_999.050_29 - 0.000_153_7 * t);
+    let eccentricity = 0.016_708_634 - t * (0.000_042_037 + 0.000_000_126_7 * t);
+
+    let m = mean_anomaly.to_radians();
+    let center = m.sin() * (1.914_602 - t * (0.004_817 + 0.000_014 * t))
+        + (2.0 * m).sin() * (0.019_993 - 0.000_101 * t)
+        + (3.0 * m).sin() * 0.000_289;
+    let true_longitude = mean_longitude + center;
+
+    // Correct for nutation and aberration
+    let omega = (125.04 - 1_934.136 * t).to_radians();
+    let apparent_longitude = true_longitude - 0.005_69 - 0.004_78 * omega.sin();
+
+    let mean_obliquity =
+        23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.000_59 - t * 0.001_813))) / 60.0) / 60.0;
+    let obliquity = (mean_obliquity + 0.002_56 * omega.cos()).to_radians();
+
+    let declination = (obliquity.sin() * apparent_longitude.to_radians().sin()).asin();
+
+    // Equation of time in minutes
+    let y = (obliquity / 2.0).tan().powi(2);
+    let l = mean_longitude.to_radians();
+    let equation_of_time = 4.0
+        * (y * (2.0 * l).sin() - 2.0 * eccentricity * m.sin()
+            + 4.0 * eccentricity * y * m.sin() * (2.0 * l).cos()
+            - 0.5 * y * y * (4.0 * l).sin()
+            - 1.25 * eccentricity * eccentricity * (2.0 * m).sin())
+        .to_degrees();
+
+    let minutes = datetime.time.seconds_of_day() / 60.0;
+    let true_solar_time = (minutes + equation_of_time + 4.0 * location.lon()) % 1440.0;
+    let true_solar_time = if true_solar_time < 0.0 {
+        true_solar_time + 1440.0
+    } else {
+        true_solar_time
+    };
+    let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+
+    let latitude = location.lat().to_radians();
+    let cos_zenith = (latitude.sin() * declination.sin()
+        + latitude.cos() * declination.cos() * hour_angle.cos())
+    .clamp(-1.0, 1.0);
+    let zenith = cos_zenith.acos();
+
+    // Measured from north, so flip around for the afternoon
+    let cos_azimuth = ((latitude.sin() * cos_zenith - declination.sin())
+        / (latitude.cos() * zenith.sin()))
+    .clamp(-1.0, 1.0);
+    let azimuth = if hour_angle > 0.0 {
+        cos_azimuth.acos().to_degrees() + 180.0
+    } else {
+        540.0 - cos_azimuth.acos().to_degrees()
+    };
+
+    Horizontal {
+        azimuth: Angle::from_degrees(astro::normalize_degrees(azimuth) as f32),
+        altitude: Angle::from_degrees(90.0 - zenith.to_degrees() as f32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sun(datetime: &str, lat: f64, lon: f64) -> (f32, f32) {
+        let position = sun_position(
+            &DateTime::parse(datetime).unwrap(),
+            &GeoPoint::new(lat, lon).unwrap(),
+        );
+        (position.azimuth.degrees(), position.altitude.degrees())
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn nrel_spa_example() {
+        // The worked example in NREL's Solar Position Algorithm report:
+        // zenith 50.11162° and azimuth 194.34024°, less the 0.0164° the
+        // report's atmosphere refracts it by
+        let (azimuth, altitude) = sun("2003-10-17T19:30:30Z", 39.742476, -105.1786);
+        assert_close(azimuth, 194.340, 0.01);
+        assert_close(altitude, 90.0 - 50.11162 - 0.0164, 0.01);
+    }
+
+    #[test]
+    fn equinox_on_the_equator() {
+        // At the March 2024 equinox the sun is on the celestial equator, so
+        // from the equator it rises due east and climbs straight up.  Here
+        // it's three hours after sunrise by the sun.
+        let (azimuth, altitude) = sun("2024-03-20T03:06:00Z", 0.0, 90.375);
+        assert_close(azimuth, 90.0, 0.01);
+        assert_close(altitude, 45.0, 0.1);
+    }
+
+    #[test]
+    fn solstice_noon() {
+        // The sun is 23.44° north of the equator at the June solstice
+        let (azimuth, altitude) = sun("2024-06-20T12:02:00Z", 51.4779, 0.0);
+        assert_close(azimuth, 180.0, 0.5);
+        assert_close(altitude, 90.0 - 51.4779 + 23.44, 0.02);
+
+        // South of the equator it's due north at noon
+        let (azimuth, altitude) = sun("2024-06-20T01:57:00Z", -33.8688, 151.2093);
+        assert!(!(0.2..359.8).contains(&azimuth), "{}", azimuth);
+        assert_close(altitude, 90.0 - 33.8688 - 23.44, 0.02);
+    }
+
+    #[test]
+    fn equation_of_time() {
+        // The almanac has the sun 16m 25s fast on 3 November 2024, so it
+        // crosses the Greenwich meridian at 11:43:35
+        let (before, _) = sun("2024-11-03T11:43:00Z", 51.4779, 0.0);
+        let (after, _) = sun("2024-11-03T11:44:00Z", 51.4779, 0.0);
+        assert!(before < 180.0 && after > 180.0, "{} {}", before, after);
+        assert_close(before, 180.0, 0.2);
+    }
+
+    #[test]
+    fn below_the_horizon_at_night() {
+        let (azimuth, altitude) = sun("2024-06-21T00:00:00Z", 51.4779, 0.0);
+        assert!(!(1.0..359.0).contains(&azimuth), "{}", azimuth);
+        assert!(altitude < -10.0);
+
+        let (_, altitude) = sun("2024-12-21T00:00:00Z", -33.8688, -151.2093);
+        assert!(altitude > 0.0, "midsummer afternoon, {}", altitude);
+    }
+}
@@ -0,0 +1,183 @@
+/// 3-dimensional vector maths
+use core::ops::{Add, Mul, Neg, Sub};
+
+use accelerometer::vector::{F32x3, I32x3};
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::Angle;
+
+/// A 3-dimensional vector.  Sensor readings use the board's axes: x toward
+/// the front, y toward the left and z up.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vector3 {
+    pub const ZERO: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn dot(&self, other: Vector3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: Vector3) -> Vector3 {
+        Vector3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn magnitude(&self) -> f32 {
+        self.dot(*self).sqrt()
+    }
+
+    /// The unit vector in the same direction, or `None` for a zero vector
+    pub fn normalized(&self) -> Option<Vector3> {
+        let magnitude = self.magnitude();
+        if magnitude > 0.0 && magnitude.is_finite() {
+            Some(*self * (1.0 / magnitude))
+        } else {
+            None
+        }
+    }
+
+    /// Angle of this vector above the horizontal, given which way is up (e.g.
+    /// an accelerometer reading at rest), or `None` if either is zero
+    pub fn elevation(&self, up: Vector3) -> Option<Angle> {
+        let sine = self.normalized()?.dot(up.normalized()?);
+        Some(Angle::from_radians(sine.clamp(-1.0, 1.0).asin()))
+    }
+}
+
+impl Add for Vector3 {
+    type Output = Vector3;
+
+    fn add(self, other: Vector3) -> Vector3 {
+        Vector3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vector3 {
+    type Output = Vector3;
+
+    fn sub(self, other: Vector3) -> Vector3 {
+        Vector3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Neg for Vector3 {
+    type Output = Vector3;
+
+    fn neg(self) -> Vector3 {
+        Vector3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<f32> for Vector3 {
+    type Output = Vector3;
+
+    fn mul(self, scale: f32) -> Vector3 {
+        Vector3::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+impl From<I32x3> for Vector3 {
+    fn from(reading: I32x3) -> Self {
+        Vector3::new(reading.x as f32, reading.y as f32, reading.z as f32)
+    }
+}
+
+impl From<F32x3> for Vector3 {
+    fn from(reading: F32x3) -> Self {
+        Vector3::new(reading.x, reading.y, reading.z)
+    }
+}
+
+impl From<Vector3> for F32x3 {
+    fn from(vector: Vector3) -> Self {
+        F32x3::new(vector.x, vector.y, vector.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).magnitude() < 1e-5
+    }
+
+    #[test]
+    fn dot_product() {
+        let a = Vector3::new(1.0, 2.0, 3.0);
+        let b = Vector3::new(-4.0, 5.0, 0.5);
+        assert_eq!(a.dot(b), 7.5);
+        assert_eq!(a.dot(b), b.dot(a));
+        assert_eq!(
+            Vector3::new(1.0, 0.0, 0.0).dot(Vector3::new(0.0, 1.0, 0.0)),
+            0.0
+        );
+        assert!((a.dot(a) - a.magnitude() * a.magnitude()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cross_product() {
+        let x = Vector3::new(1.0, 0.0, 0.0);
+        let y = Vector3::new(0.0, 1.0, 0.0);
+        let z = Vector3::new(0.0, 0.0, 1.0);
+        assert_eq!(x.cross(y), z);
+        assert_eq!(y.cross(z), x);
+        assert_eq!(z.cross(x), y);
+        assert_eq!(y.cross(x), -z);
+
+        let a = Vector3::new(1.0, 2.0, 3.0);
+        let b = Vector3::new(-4.0, 5.0, 0.5);
+        let c = a.cross(b);
+        assert_eq!(c, Vector3::new(-14.0, -12.5, 13.0));
+        // Perpendicular to both, and zero for parallel vectors
+        assert!(c.dot(a).abs() < 1e-4);
+        assert!(c.dot(b).abs() < 1e-4);
+        assert_eq!(a.cross(a * 2.0), Vector3::ZERO);
+    }
+
+    #[test]
+    fn normalisation() {
+        let v = Vector3::new(3.0, 0.0, -4.0);
+        assert_eq!(v.magnitude(), 5.0);
+        let unit = v.normalized().unwrap();
+        assert!(close(unit, Vector3::new(0.6, 0.0, -0.8)));
+        assert!((unit.magnitude() - 1.0).abs() < 1e-6);
+        assert_eq!(Vector3::ZERO.normalized(), None);
+        assert_eq!(Vector3::new(f32::INFINITY, 0.0, 0.0).normalized(), None);
+    }
+
+    #[test]
+    fn elevation_from_up() {
+        let up = Vector3::new(0.0, 0.0, 1000.0);
+        let elevation = |v: Vector3| v.elevation(up).unwrap().degrees();
+        assert!(elevation(Vector3::new(1.0, 0.0, 0.0)).abs() < 1e-4);
+        assert!((elevation(Vector3::new(1.0, 0.0, 1.0)) - 45.0).abs() < 1e-4);
+        assert!((elevation(Vector3::new(0.0, 0.0, -2.0)) + 90.0).abs() < 1e-4);
+        assert_eq!(Vector3::new(1.0, 0.0, 0.0).elevation(Vector3::ZERO), None);
+    }
+
+    #[test]
+    fn from_sensor_readings() {
+        let v = Vector3::from(I32x3::new(1, -2, 3));
+        assert_eq!(v, Vector3::new(1.0, -2.0, 3.0));
+        let f: F32x3 = v.into();
+        assert_eq!(Vector3::from(f), v);
+    }
+}
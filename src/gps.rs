@@ -0,0 +1,198 @@
+/// Struct for GPS receiver configuration and control
+use stm32f3xx_hal::gpio::{gpioc, PushPull, AF7};
+use stm32f3xx_hal::nb;
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::rcc;
+use stm32f3xx_hal::serial::{Event, Serial};
+
+use crate::datetime::{Date, DateTime, Time};
+use crate::geo::{Angle, GeoPoint};
+use crate::nmea::{self, FixQuality, LineBuffer, Sentence};
+use crate::Error;
+
+/// Baud rate of the receiver.  Nearly every NMEA receiver defaults to this.
+pub const BAUD_RATE: u32 = 9600;
+
+pub type GpsSerial = Serial<pac::USART1, (gpioc::PC4<AF7<PushPull>>, gpioc::PC5<AF7<PushPull>>)>;
+
+/// The latest known position and time from the receiver
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fix {
+    pub position: Option<GeoPoint>,
+    pub quality: FixQuality,
+    pub satellites: u8,
+    /// Altitude above mean sea level in meters
+    pub altitude: Option<f32>,
+    pub date: Option<Date>,
+    pub time: Option<Time>,
+    /// Magnetic variation the receiver reports for the position, east is
+    /// positive
+    pub magnetic_variation: Option<Angle>,
+}
+
+impl Fix {
+    /// Merge the data from a received sentence into the fix
+    pub fn update(&mut self, sentence: &Sentence) {
+        match sentence {
+            Sentence::Gga(gga) => {
+                self.quality = gga.quality;
+                self.satellites = gga.satellites;
+                self.altitude = gga.altitude;
+                self.position = if gga.quality.is_valid() {
+                    gga.position
+                } else {
+                    None
+                };
+                self.time = gga.time.or(self.time);
+            }
+            Sentence::Rmc(rmc) => {
+                if rmc.valid {
+                    self.position = rmc.position.or(self.position);
+                    self.magnetic_variation = rmc.magnetic_variation.or(self.magnetic_variation);
+                }
+                self.date = rmc.date.or(self.date);
+                self.time = rmc.time.or(self.time);
+            }
+            Sentence::Vtg(_) => {}
+        }
+    }
+
+    /// Whether the fix has a usable position
+    pub fn has_position(&self) -> bool {
+        self.quality.is_valid() && self.position.is_some()
+    }
+
+    pub fn datetime(&self) -> Option<DateTime> {
+        Some(DateTime::new(self.date?, self.time?))
+    }
+}
+
+/// The time of a valid fix if the sentence reports one.  Unlike the time
+/// kept in a `Fix`, it's only as old as the sentence.
+pub fn fix_time(sentence: &Sentence) -> Option<DateTime> {
+    match sentence {
+        Sentence::Rmc(rmc) if rmc.valid => rmc.datetime(),
+        _ => None,
+    }
+}
+
+/*
+ * Pinout:
+ *   PC4 -> USART1 TX (to receiver RX)
+ *   PC5 -> USART1 RX (from receiver TX)
+ */
+pub struct Gps {
+    serial: GpsSerial,
+    line: LineBuffer,
+}
+
+impl Gps {
+    pub fn new<Pc4Mode, Pc5Mode>(
+        pc4: gpioc::PC4<Pc4Mode>,
+        pc5: gpioc::PC5<Pc5Mode>,
+        moder: &mut gpioc::MODER,
+        otyper: &mut gpioc::OTYPER,
+        afl: &mut gpioc::AFRL,
+        usart1: pac::USART1,
+        clocks: rcc::Clocks,
+        advanced_periph_bus: &mut rcc::APB2,
+    ) -> Result<Self, Error> {
+        let tx = pc4.into_af_push_pull(moder, otyper, afl);
+        let rx = pc5.into_af_push_pull(moder, otyper, afl);
+        let mut serial = Serial::new(
+            usart1,
+            (tx, rx),
+            BAUD_RATE.Bd(),
+            clocks,
+            advanced_periph_bus,
+        );
+        serial.enable_interrupt(Event::ReceiveDataRegisterNotEmpty);
+
+        Ok(Self {
+            serial,
+            line: LineBuffer::new(),
+        })
+    }
+
+    /// Read a received byte, returning the sentence once one is complete.
+    /// Intended to be called from the USART1 interrupt.
+    pub fn read(&mut self) -> Option<Result<Sentence, Error>> {
+        match self.serial.read() {
+            Ok(byte) => self.line.push(byte).map(nmea::parse),
+            Err(nb::Error::WouldBlock) => None,
+            Err(nb::Error::Other(_)) => {
+                // Framing, noise or overrun errors corrupt the current
+                // sentence
+                self.line.clear();
+                Some(Err(Error::Hardware))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nmea::{Gga, Rmc};
+
+    fn rmc(valid: bool, variation: Option<f32>) -> Sentence {
+        Sentence::Rmc(Rmc {
+            time: None,
+            date: None,
+            valid,
+            position: None,
+            speed_knots: None,
+            course: None,
+            magnetic_variation: variation.map(Angle::from_degrees),
+        })
+    }
+
+    #[test]
+    fn fix_time_only_from_valid_rmc() {
+        let time = Time::new(12, 35, 19, 0).unwrap();
+        let date = Date::new(2024, 3, 23).unwrap();
+        let with_time = |valid| {
+            Sentence::Rmc(Rmc {
+                time: Some(time),
+                date: Some(date),
+                valid,
+                position: None,
+                speed_knots: None,
+                course: None,
+                magnetic_variation: None,
+            })
+        };
+        assert_eq!(fix_time(&with_time(true)), Some(DateTime::new(date, time)));
+        assert_eq!(fix_time(&with_time(false)), None);
+        assert_eq!(fix_time(&rmc(true, None)), None);
+
+        // Even when the fix still holds the time from before
+        let mut fix = Fix::default();
+        fix.update(&with_time(true));
+        fix.update(&with_time(false));
+        assert!(fix.datetime().is_some());
+    }
+
+    #[test]
+    fn magnetic_variation_from_valid_rmc() {
+        let mut fix = Fix::default();
+        fix.update(&rmc(false, Some(4.0)));
+        assert_eq!(fix.magnetic_variation, None);
+
+        fix.update(&rmc(true, Some(-3.1)));
+        assert_eq!(fix.magnetic_variation, Some(Angle::from_degrees(-3.1)));
+
+        // Kept through sentences without one
+        fix.update(&rmc(true, None));
+        fix.update(&Sentence::Gga(Gga {
+            time: None,
+            position: None,
+            quality: FixQuality::Invalid,
+            satellites: 0,
+            hdop: None,
+            altitude: None,
+        }));
+        assert_eq!(fix.magnetic_variation, Some(Angle::from_degrees(-3.1)));
+    }
+}
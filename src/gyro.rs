@@ -0,0 +1,142 @@
+/// Driver for the 3-axis gyroscope on the STM32F3DISCOVERY, an I3G4250D on
+/// Rev E boards and an L3GD20 before that.  Both have the same registers.
+use stm32f3xx_hal::gpio::gpioa::{PA5, PA6, PA7};
+use stm32f3xx_hal::gpio::gpioe::PE3;
+use stm32f3xx_hal::gpio::{gpioa, gpioe, Output, PushPull, AF5};
+use stm32f3xx_hal::hal::spi::MODE_3;
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::rcc;
+use stm32f3xx_hal::spi::{config, Spi};
+
+use crate::geo::Vector3;
+use crate::Error;
+
+pub type GyroSpi = Spi<pac::SPI1, (PA5<AF5<PushPull>>, PA6<AF5<PushPull>>, PA7<AF5<PushPull>>)>;
+
+pub mod register {
+    pub const WHO_AM_I: u8 = 0x0f;
+    pub const CTRL_REG1: u8 = 0x20;
+    pub const CTRL_REG4: u8 = 0x23;
+    pub const OUT_X_L: u8 = 0x28;
+}
+
+/// Address bits to read rather than write, and to step through registers
+const READ: u8 = 0x80;
+const MULTIPLE: u8 = 0x40;
+
+/// Power on with all three axes at about 100Hz, the lowest rate
+const CTRL_REG1_ON: u8 = 0x0f;
+
+/// ±250°/s full scale, plenty for a turntable
+const CTRL_REG4_250DPS: u8 = 0x00;
+
+/// Degrees per second per digit at ±250°/s
+const SENSITIVITY: f32 = 0.008_75;
+
+/// The chips the gyroscope can be, by their WHO_AM_I values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GyroChip {
+    I3g4250d,
+    L3gd20,
+}
+
+impl GyroChip {
+    pub fn from_who_am_i(value: u8) -> Result<Self, Error> {
+        match value {
+            0xd3 => Ok(GyroChip::I3g4250d),
+            0xd4 => Ok(GyroChip::L3gd20),
+            _ => Err(Error::Hardware),
+        }
+    }
+}
+
+/*
+ * Pinout:
+ *   PA5 -> SPI1 SCK
+ *   PA6 -> SPI1 MISO
+ *   PA7 -> SPI1 MOSI
+ *   PE3 -> CS (chip select, active low)
+ */
+pub struct Gyro {
+    spi: GyroSpi,
+    cs: PE3<Output<PushPull>>,
+    chip: GyroChip,
+}
+
+impl Gyro {
+    pub fn new<Pa5Mode, Pa6Mode, Pa7Mode, Pe3Mode>(
+        pa5: PA5<Pa5Mode>,
+        pa6: PA6<Pa6Mode>,
+        pa7: PA7<Pa7Mode>,
+        moder: &mut gpioa::MODER,
+        otyper: &mut gpioa::OTYPER,
+        afl: &mut gpioa::AFRL,
+        pe3: PE3<Pe3Mode>,
+        cs_moder: &mut gpioe::MODER,
+        cs_otyper: &mut gpioe::OTYPER,
+        spi1: pac::SPI1,
+        clocks: rcc::Clocks,
+        advanced_periph_bus: &mut rcc::APB2,
+    ) -> Result<Self, Error> {
+        let sck = pa5.into_af_push_pull(moder, otyper, afl);
+        let miso = pa6.into_af_push_pull(moder, otyper, afl);
+        let mosi = pa7.into_af_push_pull(moder, otyper, afl);
+        let mut cs = pe3.into_push_pull_output(cs_moder, cs_otyper);
+        cs.set_high().map_err(|_| Error::Hardware)?;
+
+        let config = config::Config::default().frequency(1.MHz()).mode(MODE_3);
+        let spi = Spi::new(spi1, (sck, miso, mosi), config, clocks, advanced_periph_bus);
+
+        let mut gyro = Self {
+            spi,
+            cs,
+            chip: GyroChip::L3gd20,
+        };
+        gyro.chip = GyroChip::from_who_am_i(gyro.read_register(register::WHO_AM_I)?)?;
+        gyro.write_register(register::CTRL_REG4, CTRL_REG4_250DPS)?;
+        gyro.write_register(register::CTRL_REG1, CTRL_REG1_ON)?;
+
+        Ok(gyro)
+    }
+
+    pub fn chip(&self) -> GyroChip {
+        self.chip
+    }
+
+    /// Rate of turn in radians per second about the board's axes,
+    /// anticlockwise positive looking down each axis.  The gyroscope's axes
+    /// are taken to line up with the board's.
+    pub fn rate(&mut self) -> Result<Vector3, Error> {
+        let mut data = [0; 7];
+        data[0] = register::OUT_X_L | READ | MULTIPLE;
+        self.transfer(&mut data)?;
+
+        let axis = |low: usize| {
+            let digits = i16::from_le_bytes([data[low], data[low + 1]]);
+            (digits as f32 * SENSITIVITY).to_radians()
+        };
+        Ok(Vector3::new(axis(1), axis(3), axis(5)))
+    }
+
+    pub fn read_register(&mut self, register: u8) -> Result<u8, Error> {
+        let mut data = [register | READ, 0];
+        self.transfer(&mut data)?;
+        Ok(data[1])
+    }
+
+    pub fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error> {
+        let mut data = [register, value];
+        self.transfer(&mut data)
+    }
+
+    /// Clock the bytes out and the replies back in over them, with the chip
+    /// selected throughout
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        self.cs.set_low().map_err(|_| Error::Hardware)?;
+        let result = self.spi.transfer(data).map(|_| ());
+        self.cs.set_high().map_err(|_| Error::Hardware)?;
+
+        result.map_err(|_| Error::Hardware)
+    }
+}
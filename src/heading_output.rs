@@ -0,0 +1,115 @@
+/// Struct for transmitting NMEA heading sentences
+use stm32f3xx_hal::gpio::{gpioa, PushPull, AF7};
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::rcc;
+use stm32f3xx_hal::serial::{Event, Serial};
+
+use crate::nmea::{self, Heading, MAX_SENTENCE_LENGTH};
+use crate::Error;
+
+/// Standard NMEA 0183 baud rate
+pub const BAUD_RATE: u32 = 4800;
+
+/// Room for a full set of HDG, HDM and HDT sentences
+const QUEUE_SIZE: usize = MAX_SENTENCE_LENGTH * 3;
+
+pub type HeadingSerial =
+    Serial<pac::USART2, (gpioa::PA2<AF7<PushPull>>, gpioa::PA3<AF7<PushPull>>)>;
+
+/*
+ * Pinout:
+ *   PA2 -> USART2 TX (NMEA out)
+ *   PA3 -> USART2 RX (unused)
+ */
+pub struct HeadingOutput {
+    serial: HeadingSerial,
+    queue: [u8; QUEUE_SIZE],
+    length: usize,
+    position: usize,
+}
+
+impl HeadingOutput {
+    pub fn new<Pa2Mode, Pa3Mode>(
+        pa2: gpioa::PA2<Pa2Mode>,
+        pa3: gpioa::PA3<Pa3Mode>,
+        moder: &mut gpioa::MODER,
+        otyper: &mut gpioa::OTYPER,
+        afl: &mut gpioa::AFRL,
+        usart2: pac::USART2,
+        clocks: rcc::Clocks,
+        advanced_periph_bus: &mut rcc::APB1,
+    ) -> Result<Self, Error> {
+        let tx = pa2.into_af_push_pull(moder, otyper, afl);
+        let rx = pa3.into_af_push_pull(moder, otyper, afl);
+        let serial = Serial::new(
+            usart2,
+            (tx, rx),
+            BAUD_RATE.Bd(),
+            clocks,
+            advanced_periph_bus,
+        );
+
+        Ok(Self {
+            serial,
+            queue: [0; QUEUE_SIZE],
+            length: 0,
+            position: 0,
+        })
+    }
+
+    /// Queue HDG, HDM and HDT sentences for the given heading.  Nothing is
+    /// queued if the previous sentences are still being sent.
+    pub fn send_heading(&mut self, heading: &Heading) -> Result<(), Error> {
+        if self.is_busy() {
+            return Err(Error::Overflow);
+        }
+
+        for sentence in [
+            nmea::format_hdg(heading)?,
+            nmea::format_hdm(heading)?,
+            nmea::format_hdt(heading)?,
+        ]
+        .iter()
+        {
+            self.queue(sentence.as_bytes())?;
+        }
+
+        self.serial
+            .enable_interrupt(Event::TransmitDataRegisterEmtpy);
+
+        Ok(())
+    }
+
+    /// Whether queued data is still being transmitted
+    pub fn is_busy(&self) -> bool {
+        self.position < self.length
+    }
+
+    fn queue(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.length + data.len();
+        if end > self.queue.len() {
+            return Err(Error::Overflow);
+        }
+        self.queue[self.length..end].copy_from_slice(data);
+        self.length = end;
+
+        Ok(())
+    }
+
+    /// Send the next queued byte.  Intended to be called from the USART2
+    /// interrupt.
+    pub fn transmit(&mut self) {
+        while self.is_busy() {
+            match self.serial.write(self.queue[self.position]) {
+                Ok(()) => self.position += 1,
+                Err(_) => return,
+            }
+        }
+
+        self.serial
+            .disable_interrupt(Event::TransmitDataRegisterEmtpy);
+        self.length = 0;
+        self.position = 0;
+    }
+}
@@ -0,0 +1,74 @@
+/// Driver for the HMC5883L magnetometer, which has the same registers as the
+/// LSM303DLHC's magnetometer
+use accelerometer::vector::I32x3;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::magnetometer::{read_registers, to_nanotesla, write_register, Magnetometer};
+use crate::Error;
+
+pub const ADDRESS: u8 = 0x1e;
+
+/// Register addresses
+pub mod register {
+    pub const CONFIG_A: u8 = 0x00;
+    pub const CONFIG_B: u8 = 0x01;
+    pub const MODE: u8 = 0x02;
+    pub const DATA_X_H: u8 = 0x03;
+    /// Identification, reading "H43"
+    pub const ID_A: u8 = 0x0a;
+}
+
+pub const ID: [u8; 3] = *b"H43";
+
+/// Average 8 samples, 15Hz
+const CONFIG_A_15HZ: u8 = 0x70;
+
+/// ±1.3 gauss full scale
+const CONFIG_B_1_3G: u8 = 0x20;
+const MODE_CONTINUOUS: u8 = 0x00;
+
+/// Digits per gauss at ±1.3 gauss
+const GAIN: i32 = 1090;
+
+pub struct Hmc5883l<I> {
+    i2c: I,
+}
+
+impl<I, E> Hmc5883l<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn is_present(i2c: &mut I) -> bool {
+        let mut id = [0; 3];
+        read_registers(i2c, ADDRESS, register::ID_A, &mut id).is_ok() && id == ID
+    }
+
+    pub fn new(mut i2c: I) -> Result<Self, Error> {
+        write_register(&mut i2c, ADDRESS, register::CONFIG_A, CONFIG_A_15HZ)?;
+        write_register(&mut i2c, ADDRESS, register::CONFIG_B, CONFIG_B_1_3G)?;
+        write_register(&mut i2c, ADDRESS, register::MODE, MODE_CONTINUOUS)?;
+
+        Ok(Self { i2c })
+    }
+
+    pub fn destroy(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I, E> Magnetometer for Hmc5883l<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    fn magnetic_field(&mut self) -> Result<I32x3, Error> {
+        let mut data = [0; 6];
+        read_registers(&mut self.i2c, ADDRESS, register::DATA_X_H, &mut data)?;
+
+        // Big endian, and in the order x, z, y
+        let axis = |high: usize| {
+            let digits = i16::from_be_bytes([data[high], data[high + 1]]) as i32;
+            to_nanotesla(digits, GAIN)
+        };
+        Ok(I32x3::new(axis(0), axis(4), axis(2)))
+    }
+}
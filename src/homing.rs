@@ -0,0 +1,444 @@
+/// Homing the turntable against an index switch, giving a repeatable
+/// mechanical reference for the step counter
+#[allow(unused_imports)]
+use num_traits::float::Float;
+use stm32f3xx_hal::gpio::gpioc::PC1;
+use stm32f3xx_hal::gpio::{gpioc, Edge, Input};
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::syscfg::SysCfg;
+
+use crate::backlash::GearPlay;
+use crate::stepper::CircularDirection;
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HomingConfig {
+    /// Whether to home at startup.  An unfitted switch reads the same as one
+    /// that isn't at the index, so this is only turned on when there is one.
+    pub at_startup: bool,
+    /// Which way to turn looking for the index
+    pub direction: CircularDirection,
+    /// Full steps per second while looking for the index
+    pub seek_rate: u32,
+    /// Full steps per second for the final approach
+    pub approach_rate: u32,
+    /// Turntable degrees to back off the index before approaching it again.
+    /// Until the steps per revolution are measured these are motor degrees.
+    pub backoff: f32,
+    /// Turntable degrees to search before giving up on finding the index,
+    /// or on backing off it, or motor degrees before the steps per
+    /// revolution are measured
+    pub max_travel: f32,
+}
+
+impl Default for HomingConfig {
+    fn default() -> Self {
+        Self {
+            at_startup: false,
+            direction: CircularDirection::CW,
+            seek_rate: 200,
+            approach_rate: 20,
+            backoff: 10.0,
+            max_travel: 400.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HomingAction {
+    /// Run the motor this way at this many full steps per second
+    Run(CircularDirection, u32),
+    /// Stop, the index is at the current position
+    Done,
+    /// Stop, the index couldn't be found
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Seeking,
+    BackingOff,
+    Approaching,
+    Done,
+    Failed,
+}
+
+/// Turns until the index triggers, backs off and re-approaches slowly so
+/// the index is always found from the same side at the same speed.  Each
+/// move first turns the motor through any play in the gears that way, so
+/// the distances are what the turntable side moves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Homing {
+    state: State,
+    /// Motor position in degrees the current state started at, from the
+    /// first update
+    from: Option<f32>,
+    /// Extra motor degrees the current state turns to take up the play
+    take_up: f32,
+}
+
+impl Default for Homing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Homing {
+    pub fn new() -> Self {
+        Self {
+            state: State::Seeking,
+            from: None,
+            take_up: 0.0,
+        }
+    }
+
+    /// Move on given the motor position in degrees and where it is in the
+    /// play, both in the same degrees as the config, whether the index has
+    /// triggered since the last update and whether the switch is at the index
+    /// now
+    pub fn update(
+        &mut self,
+        config: &HomingConfig,
+        position: f32,
+        play: &GearPlay,
+        index: bool,
+        at_index: bool,
+    ) -> HomingAction {
+        let reverse = config.direction.reverse();
+        if self.from.is_none() {
+            self.enter(State::Seeking, position, play.take_up(config.direction));
+        }
+        let travelled = (position - self.from.unwrap_or(position)).abs() - self.take_up;
+
+        match self.state {
+            // Starting at the index there's no edge to see
+            State::Seeking if index || at_index => {
+                self.enter(State::BackingOff, position, play.take_up(reverse))
+            }
+            State::Seeking if travelled > config.max_travel => {
+                self.enter(State::Failed, position, 0.0)
+            }
+            // The approach has to start from clear of the index to see it
+            // trigger
+            State::BackingOff if travelled >= config.backoff && !at_index => {
+                self.enter(State::Approaching, position, play.take_up(config.direction))
+            }
+            State::BackingOff if travelled > config.max_travel => {
+                self.enter(State::Failed, position, 0.0)
+            }
+            // Overshooting the index by more than we backed off means it's
+            // been missed
+            State::Approaching if index => self.enter(State::Done, position, 0.0),
+            State::Approaching if travelled > config.backoff * 2.0 => {
+                self.enter(State::Failed, position, 0.0)
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Seeking => HomingAction::Run(config.direction, config.seek_rate),
+            State::BackingOff => HomingAction::Run(reverse, config.seek_rate),
+            State::Approaching => HomingAction::Run(config.direction, config.approach_rate),
+            State::Done => HomingAction::Done,
+            State::Failed => HomingAction::Failed,
+        }
+    }
+
+    fn enter(&mut self, state: State, position: f32, take_up: f32) {
+        self.state = state;
+        self.from = Some(position);
+        self.take_up = take_up;
+    }
+}
+
+/*
+ * Pinout:
+ *   PC1 -> Index switch, pulled low at the index (e.g. a hall effect or
+ *          slotted optical sensor with an open collector output)
+ */
+pub struct IndexSwitch {
+    pin: PC1<Input>,
+}
+
+impl IndexSwitch {
+    pub fn new<Pc1Mode>(
+        pc1: PC1<Pc1Mode>,
+        moder: &mut gpioc::MODER,
+        pupdr: &mut gpioc::PUPDR,
+        syscfg: &mut SysCfg,
+        exti: &mut pac::EXTI,
+    ) -> Result<Self, Error> {
+        let mut pin = pc1.into_pull_up_input(moder, pupdr);
+        syscfg.select_exti_interrupt_source(&pin);
+        pin.trigger_on_edge(exti, Edge::Falling);
+        pin.enable_interrupt(exti);
+
+        Ok(Self { pin })
+    }
+
+    /// Whether the index is under the switch
+    pub fn is_active(&self) -> bool {
+        self.pin.is_low().unwrap_or(false)
+    }
+
+    /// Acknowledge the EXTI1 interrupt
+    pub fn clear_interrupt(&mut self) {
+        self.pin.clear_interrupt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: HomingConfig = HomingConfig {
+        at_startup: false,
+        direction: CircularDirection::CW,
+        seek_rate: 200,
+        approach_rate: 20,
+        backoff: 10.0,
+        max_travel: 400.0,
+    };
+
+    fn rigid() -> GearPlay {
+        GearPlay::new(0.0)
+    }
+
+    fn seek() -> HomingAction {
+        HomingAction::Run(CircularDirection::CW, CONFIG.seek_rate)
+    }
+
+    fn back_off() -> HomingAction {
+        HomingAction::Run(CircularDirection::CCW, CONFIG.seek_rate)
+    }
+
+    fn approach() -> HomingAction {
+        HomingAction::Run(CircularDirection::CW, CONFIG.approach_rate)
+    }
+
+    #[test]
+    fn finds_the_index() {
+        let mut homing = Homing::new();
+        assert_eq!(homing.update(&CONFIG, 50.0, &rigid(), false, false), seek());
+        assert_eq!(
+            homing.update(&CONFIG, 100.0, &rigid(), false, false),
+            seek()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 120.0, &rigid(), true, true),
+            back_off()
+        );
+        // Still over the index
+        assert_eq!(
+            homing.update(&CONFIG, 115.0, &rigid(), false, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 109.0, &rigid(), false, false),
+            approach()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 115.0, &rigid(), false, false),
+            approach()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 119.0, &rigid(), true, true),
+            HomingAction::Done
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 119.0, &rigid(), false, true),
+            HomingAction::Done
+        );
+    }
+
+    #[test]
+    fn starts_at_the_index() {
+        let mut homing = Homing::new();
+        assert_eq!(
+            homing.update(&CONFIG, 0.0, &rigid(), false, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -11.0, &rigid(), false, false),
+            approach()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -1.0, &rigid(), true, true),
+            HomingAction::Done
+        );
+    }
+
+    #[test]
+    fn waits_to_clear_the_index() {
+        // A wide index is still under the switch after the backoff
+        let mut homing = Homing::new();
+        assert_eq!(
+            homing.update(&CONFIG, 0.0, &rigid(), true, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -12.0, &rigid(), false, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -30.0, &rigid(), false, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -35.0, &rigid(), false, false),
+            approach()
+        );
+    }
+
+    #[test]
+    fn stuck_switch() {
+        let mut homing = Homing::new();
+        assert_eq!(
+            homing.update(&CONFIG, 0.0, &rigid(), true, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -200.0, &rigid(), false, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -401.0, &rigid(), false, true),
+            HomingAction::Failed
+        );
+    }
+
+    #[test]
+    fn no_index() {
+        let mut homing = Homing::new();
+        assert_eq!(
+            homing.update(&CONFIG, 1000.0, &rigid(), false, false),
+            seek()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 1399.0, &rigid(), false, false),
+            seek()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 1401.0, &rigid(), false, false),
+            HomingAction::Failed
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 1401.0, &rigid(), true, true),
+            HomingAction::Failed
+        );
+    }
+
+    #[test]
+    fn missed_on_the_approach() {
+        let mut homing = Homing::new();
+        assert_eq!(
+            homing.update(&CONFIG, 0.0, &rigid(), true, true),
+            back_off()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, -10.0, &rigid(), false, false),
+            approach()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 5.0, &rigid(), false, false),
+            approach()
+        );
+        assert_eq!(
+            homing.update(&CONFIG, 11.0, &rigid(), false, false),
+            HomingAction::Failed
+        );
+    }
+
+    #[test]
+    fn counterclockwise() {
+        let config = HomingConfig {
+            direction: CircularDirection::CCW,
+            ..CONFIG
+        };
+        let mut homing = Homing::new();
+        assert_eq!(
+            homing.update(&config, 0.0, &rigid(), false, false),
+            HomingAction::Run(CircularDirection::CCW, config.seek_rate)
+        );
+        assert_eq!(
+            homing.update(&config, -50.0, &rigid(), true, true),
+            HomingAction::Run(CircularDirection::CW, config.seek_rate)
+        );
+    }
+
+    /// A turntable geared down by `ratio` through `backlash` motor degrees
+    /// of play, starting pressed the other way to the seek, with an index a
+    /// degree wide at 0°
+    struct Plant {
+        play: GearPlay,
+        ratio: f32,
+        turntable: f32,
+    }
+
+    impl Plant {
+        fn new(backlash: f32, ratio: f32, turntable: f32) -> Self {
+            let mut play = GearPlay::new(backlash);
+            play.turn(-backlash);
+            Self {
+                play,
+                ratio,
+                turntable,
+            }
+        }
+
+        fn at_index(&self) -> bool {
+            (0.0..1.0).contains(&self.turntable)
+        }
+    }
+
+    /// Home against the plant, a motor degree per update, counting the
+    /// play as the step counter would.  Returns the result, where the
+    /// turntable ended up and how far it backed off the index.
+    fn home(backlash: f32, ratio: f32) -> (HomingAction, f32, f32) {
+        let mut plant = Plant::new(backlash, ratio, -5.0);
+        let mut counted = plant.play;
+        let mut position = 0.0;
+        let mut homing = Homing::new();
+        let mut was_at_index = false;
+        let mut lowest: Option<f32> = None;
+        for _ in 0..10_000 {
+            let at_index = plant.at_index();
+            let index = at_index && !was_at_index;
+            was_at_index = at_index;
+            let step = match homing.update(&CONFIG, position, &counted, index, at_index) {
+                HomingAction::Run(CircularDirection::CW, _) => 1.0,
+                HomingAction::Run(CircularDirection::CCW, _) => -1.0,
+                result => return (result, plant.turntable, -lowest.unwrap_or(0.0)),
+            };
+            position += step;
+            counted.turn(step);
+            plant.turntable += plant.play.turn(step) * plant.ratio;
+            // Backed off from the index's edge once it's found
+            if index || lowest.is_some() {
+                lowest = Some(lowest.unwrap_or(0.0).min(plant.turntable));
+            }
+        }
+
+        panic!("homing didn't finish");
+    }
+
+    #[test]
+    fn takes_up_the_play_on_each_reversal() {
+        // More play than the backoff, so the approach would be missed without
+        // the extra travel
+        for (backlash, ratio) in [(0.0, 0.1), (30.0, 0.1), (5.0, 1.0), (50.0, 0.5)] {
+            let (result, turntable, backed_off) = home(backlash, ratio);
+            assert_eq!(result, HomingAction::Done, "{} {}", backlash, ratio);
+            // Found from the seek side
+            assert!((0.0..=ratio).contains(&turntable), "{}", turntable);
+            // The whole backoff made it to the turntable
+            assert!(
+                backed_off >= (CONFIG.backoff - 1.0) * ratio,
+                "{} {}",
+                backed_off,
+                backlash
+            );
+        }
+    }
+}
@@ -4,10 +4,15 @@ use panic_semihosting as _;
 use core::iter::FusedIterator;
 use stm32f3xx_hal::gpio::gpioe::PEx;
 use stm32f3xx_hal::gpio::{gpioe, Output, PushPull};
-use switch_hal::{ActiveHigh, Switch};
+use switch_hal::{ActiveHigh, OutputSwitch, Switch};
+
+use crate::animation::Frame;
+use crate::geo::{Angle, CompassRose};
+use crate::Error;
 
 pub type Led = Switch<PEx<Output<PushPull>>, ActiveHigh>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LedId {
     Led3,  // North
     Led4,  // Northwest
@@ -19,6 +24,30 @@ pub enum LedId {
     Led10, // South
 }
 
+impl LedId {
+    /// LEDs in clockwise order starting from north.  This is the same order
+    /// LedsMutIterator yields them in.
+    pub const CLOCKWISE: [LedId; ITERATOR_SIZE] = [
+        LedId::Led3,
+        LedId::Led5,
+        LedId::Led7,
+        LedId::Led9,
+        LedId::Led10,
+        LedId::Led8,
+        LedId::Led6,
+        LedId::Led4,
+    ];
+
+    /// Find the LED closest to the sector of the rose the bearing falls in
+    pub fn from_bearing(rose: &CompassRose, bearing: Angle) -> Result<LedId, Error> {
+        let sector = rose.sector(bearing)?;
+        let step = 360.0 / ITERATOR_SIZE as f32;
+        let index = (rose.sector_center(sector).degrees() / step + 0.5) as usize % ITERATOR_SIZE;
+
+        Ok(LedId::CLOCKWISE[index])
+    }
+}
+
 // Implementation based on stm32f3-discovery crate but updated for new HAL
 // version.  Ref: https://github.com/rubberduck203/stm32f3-discovery/blob/0ec450e604420c5e05591eba71b55e58fc9ee5fb/src/leds.rs
 pub struct Leds {
@@ -69,10 +98,35 @@ impl Leds {
         }
     }
 
+    /// Return the LED for the given ID
+    pub fn get_mut(&mut self, led_id: LedId) -> &mut Led {
+        match led_id {
+            LedId::Led3 => &mut self.ld3,
+            LedId::Led4 => &mut self.ld4,
+            LedId::Led5 => &mut self.ld5,
+            LedId::Led6 => &mut self.ld6,
+            LedId::Led7 => &mut self.ld7,
+            LedId::Led8 => &mut self.ld8,
+            LedId::Led9 => &mut self.ld9,
+            LedId::Led10 => &mut self.ld10,
+        }
+    }
+
     /// Return a mutable iterator of all configured LEDs
     pub fn iter_mut(&mut self) -> LedsMutIterator {
         LedsMutIterator::new(self)
     }
+
+    /// Light the LEDs in the given frame and turn off the rest
+    pub fn show(&mut self, frame: Frame) {
+        for (index, led) in self.iter_mut().enumerate() {
+            if frame.is_on(index) {
+                led.on().ok();
+            } else {
+                led.off().ok();
+            }
+        }
+    }
 }
 
 impl<'a> IntoIterator for &'a mut Leds {
@@ -188,3 +242,74 @@ impl<'a> ExactSizeIterator for LedsMutIterator<'a> {
 
 ///Marker trait that indicates LedsMutIterator never starts returning Some after returning None
 impl<'a> FusedIterator for LedsMutIterator<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::RosePoints;
+
+    #[test]
+    fn bearings_map_clockwise() {
+        let rose = CompassRose::new(RosePoints::Eight);
+        for (i, led) in LedId::CLOCKWISE.iter().enumerate() {
+            let center = i as f32 * 45.0;
+            for bearing in [center - 22.4, center, center + 22.4] {
+                let found = LedId::from_bearing(&rose, Angle::from_degrees(bearing)).unwrap();
+                assert_eq!(found, *led, "bearing {}", bearing);
+            }
+        }
+        assert_eq!(
+            LedId::from_bearing(&rose, Angle::from_degrees(22.5)).unwrap(),
+            LedId::Led5
+        );
+    }
+
+    #[test]
+    fn coarse_and_fine_roses() {
+        let rose = CompassRose::new(RosePoints::Four);
+        let leds = [0.0, 90.0, 180.0, 270.0]
+            .map(|b| LedId::from_bearing(&rose, Angle::from_degrees(b)).unwrap());
+        assert_eq!(leds, [LedId::Led3, LedId::Led7, LedId::Led10, LedId::Led6]);
+        assert_eq!(
+            LedId::from_bearing(&rose, Angle::from_degrees(44.9)).unwrap(),
+            LedId::Led3
+        );
+
+        // Sixteen points round half-way sectors clockwise onto the next LED
+        let rose = CompassRose::new(RosePoints::Sixteen);
+        assert_eq!(
+            LedId::from_bearing(&rose, Angle::from_degrees(11.2)).unwrap(),
+            LedId::Led3
+        );
+        assert_eq!(
+            LedId::from_bearing(&rose, Angle::from_degrees(22.5)).unwrap(),
+            LedId::Led5
+        );
+        assert_eq!(
+            LedId::from_bearing(&rose, Angle::from_degrees(337.5)).unwrap(),
+            LedId::Led3
+        );
+    }
+
+    #[test]
+    fn offset_roses_wrap_to_north() {
+        let rose = CompassRose::new(RosePoints::Eight).with_offset(Angle::from_degrees(-22.5));
+        assert_eq!(
+            LedId::from_bearing(&rose, Angle::from_degrees(350.0)).unwrap(),
+            LedId::Led3
+        );
+        assert_eq!(
+            LedId::from_bearing(&rose, Angle::from_degrees(40.0)).unwrap(),
+            LedId::Led5
+        );
+    }
+
+    #[test]
+    fn invalid_bearings_are_rejected() {
+        let rose = CompassRose::new(RosePoints::Eight);
+        assert!(matches!(
+            LedId::from_bearing(&rose, Angle::from_degrees(f32::NAN)),
+            Err(Error::InvalidAngle)
+        ));
+    }
+}
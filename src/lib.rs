@@ -8,46 +8,108 @@ use cortex_m_semihosting::{heprintln, hprintln};
 use stm32f3xx_hal::pac;
 use stm32f3xx_hal::prelude::*;
 use stm32f3xx_hal::rcc;
+use stm32f3xx_hal::syscfg::SysCfgExt;
 use stm32f3xx_hal::time::rate::*;
 use switch_hal::OutputSwitch;
 
+pub mod ahrs;
+pub mod animation;
+pub mod backlash;
+pub mod button;
+pub mod cable_wrap;
+pub mod calibration;
+pub mod clock;
 pub mod compass;
+pub mod config;
+pub mod console;
+pub mod datetime;
+pub mod estimator;
 pub mod geo;
+pub mod gps;
+pub mod gyro;
+pub mod heading_output;
+pub mod hmc5883l;
+pub mod homing;
 pub mod leds;
+pub mod lis3mdl;
+pub mod lsm303dlhc;
+pub mod magnetometer;
+pub mod mmc5883;
+#[cfg(test)]
+mod mock_i2c;
+pub mod nmea;
+pub mod qmc5883l;
+pub mod stall;
 pub mod stepper;
+pub mod storage;
+pub mod target;
+pub mod tmc2209;
+pub mod tuning;
+pub mod unipolar;
 
 #[derive(Debug)]
 pub enum Error {
     Hardware,
-}
-
-pub struct RangeF32 {
-    start: f32,
-    end: f32, // exclusive
-}
-
-impl RangeF32 {
-    pub fn contains(self: &Self, n: f32) -> bool {
-        n >= self.start && n < self.end
-    }
+    /// An angle that can not be mapped (e.g. NaN or infinite)
+    InvalidAngle,
+    /// A latitude or longitude outside of its valid range
+    InvalidCoordinate,
+    /// Input text that could not be parsed
+    Parse,
+    /// An iterative calculation that failed to converge
+    NoConvergence,
+    /// A date or time with out of range fields
+    InvalidDateTime,
+    /// A sentence or message with a missing or incorrect checksum
+    Checksum,
+    /// A well formed NMEA sentence of a type we don't handle
+    UnsupportedSentence,
+    /// Data too long for the buffer it is written to
+    Overflow,
+    /// An orbit that can't be propagated (e.g. deep-space or decayed)
+    Propagation,
+    /// A setting outside of what the hardware supports
+    InvalidSetting,
+    /// A sensor that hasn't finished its first measurement yet
+    NotReady,
 }
 
 /// The struct representing the entire device. All operations and memory writes
 /// should generally be done through this struct.
 pub struct ConfiguredDevice {
     pub clocks: rcc::Clocks,
-    pub leds: leds::Leds,
-    pub compass: Option<compass::Compass>,
-    pub stepper: Option<stepper::Stepper>,
+    /// Taken by the LED animation task, so it needn't lock the board
+    pub leds: Option<leds::Leds>,
+    pub compass: Option<compass::DeviceCompass>,
+    pub gyro: Option<gyro::Gyro>,
+    pub stepper: Option<stepper::AzimuthStepper>,
+    pub elevation_stepper: Option<stepper::ElevationStepper>,
+    /// Taken by the TIM7 interrupt task, so it needn't lock the board
+    pub unipolar_stepper: Option<unipolar::UnipolarStepper>,
+    pub index_switch: Option<homing::IndexSwitch>,
+    pub button: Option<button::UserButton>,
+    /// Which of the steppers turns the turntable
+    pub azimuth_driver: stepper::DriverKind,
+    pub gps: Option<gps::Gps>,
+    pub heading_output: Option<heading_output::HeadingOutput>,
+    pub clock: Option<clock::Clock>,
+    pub console: Option<console::Console>,
+    pub storage: storage::Storage,
 }
 
 impl ConfiguredDevice {
     pub fn new(device: pac::Peripherals) -> Self {
         let mut rcc = device.RCC.constrain();
+        let mut pwr = device.PWR;
         let mut flash = device.FLASH.constrain();
         let clocks = rcc.cfgr.freeze(&mut flash.acr);
+        let mut syscfg = device.SYSCFG.constrain(&mut rcc.apb2);
+        let mut exti = device.EXTI;
 
+        let mut gpioa = device.GPIOA.split(&mut rcc.ahb);
         let mut gpiob = device.GPIOB.split(&mut rcc.ahb);
+        let mut gpioc = device.GPIOC.split(&mut rcc.ahb);
+        let mut gpiod = device.GPIOD.split(&mut rcc.ahb);
         let mut gpioe = device.GPIOE.split(&mut rcc.ahb);
         let mut gpiof = device.GPIOF.split(&mut rcc.ahb);
 
@@ -80,8 +142,70 @@ impl ConfiguredDevice {
         )
         .ok();
 
+        hprintln!("Configring external magnetometer...").ok();
+        let external = compass::external_magnetometer(
+            gpioa.pa9,
+            gpioa.pa10,
+            &mut gpioa.moder,
+            &mut gpioa.otyper,
+            &mut gpioa.afrh,
+            device.I2C2,
+            clocks,
+            &mut rcc.apb1,
+        )
+        .ok();
+        // The board's own accelerometer is still needed for the tilt
+        let compass = match (compass, external) {
+            (Some(compass), Some(external)) => Some(compass.with_external(external)),
+            (compass, _) => compass,
+        };
+
+        hprintln!("Configring gyroscope...").ok();
+        let gyro = gyro::Gyro::new(
+            gpioa.pa5,
+            gpioa.pa6,
+            gpioa.pa7,
+            &mut gpioa.moder,
+            &mut gpioa.otyper,
+            &mut gpioa.afrl,
+            gpioe.pe3,
+            &mut gpioe.moder,
+            &mut gpioe.otyper,
+            device.SPI1,
+            clocks,
+            &mut rcc.apb2,
+        )
+        .ok();
+
+        hprintln!("Configring stepper driver UART...").ok();
+        let driver = tmc2209::Tmc2209::new(
+            gpioc.pc12,
+            gpiod.pd2,
+            &mut gpioc.moder,
+            &mut gpioc.otyper,
+            &mut gpioc.afrh,
+            &mut gpiod.moder,
+            &mut gpiod.otyper,
+            &mut gpiod.afrl,
+            device.UART5,
+            clocks,
+            &mut rcc.apb1,
+        )
+        .ok();
+
+        hprintln!("Configring microstep pins...").ok();
+        let microstep_pins = stepper::MicrostepPins::new(
+            gpiod.pd8,
+            gpiod.pd9,
+            gpiod.pd10,
+            &mut gpiod.moder,
+            &mut gpiod.otyper,
+            stepper::MicrostepTable::A4988,
+        )
+        .ok();
+
         hprintln!("Configring Stepper...").ok();
-        let stepper = stepper::Stepper::new(
+        let stepper = stepper::AzimuthStepper::new(
             gpiof.pf6,
             gpiof.pf9,
             gpiof.pf10,
@@ -89,15 +213,121 @@ impl ConfiguredDevice {
             &mut gpiof.otyper,
             &mut gpiof.afrh,
             device.TIM15,
+            driver,
+            microstep_pins,
+            clocks,
+        )
+        .ok();
+
+        hprintln!("Configring elevation stepper...").ok();
+        let elevation_stepper = stepper::ElevationStepper::new(
+            gpiod.pd12,
+            gpiod.pd13,
+            gpiod.pd14,
+            &mut gpiod.moder,
+            &mut gpiod.otyper,
+            &mut gpiod.afrh,
+            device.TIM4,
+            clocks,
+        )
+        .ok();
+
+        hprintln!("Configring unipolar stepper...").ok();
+        let unipolar_stepper = unipolar::UnipolarStepper::new(
+            gpiod.pd4,
+            gpiod.pd5,
+            gpiod.pd6,
+            gpiod.pd7,
+            &mut gpiod.moder,
+            &mut gpiod.otyper,
+            device.TIM7,
             clocks,
+            &mut rcc.apb1,
         )
         .ok();
 
+        hprintln!("Configring index switch...").ok();
+        let index_switch = homing::IndexSwitch::new(
+            gpioc.pc1,
+            &mut gpioc.moder,
+            &mut gpioc.pupdr,
+            &mut syscfg,
+            &mut exti,
+        )
+        .ok();
+
+        hprintln!("Configring button...").ok();
+        let button = button::UserButton::new(
+            gpioa.pa0,
+            &mut gpioa.moder,
+            &mut gpioa.pupdr,
+            &mut syscfg,
+            &mut exti,
+        )
+        .ok();
+
+        hprintln!("Configring GPS...").ok();
+        let gps = gps::Gps::new(
+            gpioc.pc4,
+            gpioc.pc5,
+            &mut gpioc.moder,
+            &mut gpioc.otyper,
+            &mut gpioc.afrl,
+            device.USART1,
+            clocks,
+            &mut rcc.apb2,
+        )
+        .ok();
+
+        hprintln!("Configring heading output...").ok();
+        let heading_output = heading_output::HeadingOutput::new(
+            gpioa.pa2,
+            gpioa.pa3,
+            &mut gpioa.moder,
+            &mut gpioa.otyper,
+            &mut gpioa.afrl,
+            device.USART2,
+            clocks,
+            &mut rcc.apb1,
+        )
+        .ok();
+
+        hprintln!("Configring clock...").ok();
+        let clock =
+            clock::Clock::new(device.RTC, &mut rcc.apb1, &mut rcc.bdcr, &mut pwr, clocks).ok();
+
+        hprintln!("Configring console...").ok();
+        let console = console::Console::new(
+            gpioc.pc10,
+            gpioc.pc11,
+            &mut gpioc.moder,
+            &mut gpioc.otyper,
+            &mut gpioc.afrh,
+            device.USART3,
+            clocks,
+            &mut rcc.apb1,
+        )
+        .ok();
+
+        // The clocks are frozen, so the flash is free for settings
+        let storage = storage::Storage::new(flash);
+
         Self {
             clocks,
-            leds: _leds,
+            leds: Some(_leds),
             compass,
+            gyro,
             stepper,
+            elevation_stepper,
+            unipolar_stepper,
+            index_switch,
+            button,
+            azimuth_driver: stepper::DriverKind::default(),
+            gps,
+            heading_output,
+            clock,
+            console,
+            storage,
         }
     }
 
@@ -111,41 +341,25 @@ impl ConfiguredDevice {
 
     /// Turn off all configured LEDs
     pub fn leds_clear(self: &mut Self) {
-        for led in self.leds.iter_mut() {
-            led.off().ok();
+        if let Some(leds) = &mut self.leds {
+            for led in leds.iter_mut() {
+                led.off().ok();
+            }
         }
     }
 
     /// Turn the given LED on
     pub fn led_on(self: &mut Self, led_id: leds::LedId) {
-        (match led_id {
-            leds::LedId::Led3 => &mut self.leds.ld3,
-            leds::LedId::Led4 => &mut self.leds.ld4,
-            leds::LedId::Led5 => &mut self.leds.ld5,
-            leds::LedId::Led6 => &mut self.leds.ld6,
-            leds::LedId::Led7 => &mut self.leds.ld7,
-            leds::LedId::Led8 => &mut self.leds.ld8,
-            leds::LedId::Led9 => &mut self.leds.ld9,
-            leds::LedId::Led10 => &mut self.leds.ld10,
-        })
-        .on()
-        .ok();
+        if let Some(leds) = &mut self.leds {
+            leds.get_mut(led_id).on().ok();
+        }
     }
 
     /// Turn the given LED off
     pub fn led_off(self: &mut Self, led_id: leds::LedId) {
-        (match led_id {
-            leds::LedId::Led3 => &mut self.leds.ld3,
-            leds::LedId::Led4 => &mut self.leds.ld4,
-            leds::LedId::Led5 => &mut self.leds.ld5,
-            leds::LedId::Led6 => &mut self.leds.ld6,
-            leds::LedId::Led7 => &mut self.leds.ld7,
-            leds::LedId::Led8 => &mut self.leds.ld8,
-            leds::LedId::Led9 => &mut self.leds.ld9,
-            leds::LedId::Led10 => &mut self.leds.ld10,
-        })
-        .off()
-        .ok();
+        if let Some(leds) = &mut self.leds {
+            leds.get_mut(led_id).off().ok();
+        }
     }
 
     /// Flash the top LED repeatedly to indicate an error
@@ -160,7 +374,7 @@ impl ConfiguredDevice {
     }
 
     /// Read compass bearing toward north
-    pub fn bearing_north(self: &mut Self) -> f32 {
+    pub fn bearing_north(self: &mut Self) -> Result<geo::Angle, Error> {
         match &mut self.compass {
             Some(compass) => compass.bearing_north(),
             None => {
@@ -170,148 +384,156 @@ impl ConfiguredDevice {
         }
     }
 
+    /// Read the calibration kept in flash
+    pub fn load_calibration(self: &Self) -> Result<calibration::Calibration, Error> {
+        let mut words = [0; calibration::RECORD_WORDS];
+        self.storage.read(&mut words);
+        calibration::Calibration::from_words(&words)
+    }
+
+    /// Keep the calibration in flash for the next reset
+    pub fn save_calibration(
+        self: &mut Self,
+        calibration: &calibration::Calibration,
+    ) -> Result<(), Error> {
+        self.storage.write(&calibration.to_words())
+    }
+
+    /// Read the current UTC time from the clock
+    pub fn now(self: &mut Self) -> Option<datetime::DateTime> {
+        self.clock.as_mut().and_then(|clock| clock.now().ok())
+    }
+
+    /// Read the gyroscope, accelerometer and magnetometer together for
+    /// sensor fusion, if both chips are there
+    pub fn motion(self: &mut Self) -> Option<(geo::Vector3, geo::Vector3, geo::Vector3)> {
+        let gyro = self.gyro.as_mut()?.rate().ok()?;
+        let compass = self.compass.as_mut()?;
+        let accel = compass.accel_raw().ok()?;
+        let mag = compass.mag_raw().ok()?;
+
+        Some((gyro, accel.into(), mag.into()))
+    }
+
+    /// Read the device's tilt above the horizon
+    pub fn elevation(self: &mut Self) -> Option<geo::Angle> {
+        self.compass
+            .as_mut()
+            .and_then(|compass| compass.elevation().ok())
+    }
+
+    /// The stepper driving the given axis.  The unipolar stepper is its own
+    /// resource, so it's passed in.
+    pub fn stepper_mut<'a>(
+        self: &'a mut Self,
+        axis: stepper::Axis,
+        unipolar: &'a mut Option<unipolar::UnipolarStepper>,
+    ) -> Option<&'a mut dyn stepper::StepperDriver> {
+        match axis {
+            stepper::Axis::Azimuth => match self.azimuth_driver {
+                stepper::DriverKind::StepDir => self
+                    .stepper
+                    .as_mut()
+                    .map(|s| s as &mut dyn stepper::StepperDriver),
+                stepper::DriverKind::Unipolar => unipolar
+                    .as_mut()
+                    .map(|s| s as &mut dyn stepper::StepperDriver),
+            },
+            stepper::Axis::Elevation => self
+                .elevation_stepper
+                .as_mut()
+                .map(|s| s as &mut dyn stepper::StepperDriver),
+        }
+    }
+
     /// Turn on the stepper PWM signal
-    pub fn stepper_enable(self: &mut Self) {
-        match &mut self.stepper {
+    pub fn stepper_enable(
+        self: &mut Self,
+        axis: stepper::Axis,
+        unipolar: &mut Option<unipolar::UnipolarStepper>,
+    ) {
+        match self.stepper_mut(axis, unipolar) {
             Some(stepper) => {
                 stepper.enable();
             }
             None => {
-                heprintln!("stepper_enable: stepper not configured").ok();
+                heprintln!("stepper_enable: {:?} stepper not configured", axis).ok();
                 self.flash_error();
             }
         }
     }
 
     /// Turn off the stepper PWM signal
-    pub fn stepper_disable(self: &mut Self) {
-        match &mut self.stepper {
+    pub fn stepper_disable(
+        self: &mut Self,
+        axis: stepper::Axis,
+        unipolar: &mut Option<unipolar::UnipolarStepper>,
+    ) {
+        match self.stepper_mut(axis, unipolar) {
             Some(stepper) => {
                 stepper.disable();
             }
             None => {
-                heprintln!("stepper_disable: stepper not configured").ok();
+                heprintln!("stepper_disable: {:?} stepper not configured", axis).ok();
                 self.flash_error();
             }
         }
     }
 
     /// Set the driection of the stepper
-    pub fn stepper_set_direction(self: &mut Self, dir: stepper::CircularDirection) {
-        match &mut self.stepper {
+    pub fn stepper_set_direction(
+        self: &mut Self,
+        axis: stepper::Axis,
+        dir: stepper::CircularDirection,
+        unipolar: &mut Option<unipolar::UnipolarStepper>,
+    ) {
+        match self.stepper_mut(axis, unipolar) {
             Some(stepper) => {
                 stepper.set_direction(dir);
             }
             None => {
-                heprintln!("stepper_set_direction: stepper not configured").ok();
+                heprintln!("stepper_set_direction: {:?} stepper not configured", axis).ok();
                 self.flash_error();
             }
         }
     }
 
+    /// Set the step resolution of the stepper
+    pub fn stepper_set_microstepping(
+        self: &mut Self,
+        axis: stepper::Axis,
+        microstepping: stepper::Microstepping,
+        unipolar: &mut Option<unipolar::UnipolarStepper>,
+    ) -> Result<(), Error> {
+        self.stepper_mut(axis, unipolar)
+            .ok_or(Error::Hardware)?
+            .set_microstepping(microstepping)
+    }
+
+    /// Speed the stepper turns at while running, in degrees per second
+    pub fn stepper_speed(
+        self: &mut Self,
+        axis: stepper::Axis,
+        unipolar: &mut Option<unipolar::UnipolarStepper>,
+    ) -> Option<f32> {
+        self.stepper_mut(axis, unipolar)
+            .map(|stepper| stepper.speed())
+    }
+
     /// Toggle the driection of the stepper
-    pub fn stepper_toggle_direction(self: &mut Self) {
-        match &mut self.stepper {
+    pub fn stepper_toggle_direction(
+        self: &mut Self,
+        axis: stepper::Axis,
+        unipolar: &mut Option<unipolar::UnipolarStepper>,
+    ) {
+        match self.stepper_mut(axis, unipolar) {
             Some(stepper) => {
                 stepper.toggle_direction();
             }
             None => {
-                heprintln!("stepper_set_direction: stepper not configured").ok();
+                heprintln!("stepper_set_direction: {:?} stepper not configured", axis).ok();
                 self.flash_error();
             }
         }
     }
 }
-
-pub fn display_bearing(board: &mut ConfiguredDevice, bearing: f32) -> Result<(), Error> {
-    board.leds_clear();
-
-    // LED CW order start from N 3, 5, 7, 9, 10, 8, 6, 4
-    match bearing {
-        x if (RangeF32 {
-            start: -22.0,
-            end: 22.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld3.on().ok()
-        }
-        // NE
-        x if (RangeF32 {
-            start: 22.0,
-            end: 67.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld5.on().ok()
-        }
-        // E
-        x if (RangeF32 {
-            start: 67.0,
-            end: 112.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld7.on().ok()
-        }
-        // SE
-        x if (RangeF32 {
-            start: 112.0,
-            end: 157.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld9.on().ok()
-        }
-        // South
-        x if (RangeF32 {
-            start: 157.0,
-            end: 180.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld10.on().ok()
-        }
-        x if (RangeF32 {
-            start: -180.0,
-            end: -157.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld10.on().ok()
-        }
-        // SW
-        x if (RangeF32 {
-            start: -157.0,
-            end: -112.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld8.on().ok()
-        }
-        // W
-        x if (RangeF32 {
-            start: -112.0,
-            end: -67.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld6.on().ok()
-        }
-        // NW
-        x if (RangeF32 {
-            start: -67.0,
-            end: -22.0,
-        })
-        .contains(x) =>
-        {
-            board.leds.ld4.on().ok()
-        }
-        x => {
-            heprintln!("Reading {} is out of range", x).unwrap();
-            board.flash_error();
-        }
-    };
-
-    Ok(())
-}
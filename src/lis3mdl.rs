@@ -0,0 +1,89 @@
+/// Driver for the LIS3MDL magnetometer
+use accelerometer::vector::I32x3;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::magnetometer::{read_registers, to_nanotesla, write_register, Magnetometer};
+use crate::Error;
+
+/// The address with SA1 low and high
+pub const ADDRESSES: [u8; 2] = [0x1c, 0x1e];
+
+/// Register addresses
+pub mod register {
+    pub const WHO_AM_I: u8 = 0x0f;
+    pub const CTRL_REG1: u8 = 0x20;
+    pub const CTRL_REG2: u8 = 0x21;
+    pub const CTRL_REG3: u8 = 0x22;
+    pub const CTRL_REG4: u8 = 0x23;
+    pub const OUT_X_L: u8 = 0x28;
+}
+
+pub const WHO_AM_I: u8 = 0x3d;
+
+/// Address bit to step through registers
+const MULTIPLE: u8 = 0x80;
+
+/// Ultra-high performance x and y at 10Hz
+const CTRL_REG1_10HZ: u8 = 0x70;
+
+/// ±4 gauss full scale
+const CTRL_REG2_4G: u8 = 0x00;
+const CTRL_REG3_CONTINUOUS: u8 = 0x00;
+
+/// Ultra-high performance z
+const CTRL_REG4_UHP: u8 = 0x0c;
+
+/// Digits per gauss at ±4 gauss
+const GAIN: i32 = 6842;
+
+pub struct Lis3mdl<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I, E> Lis3mdl<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// The address the chip answers on, if it's there
+    pub fn find(i2c: &mut I) -> Option<u8> {
+        ADDRESSES.iter().copied().find(|&address| {
+            let mut id = [0];
+            read_registers(i2c, address, register::WHO_AM_I, &mut id).is_ok() && id[0] == WHO_AM_I
+        })
+    }
+
+    pub fn new(mut i2c: I, address: u8) -> Result<Self, Error> {
+        write_register(&mut i2c, address, register::CTRL_REG1, CTRL_REG1_10HZ)?;
+        write_register(&mut i2c, address, register::CTRL_REG2, CTRL_REG2_4G)?;
+        write_register(&mut i2c, address, register::CTRL_REG4, CTRL_REG4_UHP)?;
+        write_register(&mut i2c, address, register::CTRL_REG3, CTRL_REG3_CONTINUOUS)?;
+
+        Ok(Self { i2c, address })
+    }
+
+    pub fn destroy(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I, E> Magnetometer for Lis3mdl<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    fn magnetic_field(&mut self) -> Result<I32x3, Error> {
+        let mut data = [0; 6];
+        read_registers(
+            &mut self.i2c,
+            self.address,
+            register::OUT_X_L | MULTIPLE,
+            &mut data,
+        )?;
+
+        let axis = |low: usize| {
+            let digits = i16::from_le_bytes([data[low], data[low + 1]]) as i32;
+            to_nanotesla(digits, GAIN)
+        };
+        Ok(I32x3::new(axis(0), axis(2), axis(4)))
+    }
+}
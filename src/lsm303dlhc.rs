@@ -0,0 +1,261 @@
+/// Driver for the LSM303DLHC accelerometer and magnetometer on
+/// STM32F3DISCOVERY boards before Rev E, and detection of which of it and
+/// the LSM303AGR a board has
+use accelerometer::vector::I32x3;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::magnetometer::{read_registers, to_nanotesla, write_register, Magnetometer};
+use crate::Error;
+
+/// Both chips answer at the same addresses
+pub const ACCEL_ADDRESS: u8 = 0x19;
+pub const MAG_ADDRESS: u8 = 0x1e;
+
+/// Register addresses
+pub mod register {
+    pub const CTRL_REG1_A: u8 = 0x20;
+    pub const CTRL_REG4_A: u8 = 0x23;
+    pub const OUT_X_L_A: u8 = 0x28;
+    pub const CRA_REG_M: u8 = 0x00;
+    pub const CRB_REG_M: u8 = 0x01;
+    pub const MR_REG_M: u8 = 0x02;
+    pub const OUT_X_H_M: u8 = 0x03;
+    /// Identification, reading "H43"
+    pub const IRA_REG_M: u8 = 0x0a;
+    /// WHO_AM_I of the LSM303AGR's magnetometer, reserved on the DLHC
+    pub const AGR_WHO_AM_I_M: u8 = 0x4f;
+}
+
+pub const DLHC_ID: [u8; 3] = *b"H43";
+pub const AGR_MAG_ID: u8 = 0x40;
+
+/// Address bit to step through the accelerometer's registers.  The
+/// magnetometer always does.
+const MULTIPLE: u8 = 0x80;
+
+/// All three axes at 50Hz
+const CTRL_REG1_A_50HZ: u8 = 0x47;
+
+/// ±2g full scale at high resolution, 1mg per digit
+const CTRL_REG4_A_HR: u8 = 0x08;
+
+/// 15Hz output
+const CRA_REG_M_15HZ: u8 = 0x10;
+
+/// ±1.3 gauss full scale, plenty for the earth's field
+const CRB_REG_M_1_3G: u8 = 0x20;
+const MR_REG_M_CONTINUOUS: u8 = 0x00;
+
+/// Digits per gauss at ±1.3 gauss, less along z
+const GAIN_XY: i32 = 1100;
+const GAIN_Z: i32 = 980;
+
+/// Which compass chip the board has, which tells the revisions apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardRevision {
+    /// Rev E on, with the LSM303AGR
+    RevE,
+    /// Before Rev E, with the LSM303DLHC
+    Earlier,
+}
+
+impl BoardRevision {
+    /// Tell the chips apart by their magnetometers, the AGR's by its
+    /// WHO_AM_I and the DLHC's by its identification registers
+    pub fn detect<I, E>(i2c: &mut I) -> Result<Self, Error>
+    where
+        I: WriteRead<Error = E>,
+    {
+        let mut who_am_i = [0];
+        let agr = read_registers(i2c, MAG_ADDRESS, register::AGR_WHO_AM_I_M, &mut who_am_i);
+        if agr.is_ok() && who_am_i[0] == AGR_MAG_ID {
+            return Ok(BoardRevision::RevE);
+        }
+
+        let mut id = [0; 3];
+        read_registers(i2c, MAG_ADDRESS, register::IRA_REG_M, &mut id)?;
+        match id {
+            DLHC_ID => Ok(BoardRevision::Earlier),
+            _ => Err(Error::Hardware),
+        }
+    }
+}
+
+pub struct Lsm303dlhc<I> {
+    i2c: I,
+}
+
+impl<I, E> Lsm303dlhc<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Start both sensors measuring continuously
+    pub fn new(mut i2c: I) -> Result<Self, Error> {
+        write_register(
+            &mut i2c,
+            ACCEL_ADDRESS,
+            register::CTRL_REG4_A,
+            CTRL_REG4_A_HR,
+        )?;
+        write_register(
+            &mut i2c,
+            ACCEL_ADDRESS,
+            register::CTRL_REG1_A,
+            CTRL_REG1_A_50HZ,
+        )?;
+        write_register(&mut i2c, MAG_ADDRESS, register::CRA_REG_M, CRA_REG_M_15HZ)?;
+        write_register(&mut i2c, MAG_ADDRESS, register::CRB_REG_M, CRB_REG_M_1_3G)?;
+        write_register(
+            &mut i2c,
+            MAG_ADDRESS,
+            register::MR_REG_M,
+            MR_REG_M_CONTINUOUS,
+        )?;
+
+        Ok(Self { i2c })
+    }
+
+    /// Acceleration in mg
+    pub fn acceleration(&mut self) -> Result<I32x3, Error> {
+        let mut data = [0; 6];
+        read_registers(
+            &mut self.i2c,
+            ACCEL_ADDRESS,
+            register::OUT_X_L_A | MULTIPLE,
+            &mut data,
+        )?;
+
+        // Left justified 12 bits
+        let axis = |low: usize| (i16::from_le_bytes([data[low], data[low + 1]]) >> 4) as i32;
+        Ok(I32x3::new(axis(0), axis(2), axis(4)))
+    }
+
+    pub fn destroy(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I, E> Magnetometer for Lsm303dlhc<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    fn magnetic_field(&mut self) -> Result<I32x3, Error> {
+        let mut data = [0; 6];
+        read_registers(&mut self.i2c, MAG_ADDRESS, register::OUT_X_H_M, &mut data)?;
+
+        // Big endian, and in the order x, z, y
+        let axis = |high: usize, gain: i32| {
+            let digits = i16::from_be_bytes([data[high], data[high + 1]]) as i32;
+            to_nanotesla(digits, gain)
+        };
+        Ok(I32x3::new(
+            axis(0, GAIN_XY),
+            axis(4, GAIN_XY),
+            axis(2, GAIN_Z),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_i2c::{be, le, Bus, Tx};
+
+    #[test]
+    fn rev_e_answers_its_who_am_i() {
+        let expected = [Tx::WriteRead(0x1e, &[0x4f], &[0x40])];
+        let mut bus = Bus::new(&expected);
+        assert_eq!(
+            BoardRevision::detect(&mut bus).unwrap(),
+            BoardRevision::RevE
+        );
+        bus.done();
+    }
+
+    #[test]
+    fn earlier_boards_read_h43() {
+        // The DLHC on a Rev C board NACKs the AGR's WHO_AM_I, or reads
+        // whatever's in the reserved register
+        for who_am_i in [&[][..], &[0x00], &[0x3c]] {
+            let expected = [
+                Tx::WriteRead(0x1e, &[0x4f], who_am_i),
+                Tx::WriteRead(0x1e, &[0x0a], b"H43"),
+            ];
+            let mut bus = Bus::new(&expected);
+            assert_eq!(
+                BoardRevision::detect(&mut bus).unwrap(),
+                BoardRevision::Earlier
+            );
+            bus.done();
+        }
+    }
+
+    #[test]
+    fn no_guessing_without_an_answer() {
+        // Nothing on the bus
+        let expected = [
+            Tx::WriteRead(0x1e, &[0x4f], &[]),
+            Tx::WriteRead(0x1e, &[0x0a], &[]),
+        ];
+        let mut bus = Bus::new(&expected);
+        assert!(matches!(
+            BoardRevision::detect(&mut bus),
+            Err(Error::Hardware)
+        ));
+        bus.done();
+
+        // Something that's neither chip
+        let expected = [
+            Tx::WriteRead(0x1e, &[0x4f], &[0x00]),
+            Tx::WriteRead(0x1e, &[0x0a], b"H42"),
+        ];
+        let mut bus = Bus::new(&expected);
+        assert!(matches!(
+            BoardRevision::detect(&mut bus),
+            Err(Error::Hardware)
+        ));
+        bus.done();
+    }
+
+    #[test]
+    fn setup_and_readings() {
+        // 1g, -0.5g and 0.25g left justified in 16 bits
+        let acceleration = le([1000 << 4, -500 << 4, 250 << 4]);
+        // 1 gauss along x, -0.5 along z and 0.5 along y, in that order
+        let field = be([1100, -490, 550]);
+        let expected = [
+            Tx::Write(0x19, &[0x23, 0x08]),
+            Tx::Write(0x19, &[0x20, 0x47]),
+            Tx::Write(0x1e, &[0x00, 0x10]),
+            Tx::Write(0x1e, &[0x01, 0x20]),
+            Tx::Write(0x1e, &[0x02, 0x00]),
+            // Stepping through the accelerometer's registers
+            Tx::WriteRead(0x19, &[0xa8], &acceleration),
+            Tx::WriteRead(0x1e, &[0x03], &field),
+        ];
+        let mut bus = Bus::new(&expected);
+        let mut lsm303 = Lsm303dlhc::new(&mut bus).unwrap();
+
+        let a = lsm303.acceleration().unwrap();
+        assert_eq!((a.x, a.y, a.z), (1000, -500, 250));
+        let m = lsm303.magnetic_field().unwrap();
+        assert_eq!((m.x, m.y, m.z), (100_000, 50_000, -50_000));
+        bus.done();
+    }
+
+    #[test]
+    fn a_failed_read_is_an_error() {
+        let expected = [
+            Tx::Write(0x19, &[0x23, 0x08]),
+            Tx::Write(0x19, &[0x20, 0x47]),
+            Tx::Write(0x1e, &[0x00, 0x10]),
+            Tx::Write(0x1e, &[0x01, 0x20]),
+            Tx::Write(0x1e, &[0x02, 0x00]),
+            Tx::WriteRead(0x1e, &[0x03], &[]),
+        ];
+        let mut bus = Bus::new(&expected);
+        let mut lsm303 = Lsm303dlhc::new(&mut bus).unwrap();
+        assert!(matches!(lsm303.magnetic_field(), Err(Error::Hardware)));
+        bus.done();
+    }
+}
@@ -0,0 +1,231 @@
+/// What the compass needs from a magnetometer, whichever chip it is, and
+/// the magnetometers that can be mounted away from the board on their own
+/// I2C bus
+use accelerometer::vector::I32x3;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::hmc5883l::Hmc5883l;
+use crate::lis3mdl::Lis3mdl;
+use crate::mmc5883::Mmc5883;
+use crate::qmc5883l::Qmc5883l;
+use crate::Error;
+
+pub trait Magnetometer {
+    /// The magnetic field in nT (nanotesla) along the same axes as the
+    /// on-board accelerometer's
+    fn magnetic_field(&mut self) -> Result<I32x3, Error>;
+}
+
+/// The external magnetometers there are backends for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MagnetometerChip {
+    Qmc5883l,
+    Hmc5883l,
+    Lis3mdl,
+    Mmc5883,
+}
+
+/// A magnetometer on its own bus, e.g. up a mast away from the motor.  It
+/// has to be mounted level with its x axis toward the front of the device.
+pub enum ExternalMagnetometer<I> {
+    Qmc5883l(Qmc5883l<I>),
+    Hmc5883l(Hmc5883l<I>),
+    Lis3mdl(Lis3mdl<I>),
+    Mmc5883(Mmc5883<I>),
+}
+
+impl<I, E> ExternalMagnetometer<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Find which chip is on the bus by its identification registers and
+    /// start it measuring
+    pub fn detect(mut i2c: I) -> Result<Self, Error> {
+        if Qmc5883l::is_present(&mut i2c) {
+            Ok(ExternalMagnetometer::Qmc5883l(Qmc5883l::new(i2c)?))
+        } else if Mmc5883::is_present(&mut i2c) {
+            Ok(ExternalMagnetometer::Mmc5883(Mmc5883::new(i2c)?))
+        } else if let Some(address) = Lis3mdl::find(&mut i2c) {
+            Ok(ExternalMagnetometer::Lis3mdl(Lis3mdl::new(i2c, address)?))
+        } else if Hmc5883l::is_present(&mut i2c) {
+            Ok(ExternalMagnetometer::Hmc5883l(Hmc5883l::new(i2c)?))
+        } else {
+            Err(Error::Hardware)
+        }
+    }
+
+    pub fn chip(&self) -> MagnetometerChip {
+        match self {
+            ExternalMagnetometer::Qmc5883l(_) => MagnetometerChip::Qmc5883l,
+            ExternalMagnetometer::Hmc5883l(_) => MagnetometerChip::Hmc5883l,
+            ExternalMagnetometer::Lis3mdl(_) => MagnetometerChip::Lis3mdl,
+            ExternalMagnetometer::Mmc5883(_) => MagnetometerChip::Mmc5883,
+        }
+    }
+}
+
+impl<I, E> Magnetometer for ExternalMagnetometer<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    fn magnetic_field(&mut self) -> Result<I32x3, Error> {
+        match self {
+            ExternalMagnetometer::Qmc5883l(magnetometer) => magnetometer.magnetic_field(),
+            ExternalMagnetometer::Hmc5883l(magnetometer) => magnetometer.magnetic_field(),
+            ExternalMagnetometer::Lis3mdl(magnetometer) => magnetometer.magnetic_field(),
+            ExternalMagnetometer::Mmc5883(magnetometer) => magnetometer.magnetic_field(),
+        }
+    }
+}
+
+/// Read consecutive registers from a chip on the bus
+pub fn read_registers<I, E>(
+    i2c: &mut I,
+    address: u8,
+    register: u8,
+    data: &mut [u8],
+) -> Result<(), Error>
+where
+    I: WriteRead<Error = E>,
+{
+    i2c.write_read(address, &[register], data)
+        .map_err(|_| Error::Hardware)
+}
+
+pub fn write_register<I, E>(i2c: &mut I, address: u8, register: u8, value: u8) -> Result<(), Error>
+where
+    I: Write<Error = E>,
+{
+    i2c.write(address, &[register, value])
+        .map_err(|_| Error::Hardware)
+}
+
+/// Scale a reading in digits to nT, given the digits per gauss
+pub fn to_nanotesla(digits: i32, digits_per_gauss: i32) -> i32 {
+    (digits as i64 * 100_000 / digits_per_gauss as i64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_i2c::{be, le, Bus, Tx};
+
+    fn field(m: &mut impl Magnetometer) -> (i32, i32, i32) {
+        let f = m.magnetic_field().unwrap();
+        (f.x, f.y, f.z)
+    }
+
+    #[test]
+    fn qmc5883l() {
+        // 1 gauss x, -0.5 y, 0.25 z
+        let data = le([12000, -6000, 3000]);
+        let expected = [
+            Tx::WriteRead(0x0d, &[0x0d], &[0xff]),
+            Tx::Write(0x0d, &[0x0b, 0x01]),
+            Tx::Write(0x0d, &[0x09, 0x05]),
+            Tx::WriteRead(0x0d, &[0x00], &data),
+        ];
+        let mut bus = Bus::new(&expected);
+        let mut m = ExternalMagnetometer::detect(&mut bus).unwrap();
+        assert_eq!(m.chip(), MagnetometerChip::Qmc5883l);
+        assert_eq!(field(&mut m), (100_000, -50_000, 25_000));
+        bus.done();
+    }
+
+    #[test]
+    fn mmc5883() {
+        let mut ready = [0; 8];
+        for (chunk, digits) in ready.chunks_mut(2).zip([4096i32, -2048, 1024].iter()) {
+            chunk.copy_from_slice(&((32_768 + digits) as u16).to_le_bytes());
+        }
+        ready[7] = 0x01;
+        let expected = [
+            Tx::WriteRead(0x0d, &[0x0d], &[]),
+            Tx::WriteRead(0x30, &[0x2f], &[0x0c]),
+            // SET, then start measuring
+            Tx::Write(0x30, &[0x08, 0x08]),
+            Tx::Write(0x30, &[0x08, 0x01]),
+            Tx::WriteRead(0x30, &[0x00], &[0; 8]),
+            Tx::WriteRead(0x30, &[0x00], &ready),
+            Tx::Write(0x30, &[0x08, 0x01]),
+            Tx::WriteRead(0x30, &[0x00], &[0; 8]),
+        ];
+        let mut bus = Bus::new(&expected);
+        let mut m = ExternalMagnetometer::detect(&mut bus).unwrap();
+        assert_eq!(m.chip(), MagnetometerChip::Mmc5883);
+        // Nothing to give before the first measurement finishes
+        assert!(matches!(m.magnetic_field(), Err(Error::NotReady)));
+        assert_eq!(field(&mut m), (100_000, -50_000, 25_000));
+        // The last one while the next is measured
+        assert_eq!(field(&mut m), (100_000, -50_000, 25_000));
+        bus.done();
+    }
+
+    #[test]
+    fn lis3mdl_at_its_second_address() {
+        let data = le([6842, -3421, 0]);
+        let expected = [
+            Tx::WriteRead(0x0d, &[0x0d], &[]),
+            Tx::WriteRead(0x30, &[0x2f], &[]),
+            Tx::WriteRead(0x1c, &[0x0f], &[]),
+            Tx::WriteRead(0x1e, &[0x0f], &[0x3d]),
+            Tx::Write(0x1e, &[0x20, 0x70]),
+            Tx::Write(0x1e, &[0x21, 0x00]),
+            Tx::Write(0x1e, &[0x23, 0x0c]),
+            Tx::Write(0x1e, &[0x22, 0x00]),
+            // Auto-incrementing from OUT_X_L
+            Tx::WriteRead(0x1e, &[0xa8], &data),
+        ];
+        let mut bus = Bus::new(&expected);
+        let mut m = ExternalMagnetometer::detect(&mut bus).unwrap();
+        assert_eq!(m.chip(), MagnetometerChip::Lis3mdl);
+        assert_eq!(field(&mut m), (100_000, -50_000, 0));
+        bus.done();
+    }
+
+    #[test]
+    fn hmc5883l_sharing_the_lis3mdl_address() {
+        // Big endian x, z, y
+        let data = be([1090, -545, 545]);
+        let expected = [
+            Tx::WriteRead(0x0d, &[0x0d], &[]),
+            Tx::WriteRead(0x30, &[0x2f], &[]),
+            Tx::WriteRead(0x1c, &[0x0f], &[]),
+            Tx::WriteRead(0x1e, &[0x0f], &[0x00]),
+            Tx::WriteRead(0x1e, &[0x0a], b"H43"),
+            Tx::Write(0x1e, &[0x00, 0x70]),
+            Tx::Write(0x1e, &[0x01, 0x20]),
+            Tx::Write(0x1e, &[0x02, 0x00]),
+            Tx::WriteRead(0x1e, &[0x03], &data),
+        ];
+        let mut bus = Bus::new(&expected);
+        let mut m = ExternalMagnetometer::detect(&mut bus).unwrap();
+        assert_eq!(m.chip(), MagnetometerChip::Hmc5883l);
+        assert_eq!(field(&mut m), (100_000, 50_000, -50_000));
+        bus.done();
+    }
+
+    #[test]
+    fn nothing_on_the_bus() {
+        let expected = [
+            Tx::WriteRead(0x0d, &[0x0d], &[]),
+            Tx::WriteRead(0x30, &[0x2f], &[]),
+            Tx::WriteRead(0x1c, &[0x0f], &[]),
+            Tx::WriteRead(0x1e, &[0x0f], &[]),
+            Tx::WriteRead(0x1e, &[0x0a], &[]),
+        ];
+        let mut bus = Bus::new(&expected);
+        assert!(matches!(
+            ExternalMagnetometer::detect(&mut bus),
+            Err(Error::Hardware)
+        ));
+        bus.done();
+    }
+
+    #[test]
+    fn nanotesla() {
+        assert_eq!(to_nanotesla(1090, 1090), 100_000);
+        assert_eq!(to_nanotesla(-545, 1090), -50_000);
+        assert_eq!(to_nanotesla(0, 4096), 0);
+    }
+}
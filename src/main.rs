@@ -5,25 +5,87 @@ use panic_semihosting as _;
 
 use rtic::app;
 
-#[app(device = stm32f3xx_hal::pac, peripherals = true, dispatchers = [SPI1, SPI2, SPI3])]
+#[app(device = stm32f3xx_hal::pac, peripherals = true, dispatchers = [SPI2, SPI3, UART4_EXTI34])]
 mod app {
-    use cortex_m_semihosting::hprintln;
-    use num_traits::float::Float;
+    use cortex_m_semihosting::{heprintln, hprintln};
     use systick_monotonic::fugit::ExtU64;
     use systick_monotonic::Systick;
 
-    use orient::stepper::CircularDirection;
-    use orient::{display_bearing, ConfiguredDevice};
+    use orient::ahrs::Ahrs;
+    use orient::animation::{self, Animator, Frame, Pattern};
+    use orient::backlash::{BacklashMeasurement, MeasurementAction};
+    use orient::button::UserButton;
+    use orient::cable_wrap::{self, CableWrap};
+    use orient::calibration::{
+        DirectionDiscovery, DiscoveryAction, RevolutionAction, RevolutionMeasurement,
+        RevolutionStats,
+    };
+    use orient::config::Config;
+    use orient::console::{Command, Console};
+    use orient::datetime::DateTime;
+    use orient::estimator::HeadingEstimator;
+    use orient::geo::satellite::{self, Pass, PassSearch, SearchStatus};
+    use orient::geo::sgp4::{Sgp4, TleLine1};
+    use orient::geo::{Angle, CompassRose, RosePoints};
+    use orient::gps::{self, Fix, Gps};
+    use orient::heading_output::HeadingOutput;
+    use orient::homing::{Homing, HomingAction, HomingConfig, IndexSwitch};
+    use orient::leds::{LedId, Leds};
+    use orient::nmea::Heading;
+    use orient::stall::{StallDetector, StallStatus};
+    use orient::stepper::{self, Axis, CircularDirection, Microstepping, StepCounter};
+    use orient::target::Target;
+    use orient::tuning::{RelayTuning, TuningAction};
+    use orient::unipolar::UnipolarStepper;
+    use orient::ConfiguredDevice;
 
     #[shared]
     struct Shared {
         board: ConfiguredDevice,
-        bearing_north: f32,
+        /// Sequenced from the TIM7 interrupt, so kept out of the board
+        unipolar: Option<UnipolarStepper>,
+        animator: Animator,
+        bearing_north: Angle,
+        /// Orientation fused from the gyroscope, accelerometer and
+        /// magnetometer, when there's a gyroscope
+        ahrs: Option<Ahrs>,
+        /// Heading from the compass and the stepper's motion, for the
+        /// controller
+        estimator: HeadingEstimator,
+        cable_wrap: CableWrap,
+        elevation: Option<Angle>,
         stepper_enabled: bool,
+        fix: Fix,
+        config: Config,
+        heading_output: Option<HeadingOutput>,
+        target_azimuth: Option<Angle>,
+        target_elevation: Option<Angle>,
+        next_pass: Option<Pass>,
+        stall: StallDetector,
+        step_counter: StepCounter,
+        homing: Option<Homing>,
+        measurement: Option<BacklashMeasurement>,
+        discovery: Option<DirectionDiscovery>,
+        revolution: Option<RevolutionMeasurement>,
+        /// Steps counted for every revolution measured since startup
+        revolution_stats: RevolutionStats,
+        tuning: Option<RelayTuning>,
+        index_switch: Option<IndexSwitch>,
+        index_triggered: bool,
+        /// Compass heading when the turntable was last homed
+        index_heading: Option<Angle>,
+        console: Option<Console>,
     }
 
     #[local]
-    struct Local {}
+    struct Local {
+        gps: Option<Gps>,
+        button: Option<UserButton>,
+        leds: Option<Leds>,
+        /// Home once the startup direction discovery is done, rather than
+        /// both fighting over the turntable
+        home_after_discovery: bool,
+    }
 
     #[monotonic(binds = SysTick, default = true)]
     type MonoTimer = Systick<1_000>;
@@ -32,29 +94,131 @@ mod app {
     /// orientating for fuzzy readings or jerky movements.
     const ACCURACY_THRESHOLD: f32 = 30.0;
 
+    /// The accelerometer is far steadier than the magnetometer, so elevation
+    /// can be held more tightly
+    const ELEVATION_ACCURACY_THRESHOLD: f32 = 5.0;
+
+    /// Degrees from the target within which the steppers switch to fine
+    /// microsteps
+    const FINE_MICROSTEPPING_WITHIN: f32 = 90.0;
+
+    /// Minutes of pass search to run each time the target is updated, kept
+    /// small so the propagation doesn't hold up other tasks for long
+    const PASS_SEARCH_SAMPLES: usize = 10;
+
+    /// Milliseconds between sensor fusion updates, quick enough for the
+    /// gyroscope to follow the turntable at full speed
+    const AHRS_PERIOD_MS: u64 = 20;
+
+    /// Milliseconds between updates of the homing and measurement routines,
+    /// short enough not to overshoot by much
+    const ROUTINE_PERIOD_MS: u64 = 20;
+
+    /// Milliseconds between setting the clock from GPS time
+    const CLOCK_SYNC_PERIOD_MS: u64 = 60_000;
+
     #[init]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         hprintln!("Configuring device").ok();
 
-        let board = ConfiguredDevice::new(cx.device);
+        let mut board = ConfiguredDevice::new(cx.device);
+        // The GPS is serviced directly from its interrupt
+        let gps = board.gps.take();
+        let heading_output = board.heading_output.take();
+        let console = board.console.take();
+        let index_switch = board.index_switch.take();
+        let button = board.button.take();
+        let leds = board.leds.take();
+        let mut unipolar = board.unipolar_stepper.take();
+        let ahrs = match (&board.gyro, &board.compass) {
+            (Some(_), Some(_)) => Some(Ahrs::default()),
+            _ => None,
+        };
         let tick = Systick::new(cx.core.SYST, board.clocks.sysclk().0);
         let mono = init::Monotonics(tick);
 
         hprintln!("Device initialized").ok();
 
+        // Show the spinner until we start orienting
+        animate::spawn().unwrap();
+
         // Start update cycle for bearing
         update_bearing::spawn_after(1u64.secs()).unwrap();
+        if ahrs.is_some() {
+            update_ahrs::spawn_after(1u64.secs()).unwrap();
+        }
+
+        // Start reporting heading once there is a bearing to report
+        send_heading::spawn_after(2u64.secs()).unwrap();
+
+        // Start tracking the target
+        update_target::spawn().unwrap();
 
         // Start orienting the device after a bit of an arbitrary delay
         orientate::spawn_after(3u64.secs()).unwrap();
 
+        let mut config = Config::default();
+        match board.load_calibration() {
+            Ok(calibration) => config.calibration = calibration,
+            Err(e) => {
+                hprintln!("No stored calibration: {:?}", e).ok();
+            }
+        }
+
+        // Find which way the stepper turns the turntable if it's never been
+        // found
+        let discover = board.stepper_mut(Axis::Azimuth, &mut unipolar).is_some()
+            && config.calibration.increasing.is_none();
+        if discover {
+            start_discovery::spawn_after(3u64.secs()).unwrap();
+        }
+
+        // Find the mechanical reference first if there's a switch to find
+        let home = index_switch.is_some() && config.homing.at_startup;
+        let homing = match home {
+            true if !discover => {
+                home::spawn_after(3u64.secs()).unwrap();
+                Some(Homing::new())
+            }
+            _ => None,
+        };
+
         (
             Shared {
-                bearing_north: 0.0,
+                animator: Animator::new(Pattern::Spinner),
+                bearing_north: Angle::ZERO,
+                ahrs,
+                estimator: HeadingEstimator::new(),
+                cable_wrap: CableWrap::new(),
+                elevation: None,
                 board,
+                unipolar,
                 stepper_enabled: false,
+                fix: Fix::default(),
+                config,
+                heading_output,
+                target_azimuth: None,
+                target_elevation: None,
+                next_pass: None,
+                stall: StallDetector::new(),
+                step_counter: StepCounter::new(),
+                homing,
+                measurement: None,
+                discovery: None,
+                revolution: None,
+                revolution_stats: RevolutionStats::new(),
+                tuning: None,
+                index_switch,
+                index_triggered: false,
+                index_heading: None,
+                console,
+            },
+            Local {
+                gps,
+                button,
+                leds,
+                home_after_discovery: home && discover,
             },
-            Local {},
             mono,
         )
     }
@@ -74,30 +238,136 @@ mod app {
     /// Tasks
     ///
 
-    /// An interupt loop to orient the deivce by rotating the stepper
-    #[task(priority = 1, shared = [bearing_north, board])]
+    /// An interupt loop to orient the deivce by rotating the steppers
+    #[task(
+        priority = 1,
+        shared = [
+            animator,
+            bearing_north,
+            board,
+            unipolar,
+            cable_wrap,
+            config,
+            discovery,
+            elevation,
+            estimator,
+            fix,
+            homing,
+            measurement,
+            revolution,
+            stall,
+            stepper_enabled,
+            target_azimuth,
+            tuning,
+            target_elevation
+        ]
+    )]
     fn orientate(mut cx: orientate::Context) {
-        let mut enable = false;
-        let mut direction = CircularDirection::CW;
+        let bearing = cx.shared.bearing_north.lock(|bearing| *bearing);
+        let config = cx.shared.config.lock(|config| *config);
+        let variation = cx.shared.fix.lock(|fix| config.variation(fix));
+        let target = cx.shared.target_azimuth.lock(|target| *target);
+        let turned = cx.shared.cable_wrap.lock(|wrap| wrap.total());
 
-        cx.shared.bearing_north.lock(|bearing| {
-            // If we're of more than the threshold
-            if bearing.abs() > ACCURACY_THRESHOLD {
-                // enable the stepper
-                enable = true;
+        // Check the turntable actually turned since last time
+        let speed = match cx.shared.stepper_enabled.lock(|enabled| *enabled) {
+            true => (&mut cx.shared.board, &mut cx.shared.unipolar)
+                .lock(|b, u| {
+                    b.stepper_mut(Axis::Azimuth, u)
+                        .map(|s| (s.rate(), s.speed()))
+                })
+                .map_or(0.0, |(rate, speed)| {
+                    config.calibration.expected_speed(rate, speed)
+                }),
+            false => 0.0,
+        };
+        let now = monotonics::now().ticks();
+        let stall = cx
+            .shared
+            .stall
+            .lock(|stall| stall.update(&config.stall, now, speed, -bearing));
+        if stall == StallStatus::Stalled {
+            heprintln!("orientate: azimuth stalled, backing off").ok();
+            cx.shared
+                .animator
+                .lock(|a| a.set_pattern(Pattern::BlinkCode(animation::BLINK_STALL)));
+        }
 
-                // If we're on the "right" side of the bearing
-                if *bearing > 0.0 {
-                    // Move left (?) TODO: update when chassis is built
-                    direction = CircularDirection::CCW;
-                }
-            }
+        // Without a target, or while stalled, there's nothing to point at
+        let target = target.filter(|_| stall == StallStatus::Ok);
+        let (estimate, variance) = cx
+            .shared
+            .estimator
+            .lock(|estimator| (estimator.heading(), estimator.variance()));
+        let azimuth = target.and_then(|target| {
+            let heading = Heading {
+                sensor: estimate.unwrap_or(-bearing),
+                deviation: config.deviation,
+                variation,
+            };
+            let error = heading.true_heading().difference(target).degrees();
+            // Go the long way round rather than wind the cables up too far
+            let error = cable_wrap::rotation(turned, error, config.cable_wrap_limit);
+
+            // Only move if we're off by more than the threshold, and by
+            // more than the estimate could be off by
+            let threshold = match estimate {
+                Some(_) => ACCURACY_THRESHOLD.max(2.0 * variance.sqrt()),
+                None => ACCURACY_THRESHOLD,
+            };
+            let positive = config.calibration.positive();
+            stepper::correction(error, threshold, positive).map(|direction| {
+                (
+                    direction,
+                    Microstepping::for_error(error, FINE_MICROSTEPPING_WITHIN),
+                )
+            })
         });
 
-        if enable {
-            enable_stepper::spawn(direction).unwrap();
-        } else {
-            disable_stepper::spawn().unwrap();
+        // Homing, measuring and calibrating have the turntable to themselves
+        let busy = cx.shared.homing.lock(|homing| homing.is_some())
+            || cx
+                .shared
+                .measurement
+                .lock(|measurement| measurement.is_some())
+            || cx.shared.discovery.lock(|discovery| discovery.is_some())
+            || cx.shared.revolution.lock(|revolution| revolution.is_some())
+            || cx.shared.tuning.lock(|tuning| tuning.is_some());
+        match azimuth {
+            _ if busy => {}
+            Some((direction, microstepping)) => {
+                enable_stepper::spawn(Axis::Azimuth, direction, microstepping).unwrap()
+            }
+            None => disable_stepper::spawn(Axis::Azimuth).unwrap(),
+        }
+
+        if config.elevation_axis {
+            let elevation = cx.shared.elevation.lock(|elevation| *elevation);
+            let target = cx.shared.target_elevation.lock(|target| *target);
+
+            let correction = match (elevation, target) {
+                (Some(elevation), Some(target)) => {
+                    let error = (elevation - target).signed().degrees();
+                    // A positive error is above the target, so tilt down
+                    let positive = config.elevation_down;
+                    stepper::correction(error, ELEVATION_ACCURACY_THRESHOLD, positive).map(
+                        |direction| {
+                            (
+                                direction,
+                                Microstepping::for_error(error, FINE_MICROSTEPPING_WITHIN),
+                            )
+                        },
+                    )
+                }
+                _ => None,
+            };
+
+            match correction {
+                Some((direction, microstepping)) => {
+                    enable_stepper::spawn(Axis::Elevation, direction, microstepping).unwrap()
+                }
+                None => disable_stepper::spawn(Axis::Elevation).unwrap(),
+            }
         }
 
         // For responsiveness, keep this somewhat short without being an
@@ -105,45 +375,683 @@ mod app {
         orientate::spawn_after(250u64.millis()).unwrap();
     }
 
-    /// Enable the stepper in the given direction
-    #[task(priority = 1, shared = [board, stepper_enabled])]
-    fn enable_stepper(cx: enable_stepper::Context, direction: CircularDirection) {
+    /// Enable the stepper for an axis in the given direction
+    #[task(
+        priority = 1,
+        capacity = 2,
+        shared = [animator, board, unipolar, step_counter, stepper_enabled]
+    )]
+    fn enable_stepper(
+        cx: enable_stepper::Context,
+        axis: Axis,
+        direction: CircularDirection,
+        microstepping: Microstepping,
+    ) {
+        let mut animator = cx.shared.animator;
         let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
         let mut enabled = cx.shared.stepper_enabled;
+        let mut step_counter = cx.shared.step_counter;
 
-        board.lock(|b| {
+        let speed = (&mut board, &mut unipolar).lock(|b, u| {
             // TODO: what happens if we change direction and stepper already
             // enabled?
-            b.stepper_set_direction(direction);
-            b.stepper_enable();
-            enabled.lock(|e| *e = true);
+            b.stepper_set_direction(axis, direction, u);
+            // Not every driver's resolution can be changed
+            b.stepper_set_microstepping(axis, microstepping, u).ok();
+            b.stepper_enable(axis, u);
+            b.stepper_speed(axis, u)
         });
+
+        // The LEDs show the turntable's motion
+        if axis == Axis::Azimuth {
+            let now = monotonics::now().ticks();
+            step_counter.lock(|c| c.start(now, direction, speed.unwrap_or(0.0)));
+            enabled.lock(|e| *e = true);
+            animator.lock(|a| a.set_pattern(Pattern::Chase(direction)));
+        }
     }
 
-    /// Disable the stepper
-    #[task(priority = 1, shared = [board, stepper_enabled])]
-    fn disable_stepper(cx: disable_stepper::Context) {
+    /// Disable the stepper for an axis
+    #[task(
+        priority = 1,
+        capacity = 2,
+        shared = [animator, board, unipolar, step_counter, stepper_enabled]
+    )]
+    fn disable_stepper(cx: disable_stepper::Context, axis: Axis) {
+        let mut animator = cx.shared.animator;
         let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
         let mut enabled = cx.shared.stepper_enabled;
+        let mut step_counter = cx.shared.step_counter;
+
+        (&mut board, &mut unipolar).lock(|b, u| b.stepper_disable(axis, u));
 
-        board.lock(|b| {
-            b.stepper_disable();
+        if axis == Axis::Azimuth {
+            let now = monotonics::now().ticks();
+            step_counter.lock(|c| c.stop(now));
             enabled.lock(|e| *e = false);
+
+            // Idle until the next display update fills in the bearing,
+            // leaving any fault showing
+            animator.lock(|a| {
+                if let Pattern::Chase(_) | Pattern::Spinner = a.pattern() {
+                    a.set_pattern(Pattern::Breathing(Frame::empty()));
+                }
+            });
+        }
+    }
+
+    /// Turn the turntable to the index switch and zero the step counter there
+    #[task(
+        priority = 1,
+        shared = [
+            animator,
+            board,
+            unipolar,
+            bearing_north,
+            config,
+            homing,
+            index_heading,
+            index_switch,
+            index_triggered,
+            step_counter
+        ],
+        local = [saved_rate: Option<u32> = None]
+    )]
+    fn home(cx: home::Context) {
+        let mut animator = cx.shared.animator;
+        let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut config = cx.shared.config;
+        let mut homing = cx.shared.homing;
+        let mut index_heading = cx.shared.index_heading;
+        let mut index_switch = cx.shared.index_switch;
+        let mut index_triggered = cx.shared.index_triggered;
+        let mut step_counter = cx.shared.step_counter;
+        let saved_rate = cx.local.saved_rate;
+
+        let now = monotonics::now().ticks();
+        let (config, calibration) = config.lock(|config| (config.homing, config.calibration));
+        let index = index_triggered.lock(|triggered| core::mem::replace(triggered, false));
+        let at_index = index_switch.lock(|switch| switch.as_ref().is_some_and(|s| s.is_active()));
+
+        // Homing distances are turntable degrees once the drive train has
+        // been measured, and motor degrees until then
+        let motor_steps = (&mut board, &mut unipolar).lock(|b, u| {
+            b.stepper_mut(Axis::Azimuth, u)
+                .map(|stepper| stepper.full_steps_per_revolution())
+        });
+        let config = motor_steps.map(|steps| {
+            let motor = |degrees| calibration.motor_degrees(degrees, steps).unwrap_or(degrees);
+            HomingConfig {
+                backoff: motor(config.backoff),
+                max_travel: motor(config.max_travel),
+                ..config
+            }
+        });
+        let (position, play) = step_counter.lock(|c| (c.motor_position(now), c.play(now)));
+
+        let action = homing.lock(|homing| {
+            homing.as_mut().map(|homing| match &config {
+                Some(config) => homing.update(config, position, &play, index, at_index),
+                None => HomingAction::Failed,
+            })
+        });
+
+        match action {
+            Some(HomingAction::Run(direction, rate)) => {
+                match (&mut board, &mut unipolar)
+                    .lock(|b, u| run_azimuth(b, u, saved_rate, direction, rate))
+                {
+                    Some(speed) => {
+                        step_counter.lock(|c| c.start(now, direction, speed));
+                        animator.lock(|a| a.set_pattern(Pattern::Spinner));
+                        home::spawn_after(ROUTINE_PERIOD_MS.millis()).unwrap();
+                    }
+                    None => {
+                        heprintln!("home: azimuth stepper not configured").ok();
+                        homing.lock(|homing| *homing = None);
+                        animator.lock(|a| {
+                            a.set_pattern(Pattern::BlinkCode(animation::BLINK_HOMING_FAILED))
+                        });
+                    }
+                }
+            }
+            Some(result) => {
+                (&mut board, &mut unipolar).lock(|b, u| stop_azimuth(b, u, saved_rate));
+                step_counter.lock(|c| c.stop(now));
+
+                if result == HomingAction::Done {
+                    step_counter.lock(|c| c.zero(now));
+                    let bearing = bearing_north.lock(|bearing| *bearing);
+                    index_heading.lock(|heading| *heading = Some(-bearing));
+                    animator.lock(|a| a.set_pattern(Pattern::Breathing(Frame::empty())));
+                } else {
+                    match config {
+                        None => heprintln!("home: azimuth stepper not configured").ok(),
+                        Some(_) => heprintln!("home: index not found").ok(),
+                    };
+                    animator.lock(|a| {
+                        a.set_pattern(Pattern::BlinkCode(animation::BLINK_HOMING_FAILED))
+                    });
+                }
+                homing.lock(|homing| *homing = None);
+            }
+            None => {}
+        }
+    }
+
+    /// Measure the backlash in the azimuth gears
+    #[task(
+        priority = 1,
+        shared = [animator, board, unipolar, bearing_north, config, measurement, step_counter],
+        local = [saved_rate: Option<u32> = None]
+    )]
+    fn measure_backlash(cx: measure_backlash::Context) {
+        let mut animator = cx.shared.animator;
+        let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut config = cx.shared.config;
+        let mut measurement = cx.shared.measurement;
+        let mut step_counter = cx.shared.step_counter;
+        let saved_rate = cx.local.saved_rate;
+
+        let now = monotonics::now().ticks();
+        let heading = -bearing_north.lock(|bearing| *bearing);
+        let position = step_counter.lock(|c| c.position(now));
+
+        let action = measurement.lock(|measurement| {
+            measurement
+                .as_mut()
+                .map(|measurement| measurement.update(position, heading))
+        });
+
+        match action {
+            Some(MeasurementAction::Run(direction, rate)) => {
+                match (&mut board, &mut unipolar)
+                    .lock(|b, u| run_azimuth(b, u, saved_rate, direction, rate))
+                {
+                    Some(speed) => {
+                        step_counter.lock(|c| c.start(now, direction, speed));
+                        animator.lock(|a| a.set_pattern(Pattern::Spinner));
+                        measure_backlash::spawn_after(ROUTINE_PERIOD_MS.millis()).unwrap();
+                    }
+                    None => {
+                        heprintln!("measure_backlash: azimuth stepper not configured").ok();
+                        measurement.lock(|measurement| *measurement = None);
+                    }
+                }
+            }
+            Some(result) => {
+                (&mut board, &mut unipolar).lock(|b, u| stop_azimuth(b, u, saved_rate));
+
+                let backlash = config.lock(|config| {
+                    if let MeasurementAction::Done(backlash) = result {
+                        config.backlash = backlash;
+                    }
+                    config.backlash
+                });
+                if result == MeasurementAction::Failed {
+                    heprintln!("measure_backlash: turntable didn't move").ok();
+                }
+                step_counter.lock(|c| {
+                    c.stop(now);
+                    c.set_backlash(now, backlash);
+                });
+                measurement.lock(|measurement| *measurement = None);
+                animator.lock(|a| a.set_pattern(Pattern::Breathing(Frame::empty())));
+            }
+            None => {}
+        }
+    }
+
+    /// Start finding which way the stepper turns the turntable, unless
+    /// already
+    #[task(priority = 1, shared = [bearing_north, discovery])]
+    fn start_discovery(cx: start_discovery::Context) {
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut discovery = cx.shared.discovery;
+
+        let now = monotonics::now().ticks();
+        let heading = -bearing_north.lock(|bearing| *bearing);
+        let started = discovery.lock(|discovery| match discovery {
+            Some(_) => false,
+            None => {
+                *discovery = Some(DirectionDiscovery::new(now, heading));
+                true
+            }
+        });
+
+        if started {
+            discover::spawn().unwrap();
+        }
+    }
+
+    /// Run the stepper clockwise briefly and store which way it turned the
+    /// heading
+    #[task(
+        priority = 1,
+        shared = [
+            animator,
+            board,
+            unipolar,
+            bearing_north,
+            config,
+            discovery,
+            homing,
+            index_triggered,
+            step_counter
+        ],
+        local = [home_after_discovery, saved_rate: Option<u32> = None]
+    )]
+    fn discover(cx: discover::Context) {
+        let mut animator = cx.shared.animator;
+        let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut config = cx.shared.config;
+        let mut discovery = cx.shared.discovery;
+        let mut homing = cx.shared.homing;
+        let mut index_triggered = cx.shared.index_triggered;
+        let mut step_counter = cx.shared.step_counter;
+        let saved_rate = cx.local.saved_rate;
+
+        let now = monotonics::now().ticks();
+        let heading = -bearing_north.lock(|bearing| *bearing);
+
+        let action = discovery.lock(|discovery| {
+            discovery
+                .as_mut()
+                .map(|discovery| discovery.update(now, heading))
+        });
+
+        match action {
+            Some(DiscoveryAction::Run(direction)) => {
+                // At the normal rate, so the speed found is the usual one
+                let speed = (&mut board, &mut unipolar).lock(|b, u| {
+                    let rate = b.stepper_mut(Axis::Azimuth, u)?.rate();
+                    run_azimuth(b, u, saved_rate, direction, rate)
+                });
+                match speed {
+                    Some(speed) => {
+                        step_counter.lock(|c| c.start(now, direction, speed));
+                        animator.lock(|a| a.set_pattern(Pattern::Spinner));
+                        discover::spawn_after(ROUTINE_PERIOD_MS.millis()).unwrap();
+                    }
+                    None => {
+                        heprintln!("discover: azimuth stepper not configured").ok();
+                        discovery.lock(|discovery| *discovery = None);
+                        animator.lock(|a| {
+                            a.set_pattern(Pattern::BlinkCode(animation::BLINK_DISCOVERY_FAILED))
+                        });
+                    }
+                }
+            }
+            Some(DiscoveryAction::Stop) => {
+                (&mut board, &mut unipolar).lock(|b, u| stop_azimuth(b, u, saved_rate));
+                step_counter.lock(|c| c.stop(now));
+                discover::spawn_after(ROUTINE_PERIOD_MS.millis()).unwrap();
+            }
+            Some(result) => {
+                (&mut board, &mut unipolar).lock(|b, u| stop_azimuth(b, u, saved_rate));
+                step_counter.lock(|c| c.stop(now));
+
+                if let DiscoveryAction::Done(increasing, turn_rate) = result {
+                    let calibration = config.lock(|config| {
+                        config.calibration.increasing = Some(increasing);
+                        config.calibration.turn_rate = Some(turn_rate);
+                        config.calibration
+                    });
+                    if let Err(e) = board.lock(|b| b.save_calibration(&calibration)) {
+                        heprintln!("discover: calibration not saved: {:?}", e).ok();
+                    }
+                    animator.lock(|a| a.set_pattern(Pattern::Breathing(Frame::empty())));
+                } else {
+                    heprintln!("discover: turntable didn't turn").ok();
+                    animator.lock(|a| {
+                        a.set_pattern(Pattern::BlinkCode(animation::BLINK_DISCOVERY_FAILED))
+                    });
+                }
+                discovery.lock(|discovery| *discovery = None);
+
+                // Startup homing waited for this
+                if core::mem::replace(cx.local.home_after_discovery, false) {
+                    homing.lock(|homing| *homing = Some(Homing::new()));
+                    index_triggered.lock(|triggered| *triggered = false);
+                    home::spawn().unwrap();
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Count the steps for whole revolutions of the turntable
+    #[task(
+        priority = 1,
+        shared = [
+            animator,
+            board,
+            unipolar,
+            bearing_north,
+            config,
+            console,
+            revolution,
+            revolution_stats,
+            step_counter
+        ],
+        local = [saved_rate: Option<u32> = None]
+    )]
+    fn measure_revolution(cx: measure_revolution::Context) {
+        let mut animator = cx.shared.animator;
+        let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut config = cx.shared.config;
+        let mut console = cx.shared.console;
+        let mut revolution = cx.shared.revolution;
+        let mut stats = cx.shared.revolution_stats;
+        let mut step_counter = cx.shared.step_counter;
+        let saved_rate = cx.local.saved_rate;
+
+        let now = monotonics::now().ticks();
+        let heading = -bearing_north.lock(|bearing| *bearing);
+
+        let action = revolution.lock(|revolution| {
+            revolution
+                .as_mut()
+                .map(|revolution| revolution.update(now, heading))
+        });
+
+        match action {
+            Some(RevolutionAction::Run(direction, rate)) => {
+                match (&mut board, &mut unipolar)
+                    .lock(|b, u| run_azimuth(b, u, saved_rate, direction, rate))
+                {
+                    Some(speed) => {
+                        step_counter.lock(|c| c.start(now, direction, speed));
+                        animator.lock(|a| a.set_pattern(Pattern::Spinner));
+                        measure_revolution::spawn_after(ROUTINE_PERIOD_MS.millis()).unwrap();
+                    }
+                    None => {
+                        heprintln!("measure_revolution: azimuth stepper not configured").ok();
+                        revolution.lock(|revolution| *revolution = None);
+                    }
+                }
+            }
+            Some(RevolutionAction::Counted(steps)) => {
+                stats.lock(|stats| stats.add(steps));
+                measure_revolution::spawn_after(ROUTINE_PERIOD_MS.millis()).unwrap();
+            }
+            Some(result) => {
+                (&mut board, &mut unipolar).lock(|b, u| stop_azimuth(b, u, saved_rate));
+                step_counter.lock(|c| c.stop(now));
+                revolution.lock(|revolution| *revolution = None);
+                animator.lock(|a| a.set_pattern(Pattern::Breathing(Frame::empty())));
+
+                let steps = match result {
+                    RevolutionAction::Done(steps) => steps,
+                    _ => {
+                        heprintln!("measure_revolution: turntable stopped turning").ok();
+                        return;
+                    }
+                };
+
+                // Every revolution since startup counts toward the result
+                let stats = stats.lock(|stats| {
+                    stats.add(steps);
+                    *stats
+                });
+                let calibration = config.lock(|config| {
+                    config.calibration.steps_per_revolution = stats.mean();
+                    config.calibration
+                });
+                if let Err(e) = board.lock(|b| b.save_calibration(&calibration)) {
+                    heprintln!("measure_revolution: calibration not saved: {:?}", e).ok();
+                }
+
+                console.lock(|console| {
+                    if let Some(console) = console {
+                        console.write_line(format_args!(
+                            "steps per revolution: {:?} over {} revolutions, last {}, \
+                             std dev {:?}, range {:?}",
+                            stats.mean(),
+                            stats.count(),
+                            steps,
+                            stats.std_dev(),
+                            stats.range()
+                        ));
+                    }
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Oscillate the turntable around the target to tune the controller
+    #[task(
+        priority = 1,
+        shared = [
+            animator,
+            board,
+            unipolar,
+            bearing_north,
+            config,
+            console,
+            fix,
+            step_counter,
+            tuning
+        ],
+        local = [saved_rate: Option<u32> = None]
+    )]
+    fn tune(cx: tune::Context) {
+        let mut animator = cx.shared.animator;
+        let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut config = cx.shared.config;
+        let mut console = cx.shared.console;
+        let mut fix = cx.shared.fix;
+        let mut step_counter = cx.shared.step_counter;
+        let mut tuning = cx.shared.tuning;
+        let saved_rate = cx.local.saved_rate;
+
+        let now = monotonics::now().ticks();
+        let bearing = bearing_north.lock(|bearing| *bearing);
+        let fix = fix.lock(|fix| *fix);
+        let heading = config.lock(|config| Heading {
+            sensor: -bearing,
+            deviation: config.deviation,
+            variation: config.variation(&fix),
+        });
+
+        let action = tuning.lock(|tuning| {
+            tuning
+                .as_mut()
+                .map(|tuning| tuning.update(now, heading.true_heading()))
+        });
+
+        match action {
+            Some(TuningAction::Run(direction, rate)) => {
+                match (&mut board, &mut unipolar)
+                    .lock(|b, u| run_azimuth(b, u, saved_rate, direction, rate))
+                {
+                    Some(speed) => {
+                        step_counter.lock(|c| c.start(now, direction, speed));
+                        animator.lock(|a| a.set_pattern(Pattern::Spinner));
+                        tune::spawn_after(ROUTINE_PERIOD_MS.millis()).unwrap();
+                    }
+                    None => {
+                        heprintln!("tune: azimuth stepper not configured").ok();
+                        tuning.lock(|tuning| *tuning = None);
+                    }
+                }
+            }
+            Some(result) => {
+                (&mut board, &mut unipolar).lock(|b, u| stop_azimuth(b, u, saved_rate));
+                step_counter.lock(|c| c.stop(now));
+                tuning.lock(|tuning| *tuning = None);
+                animator.lock(|a| a.set_pattern(Pattern::Breathing(Frame::empty())));
+
+                let gains = match result {
+                    TuningAction::Done(gains) => gains,
+                    _ => {
+                        heprintln!("tune: no steady oscillation").ok();
+                        return;
+                    }
+                };
+                let calibration = config.lock(|config| {
+                    config.calibration.gains = Some(gains);
+                    config.calibration
+                });
+                if let Err(e) = board.lock(|b| b.save_calibration(&calibration)) {
+                    heprintln!("tune: calibration not saved: {:?}", e).ok();
+                }
+
+                console.lock(|console| {
+                    if let Some(console) = console {
+                        console.write_line(format_args!("gains: {:?}", gains));
+                    }
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Find the stepper's direction again when the user button is pressed
+    #[task(binds = EXTI0, priority = 3, local = [button])]
+    fn button(cx: button::Context) {
+        if let Some(button) = cx.local.button {
+            if button.pressed(monotonics::now().ticks()) {
+                // A press while it's already queued is the same request
+                start_discovery::spawn().ok();
+            }
+        }
+    }
+
+    /// Note the index switch triggering for the homing task
+    #[task(binds = EXTI1, priority = 3, shared = [index_switch, index_triggered])]
+    fn index_switch(mut cx: index_switch::Context) {
+        cx.shared.index_switch.lock(|switch| {
+            if let Some(switch) = switch {
+                switch.clear_interrupt();
+            }
         });
+        cx.shared
+            .index_triggered
+            .lock(|triggered| *triggered = true);
     }
 
-    /// Update the bearing toward north from the compass
-    #[task(priority = 2, shared = [bearing_north, board])]
+    /// Fold the latest gyroscope, accelerometer and magnetometer readings
+    /// into the fused orientation
+    #[task(priority = 2, shared = [ahrs, board], local = [last: Option<u64> = None])]
+    fn update_ahrs(cx: update_ahrs::Context) {
+        let mut ahrs = cx.shared.ahrs;
+        let mut board = cx.shared.board;
+
+        let last = cx.local.last;
+
+        // A missed reading is made up for by a longer step next time
+        if let Some((gyro, accel, mag)) = board.lock(|b| b.motion()) {
+            let now = monotonics::now().ticks();
+            let dt = last.map_or(0.0, |last| (now - last) as f32 / 1000.0);
+            *last = Some(now);
+
+            ahrs.lock(|ahrs| {
+                if let Some(ahrs) = ahrs {
+                    ahrs.update(gyro, accel, mag, dt);
+                }
+            });
+        }
+
+        update_ahrs::spawn_after(AHRS_PERIOD_MS.millis()).unwrap();
+    }
+
+    /// Update the bearing toward north and the tilt from the compass, using
+    /// the fused orientation for the bearing when there is one, and fold the
+    /// bearing into the heading estimate
+    #[task(
+        priority = 2,
+        shared = [
+            ahrs,
+            bearing_north,
+            board,
+            cable_wrap,
+            config,
+            elevation,
+            estimator,
+            step_counter,
+            unipolar
+        ],
+        local = [last: Option<u64> = None]
+    )]
     fn update_bearing(cx: update_bearing::Context) {
+        let mut ahrs = cx.shared.ahrs;
         let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
         let mut bearing_north = cx.shared.bearing_north;
+        let mut cable_wrap = cx.shared.cable_wrap;
+        let mut config = cx.shared.config;
+        let mut elevation = cx.shared.elevation;
+        let mut estimator = cx.shared.estimator;
+        let mut step_counter = cx.shared.step_counter;
 
-        board.lock(|b| {
-            let new_bearing = b.bearing_north();
+        let fused = ahrs.lock(|ahrs| {
+            ahrs.filter(|ahrs| ahrs.is_started())
+                .map(|ahrs| ahrs.bearing_north())
+        });
+
+        let new_bearing = board.lock(|b| {
+            let new_bearing = match fused {
+                Some(bearing) => bearing,
+                None => b.bearing_north().ok()?,
+            };
             bearing_north.lock(|bearing| *bearing = new_bearing);
+            // Often enough to follow the turntable at full speed
+            cable_wrap.lock(|wrap| wrap.update(-new_bearing));
+
+            let new_elevation = b.elevation();
+            elevation.lock(|elevation| *elevation = new_elevation);
 
             // Update the LED directionals
             update_display::spawn(new_bearing).unwrap();
+            Some(new_bearing)
+        });
+        // Skip a sample the magnetometer couldn't give us, e.g. before its
+        // first measurement has finished
+        let new_bearing = match new_bearing {
+            Some(bearing) => bearing,
+            None => {
+                update_bearing::spawn_after(100u64.millis()).unwrap();
+                return;
+            }
+        };
+
+        // Move the estimate on by what the stepper's been doing since the
+        // last bearing, then correct it with this one
+        let now = monotonics::now().ticks();
+        let dt = cx
+            .local
+            .last
+            .replace(now)
+            .map_or(0.0, |last| (now - last) as f32 / 1000.0);
+        let velocity = step_counter.lock(|c| c.velocity());
+        let config = config.lock(|config| *config);
+        let moving = velocity != 0.0;
+        let rate = match moving {
+            true => (&mut board, &mut unipolar)
+                .lock(|b, u| {
+                    b.stepper_mut(Axis::Azimuth, u)
+                        .map(|stepper| stepper.full_steps_per_revolution())
+                })
+                .and_then(|steps| config.calibration.heading_rate(velocity, steps)),
+            false => Some(0.0),
+        };
+        estimator.lock(|estimator| {
+            estimator.predict(&config.estimator, rate, dt);
+            estimator.update(&config.estimator, -new_bearing, moving);
         });
 
         // Generally, we probably want this to update at least as often as the
@@ -152,10 +1060,616 @@ mod app {
     }
 
     /// Update the LED display to show the given bearing
-    #[task(priority = 2, shared = [bearing_north, board])]
-    fn update_display(mut cx: update_display::Context, bearing: f32) {
-        cx.shared.board.lock(|board| {
-            display_bearing(board, bearing).unwrap();
+    #[task(priority = 2, shared = [animator])]
+    fn update_display(mut cx: update_display::Context, bearing: Angle) {
+        let rose = CompassRose::new(RosePoints::Eight);
+
+        cx.shared.animator.lock(|animator| {
+            // Startup and motion patterns take precedence over the bearing
+            if !animator.is_idle() {
+                return;
+            }
+
+            match LedId::from_bearing(&rose, bearing) {
+                Ok(led_id) => animator.set_pattern(Pattern::Breathing(Frame::from(led_id))),
+                Err(e) => {
+                    heprintln!("Unable to display bearing {}: {:?}", bearing, e).ok();
+                    animator.set_pattern(Pattern::BlinkCode(animation::BLINK_INVALID_BEARING));
+                }
+            }
+        });
+    }
+
+    /// Render the next frame of the current LED animation
+    #[task(priority = 1, local = [leds], shared = [animator])]
+    fn animate(mut cx: animate::Context) {
+        let frame = cx.shared.animator.lock(|a| a.next_frame());
+        if let Some(leds) = cx.local.leds {
+            leds.show(frame);
+        }
+
+        animate::spawn_after(animation::FRAME_PERIOD_MS.millis()).unwrap();
+    }
+
+    /// Receive NMEA data from the GPS and publish any new fix
+    #[task(binds = USART1_EXTI25, priority = 3, local = [gps], shared = [fix])]
+    fn gps_receive(mut cx: gps_receive::Context) {
+        let gps = match cx.local.gps {
+            Some(gps) => gps,
+            None => return,
+        };
+
+        // Bad sentences are dropped here.  Printing over semihosting would
+        // block long enough to overrun the receiver.
+        if let Some(Ok(sentence)) = gps.read() {
+            cx.shared.fix.lock(|fix| fix.update(&sentence));
+            if let Some(now) = gps::fix_time(&sentence) {
+                sync_clock::spawn(now).ok();
+            }
+        }
+    }
+
+    /// Send the current heading as NMEA sentences
+    #[task(priority = 1, shared = [bearing_north, config, fix, heading_output])]
+    fn send_heading(cx: send_heading::Context) {
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut config = cx.shared.config;
+        let mut fix = cx.shared.fix;
+        let mut heading_output = cx.shared.heading_output;
+
+        let bearing = bearing_north.lock(|bearing| *bearing);
+        let config = config.lock(|config| *config);
+        let fix = fix.lock(|fix| *fix);
+
+        if config.heading_output_period_ms > 0 {
+            let heading = Heading {
+                // We know where north is relative to the front of the device,
+                // so the device's heading is the opposite rotation
+                sensor: -bearing,
+                deviation: config.deviation,
+                variation: config.variation(&fix),
+            };
+
+            heading_output.lock(|output| {
+                if let Some(output) = output {
+                    if let Err(e) = output.send_heading(&heading) {
+                        heprintln!("send_heading: {:?}", e).ok();
+                    }
+                }
+            });
+        }
+
+        // Check again in a bit if output is disabled in case it's re-enabled
+        let period = match config.heading_output_period_ms {
+            0 => 1000,
+            period => period,
+        };
+        send_heading::spawn_after((period as u64).millis()).unwrap();
+    }
+
+    /// Feed queued heading sentences to the USART
+    #[task(binds = USART2_EXTI26, priority = 3, shared = [heading_output])]
+    fn heading_transmit(mut cx: heading_transmit::Context) {
+        cx.shared.heading_output.lock(|output| {
+            if let Some(output) = output {
+                output.transmit();
+            }
+        });
+    }
+
+    /// Find where the current target is
+    #[task(
+        priority = 1,
+        shared = [board, config, fix, target_azimuth, target_elevation, next_pass],
+        local = [tracked: Option<Sgp4> = None, pass_search: Option<PassSearch> = None]
+    )]
+    fn update_target(cx: update_target::Context) {
+        let mut board = cx.shared.board;
+        let mut config = cx.shared.config;
+        let mut fix = cx.shared.fix;
+        let mut target_azimuth = cx.shared.target_azimuth;
+        let mut target_elevation = cx.shared.target_elevation;
+        let mut next_pass = cx.shared.next_pass;
+        let tracked = cx.local.tracked;
+        let pass_search = cx.local.pass_search;
+
+        let now = board.lock(|b| b.now());
+        let config = config.lock(|config| *config);
+        let fix = fix.lock(|fix| *fix);
+
+        // Prefer the GPS position when we have one
+        let location = if fix.has_position() {
+            fix.position
+        } else {
+            config.location
+        };
+        let mut azimuth = config.target.azimuth(now, location);
+        let mut position = config.target.position(now, location);
+
+        if let (Target::Satellite, Some(satellite), Some(now), Some(location)) =
+            (config.target, config.satellite, now, location)
+        {
+            // Forget the last prediction when the satellite changes
+            if *tracked != Some(satellite) {
+                *tracked = Some(satellite);
+                *pass_search = None;
+                next_pass.lock(|pass| *pass = None);
+            }
+
+            // Look for the next pass once the last one is over
+            let pass = next_pass.lock(|pass| *pass);
+            if pass_search.is_none() && pass.is_none_or(|pass| pass.set < now) {
+                *pass_search = Some(PassSearch::new(&now));
+            }
+
+            if let Some(search) = pass_search {
+                match search.step(&satellite, &location, PASS_SEARCH_SAMPLES) {
+                    Ok(SearchStatus::Searching) => {}
+                    Ok(SearchStatus::Found(pass)) => {
+                        next_pass.lock(|next| *next = Some(pass));
+                        *pass_search = None;
+                    }
+                    Ok(SearchStatus::NotFound) => {
+                        next_pass.lock(|next| *next = None);
+                        *pass_search = None;
+                    }
+                    Err(e) => {
+                        heprintln!("update_target: {:?}", e).ok();
+                        *pass_search = None;
+                    }
+                }
+            }
+
+            // Wait where the satellite will rise while it's below the horizon
+            position = satellite::look_angles(&satellite, &now, &location).ok();
+            azimuth = match position {
+                Some(position) if position.is_above_horizon() => Some(position.azimuth),
+                _ => next_pass
+                    .lock(|pass| *pass)
+                    .map(|pass| pass.rise_azimuth)
+                    .or(position.map(|position| position.azimuth)),
+            };
+        } else {
+            *tracked = None;
+        }
+
+        // Wait level while the target is below the horizon
+        let elevation = position.map(|position| {
+            if position.is_above_horizon() {
+                position.altitude
+            } else {
+                Angle::ZERO
+            }
+        });
+
+        target_azimuth.lock(|target| *target = azimuth);
+        target_elevation.lock(|target| *target = elevation);
+
+        // The sun moves about a quarter of a degree a minute, so this is plenty
+        update_target::spawn_after(1u64.secs()).unwrap();
+    }
+
+    /// Sequence the unipolar stepper's coils
+    #[task(binds = TIM7, priority = 3, shared = [unipolar])]
+    fn unipolar_step(mut cx: unipolar_step::Context) {
+        cx.shared.unipolar.lock(|unipolar| {
+            if let Some(stepper) = unipolar {
+                stepper.step();
+            }
+        });
+    }
+
+    /// Set the clock from the time of a valid fix as it arrives, at most
+    /// once a minute
+    #[task(priority = 1, shared = [board], local = [last: Option<u64> = None])]
+    fn sync_clock(mut cx: sync_clock::Context, now: DateTime) {
+        let ticks = monotonics::now().ticks();
+        if let Some(last) = *cx.local.last {
+            if ticks.wrapping_sub(last) < CLOCK_SYNC_PERIOD_MS {
+                return;
+            }
+        }
+
+        *cx.local.last = Some(ticks);
+        cx.shared.board.lock(|b| {
+            if let Some(clock) = &mut b.clock {
+                if let Err(e) = clock.set(&now) {
+                    heprintln!("sync_clock: {:?}", e).ok();
+                }
+            }
         });
     }
+
+    /// Receive console input and run any complete commands
+    #[task(binds = USART3_EXTI28, priority = 2, shared = [console])]
+    fn console_receive(mut cx: console_receive::Context) {
+        let received = cx
+            .shared
+            .console
+            .lock(|console| console.as_mut().and_then(|c| c.read()));
+
+        match received {
+            // Lines can come in faster than commands run, e.g. pasting both
+            // lines of a TLE while a pass search is running
+            Some(Ok(command)) => match handle_command::spawn(command) {
+                Ok(()) => {}
+                Err(_) => cx.shared.console.lock(|console| {
+                    if let Some(console) = console {
+                        console.write_line(format_args!("error: busy"));
+                    }
+                }),
+            },
+            Some(Err(e)) => cx.shared.console.lock(|console| {
+                if let Some(console) = console {
+                    console.write_line(format_args!("error: {:?}", e));
+                }
+            }),
+            None => {}
+        }
+    }
+
+    /// Run a command entered on the console
+    #[task(
+        priority = 1,
+        capacity = 4,
+        shared = [
+            board,
+            unipolar,
+            bearing_north,
+            cable_wrap,
+            config,
+            console,
+            estimator,
+            fix,
+            homing,
+            index_heading,
+            index_triggered,
+            measurement,
+            next_pass,
+            revolution,
+            revolution_stats,
+            stall,
+            step_counter,
+            target_azimuth,
+            tuning
+        ],
+        local = [tle_line1: Option<TleLine1> = None]
+    )]
+    fn handle_command(cx: handle_command::Context, command: Command) {
+        let mut board = cx.shared.board;
+        let mut unipolar = cx.shared.unipolar;
+        let mut config = cx.shared.config;
+        let mut console = cx.shared.console;
+        let mut estimator = cx.shared.estimator;
+        let mut fix = cx.shared.fix;
+        let mut target_azimuth = cx.shared.target_azimuth;
+        let mut next_pass = cx.shared.next_pass;
+        let mut stall = cx.shared.stall;
+        let mut cable_wrap = cx.shared.cable_wrap;
+        let mut homing = cx.shared.homing;
+        let mut measurement = cx.shared.measurement;
+        let mut index_heading = cx.shared.index_heading;
+        let mut index_triggered = cx.shared.index_triggered;
+        let mut step_counter = cx.shared.step_counter;
+        let mut bearing_north = cx.shared.bearing_north;
+        let mut revolution = cx.shared.revolution;
+        let mut revolution_stats = cx.shared.revolution_stats;
+        let mut tuning = cx.shared.tuning;
+        let tle_line1 = cx.local.tle_line1;
+
+        let result = match command {
+            Command::SetTime(now) => board.lock(|b| match &mut b.clock {
+                Some(clock) => clock.set(&now),
+                None => Err(orient::Error::Hardware),
+            }),
+            Command::SetLocation(location) => {
+                config.lock(|config| config.location = Some(location));
+                Ok(())
+            }
+            Command::SetTarget(target) => {
+                config.lock(|config| config.target = target);
+                // Setting a target again retries after a stall fault
+                stall.lock(|stall| stall.reset());
+                Ok(())
+            }
+            Command::TleLine1(line1) => {
+                *tle_line1 = Some(line1);
+                Ok(())
+            }
+            Command::TleLine2(line2) => match tle_line1.take() {
+                Some(line1) => Sgp4::new(&line1, &line2).map(|satellite| {
+                    config.lock(|config| {
+                        config.satellite = Some(satellite);
+                        config.target = Target::Satellite;
+                    })
+                }),
+                None => Err(orient::Error::Parse),
+            },
+            Command::SetDeclination(declination) => {
+                config.lock(|config| config.declination = declination);
+                Ok(())
+            }
+            Command::SetDeviation(deviation) => {
+                config.lock(|config| config.deviation = deviation);
+                Ok(())
+            }
+            Command::SetHeadingOutputPeriod(period) => {
+                config.lock(|config| config.heading_output_period_ms = period);
+                Ok(())
+            }
+            Command::SetElevationDown(direction) => {
+                config.lock(|config| config.elevation_down = direction);
+                Ok(())
+            }
+            Command::SetElevationAxis(enabled) => {
+                config.lock(|config| config.elevation_axis = enabled);
+                if !enabled {
+                    disable_stepper::spawn(Axis::Elevation).ok();
+                }
+                Ok(())
+            }
+            Command::SetAzimuthDriver(driver) => {
+                (&mut board, &mut unipolar).lock(|b, u| {
+                    b.stepper_disable(Axis::Azimuth, u);
+                    b.azimuth_driver = driver;
+                });
+                stall.lock(|stall| stall.reset());
+                Ok(())
+            }
+            Command::SetBacklash(backlash) => {
+                config.lock(|config| config.backlash = backlash);
+                let now = monotonics::now().ticks();
+                step_counter.lock(|c| c.set_backlash(now, backlash));
+                Ok(())
+            }
+            Command::MeasureBacklash => {
+                // The measurement needs the raw motor travel
+                let now = monotonics::now().ticks();
+                let position = step_counter.lock(|c| {
+                    c.set_backlash(now, 0.0);
+                    c.position(now)
+                });
+                let started = measurement.lock(|measurement| match measurement {
+                    Some(_) => false,
+                    None => {
+                        *measurement = Some(BacklashMeasurement::new(position));
+                        true
+                    }
+                });
+
+                if started {
+                    measure_backlash::spawn().unwrap();
+                }
+                Ok(())
+            }
+            Command::Home => {
+                let started = homing.lock(|homing| match homing {
+                    Some(_) => false,
+                    None => {
+                        *homing = Some(Homing::new());
+                        true
+                    }
+                });
+
+                // Don't run two homing loops at once
+                if started {
+                    index_triggered.lock(|triggered| *triggered = false);
+                    home::spawn().unwrap();
+                }
+                Ok(())
+            }
+            Command::CalibrateDirection => {
+                start_discovery::spawn().ok();
+                Ok(())
+            }
+            Command::CalibrateSteps(revolutions) => {
+                // Turn the way that unwinds the cables
+                let positive = config.lock(|config| config.calibration.positive());
+                let direction = match cable_wrap.lock(|wrap| wrap.total()) > 0.0 {
+                    true => positive.reverse(),
+                    false => positive,
+                };
+                let now = monotonics::now().ticks();
+                let heading = -bearing_north.lock(|bearing| *bearing);
+                let started = revolution.lock(|revolution| match revolution {
+                    Some(_) => false,
+                    None => {
+                        *revolution = Some(RevolutionMeasurement::new(
+                            now,
+                            heading,
+                            direction,
+                            revolutions,
+                        ));
+                        true
+                    }
+                });
+
+                if started {
+                    measure_revolution::spawn().unwrap();
+                }
+                Ok(())
+            }
+            Command::Tune => {
+                // Around the target, or where it's pointing without one
+                let now = monotonics::now().ticks();
+                let bearing = bearing_north.lock(|bearing| *bearing);
+                let fix = fix.lock(|fix| *fix);
+                let (heading, positive) = config.lock(|config| {
+                    let heading = Heading {
+                        sensor: -bearing,
+                        deviation: config.deviation,
+                        variation: config.variation(&fix),
+                    };
+                    (heading.true_heading(), config.calibration.positive())
+                });
+                let setpoint = target_azimuth.lock(|target| target.unwrap_or(heading));
+                let started = tuning.lock(|tuning| match tuning {
+                    Some(_) => false,
+                    None => {
+                        *tuning = Some(RelayTuning::new(now, setpoint, positive));
+                        true
+                    }
+                });
+
+                if started {
+                    tune::spawn().unwrap();
+                }
+                Ok(())
+            }
+            Command::Status => {
+                let now = board.lock(|b| b.now());
+                let config = config.lock(|config| *config);
+                let fix = fix.lock(|fix| *fix);
+                let azimuth = target_azimuth.lock(|target| *target);
+                let pass = next_pass.lock(|pass| *pass);
+                let stall = stall.lock(|stall| *stall);
+                let revolution_stats = revolution_stats.lock(|stats| *stats);
+                let turned = cable_wrap.lock(|wrap| wrap.total());
+                let position = step_counter.lock(|c| c.position(monotonics::now().ticks()));
+                let motor_steps = (&mut board, &mut unipolar).lock(|b, u| {
+                    b.stepper_mut(Axis::Azimuth, u)
+                        .map(|stepper| stepper.full_steps_per_revolution())
+                });
+                let index_heading = index_heading.lock(|heading| *heading);
+                let estimate =
+                    estimator.lock(|estimator| (estimator.heading(), estimator.variance().sqrt()));
+                let revision = board.lock(|b| b.compass.as_ref().map(|c| c.revision()));
+                let external = board.lock(|b| {
+                    b.compass
+                        .as_ref()
+                        .and_then(|c| c.external())
+                        .map(|magnetometer| magnetometer.chip())
+                });
+                let gyro = board.lock(|b| b.gyro.as_ref().map(|gyro| gyro.chip()));
+                let driver = board.lock(|b| {
+                    b.stepper
+                        .as_mut()
+                        .filter(|stepper| stepper.driver.is_some())
+                        .map(|stepper| (stepper.driver_status(), stepper.stallguard()))
+                });
+
+                console.lock(|console| {
+                    if let Some(console) = console {
+                        console.write_line(format_args!("time: {:?}", now));
+                        console.write_line(format_args!("location: {:?}", config.location));
+                        console.write_line(format_args!("fix: {:?}", fix));
+                        console.write_line(format_args!(
+                            "target: {:?} at {:?}",
+                            config.target, azimuth
+                        ));
+                        if let (Target::Satellite, Some(satellite)) =
+                            (config.target, config.satellite)
+                        {
+                            console.write_line(format_args!(
+                                "satellite: {} next pass: {:?}",
+                                satellite.catalog_number(),
+                                pass
+                            ));
+                        }
+                        console.write_line(format_args!(
+                            "declination: {:?} (set {:?}, gps {:?}) deviation: {:?}",
+                            config.variation(&fix),
+                            config.declination,
+                            fix.magnetic_variation,
+                            config.deviation
+                        ));
+                        console.write_line(format_args!(
+                            "heading output every {} ms",
+                            config.heading_output_period_ms
+                        ));
+                        console.write_line(format_args!("board: {:?}", revision));
+                        console.write_line(format_args!("external magnetometer: {:?}", external));
+                        console.write_line(format_args!("gyro: {:?}", gyro));
+                        console.write_line(format_args!(
+                            "heading estimate: {:?} std dev {}",
+                            estimate.0, estimate.1
+                        ));
+                        console.write_line(format_args!("turned: {}", turned));
+                        console.write_line(format_args!("backlash: {}", config.backlash));
+                        console.write_line(format_args!(
+                            "heading increases: {:?} at {:?} deg/s",
+                            config.calibration.increasing, config.calibration.turn_rate
+                        ));
+                        console.write_line(format_args!(
+                            "steps per revolution: {:?} std dev {:?} over {} revolutions",
+                            config.calibration.steps_per_revolution,
+                            revolution_stats.std_dev(),
+                            revolution_stats.count()
+                        ));
+                        console.write_line(format_args!("gains: {:?}", config.calibration.gains));
+                        if let Some(heading) = index_heading {
+                            // Of the turntable once its steps per revolution
+                            // are known, otherwise of the motor
+                            match motor_steps.and_then(|steps| {
+                                config.calibration.turntable_degrees(position, steps)
+                            }) {
+                                Some(degrees) => console.write_line(format_args!(
+                                    "home: turntable {} from index, heading at index {}",
+                                    degrees, heading
+                                )),
+                                None => console.write_line(format_args!(
+                                    "home: motor {} from index, heading at index {}",
+                                    position, heading
+                                )),
+                            }
+                        }
+                        if stall.is_fault() {
+                            console.write_line(format_args!("stall: fault"));
+                        } else if stall.retries() > 0 {
+                            console.write_line(format_args!("stall: retry {}", stall.retries()));
+                        }
+                        if let Some((status, stallguard)) = driver {
+                            console.write_line(format_args!(
+                                "driver: {:?} stallguard: {:?}",
+                                status, stallguard
+                            ));
+                        }
+                    }
+                });
+                Ok(())
+            }
+        };
+
+        console.lock(|console| {
+            if let Some(console) = console {
+                match result {
+                    Ok(()) => console.write_line(format_args!("ok")),
+                    Err(e) => console.write_line(format_args!("error: {:?}", e)),
+                }
+            }
+        });
+    }
+
+    /// Run the azimuth stepper for a homing or measurement routine, saving
+    /// its normal rate to put back afterwards.  Returns its speed in degrees
+    /// per second.
+    fn run_azimuth(
+        board: &mut ConfiguredDevice,
+        unipolar: &mut Option<UnipolarStepper>,
+        saved_rate: &mut Option<u32>,
+        direction: CircularDirection,
+        rate: u32,
+    ) -> Option<f32> {
+        board.stepper_mut(Axis::Azimuth, unipolar).map(|stepper| {
+            saved_rate.get_or_insert(stepper.rate());
+            stepper.set_rate(rate);
+            stepper.set_direction(direction);
+            stepper.enable();
+            stepper.speed()
+        })
+    }
+
+    /// Stop the azimuth stepper at the end of a routine
+    fn stop_azimuth(
+        board: &mut ConfiguredDevice,
+        unipolar: &mut Option<UnipolarStepper>,
+        saved_rate: &mut Option<u32>,
+    ) {
+        if let Some(stepper) = board.stepper_mut(Axis::Azimuth, unipolar) {
+            stepper.disable();
+            if let Some(rate) = saved_rate.take() {
+                stepper.set_rate(rate);
+            }
+        }
+    }
 }
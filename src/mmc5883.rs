@@ -0,0 +1,83 @@
+/// Driver for the MMC5883MA magnetometer.  It only measures when told to, so
+/// each reading starts the next measurement and returns the last one.  Until
+/// the first measurement finishes there's nothing to return, so readings fail
+/// with `Error::NotReady`.
+use accelerometer::vector::I32x3;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::magnetometer::{read_registers, to_nanotesla, write_register, Magnetometer};
+use crate::Error;
+
+pub const ADDRESS: u8 = 0x30;
+
+/// Register addresses
+pub mod register {
+    pub const XOUT_L: u8 = 0x00;
+    pub const STATUS: u8 = 0x07;
+    pub const INTERNAL_CONTROL_0: u8 = 0x08;
+    pub const PRODUCT_ID: u8 = 0x2f;
+}
+
+pub const PRODUCT_ID: u8 = 0x0c;
+
+/// Status bit for a finished measurement
+const MEAS_M_DONE: u8 = 0x01;
+
+/// Internal control 0 bits to measure the field, and to set the sensor's
+/// magnetisation, which clears any left by a strong field
+const TM_M: u8 = 0x01;
+const SET: u8 = 0x08;
+
+/// Digits per gauss, which read 32768 for no field
+const GAIN: i32 = 4096;
+const OFFSET: i32 = 32_768;
+
+pub struct Mmc5883<I> {
+    i2c: I,
+    last: Option<I32x3>,
+}
+
+impl<I, E> Mmc5883<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn is_present(i2c: &mut I) -> bool {
+        let mut id = [0];
+        read_registers(i2c, ADDRESS, register::PRODUCT_ID, &mut id).is_ok() && id[0] == PRODUCT_ID
+    }
+
+    pub fn new(mut i2c: I) -> Result<Self, Error> {
+        write_register(&mut i2c, ADDRESS, register::INTERNAL_CONTROL_0, SET)?;
+        write_register(&mut i2c, ADDRESS, register::INTERNAL_CONTROL_0, TM_M)?;
+
+        Ok(Self { i2c, last: None })
+    }
+
+    pub fn destroy(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I, E> Magnetometer for Mmc5883<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// The last finished measurement, while the next is still going
+    fn magnetic_field(&mut self) -> Result<I32x3, Error> {
+        let mut data = [0; 8];
+        read_registers(&mut self.i2c, ADDRESS, register::XOUT_L, &mut data)?;
+        if data[register::STATUS as usize] & MEAS_M_DONE == 0 {
+            return self.last.ok_or(Error::NotReady);
+        }
+
+        let axis = |low: usize| {
+            let digits = u16::from_le_bytes([data[low], data[low + 1]]) as i32 - OFFSET;
+            to_nanotesla(digits, GAIN)
+        };
+        let field = I32x3::new(axis(0), axis(2), axis(4));
+        self.last = Some(field);
+        write_register(&mut self.i2c, ADDRESS, register::INTERNAL_CONTROL_0, TM_M)?;
+
+        Ok(field)
+    }
+}
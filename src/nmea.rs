@@ -0,0 +1,831 @@
+/// Heap-free parsing and formatting of NMEA 0183 sentences
+use core::fmt::{self, Write};
+use core::str::{self, FromStr};
+
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::datetime::{parse_milliseconds, Date, DateTime, Time};
+use crate::geo::{Angle, GeoPoint};
+use crate::Error;
+
+/// Longest valid sentence, including the leading `$` and trailing CRLF
+pub const MAX_SENTENCE_LENGTH: usize = 82;
+
+/// Quality of a position fix as reported by GGA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FixQuality {
+    #[default]
+    Invalid,
+    Gps,
+    Dgps,
+    Pps,
+    Rtk,
+    FloatRtk,
+    Estimated,
+    Manual,
+    Simulation,
+}
+
+impl FixQuality {
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, FixQuality::Invalid | FixQuality::Simulation)
+    }
+}
+
+/// GGA: Global positioning system fix data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gga {
+    pub time: Option<Time>,
+    pub position: Option<GeoPoint>,
+    pub quality: FixQuality,
+    pub satellites: u8,
+    pub hdop: Option<f32>,
+    /// Altitude above mean sea level in meters
+    pub altitude: Option<f32>,
+}
+
+/// RMC: Recommended minimum specific GNSS data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rmc {
+    pub time: Option<Time>,
+    pub date: Option<Date>,
+    /// Whether the receiver considers the data valid
+    pub valid: bool,
+    pub position: Option<GeoPoint>,
+    pub speed_knots: Option<f32>,
+    pub course: Option<Angle>,
+    /// Magnetic variation, east is positive
+    pub magnetic_variation: Option<Angle>,
+}
+
+impl Rmc {
+    pub fn datetime(&self) -> Option<DateTime> {
+        Some(DateTime::new(self.date?, self.time?))
+    }
+}
+
+/// VTG: Course over ground and ground speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vtg {
+    pub course_true: Option<Angle>,
+    pub course_magnetic: Option<Angle>,
+    pub speed_knots: Option<f32>,
+    pub speed_kmh: Option<f32>,
+}
+
+/// A parsed sentence from any talker (GP, GN, GL, etc.)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Vtg(Vtg),
+}
+
+/// XOR checksum of the given sentence body, i.e. everything between the `$`
+/// and the `*`
+pub fn checksum(body: &[u8]) -> u8 {
+    body.iter().fold(0, |sum, byte| sum ^ byte)
+}
+
+/// Validate the checksum of a complete sentence and return its body.  Line
+/// endings are ignored.
+pub fn validate(line: &str) -> Result<&str, Error> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let line = line
+        .strip_prefix('$')
+        .or_else(|| line.strip_prefix('!'))
+        .ok_or(Error::Parse)?;
+    let star = line.rfind('*').ok_or(Error::Checksum)?;
+    let (body, expected) = (&line[..star], &line[star + 1..]);
+
+    if expected.len() != 2 {
+        return Err(Error::Checksum);
+    }
+    let expected = u8::from_str_radix(expected, 16).map_err(|_| Error::Checksum)?;
+    if checksum(body.as_bytes()) != expected {
+        return Err(Error::Checksum);
+    }
+
+    Ok(body)
+}
+
+/// Parse a complete sentence, validating its checksum
+pub fn parse(line: &str) -> Result<Sentence, Error> {
+    let body = validate(line)?;
+    let mut fields = body.split(',');
+    let address = fields.next().ok_or(Error::Parse)?;
+    if address.len() != 5 || !address.is_ascii() {
+        return Err(Error::Parse);
+    }
+
+    match &address[2..] {
+        "GGA" => parse_gga(&mut fields).map(Sentence::Gga),
+        "RMC" => parse_rmc(&mut fields).map(Sentence::Rmc),
+        "VTG" => parse_vtg(&mut fields).map(Sentence::Vtg),
+        _ => Err(Error::UnsupportedSentence),
+    }
+}
+
+fn parse_gga<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Result<Gga, Error> {
+    let time = parse_time(next(fields)?)?;
+    let position = parse_position(fields)?;
+    let quality = match next(fields)? {
+        "" | "0" => FixQuality::Invalid,
+        "1" => FixQuality::Gps,
+        "2" => FixQuality::Dgps,
+        "3" => FixQuality::Pps,
+        "4" => FixQuality::Rtk,
+        "5" => FixQuality::FloatRtk,
+        "6" => FixQuality::Estimated,
+        "7" => FixQuality::Manual,
+        "8" => FixQuality::Simulation,
+        _ => return Err(Error::Parse),
+    };
+    let satellites = parse_optional::<u8>(next(fields)?)?.unwrap_or(0);
+    let hdop = parse_optional(next(fields)?)?;
+    let altitude = parse_optional(next(fields)?)?;
+
+    Ok(Gga {
+        time,
+        position,
+        quality,
+        satellites,
+        hdop,
+        altitude,
+    })
+}
+
+fn parse_rmc<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Result<Rmc, Error> {
+    let time = parse_time(next(fields)?)?;
+    let valid = match next(fields)? {
+        "A" => true,
+        "V" | "" => false,
+        _ => return Err(Error::Parse),
+    };
+    let position = parse_position(fields)?;
+    let speed_knots = parse_optional(next(fields)?)?;
+    let course = parse_optional(next(fields)?)?.map(Angle::from_degrees);
+    let date = parse_date(next(fields)?)?;
+    let variation = parse_optional::<f32>(next(fields)?)?;
+    let magnetic_variation = match (variation, next(fields)?) {
+        (Some(v), "E") => Some(Angle::from_degrees(v)),
+        (Some(v), "W") => Some(Angle::from_degrees(-v)),
+        (None, _) => None,
+        _ => return Err(Error::Parse),
+    };
+
+    Ok(Rmc {
+        time,
+        date,
+        valid,
+        position,
+        speed_knots,
+        course,
+        magnetic_variation,
+    })
+}
+
+fn parse_vtg<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Result<Vtg, Error> {
+    let course_true = parse_optional(next(fields)?)?.map(Angle::from_degrees);
+    next(fields)?;
+    let course_magnetic = parse_optional(next(fields)?)?.map(Angle::from_degrees);
+    next(fields)?;
+    let speed_knots = parse_optional(next(fields)?)?;
+    next(fields)?;
+    let speed_kmh = parse_optional(next(fields)?)?;
+
+    Ok(Vtg {
+        course_true,
+        course_magnetic,
+        speed_knots,
+        speed_kmh,
+    })
+}
+
+fn next<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, Error> {
+    fields.next().ok_or(Error::Parse)
+}
+
+fn parse_optional<T: FromStr>(field: &str) -> Result<Option<T>, Error> {
+    if field.is_empty() {
+        Ok(None)
+    } else {
+        field.parse().map(Some).map_err(|_| Error::Parse)
+    }
+}
+
+/// Parse `hhmmss` with an optional fraction of a second
+fn parse_time(field: &str) -> Result<Option<Time>, Error> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if field.len() < 6 || !field.is_ascii() {
+        return Err(Error::Parse);
+    }
+
+    let hour = parse_digits(&field[0..2])?;
+    let minute = parse_digits(&field[2..4])?;
+    let second = parse_digits(&field[4..6])?;
+    let millisecond = match &field[6..] {
+        "" => 0,
+        fraction => parse_milliseconds(fraction.strip_prefix('.').ok_or(Error::Parse)?)?,
+    };
+
+    Time::new(hour, minute, second, millisecond).map(Some)
+}
+
+/// Parse `ddmmyy`
+fn parse_date(field: &str) -> Result<Option<Date>, Error> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if field.len() != 6 || !field.is_ascii() {
+        return Err(Error::Parse);
+    }
+
+    let day = parse_digits(&field[0..2])?;
+    let month = parse_digits(&field[2..4])?;
+    let year = parse_digits(&field[4..6])?;
+
+    Date::new(2000 + year as u16, month, day).map(Some)
+}
+
+fn parse_digits(field: &str) -> Result<u8, Error> {
+    if !field.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Parse);
+    }
+    field.parse().map_err(|_| Error::Parse)
+}
+
+/// Parse the four latitude, N/S, longitude, E/W fields
+fn parse_position<'a>(
+    fields: &mut impl Iterator<Item = &'a str>,
+) -> Result<Option<GeoPoint>, Error> {
+    let lat = parse_optional::<f64>(next(fields)?)?;
+    let lat_hemisphere = next(fields)?;
+    let lon = parse_optional::<f64>(next(fields)?)?;
+    let lon_hemisphere = next(fields)?;
+
+    let (lat, lon) = match (lat, lon) {
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => return Ok(None),
+    };
+    let lat = match lat_hemisphere {
+        "N" => degrees_minutes(lat),
+        "S" => -degrees_minutes(lat),
+        _ => return Err(Error::Parse),
+    };
+    let lon = match lon_hemisphere {
+        "E" => degrees_minutes(lon),
+        "W" => -degrees_minutes(lon),
+        _ => return Err(Error::Parse),
+    };
+
+    GeoPoint::new(lat, lon).map(Some)
+}
+
+/// Convert NMEA's `dddmm.mmmm` to decimal degrees
+fn degrees_minutes(value: f64) -> f64 {
+    let degrees = (value / 100.0) as u32 as f64;
+    degrees + (value - degrees * 100.0) / 60.0
+}
+
+/// Accumulates received bytes into complete sentences
+pub struct LineBuffer {
+    buffer: [u8; MAX_SENTENCE_LENGTH],
+    length: usize,
+    overflowed: bool,
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        LineBuffer::new()
+    }
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; MAX_SENTENCE_LENGTH],
+            length: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Discard any partially received sentence
+    pub fn clear(&mut self) {
+        self.length = 0;
+        self.overflowed = false;
+    }
+
+    /// Add a received byte, returning the sentence once its line ending
+    /// arrives.  Bytes outside of a sentence and sentences that are too long
+    /// are dropped.
+    pub fn push(&mut self, byte: u8) -> Option<&str> {
+        match byte {
+            b'$' | b'!' => {
+                self.clear();
+                self.buffer[0] = byte;
+                self.length = 1;
+                None
+            }
+            b'\r' => None,
+            b'\n' => {
+                let length = self.length;
+                let overflowed = self.overflowed;
+                self.clear();
+
+                if length == 0 || overflowed {
+                    None
+                } else {
+                    str::from_utf8(&self.buffer[..length]).ok()
+                }
+            }
+            _ if self.length == 0 => None,
+            _ if self.length == MAX_SENTENCE_LENGTH => {
+                self.overflowed = true;
+                None
+            }
+            _ => {
+                self.buffer[self.length] = byte;
+                self.length += 1;
+                None
+            }
+        }
+    }
+}
+
+/// Talker ID used for sentences we send, i.e. a magnetic compass
+pub const TALKER_ID: &str = "HC";
+
+/// A compass heading and its corrections, as reported by HDG.  East is
+/// positive for both deviation and variation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heading {
+    /// Heading as read from the sensor
+    pub sensor: Angle,
+    /// Remaining compass deviation after calibration
+    pub deviation: Angle,
+    /// Magnetic variation (declination) at the current location
+    pub variation: Angle,
+}
+
+impl Heading {
+    /// Heading relative to magnetic north
+    pub fn magnetic(&self) -> Angle {
+        self.sensor + self.deviation
+    }
+
+    /// Heading relative to true north
+    pub fn true_heading(&self) -> Angle {
+        self.magnetic() + self.variation
+    }
+}
+
+/// A complete, checksummed sentence ready to be transmitted
+pub struct SentenceBuffer {
+    buffer: [u8; MAX_SENTENCE_LENGTH],
+    length: usize,
+}
+
+impl SentenceBuffer {
+    fn new() -> Self {
+        Self {
+            buffer: [0; MAX_SENTENCE_LENGTH],
+            length: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever written to through fmt::Write
+        str::from_utf8(self.as_bytes()).unwrap_or("")
+    }
+}
+
+impl Write for SentenceBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.length + s.len();
+        if end > self.buffer.len() {
+            return Err(fmt::Error);
+        }
+        self.buffer[self.length..end].copy_from_slice(s.as_bytes());
+        self.length = end;
+        Ok(())
+    }
+}
+
+/// Format a sentence of the given type from our talker, adding the leading
+/// `$`, checksum and line ending
+pub fn format_sentence(
+    sentence_type: &str,
+    fields: fmt::Arguments,
+) -> Result<SentenceBuffer, Error> {
+    let mut sentence = SentenceBuffer::new();
+    write!(sentence, "${}{},{}", TALKER_ID, sentence_type, fields).map_err(|_| Error::Overflow)?;
+    let sum = checksum(&sentence.as_bytes()[1..]);
+    write!(sentence, "*{:02X}\r\n", sum).map_err(|_| Error::Overflow)?;
+
+    Ok(sentence)
+}
+
+/// HDG: Heading, deviation and variation
+pub fn format_hdg(heading: &Heading) -> Result<SentenceBuffer, Error> {
+    let (deviation, deviation_direction) = east_west(heading.deviation);
+    let (variation, variation_direction) = east_west(heading.variation);
+
+    format_sentence(
+        "HDG",
+        format_args!(
+            "{:.1},{:.1},{},{:.1},{}",
+            heading_degrees(heading.sensor),
+            deviation,
+            deviation_direction,
+            variation,
+            variation_direction
+        ),
+    )
+}
+
+/// HDM: Heading relative to magnetic north
+pub fn format_hdm(heading: &Heading) -> Result<SentenceBuffer, Error> {
+    format_sentence(
+        "HDM",
+        format_args!("{:.1},M", heading_degrees(heading.magnetic())),
+    )
+}
+
+/// HDT: Heading relative to true north
+pub fn format_hdt(heading: &Heading) -> Result<SentenceBuffer, Error> {
+    format_sentence(
+        "HDT",
+        format_args!("{:.1},T", heading_degrees(heading.true_heading())),
+    )
+}
+
+/// Round a heading to the one decimal place we send, keeping it below 360
+fn heading_degrees(angle: Angle) -> f32 {
+    let degrees = (angle.normalized().degrees() * 10.0).round() / 10.0;
+    if degrees >= 360.0 {
+        0.0
+    } else {
+        degrees
+    }
+}
+
+/// Split a correction into its magnitude and E/W direction
+fn east_west(angle: Angle) -> (f32, char) {
+    let degrees = angle.signed().degrees();
+    if degrees < 0.0 {
+        (-degrees, 'W')
+    } else {
+        (degrees, 'E')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sentence with the given body and its correct checksum
+    fn sentence(body: &str) -> SentenceBuffer {
+        let mut sentence = SentenceBuffer::new();
+        write!(sentence, "${}*{:02X}\r\n", body, checksum(body.as_bytes())).unwrap();
+        sentence
+    }
+
+    fn parse_body(body: &str) -> Result<Sentence, Error> {
+        parse(sentence(body).as_str())
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"GPVTG,054.7,T,034.4,M,005.5,N,010.2,K"), 0x48);
+        assert_eq!(
+            validate("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48\r\n").ok(),
+            Some("GPVTG,054.7,T,034.4,M,005.5,N,010.2,K")
+        );
+        // Lower case hex, and AIS style sentences
+        assert!(
+            validate("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6a")
+                .is_ok()
+        );
+        assert_eq!(
+            validate("!AIVDM,1,1,,A,1*0B\r\n").ok(),
+            Some("AIVDM,1,1,,A,1")
+        );
+    }
+
+    #[test]
+    fn bad_checksums() {
+        for line in [
+            // Wrong
+            "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*49",
+            // Corrupted body
+            "$GPVTG,054.7,T,034.4,M,005.6,N,010.2,K*48",
+            // Missing, short, long and not hex
+            "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K",
+            "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*4",
+            "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*480",
+            "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*G8",
+        ]
+        .iter()
+        {
+            assert!(matches!(parse(line), Err(Error::Checksum)), "{}", line);
+        }
+        assert!(matches!(
+            parse("GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48"),
+            Err(Error::Parse)
+        ));
+    }
+
+    #[test]
+    fn gga() {
+        let line = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+        let gga = match parse(line) {
+            Ok(Sentence::Gga(gga)) => gga,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(gga.time, Some(Time::new(12, 35, 19, 0).unwrap()));
+        let position = gga.position.unwrap();
+        assert_close(position.lat(), 48.0 + 7.038 / 60.0);
+        assert_close(position.lon(), 11.0 + 31.0 / 60.0);
+        assert_eq!(gga.quality, FixQuality::Gps);
+        assert_eq!(gga.satellites, 8);
+        assert_eq!(gga.hdop, Some(0.9));
+        assert_eq!(gga.altitude, Some(545.4));
+    }
+
+    #[test]
+    fn gga_from_other_talkers_and_hemispheres() {
+        let gga = match parse_body("GNGGA,235959.250,3352.128,S,15112.558,W,2,12,,-5.0,M,,M,,") {
+            Ok(Sentence::Gga(gga)) => gga,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(gga.time, Some(Time::new(23, 59, 59, 250).unwrap()));
+        let position = gga.position.unwrap();
+        assert_close(position.lat(), -33.8688);
+        assert_close(position.lon(), -151.2093);
+        assert_eq!(gga.quality, FixQuality::Dgps);
+        assert_eq!(gga.hdop, None);
+        assert_eq!(gga.altitude, Some(-5.0));
+    }
+
+    #[test]
+    fn gga_without_a_fix() {
+        let gga = match parse_body("GPGGA,,,,,,0,00,,,M,,M,,") {
+            Ok(Sentence::Gga(gga)) => gga,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(gga.time, None);
+        assert_eq!(gga.position, None);
+        assert_eq!(gga.quality, FixQuality::Invalid);
+        assert!(!gga.quality.is_valid());
+        assert_eq!(gga.satellites, 0);
+        assert_eq!(gga.hdop, None);
+        assert_eq!(gga.altitude, None);
+    }
+
+    #[test]
+    fn rmc() {
+        let line = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+        let rmc = match parse(line) {
+            Ok(Sentence::Rmc(rmc)) => rmc,
+            other => panic!("{:?}", other),
+        };
+        assert!(rmc.valid);
+        assert_eq!(
+            rmc.datetime(),
+            Some(DateTime::new(
+                Date::new(2094, 3, 23).unwrap(),
+                Time::new(12, 35, 19, 0).unwrap()
+            ))
+        );
+        assert_close(rmc.position.unwrap().lat(), 48.0 + 7.038 / 60.0);
+        assert_eq!(rmc.speed_knots, Some(22.4));
+        assert_eq!(rmc.course, Some(Angle::from_degrees(84.4)));
+        assert_eq!(rmc.magnetic_variation, Some(Angle::from_degrees(-3.1)));
+    }
+
+    #[test]
+    fn rmc_without_a_fix() {
+        let rmc = match parse_body("GPRMC,,V,,,,,,,,,,N") {
+            Ok(Sentence::Rmc(rmc)) => rmc,
+            other => panic!("{:?}", other),
+        };
+        assert!(!rmc.valid);
+        assert_eq!(rmc.time, None);
+        assert_eq!(rmc.date, None);
+        assert_eq!(rmc.datetime(), None);
+        assert_eq!(rmc.position, None);
+        assert_eq!(rmc.course, None);
+        assert_eq!(rmc.magnetic_variation, None);
+
+        // Time without a date isn't a point in time
+        match parse_body("GPRMC,010203,V,,,,,,,,,") {
+            Ok(Sentence::Rmc(rmc)) => assert_eq!(rmc.datetime(), None),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn vtg() {
+        let vtg = match parse("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48") {
+            Ok(Sentence::Vtg(vtg)) => vtg,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(vtg.course_true, Some(Angle::from_degrees(54.7)));
+        assert_eq!(vtg.course_magnetic, Some(Angle::from_degrees(34.4)));
+        assert_eq!(vtg.speed_knots, Some(5.5));
+        assert_eq!(vtg.speed_kmh, Some(10.2));
+    }
+
+    #[test]
+    fn truncated_sentences() {
+        for body in [
+            "GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9",
+            "GPGGA,123519,4807.038,N",
+            "GPGGA",
+            "GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1",
+            "GPVTG,054.7,T,034.4,M,005.5,N",
+        ]
+        .iter()
+        {
+            assert!(matches!(parse_body(body), Err(Error::Parse)), "{}", body);
+        }
+
+        // Cut off before the checksum arrived
+        assert!(matches!(
+            parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08"),
+            Err(Error::Checksum)
+        ));
+    }
+
+    #[test]
+    fn malformed_fields() {
+        for body in [
+            // Bad address
+            "GPGG,123519,,,,,0,,,,M,,M,,",
+            "G,123519,,,,,0,,,,M,,M,,",
+            // Bad time, hemisphere and fix quality
+            "GPGGA,1235,,,,,0,,,,M,,M,,",
+            "GPGGA,12a519,,,,,0,,,,M,,M,,",
+            "GPGGA,123519:5,,,,,0,,,,M,,M,,",
+            "GPGGA,123519,4807.038,X,01131.000,E,1,08,0.9,545.4,M,46.9,M,,",
+            "GPGGA,123519,4807.038,N,01131.000,E,9,08,0.9,545.4,M,46.9,M,,",
+            "GPGGA,123519,4807.038,N,01131.000,E,1,-1,0.9,545.4,M,46.9,M,,",
+            // Bad status, date and variation direction
+            "GPRMC,123519,X,,,,,,,,,,",
+            "GPRMC,123519,A,,,,,,,2303,,,",
+            "GPRMC,123519,A,,,,,,,230394,3.1,N",
+        ]
+        .iter()
+        {
+            assert!(matches!(parse_body(body), Err(Error::Parse)), "{}", body);
+        }
+        for body in [
+            "GPGGA,256000,,,,,0,,,,M,,M,,",
+            "GPRMC,123519,A,,,,,,,310294,,",
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(parse_body(body), Err(Error::InvalidDateTime)),
+                "{}",
+                body
+            );
+        }
+        assert!(matches!(
+            parse_body("GPGGA,123519,9107.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            Err(Error::InvalidCoordinate)
+        ));
+        assert!(matches!(
+            parse_body("GPGSV,1,1,00"),
+            Err(Error::UnsupportedSentence)
+        ));
+    }
+
+    #[test]
+    fn line_buffer() {
+        let mut lines = LineBuffer::new();
+        let mut received = 0;
+        let input =
+            b"noise$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48\r\n$GP,partial$HCHDM,1.0,M*28\r\n\n";
+        for &byte in input.iter() {
+            if let Some(line) = lines.push(byte) {
+                match received {
+                    0 => assert_eq!(line, "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48"),
+                    _ => assert_eq!(line, "$HCHDM,1.0,M*28"),
+                }
+                received += 1;
+            }
+        }
+        assert_eq!(received, 2);
+    }
+
+    #[test]
+    fn line_buffer_drops_long_sentences() {
+        let mut lines = LineBuffer::new();
+        assert!(lines.push(b'$').is_none());
+        for _ in 0..MAX_SENTENCE_LENGTH {
+            assert!(lines.push(b'A').is_none());
+        }
+        assert!(lines.push(b'\n').is_none());
+
+        // And recovers for the next
+        for &byte in b"$HCHDM,1.0,M*28\r".iter() {
+            assert!(lines.push(byte).is_none());
+        }
+        assert_eq!(lines.push(b'\n'), Some("$HCHDM,1.0,M*28"));
+    }
+
+    fn heading(sensor: f32, deviation: f32, variation: f32) -> Heading {
+        Heading {
+            sensor: Angle::from_degrees(sensor),
+            deviation: Angle::from_degrees(deviation),
+            variation: Angle::from_degrees(variation),
+        }
+    }
+
+    fn formatted(heading: &Heading) -> [SentenceBuffer; 3] {
+        [
+            format_hdg(heading).unwrap(),
+            format_hdm(heading).unwrap(),
+            format_hdt(heading).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn heading_sentences() {
+        let [hdg, hdm, hdt] = formatted(&heading(123.4, -1.5, 3.3));
+        assert_eq!(hdg.as_str(), "$HCHDG,123.4,1.5,W,3.3,E*50\r\n");
+        assert_eq!(hdm.as_str(), "$HCHDM,121.9,M*22\r\n");
+        assert_eq!(hdt.as_str(), "$HCHDT,125.2,T*2D\r\n");
+    }
+
+    #[test]
+    fn heading_sentences_round_below_360() {
+        for sensor in [0.0, 359.96, -0.02, 720.0].iter() {
+            let [hdg, hdm, hdt] = formatted(&heading(*sensor, 0.0, 0.0));
+            assert_eq!(hdg.as_str(), "$HCHDG,0.0,0.0,E,0.0,E*42\r\n");
+            assert_eq!(hdm.as_str(), "$HCHDM,0.0,M*29\r\n");
+            assert_eq!(hdt.as_str(), "$HCHDT,0.0,T*29\r\n");
+        }
+
+        let [hdg, hdm, hdt] = formatted(&heading(359.94, 0.0, -12.0));
+        assert_eq!(hdg.as_str(), "$HCHDG,359.9,0.0,E,12.0,W*65\r\n");
+        assert_eq!(hdm.as_str(), "$HCHDM,359.9,M*2F\r\n");
+        assert_eq!(hdt.as_str(), "$HCHDT,347.9,T*20\r\n");
+    }
+
+    #[test]
+    fn formatted_sentences_parse_back() {
+        for sentence in formatted(&heading(42.0, 2.0, -7.5)).iter() {
+            let body = validate(sentence.as_str()).unwrap();
+            assert!(body.starts_with(TALKER_ID));
+            assert_eq!(sentence.as_bytes().len(), sentence.as_str().len());
+        }
+        assert!(matches!(
+            parse(format_hdt(&heading(42.0, 2.0, -7.5)).unwrap().as_str()),
+            Err(Error::UnsupportedSentence)
+        ));
+    }
+
+    #[test]
+    fn long_sentences_overflow() {
+        let long = [b'9'; MAX_SENTENCE_LENGTH];
+        let long = str::from_utf8(&long).unwrap();
+        assert!(matches!(
+            format_sentence("TXT", format_args!("{}", long)),
+            Err(Error::Overflow)
+        ));
+        // Room for the checksum and line ending counts too
+        let fits = &long[..MAX_SENTENCE_LENGTH - 12];
+        assert_eq!(
+            format_sentence("TXT", format_args!("{}", fits))
+                .unwrap()
+                .as_bytes()
+                .len(),
+            MAX_SENTENCE_LENGTH
+        );
+        let too_long = &long[..MAX_SENTENCE_LENGTH - 11];
+        assert!(matches!(
+            format_sentence("TXT", format_args!("{}", too_long)),
+            Err(Error::Overflow)
+        ));
+    }
+}
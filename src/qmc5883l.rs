@@ -0,0 +1,74 @@
+/// Driver for the QMC5883L magnetometer, found on many cheap compass boards
+/// sold as HMC5883L
+use accelerometer::vector::I32x3;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::magnetometer::{read_registers, to_nanotesla, write_register, Magnetometer};
+use crate::Error;
+
+pub const ADDRESS: u8 = 0x0d;
+
+/// Register addresses
+pub mod register {
+    pub const DATA_X_L: u8 = 0x00;
+    pub const CONTROL_1: u8 = 0x09;
+    pub const SET_RESET_PERIOD: u8 = 0x0b;
+    pub const CHIP_ID: u8 = 0x0d;
+}
+
+pub const CHIP_ID: u8 = 0xff;
+
+/// Continuous at 50Hz, ±2 gauss, oversampling 512
+const CONTROL_1_CONTINUOUS: u8 = 0x05;
+
+/// Recommended by the datasheet
+const SET_RESET_PERIOD: u8 = 0x01;
+
+/// Digits per gauss at ±2 gauss
+const GAIN: i32 = 12_000;
+
+pub struct Qmc5883l<I> {
+    i2c: I,
+}
+
+impl<I, E> Qmc5883l<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn is_present(i2c: &mut I) -> bool {
+        let mut id = [0];
+        read_registers(i2c, ADDRESS, register::CHIP_ID, &mut id).is_ok() && id[0] == CHIP_ID
+    }
+
+    pub fn new(mut i2c: I) -> Result<Self, Error> {
+        write_register(
+            &mut i2c,
+            ADDRESS,
+            register::SET_RESET_PERIOD,
+            SET_RESET_PERIOD,
+        )?;
+        write_register(&mut i2c, ADDRESS, register::CONTROL_1, CONTROL_1_CONTINUOUS)?;
+
+        Ok(Self { i2c })
+    }
+
+    pub fn destroy(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I, E> Magnetometer for Qmc5883l<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    fn magnetic_field(&mut self) -> Result<I32x3, Error> {
+        let mut data = [0; 6];
+        read_registers(&mut self.i2c, ADDRESS, register::DATA_X_L, &mut data)?;
+
+        let axis = |low: usize| {
+            let digits = i16::from_le_bytes([data[low], data[low + 1]]) as i32;
+            to_nanotesla(digits, GAIN)
+        };
+        Ok(I32x3::new(axis(0), axis(2), axis(4)))
+    }
+}
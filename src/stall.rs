@@ -0,0 +1,341 @@
+/// Detection of a jammed turntable by comparing how far the stepper has been
+/// told to turn with how far the compass sees it turn
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::Angle;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StallConfig {
+    /// Turntable degrees of commanded rotation to judge each check over
+    pub window: f32,
+    /// Least fraction of the commanded rotation the compass has to see.  Kept
+    /// low to allow for slip and compass lag.
+    pub min_fraction: f32,
+    /// Milliseconds to wait before the first retry, doubling for each stall
+    /// after that
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Stalls in a row before giving up until reset
+    pub max_retries: u32,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        Self {
+            window: 90.0,
+            min_fraction: 0.25,
+            backoff_ms: 2_000,
+            max_backoff_ms: 60_000,
+            max_retries: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallStatus {
+    /// Turning as commanded, or not asked to turn
+    Ok,
+    /// Just stalled, so the motor should be stopped
+    Stalled,
+    /// Waiting to retry after a stall, the motor should stay stopped
+    BackingOff,
+    /// Stalled too many times in a row, the motor should stay stopped until
+    /// the detector is reset
+    Fault,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StallDetector {
+    last_update: Option<u64>,
+    last_heading: Option<Angle>,
+    /// Turntable degrees commanded so far in the current check, and the net
+    /// rotation seen, so jitter in the compass cancels out
+    commanded: f32,
+    observed: f32,
+    retries: u32,
+    retry_at: Option<u64>,
+    fault: bool,
+}
+
+impl Default for StallDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StallDetector {
+    pub fn new() -> Self {
+        Self {
+            last_update: None,
+            last_heading: None,
+            commanded: 0.0,
+            observed: 0.0,
+            retries: 0,
+            retry_at: None,
+            fault: false,
+        }
+    }
+
+    /// Forget any stalls, e.g. once the jam has been cleared
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn is_fault(&self) -> bool {
+        self.fault
+    }
+
+    /// Stalls in a row so far
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Feed in the heading and the speed the motor has been turning the
+    /// turntable at in degrees per second (0 when stopped) since the last
+    /// update.  Only the amount of rotation is compared, so the direction the
+    /// motor turns the heading doesn't need to be known.  The heading should
+    /// change by less than half a turn between updates.
+    pub fn update(
+        &mut self,
+        config: &StallConfig,
+        now_ms: u64,
+        speed: f32,
+        heading: Angle,
+    ) -> StallStatus {
+        let elapsed = self
+            .last_update
+            .map_or(0, |last| now_ms.saturating_sub(last));
+        let observed = self
+            .last_heading
+            .map_or(0.0, |last| last.difference(heading).degrees());
+        self.last_update = Some(now_ms);
+        self.last_heading = Some(heading);
+
+        if self.fault {
+            return StallStatus::Fault;
+        }
+        if let Some(retry_at) = self.retry_at {
+            if now_ms < retry_at {
+                return StallStatus::BackingOff;
+            }
+            self.retry_at = None;
+        }
+
+        // Any drift while stopped says nothing about the motor
+        if speed <= 0.0 {
+            self.commanded = 0.0;
+            self.observed = 0.0;
+            return StallStatus::Ok;
+        }
+
+        self.commanded += speed * elapsed as f32 / 1000.0;
+        self.observed += observed;
+        if self.commanded < config.window {
+            return StallStatus::Ok;
+        }
+
+        let stalled = self.observed.abs() < self.commanded * config.min_fraction;
+        self.commanded = 0.0;
+        self.observed = 0.0;
+        if !stalled {
+            self.retries = 0;
+            return StallStatus::Ok;
+        }
+
+        self.retries += 1;
+        if self.retries > config.max_retries {
+            self.fault = true;
+            return StallStatus::Fault;
+        }
+
+        let backoff = config
+            .backoff_ms
+            .saturating_mul(1 << (self.retries - 1).min(16))
+            .min(config.max_backoff_ms);
+        self.retry_at = Some(now_ms + backoff);
+        StallStatus::Stalled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::Calibration;
+
+    const PERIOD_MS: u64 = 250;
+    const SPEED: f32 = 20.0;
+
+    /// Run the detector over a heading trace, one heading per period with the
+    /// motor running, returning the statuses
+    fn run<const N: usize>(
+        detector: &mut StallDetector,
+        start_ms: u64,
+        heading: impl Fn(usize) -> f32,
+    ) -> [StallStatus; N] {
+        let config = StallConfig::default();
+        let mut statuses = [StallStatus::Ok; N];
+        for (i, status) in statuses.iter_mut().enumerate() {
+            let now = start_ms + i as u64 * PERIOD_MS;
+            *status = detector.update(&config, now, SPEED, Angle::from_degrees(heading(i)));
+        }
+
+        statuses
+    }
+
+    /// Pseudo-random compass noise of up to ±`amplitude` degrees
+    fn noise(i: usize, amplitude: f32) -> f32 {
+        let hash = (i as u32).wrapping_mul(2_654_435_761) >> 16;
+        (hash % 2001) as f32 / 1000.0 * amplitude - amplitude
+    }
+
+    /// Updates after the first to command a whole check window
+    fn window() -> usize {
+        let per_update = SPEED * PERIOD_MS as f32 / 1000.0;
+        (StallConfig::default().window / per_update).ceil() as usize
+    }
+
+    #[test]
+    fn free_running() {
+        let mut detector = StallDetector::new();
+        let per_update = SPEED * PERIOD_MS as f32 / 1000.0;
+        let statuses: [_; 200] = run(&mut detector, 0, |i| i as f32 * per_update);
+        assert!(statuses.iter().all(|s| *s == StallStatus::Ok));
+
+        // Which way the heading goes doesn't matter
+        let mut detector = StallDetector::new();
+        let statuses: [_; 200] = run(&mut detector, 0, |i| -(i as f32) * per_update);
+        assert!(statuses.iter().all(|s| *s == StallStatus::Ok));
+    }
+
+    #[test]
+    fn slow_but_turning() {
+        // Half the commanded rotation still counts as turning
+        let mut detector = StallDetector::new();
+        let per_update = SPEED * PERIOD_MS as f32 / 1000.0 / 2.0;
+        let statuses: [_; 200] = run(&mut detector, 0, |i| i as f32 * per_update);
+        assert!(statuses.iter().all(|s| *s == StallStatus::Ok));
+    }
+
+    #[test]
+    fn blocked() {
+        let mut detector = StallDetector::new();
+        let statuses: [_; 40] = run(&mut detector, 0, |_| 123.0);
+        let stalled = statuses
+            .iter()
+            .position(|s| *s == StallStatus::Stalled)
+            .unwrap();
+        assert_eq!(stalled, window());
+        assert!(statuses[..stalled].iter().all(|s| *s == StallStatus::Ok));
+        // Then waits out the backoff
+        assert_eq!(statuses[stalled + 1], StallStatus::BackingOff);
+        assert_eq!(detector.retries(), 1);
+    }
+
+    #[test]
+    fn blocked_before_calibrating() {
+        // A 200 step motor at 200 steps per second, with nothing measured
+        let speed = Calibration::default().expected_speed(200, 360.0);
+        let config = StallConfig::default();
+        let mut detector = StallDetector::new();
+        let stalled = (0..40).find(|i| {
+            let now = *i as u64 * PERIOD_MS;
+            detector.update(&config, now, speed, Angle::from_degrees(45.0)) == StallStatus::Stalled
+        });
+        assert!(stalled.is_some());
+    }
+
+    #[test]
+    fn noisy_but_blocked() {
+        // Jitter adds up to far more than the window if it's not netted out
+        let mut detector = StallDetector::new();
+        let statuses: [_; 40] = run(&mut detector, 0, |i| 123.0 + noise(i, 10.0));
+        assert_eq!(statuses[window()], StallStatus::Stalled);
+    }
+
+    #[test]
+    fn noisy_and_turning() {
+        let mut detector = StallDetector::new();
+        let per_update = SPEED * PERIOD_MS as f32 / 1000.0;
+        let statuses: [_; 200] = run(&mut detector, 0, |i| i as f32 * per_update + noise(i, 10.0));
+        assert!(statuses.iter().all(|s| *s == StallStatus::Ok));
+    }
+
+    #[test]
+    fn backs_off_then_faults() {
+        let config = StallConfig::default();
+        let mut detector = StallDetector::new();
+        let mut now = 0;
+        let mut backoffs = [0; 5];
+        for backoff in backoffs.iter_mut() {
+            // Blocked until it stalls
+            loop {
+                now += PERIOD_MS;
+                if detector.update(&config, now, SPEED, Angle::from_degrees(90.0))
+                    == StallStatus::Stalled
+                {
+                    break;
+                }
+            }
+            let stalled_at = now;
+            loop {
+                now += PERIOD_MS;
+                if detector.update(&config, now, SPEED, Angle::from_degrees(90.0))
+                    != StallStatus::BackingOff
+                {
+                    break;
+                }
+            }
+            *backoff = now - stalled_at;
+        }
+        // Each backoff doubles, to the nearest update
+        for (i, backoff) in backoffs.iter().enumerate() {
+            let expected = (config.backoff_ms << i).min(config.max_backoff_ms);
+            assert!(*backoff >= expected && *backoff < expected + 2 * PERIOD_MS);
+        }
+
+        // Past the retries it gives up until reset
+        let mut status = StallStatus::Ok;
+        while status == StallStatus::Ok {
+            now += PERIOD_MS;
+            status = detector.update(&config, now, SPEED, Angle::from_degrees(90.0));
+        }
+        assert_eq!(status, StallStatus::Fault);
+        assert!(detector.is_fault());
+        now += PERIOD_MS;
+        assert_eq!(
+            detector.update(&config, now, 0.0, Angle::from_degrees(90.0)),
+            StallStatus::Fault
+        );
+
+        detector.reset();
+        assert!(!detector.is_fault());
+        assert_eq!(detector.retries(), 0);
+    }
+
+    #[test]
+    fn turning_again_clears_the_retries() {
+        let mut detector = StallDetector::new();
+        let statuses: [_; 19] = run(&mut detector, 0, |_| 10.0);
+        assert_eq!(statuses[18], StallStatus::Stalled);
+        assert_eq!(detector.retries(), 1);
+
+        // Freed once the backoff is over
+        let retry_at = 18 * PERIOD_MS + StallConfig::default().backoff_ms;
+        let per_update = SPEED * PERIOD_MS as f32 / 1000.0;
+        let statuses: [_; 100] = run(&mut detector, retry_at, |i| 10.0 + i as f32 * per_update);
+        assert!(statuses.iter().all(|s| *s == StallStatus::Ok));
+        assert_eq!(detector.retries(), 0);
+    }
+
+    #[test]
+    fn drift_while_stopped_is_ignored() {
+        let config = StallConfig::default();
+        let mut detector = StallDetector::new();
+        for i in 0..100 {
+            let heading = Angle::from_degrees(noise(i, 30.0) + 180.0);
+            let status = detector.update(&config, i as u64 * PERIOD_MS, 0.0, heading);
+            assert_eq!(status, StallStatus::Ok);
+        }
+    }
+}
@@ -1,37 +1,440 @@
 /// Struct for Stepper configuration and control
 use cortex_m_semihosting::hprintln;
+#[allow(unused_imports)]
+use num_traits::float::Float;
+use stm32f3xx_hal::gpio::gpiod::{PD10, PD12, PD13, PD14, PD8, PD9};
 use stm32f3xx_hal::gpio::gpiof::{PF10, PF6, PF9};
-use stm32f3xx_hal::gpio::{gpiof, Alternate, Gpiof, OpenDrain, Output, Pin, U};
+use stm32f3xx_hal::gpio::{
+    gpiod, gpiof, Alternate, Gpiod, Gpiof, OpenDrain, Output, PXx, Pin, PushPull, U,
+};
+use stm32f3xx_hal::hal::PwmPin;
 use stm32f3xx_hal::i2c;
 use stm32f3xx_hal::pac;
 use stm32f3xx_hal::prelude::*;
 use stm32f3xx_hal::pwm;
-use stm32f3xx_hal::pwm::{PwmChannel, Tim15Ch2, WithPins};
+use stm32f3xx_hal::pwm::{PwmChannel, Tim15Ch2, Tim4Ch1, WithPins};
 use stm32f3xx_hal::rcc;
 
+use crate::backlash::GearPlay;
+use crate::tmc2209::{DriverConfig, DriverStatus, Tmc2209};
+use crate::Error;
+
 type Pf10Af3Pin = Pin<Gpiof, U<10_u8>, Alternate<OpenDrain, 3u8>>;
+type Pd12Af2Pin = Pin<Gpiod, U<12_u8>, Alternate<OpenDrain, 2u8>>;
+
+/// Step rate the controllers expect
+pub const PULSE_FREQUENCY_HZ: u32 = 13_000;
+
+/// Full steps per revolution of a 1.8° motor
+pub const FULL_STEPS_PER_REVOLUTION: u32 = 200;
+
+/// Resolution the drivers are set to at startup, at which the pulses come
+/// at `PULSE_FREQUENCY_HZ`
+pub const DEFAULT_MICROSTEPPING: Microstepping = Microstepping::Sixteenth;
+
+/// Resolutions to use close to and far from the target
+pub const FINE_MICROSTEPPING: Microstepping = Microstepping::Sixteenth;
+pub const COARSE_MICROSTEPPING: Microstepping = Microstepping::Quarter;
+
+/// Timer ticks per pulse, giving a 50% duty cycle at 100
+const PULSE_RESOLUTION: u16 = 200;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CircularDirection {
     CW,
     CCW,
 }
 
+impl CircularDirection {
+    /// The other way round
+    pub fn reverse(self) -> Self {
+        match self {
+            CircularDirection::CW => CircularDirection::CCW,
+            CircularDirection::CCW => CircularDirection::CW,
+        }
+    }
+}
+
+/// The axes the device can be turned about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    /// Rotation of the turntable
+    Azimuth,
+    /// Tilt above the horizon
+    Elevation,
+}
+
+/// The kinds of driver the turntable can be built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DriverKind {
+    /// A step/direction driver pulsed by PWM
+    #[default]
+    StepDir,
+    /// A unipolar motor with its coils sequenced over GPIO
+    Unipolar,
+}
+
+/// Fraction of a full step moved by each step pulse
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Microstepping {
+    Full,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+    ThirtySecond,
+}
+
+impl Microstepping {
+    /// Pulses per full step
+    pub fn divisor(&self) -> u32 {
+        1 << *self as u32
+    }
+
+    pub fn from_divisor(divisor: u32) -> Result<Self, Error> {
+        match divisor {
+            1 => Ok(Microstepping::Full),
+            2 => Ok(Microstepping::Half),
+            4 => Ok(Microstepping::Quarter),
+            8 => Ok(Microstepping::Eighth),
+            16 => Ok(Microstepping::Sixteenth),
+            32 => Ok(Microstepping::ThirtySecond),
+            _ => Err(Error::InvalidSetting),
+        }
+    }
+
+    /// Pulses per second to turn at a number of full steps per second
+    pub fn pulse_rate(&self, full_steps_per_second: u32) -> u32 {
+        full_steps_per_second.saturating_mul(self.divisor())
+    }
+
+    /// Angle turned by each pulse in degrees
+    pub fn degrees_per_pulse(&self) -> f32 {
+        360.0 / (FULL_STEPS_PER_REVOLUTION * self.divisor()) as f32
+    }
+
+    /// Pulses needed to turn through an angle in degrees, to the nearest
+    /// pulse
+    pub fn pulses(&self, degrees: f32) -> u32 {
+        (degrees.abs() / self.degrees_per_pulse()).round() as u32
+    }
+
+    /// Fine steps near the target for accuracy, coarse ones further out for
+    /// speed and torque
+    pub fn for_error(error: f32, fine_within: f32) -> Self {
+        if error.abs() <= fine_within {
+            FINE_MICROSTEPPING
+        } else {
+            COARSE_MICROSTEPPING
+        }
+    }
+}
+
+/// Dead reckoning of how far a motor has turned from how long it's run at
+/// each speed, as the step pulses themselves aren't counted.  Clockwise is
+/// positive.  The position leaves out travel taking up backlash after a
+/// reversal, so it follows the turntable side of the gears, while the motor
+/// position is everything the motor turned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepCounter {
+    /// Degrees turned at the start of the current run
+    position: f32,
+    motor_position: f32,
+    /// Play in the gears at the start of the current run
+    play: GearPlay,
+    /// When the current run started, its direction and speed in degrees per
+    /// second
+    run: Option<(u64, CircularDirection, f32)>,
+}
+
+impl Default for StepCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StepCounter {
+    pub fn new() -> Self {
+        Self {
+            position: 0.0,
+            motor_position: 0.0,
+            play: GearPlay::new(0.0),
+            run: None,
+        }
+    }
+
+    /// Change the motor degrees of backlash to allow for
+    pub fn set_backlash(&mut self, now_ms: u64, backlash: f32) {
+        self.fold(now_ms);
+        self.play = GearPlay::new(backlash);
+    }
+
+    /// Note the motor running from now on, ending any earlier run
+    pub fn start(&mut self, now_ms: u64, direction: CircularDirection, speed: f32) {
+        self.fold(now_ms);
+        self.run = Some((now_ms, direction, speed));
+    }
+
+    pub fn stop(&mut self, now_ms: u64) {
+        self.fold(now_ms);
+        self.run = None;
+    }
+
+    /// Make the current position zero
+    pub fn zero(&mut self, now_ms: u64) {
+        self.fold(now_ms);
+        self.position = 0.0;
+        self.motor_position = 0.0;
+    }
+
+    /// Degrees turned since the counter was zeroed
+    pub fn position(&self, now_ms: u64) -> f32 {
+        let mut play = self.play;
+        self.position + play.turn(self.turned(now_ms))
+    }
+
+    /// Degrees the motor turned since the counter was zeroed, including
+    /// taking up backlash
+    pub fn motor_position(&self, now_ms: u64) -> f32 {
+        self.motor_position + self.turned(now_ms)
+    }
+
+    /// Where the motor is in the play in the gears
+    pub fn play(&self, now_ms: u64) -> GearPlay {
+        let mut play = self.play;
+        play.turn(self.turned(now_ms));
+        play
+    }
+
+    /// Degrees per second the motor is turning, clockwise positive
+    pub fn velocity(&self) -> f32 {
+        match self.run {
+            Some((_, CircularDirection::CW, speed)) => speed,
+            Some((_, CircularDirection::CCW, speed)) => -speed,
+            None => 0.0,
+        }
+    }
+
+    /// Degrees turned in the current run
+    fn turned(&self, now_ms: u64) -> f32 {
+        match self.run {
+            Some((since, direction, speed)) => {
+                let turned = speed * now_ms.saturating_sub(since) as f32 / 1000.0;
+                match direction {
+                    CircularDirection::CW => turned,
+                    CircularDirection::CCW => -turned,
+                }
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Take the current run so far into the position
+    fn fold(&mut self, now_ms: u64) {
+        let turned = self.turned(now_ms);
+        self.position += self.play.turn(turned);
+        self.motor_position += turned;
+        if let Some((_, direction, speed)) = self.run {
+            self.run = Some((now_ms, direction, speed));
+        }
+    }
+}
+
+/// How a driver's mode pins select its microstepping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicrostepTable {
+    /// MS1-MS3, full to sixteenth steps
+    A4988,
+    /// MODE0-MODE2, full to thirty-second steps
+    Drv8825,
+}
+
+impl MicrostepTable {
+    /// Levels of the three mode pins for a resolution
+    pub fn levels(&self, microstepping: Microstepping) -> Result<[bool; 3], Error> {
+        use Microstepping::*;
+
+        match (self, microstepping) {
+            (_, Full) => Ok([false, false, false]),
+            (_, Half) => Ok([true, false, false]),
+            (_, Quarter) => Ok([false, true, false]),
+            (_, Eighth) => Ok([true, true, false]),
+            (MicrostepTable::A4988, Sixteenth) => Ok([true, true, true]),
+            (MicrostepTable::A4988, ThirtySecond) => Err(Error::InvalidSetting),
+            (MicrostepTable::Drv8825, Sixteenth) => Ok([false, false, true]),
+            (MicrostepTable::Drv8825, ThirtySecond) => Ok([true, false, true]),
+        }
+    }
+}
+
+/// Control of a stepper motor driver, whatever it's wired to
+pub trait StepperDriver {
+    fn enable(&mut self);
+    fn disable(&mut self);
+    fn set_direction(&mut self, dir: CircularDirection);
+    fn toggle_direction(&mut self);
+    /// Change the step rate in full steps per second, whatever the
+    /// microstepping, which takes effect from the next step
+    fn set_rate(&mut self, steps_per_second: u32);
+    /// Step rate in full steps per second
+    fn rate(&self) -> u32;
+    /// Speed the motor shaft turns at while running, in degrees per second
+    fn speed(&self) -> f32;
+    /// Full steps per revolution of the motor shaft
+    fn full_steps_per_revolution(&self) -> u32;
+    /// Change the step resolution, keeping the same speed.  Drivers without
+    /// the resolution get as close as they can.
+    fn set_microstepping(&mut self, microstepping: Microstepping) -> Result<(), Error>;
+}
+
+/// The timer behind a PWM channel, so its rate can be changed after setup
+pub trait PulseChannel {
+    /// Frequency the timer counts at before prescaling
+    fn timer_clock(clocks: &rcc::Clocks) -> u32;
+    fn set_prescaler(prescaler: u16);
+}
+
+impl PulseChannel for Tim15Ch2 {
+    fn timer_clock(clocks: &rcc::Clocks) -> u32 {
+        // Matches the HAL's calculation when setting up the PWM, which
+        // checks APB1's prescaler for this APB2 timer
+        apb_timer_clock(clocks.pclk2().0, clocks.ppre1())
+    }
+
+    fn set_prescaler(prescaler: u16) {
+        // Safe as only the PWM channel we own uses this timer
+        unsafe { (*pac::TIM15::ptr()).psc.write(|w| w.psc().bits(prescaler)) };
+    }
+}
+
+impl PulseChannel for Tim4Ch1 {
+    fn timer_clock(clocks: &rcc::Clocks) -> u32 {
+        apb_timer_clock(clocks.pclk1().0, clocks.ppre1())
+    }
+
+    fn set_prescaler(prescaler: u16) {
+        // Safe as only the PWM channel we own uses this timer
+        unsafe { (*pac::TIM4::ptr()).psc.write(|w| w.psc().bits(prescaler)) };
+    }
+}
+
+/// Timers on an APB bus run at twice its clock when the bus is divided down
+fn apb_timer_clock(pclk: u32, ppre: u8) -> u32 {
+    pclk * if ppre == 1 { 1 } else { 2 }
+}
+
+/// Prescaler register value for a timer counting at `timer_clock` to pulse
+/// `pulse_rate` times a second, `PULSE_RESOLUTION` ticks to each pulse.
+/// Rates beyond the 16 bit prescaler's reach get as close as they can.
+fn prescaler(timer_clock: u32, pulse_rate: u32) -> u16 {
+    let ticks = pulse_rate.max(1).saturating_mul(PULSE_RESOLUTION as u32);
+    ((timer_clock / ticks).clamp(1, 1 << 16) - 1) as u16
+}
+
+/// Which way to turn to correct a pointing error in degrees, or `None` if
+/// it's within the threshold.  `positive` is the way that corrects a
+/// positive error, which depends on how the motor is wired and mounted.
+pub fn correction(
+    error: f32,
+    threshold: f32,
+    positive: CircularDirection,
+) -> Option<CircularDirection> {
+    if error.abs() <= threshold {
+        None
+    } else if error > 0.0 {
+        Some(positive)
+    } else {
+        Some(positive.reverse())
+    }
+}
+
+/*
+ * Pinout:
+ *   PD8 -> MS1/MODE0
+ *   PD9 -> MS2/MODE1
+ *   PD10 -> MS3/MODE2
+ */
+/// Mode pins selecting a driver's microstepping
+pub struct MicrostepPins {
+    pins: [PXx<Output<PushPull>>; 3],
+    table: MicrostepTable,
+}
+
+impl MicrostepPins {
+    pub fn new<Pd8Mode, Pd9Mode, Pd10Mode>(
+        pd8: PD8<Pd8Mode>,
+        pd9: PD9<Pd9Mode>,
+        pd10: PD10<Pd10Mode>,
+        moder: &mut gpiod::MODER,
+        otyper: &mut gpiod::OTYPER,
+        table: MicrostepTable,
+    ) -> Result<Self, Error> {
+        let mut pins = Self {
+            pins: [
+                pd8.into_push_pull_output(moder, otyper)
+                    .downgrade()
+                    .downgrade(),
+                pd9.into_push_pull_output(moder, otyper)
+                    .downgrade()
+                    .downgrade(),
+                pd10.into_push_pull_output(moder, otyper)
+                    .downgrade()
+                    .downgrade(),
+            ],
+            table,
+        };
+        pins.set(DEFAULT_MICROSTEPPING)?;
+
+        Ok(pins)
+    }
+
+    pub fn set(&mut self, microstepping: Microstepping) -> Result<(), Error> {
+        let levels = self.table.levels(microstepping)?;
+        for (pin, high) in self.pins.iter_mut().zip(levels.iter()) {
+            if *high {
+                pin.set_high().ok();
+            } else {
+                pin.set_low().ok();
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /*
 not yet using enable pin because we don't have a 3rd 5v drain pin
 
+azimuth:
 pf6 - enable
 pf9 - direction
 pf10 - pulse
+
+elevation:
+pd14 - enable
+pd13 - direction
+pd12 - pulse
 */
 
-pub struct Stepper {
-    pub pin_enable: PF6<Output<OpenDrain>>,
-    pub pin_direction: PF9<Output<OpenDrain>>,
-    pub pwm_pulse: PwmChannel<Tim15Ch2, WithPins>,
+/// A step/direction driver pulsed by the given timer channel
+pub struct Stepper<Channel> {
+    pub pin_enable: PXx<Output<OpenDrain>>,
+    pub pin_direction: PXx<Output<OpenDrain>>,
+    pub pwm_pulse: PwmChannel<Channel, WithPins>,
+    /// A TMC2209 configured over UART, if one answered at setup
+    pub driver: Option<Tmc2209>,
+    /// Mode pins for drivers set up by pin rather than UART
+    pub microstep_pins: Option<MicrostepPins>,
+    microstepping: Microstepping,
+    /// Full steps per second
+    rate: u32,
+    clocks: rcc::Clocks,
 }
 
-impl Stepper {
+pub type AzimuthStepper = Stepper<Tim15Ch2>;
+pub type ElevationStepper = Stepper<Tim4Ch1>;
+
+impl Stepper<Tim15Ch2> {
     pub fn new<Pf6Mode, Pf9Mode, Pf10Mode>(
         pf6: PF6<Pf6Mode>,
         pf9: PF9<Pf9Mode>,
@@ -41,6 +444,8 @@ impl Stepper {
         afh: &mut gpiof::AFRH,
         // 2-channel timer w/ complimentary output
         tim15: pac::TIM15,
+        driver: Option<Tmc2209>,
+        microstep_pins: Option<MicrostepPins>,
         clocks: rcc::Clocks,
     ) -> Result<Self, i2c::Error> {
         /*
@@ -60,40 +465,395 @@ impl Stepper {
 
         // Setup hardware timer on PWM channel. Controller expects 13kHz
         hprintln!("Configuring PWM").ok();
-        let (_, pwm_ch2) = pwm::tim15(tim15, 200, 13_000.Hz(), &clocks);
+        let (_, pwm_ch2) = pwm::tim15(tim15, PULSE_RESOLUTION, PULSE_FREQUENCY_HZ.Hz(), &clocks);
         let mut pwm_pulse = pwm_ch2.output_to_pf10(pin_pulse);
         let duty_cycle = pwm_pulse.get_max_duty() / 2; // 50%
         pwm_pulse.set_duty(duty_cycle);
 
+        // Without an answer it's treated as a plain step/direction driver
+        hprintln!("Configuring TMC2209.").ok();
+        let driver =
+            driver.and_then(
+                |mut driver| match driver.configure(&DriverConfig::default()) {
+                    Ok(()) => Some(driver),
+                    Err(e) => {
+                        hprintln!("TMC2209 not configured: {:?}", e).ok();
+                        None
+                    }
+                },
+            );
+
         hprintln!("Done configuring Stepper.").ok();
 
         Ok(Self {
-            pin_enable,
-            pin_direction,
+            pin_enable: pin_enable.downgrade().downgrade(),
+            pin_direction: pin_direction.downgrade().downgrade(),
             pwm_pulse,
+            driver,
+            microstep_pins,
+            microstepping: DEFAULT_MICROSTEPPING,
+            rate: PULSE_FREQUENCY_HZ / DEFAULT_MICROSTEPPING.divisor(),
+            clocks,
         })
     }
+}
 
-    pub fn enable(&mut self) {
+impl Stepper<Tim4Ch1> {
+    pub fn new<Pd12Mode, Pd13Mode, Pd14Mode>(
+        pd12: PD12<Pd12Mode>,
+        pd13: PD13<Pd13Mode>,
+        pd14: PD14<Pd14Mode>,
+        moder: &mut gpiod::MODER,
+        otyper: &mut gpiod::OTYPER,
+        afh: &mut gpiod::AFRH,
+        tim4: pac::TIM4,
+        clocks: rcc::Clocks,
+    ) -> Result<Self, i2c::Error> {
+        /*
+         * Pinout:
+         *   PD14 -> Enable
+         *   PD13 -> Direction
+         *   PD12 -> Pulse
+         */
+        let pin_enable = pd14.into_open_drain_output(moder, otyper);
+        let pin_direction = pd13.into_open_drain_output(moder, otyper);
+        let pin_pulse: Pd12Af2Pin = pd12.into_af_open_drain(moder, otyper, afh);
+
+        let (pwm_ch1, _, _, _) =
+            pwm::tim4(tim4, PULSE_RESOLUTION, PULSE_FREQUENCY_HZ.Hz(), &clocks);
+        let mut pwm_pulse = pwm_ch1.output_to_pd12(pin_pulse);
+        let duty_cycle = pwm_pulse.get_max_duty() / 2; // 50%
+        pwm_pulse.set_duty(duty_cycle);
+
+        Ok(Self {
+            pin_enable: pin_enable.downgrade().downgrade(),
+            pin_direction: pin_direction.downgrade().downgrade(),
+            pwm_pulse,
+            driver: None,
+            microstep_pins: None,
+            microstepping: DEFAULT_MICROSTEPPING,
+            rate: PULSE_FREQUENCY_HZ / DEFAULT_MICROSTEPPING.divisor(),
+            clocks,
+        })
+    }
+}
+
+impl<Channel> Stepper<Channel> {
+    /// Read the TMC2209's status flags
+    pub fn driver_status(&mut self) -> Result<DriverStatus, Error> {
+        self.driver.as_mut().ok_or(Error::Hardware)?.status()
+    }
+
+    /// Read the TMC2209's StallGuard load measurement
+    pub fn stallguard(&mut self) -> Result<u16, Error> {
+        self.driver.as_mut().ok_or(Error::Hardware)?.stallguard()
+    }
+
+    pub fn microstepping(&self) -> Microstepping {
+        self.microstepping
+    }
+}
+
+impl<Channel> StepperDriver for Stepper<Channel>
+where
+    Channel: PulseChannel,
+    PwmChannel<Channel, WithPins>: PwmPin<Duty = u16>,
+{
+    fn enable(&mut self) {
         self.pwm_pulse.enable();
     }
 
-    pub fn disable(&mut self) {
+    fn disable(&mut self) {
         self.pwm_pulse.disable();
     }
 
-    pub fn set_direction(&mut self, dir: CircularDirection) {
+    fn set_direction(&mut self, dir: CircularDirection) {
         match dir {
             CircularDirection::CW => self.pin_direction.set_high().ok(),
             _ => self.pin_direction.set_low().ok(),
         };
     }
 
-    pub fn toggle_direction(&mut self) {
+    fn toggle_direction(&mut self) {
         if self.pin_direction.is_high().unwrap() {
             self.pin_direction.set_low().ok()
         } else {
             self.pin_direction.set_high().ok()
         };
     }
+
+    fn set_rate(&mut self, steps_per_second: u32) {
+        self.rate = steps_per_second.max(1);
+
+        let pulse_rate = self.microstepping.pulse_rate(self.rate);
+        Channel::set_prescaler(prescaler(Channel::timer_clock(&self.clocks), pulse_rate));
+    }
+
+    fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn speed(&self) -> f32 {
+        self.rate as f32 * 360.0 / FULL_STEPS_PER_REVOLUTION as f32
+    }
+
+    fn full_steps_per_revolution(&self) -> u32 {
+        FULL_STEPS_PER_REVOLUTION
+    }
+
+    /// Needs mode pins or a TMC2209 to change anything, otherwise the
+    /// resolution is whatever the driver's been wired for
+    fn set_microstepping(&mut self, microstepping: Microstepping) -> Result<(), Error> {
+        if microstepping == self.microstepping {
+            return Ok(());
+        }
+
+        match (&mut self.microstep_pins, &mut self.driver) {
+            (Some(pins), _) => pins.set(microstepping)?,
+            (None, Some(driver)) => driver.set_microsteps(microstepping.divisor() as u16)?,
+            (None, None) => return Err(Error::Hardware),
+        }
+        self.microstepping = microstepping;
+
+        // Keep turning at the same speed
+        self.set_rate(self.rate);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Microstepping; 6] = [
+        Microstepping::Full,
+        Microstepping::Half,
+        Microstepping::Quarter,
+        Microstepping::Eighth,
+        Microstepping::Sixteenth,
+        Microstepping::ThirtySecond,
+    ];
+
+    #[test]
+    fn divisors() {
+        let divisors: [u32; 6] = [1, 2, 4, 8, 16, 32];
+        for (microstepping, divisor) in ALL.iter().zip(divisors.iter()) {
+            assert_eq!(microstepping.divisor(), *divisor);
+            assert_eq!(
+                Microstepping::from_divisor(*divisor).unwrap(),
+                *microstepping
+            );
+        }
+        for divisor in [0, 3, 64, 256] {
+            assert!(matches!(
+                Microstepping::from_divisor(divisor),
+                Err(Error::InvalidSetting)
+            ));
+        }
+    }
+
+    #[test]
+    fn a4988_mode_pins() {
+        // MS1, MS2, MS3 from the A4988 datasheet's microstep resolution table
+        let expected = [
+            [false, false, false],
+            [true, false, false],
+            [false, true, false],
+            [true, true, false],
+            [true, true, true],
+        ];
+        for (microstepping, levels) in ALL.iter().zip(expected.iter()) {
+            assert_eq!(
+                MicrostepTable::A4988.levels(*microstepping).unwrap(),
+                *levels
+            );
+        }
+        assert!(matches!(
+            MicrostepTable::A4988.levels(Microstepping::ThirtySecond),
+            Err(Error::InvalidSetting)
+        ));
+    }
+
+    #[test]
+    fn drv8825_mode_pins() {
+        // MODE0, MODE1, MODE2 from the DRV8825 datasheet's stepping format
+        // table
+        let expected = [
+            [false, false, false],
+            [true, false, false],
+            [false, true, false],
+            [true, true, false],
+            [false, false, true],
+            [true, false, true],
+        ];
+        for (microstepping, levels) in ALL.iter().zip(expected.iter()) {
+            assert_eq!(
+                MicrostepTable::Drv8825.levels(*microstepping).unwrap(),
+                *levels
+            );
+        }
+    }
+
+    #[test]
+    fn pulses_for_an_angle() {
+        assert_eq!(Microstepping::Full.degrees_per_pulse(), 1.8);
+        assert_eq!(Microstepping::Full.pulses(90.0), 50);
+        assert_eq!(Microstepping::Sixteenth.pulses(90.0), 800);
+        assert_eq!(Microstepping::Sixteenth.pulses(-90.0), 800);
+        // To the nearest pulse
+        assert_eq!(Microstepping::Full.pulses(2.6), 1);
+        assert_eq!(Microstepping::Full.pulses(2.8), 2);
+        assert_eq!(Microstepping::Full.pulses(0.0), 0);
+        // A revolution whatever the resolution
+        for microstepping in ALL.iter() {
+            assert_eq!(
+                microstepping.pulses(360.0),
+                FULL_STEPS_PER_REVOLUTION * microstepping.divisor()
+            );
+        }
+    }
+
+    #[test]
+    fn pulse_rates() {
+        assert_eq!(Microstepping::Full.pulse_rate(200), 200);
+        assert_eq!(Microstepping::ThirtySecond.pulse_rate(200), 6_400);
+        assert_eq!(Microstepping::ThirtySecond.pulse_rate(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn fine_near_the_target() {
+        assert_eq!(Microstepping::for_error(2.0, 5.0), FINE_MICROSTEPPING);
+        assert_eq!(Microstepping::for_error(-5.0, 5.0), FINE_MICROSTEPPING);
+        assert_eq!(Microstepping::for_error(5.1, 5.0), COARSE_MICROSTEPPING);
+        assert_eq!(Microstepping::for_error(-90.0, 5.0), COARSE_MICROSTEPPING);
+    }
+
+    /// Pulses per second a prescaler register value gives
+    fn pulse_rate(timer_clock: u32, prescaler: u16) -> f32 {
+        timer_clock as f32 / (prescaler as u32 + 1) as f32 / PULSE_RESOLUTION as f32
+    }
+
+    #[test]
+    fn prescaler_for_the_default_rate() {
+        // The pulse frequency the drivers start at, from the 8MHz clock
+        let pulses = DEFAULT_MICROSTEPPING.pulse_rate(PULSE_FREQUENCY_HZ / 16);
+        let prescaler = prescaler(8_000_000, pulses);
+        assert_eq!(prescaler, 2);
+        assert!((pulse_rate(8_000_000, prescaler) - 13_333.3).abs() < 1.0);
+    }
+
+    #[test]
+    fn prescaler_limits() {
+        // The fastest rates run the timer undivided
+        assert_eq!(prescaler(8_000_000, 40_000), 0);
+        assert_eq!(prescaler(8_000_000, u32::MAX), 0);
+        assert_eq!(
+            prescaler(72_000_000, Microstepping::ThirtySecond.pulse_rate(u32::MAX)),
+            0
+        );
+
+        // A full step a second still fits from 8MHz
+        let prescaler_8mhz = prescaler(8_000_000, Microstepping::Full.pulse_rate(1));
+        assert_eq!(prescaler_8mhz, 39_999);
+        assert_eq!(pulse_rate(8_000_000, prescaler_8mhz), 1.0);
+        // But not from 72MHz, which bottoms out at the largest prescaler
+        assert_eq!(prescaler(72_000_000, 1), u16::MAX);
+        assert_eq!(prescaler(72_000_000, 0), u16::MAX);
+    }
+
+    #[test]
+    fn channel_clocks() {
+        // Both buses undivided from the 8MHz clock the board starts on
+        assert_eq!(apb_timer_clock(8_000_000, 1), 8_000_000);
+        // APB1 halved from 72MHz, so TIM4 runs at the full 72MHz
+        assert_eq!(apb_timer_clock(36_000_000, 2), 72_000_000);
+        assert_eq!(apb_timer_clock(18_000_000, 4), 36_000_000);
+    }
+
+    #[test]
+    fn channel_rates() {
+        // The azimuth on TIM15 and the elevation on TIM4 step at the same
+        // rates from the default clocks
+        let timer_clock = apb_timer_clock(8_000_000, 1);
+        for &(rate, expected) in [(800, 2), (100, 24), (1, 2_499)].iter() {
+            let pulses = DEFAULT_MICROSTEPPING.pulse_rate(rate);
+            let prescaler = prescaler(timer_clock, pulses);
+            assert_eq!(prescaler, expected, "{} steps/s", rate);
+            let error = pulse_rate(timer_clock, prescaler) / pulses as f32 - 1.0;
+            assert!(error.abs() < 0.05, "{} steps/s", rate);
+        }
+
+        // TIM4 from a halved 36MHz APB1
+        let timer_clock = apb_timer_clock(36_000_000, 2);
+        let pulses = DEFAULT_MICROSTEPPING.pulse_rate(800);
+        assert_eq!(prescaler(timer_clock, pulses), 27);
+        assert_eq!(
+            prescaler(timer_clock, Microstepping::Full.pulse_rate(1)),
+            u16::MAX
+        );
+    }
+
+    #[test]
+    fn counts_runs() {
+        let mut counter = StepCounter::new();
+        counter.start(0, CircularDirection::CW, 10.0);
+        assert_eq!(counter.position(500), 5.0);
+        assert_eq!(counter.velocity(), 10.0);
+        counter.start(1_000, CircularDirection::CCW, 20.0);
+        assert_eq!(counter.position(1_250), 5.0);
+        counter.stop(2_000);
+        assert_eq!(counter.position(5_000), -10.0);
+        assert_eq!(counter.velocity(), 0.0);
+
+        counter.zero(6_000);
+        assert_eq!(counter.position(6_000), 0.0);
+    }
+
+    #[test]
+    fn same_direction_runs_count_in_full() {
+        let mut counter = StepCounter::new();
+        counter.set_backlash(0, 10.0);
+        counter.start(0, CircularDirection::CW, 10.0);
+        counter.stop(2_000);
+        counter.start(3_000, CircularDirection::CW, 10.0);
+        counter.stop(5_000);
+        assert_eq!(counter.position(6_000), 40.0);
+    }
+
+    #[test]
+    fn reversals_take_up_the_backlash() {
+        let mut counter = StepCounter::new();
+        counter.set_backlash(0, 10.0);
+        counter.start(0, CircularDirection::CW, 10.0);
+        counter.stop(3_000);
+        assert_eq!(counter.position(3_000), 30.0);
+
+        // The first 10° back don't count
+        counter.start(3_000, CircularDirection::CCW, 10.0);
+        assert_eq!(counter.position(3_500), 30.0);
+        assert_eq!(counter.position(4_000), 30.0);
+        assert_eq!(counter.position(5_000), 20.0);
+
+        // Nor the first 10° forward again
+        counter.start(5_000, CircularDirection::CW, 10.0);
+        assert_eq!(counter.position(6_000), 20.0);
+        assert_eq!(counter.position(7_000), 30.0);
+    }
+
+    #[test]
+    fn motor_position_includes_the_take_up() {
+        let mut counter = StepCounter::new();
+        counter.set_backlash(0, 10.0);
+        counter.start(0, CircularDirection::CW, 10.0);
+        counter.start(3_000, CircularDirection::CCW, 10.0);
+        assert_eq!(counter.motor_position(3_500), 25.0);
+        assert_eq!(counter.play(3_500).take_up(CircularDirection::CCW), 5.0);
+        assert_eq!(counter.play(3_500).take_up(CircularDirection::CW), 5.0);
+        counter.stop(5_000);
+        assert_eq!(counter.motor_position(6_000), 10.0);
+        assert_eq!(counter.play(6_000).take_up(CircularDirection::CW), 10.0);
+
+        counter.zero(6_000);
+        assert_eq!(counter.motor_position(6_000), 0.0);
+    }
 }
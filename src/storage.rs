@@ -0,0 +1,109 @@
+/// Settings kept in the last page of flash, so they survive a reset.  The
+/// page is left out of the program's flash in memory.x.
+use core::ptr;
+
+use stm32f3xx_hal::flash;
+use stm32f3xx_hal::pac;
+
+use crate::Error;
+
+/// Start of the last 2K page of the STM32F303VC's 256K of flash
+pub const PAGE_ADDRESS: u32 = 0x0803_f800;
+
+pub const PAGE_SIZE: usize = 2048;
+
+/// Written to FLASH_KEYR in turn to unlock programming
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+pub struct Storage {
+    _flash: flash::Parts,
+}
+
+impl Storage {
+    /// Take the flash once the clocks no longer need its access control
+    /// register
+    pub fn new(flash: flash::Parts) -> Self {
+        Self { _flash: flash }
+    }
+
+    /// Read words from the start of the page.  Erased flash reads as all
+    /// ones.
+    pub fn read(&self, words: &mut [u32]) {
+        for (i, word) in words.iter_mut().take(PAGE_SIZE / 4).enumerate() {
+            // Safe as the page is reserved and always mapped
+            *word = unsafe { ptr::read_volatile((PAGE_ADDRESS as *const u32).add(i)) };
+        }
+    }
+
+    /// Erase the page and write words from its start.  The CPU stalls on
+    /// flash reads while this runs, for some tens of milliseconds.
+    pub fn write(&mut self, words: &[u32]) -> Result<(), Error> {
+        if words.len() > PAGE_SIZE / 4 {
+            return Err(Error::Overflow);
+        }
+
+        // Safe as only this owns the flash controller once the clocks are
+        // frozen
+        let regs = unsafe { &*pac::FLASH::ptr() };
+
+        wait(regs);
+        if regs.cr.read().lock().bit_is_set() {
+            regs.keyr.write(|w| w.fkeyr().bits(KEY1));
+            regs.keyr.write(|w| w.fkeyr().bits(KEY2));
+        }
+
+        let result = erase(regs).and_then(|_| program(regs, words));
+        regs.cr.modify(|_, w| w.lock().set_bit());
+
+        result
+    }
+}
+
+fn erase(regs: &pac::flash::RegisterBlock) -> Result<(), Error> {
+    regs.cr.modify(|_, w| w.per().set_bit());
+    regs.ar.write(|w| w.far().bits(PAGE_ADDRESS));
+    regs.cr.modify(|_, w| w.strt().set_bit());
+    let result = finish(regs);
+    regs.cr.modify(|_, w| w.per().clear_bit());
+
+    result
+}
+
+/// Flash is programmed a half word at a time
+fn program(regs: &pac::flash::RegisterBlock, words: &[u32]) -> Result<(), Error> {
+    regs.cr.modify(|_, w| w.pg().set_bit());
+    let address = PAGE_ADDRESS as *mut u16;
+
+    let result = words
+        .iter()
+        .flat_map(|word| [*word as u16, (*word >> 16) as u16])
+        .enumerate()
+        .try_for_each(|(i, half)| {
+            // Safe as the page is reserved and was just erased
+            unsafe { ptr::write_volatile(address.add(i), half) };
+            finish(regs)
+        });
+    regs.cr.modify(|_, w| w.pg().clear_bit());
+
+    result
+}
+
+fn wait(regs: &pac::flash::RegisterBlock) {
+    while regs.sr.read().bsy().bit_is_set() {}
+}
+
+/// Wait for an erase or program to finish and clear its status
+fn finish(regs: &pac::flash::RegisterBlock) -> Result<(), Error> {
+    wait(regs);
+    let sr = regs.sr.read();
+    let failed = sr.pgerr().bit_is_set() || sr.wrprterr().bit_is_set();
+    regs.sr
+        .write(|w| w.eop().set_bit().pgerr().set_bit().wrprterr().set_bit());
+
+    if failed || sr.eop().bit_is_clear() {
+        Err(Error::Hardware)
+    } else {
+        Ok(())
+    }
+}
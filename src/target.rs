@@ -0,0 +1,51 @@
+/// What the device should point at
+use crate::datetime::DateTime;
+use crate::geo::astro::Equatorial;
+use crate::geo::{solar, Angle, GeoPoint, Horizontal};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Target {
+    /// True north
+    #[default]
+    North,
+    /// The sun's azimuth at the current time and location
+    Sun,
+    /// A fixed object on the celestial sphere, in J2000.0 coordinates
+    Celestial(Equatorial),
+    /// The satellite whose orbit is stored in the config.  Its position comes
+    /// from `geo::satellite` rather than from here.
+    Satellite,
+}
+
+impl Target {
+    /// Position of the target in the sky, or `None` if it doesn't have one or
+    /// we don't have the time or location needed to find it
+    pub fn position(
+        &self,
+        now: Option<DateTime>,
+        location: Option<GeoPoint>,
+    ) -> Option<Horizontal> {
+        match self {
+            Target::North => None,
+            Target::Sun => Some(solar::sun_position(&now?, &location?)),
+            Target::Celestial(coordinates) => {
+                let now = now?;
+                Some(
+                    coordinates
+                        .precess_from_j2000(&now)
+                        .to_horizontal(&now, &location?),
+                )
+            }
+            Target::Satellite => None,
+        }
+    }
+
+    /// Azimuth of the target clockwise from true north, or `None` if we don't
+    /// have the time or location needed to find it
+    pub fn azimuth(&self, now: Option<DateTime>, location: Option<GeoPoint>) -> Option<Angle> {
+        match self {
+            Target::North => Some(Angle::ZERO),
+            _ => Some(self.position(now, location)?.azimuth),
+        }
+    }
+}
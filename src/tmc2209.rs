@@ -0,0 +1,545 @@
+/// Struct for TMC2209 stepper driver configuration over its single wire UART
+use stm32f3xx_hal::gpio::{gpioc, gpiod, PushPull, AF5};
+use stm32f3xx_hal::nb;
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::rcc;
+use stm32f3xx_hal::serial::Serial;
+
+use crate::stepper::DEFAULT_MICROSTEPPING;
+use crate::Error;
+
+/// The driver detects the baud rate from the sync nibble of each datagram
+pub const BAUD_RATE: u32 = 115_200;
+
+/// Set by the MS1 and MS2 pins, both low by default
+pub const DEFAULT_SLAVE_ADDRESS: u8 = 0;
+
+/// Polls of the USART to wait for each byte of a reply, a few milliseconds
+/// at our clock speeds
+const READ_TIMEOUT_POLLS: u32 = 20_000;
+
+const SYNC: u8 = 0x05;
+const MASTER_ADDRESS: u8 = 0xff;
+const WRITE: u8 = 0x80;
+
+pub const WRITE_LENGTH: usize = 8;
+pub const READ_REQUEST_LENGTH: usize = 4;
+pub const READ_REPLY_LENGTH: usize = 8;
+
+/// Register addresses
+pub mod register {
+    pub const GCONF: u8 = 0x00;
+    pub const GSTAT: u8 = 0x01;
+    pub const IFCNT: u8 = 0x02;
+    pub const IHOLD_IRUN: u8 = 0x10;
+    pub const TPWMTHRS: u8 = 0x13;
+    pub const TCOOLTHRS: u8 = 0x14;
+    pub const SGTHRS: u8 = 0x40;
+    pub const SG_RESULT: u8 = 0x41;
+    pub const CHOPCONF: u8 = 0x6c;
+    pub const DRV_STATUS: u8 = 0x6f;
+}
+
+const GCONF_I_SCALE_ANALOG: u32 = 1 << 0;
+const GCONF_EN_SPREAD_CYCLE: u32 = 1 << 2;
+const GCONF_PDN_DISABLE: u32 = 1 << 6;
+const GCONF_MSTEP_REG_SELECT: u32 = 1 << 7;
+const GCONF_MULTISTEP_FILT: u32 = 1 << 8;
+
+/// Power on CHOPCONF (TOFF 3, HSTRT 5, interpolation on) less the
+/// microstep resolution
+const CHOPCONF_BASE: u32 = 0x1000_0053;
+const CHOPCONF_MRES_SHIFT: u32 = 24;
+
+/// Standstill time before dropping to the hold current
+const IHOLDDELAY: u32 = 8;
+
+/// Let StallGuard run at all speeds
+const TCOOLTHRS_MAX: u32 = 0xf_ffff;
+
+/// CRC8 with polynomial x^8 + x^2 + x + 1, each byte fed in LSB first
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            crc = if (crc >> 7) ^ (byte & 1) != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+            byte >>= 1;
+        }
+    }
+
+    crc
+}
+
+/// Datagram writing a value to a register
+pub fn write_datagram(slave: u8, register: u8, value: u32) -> [u8; WRITE_LENGTH] {
+    let value = value.to_be_bytes();
+    let mut datagram = [
+        SYNC,
+        slave,
+        register | WRITE,
+        value[0],
+        value[1],
+        value[2],
+        value[3],
+        0,
+    ];
+    datagram[WRITE_LENGTH - 1] = crc8(&datagram[..WRITE_LENGTH - 1]);
+
+    datagram
+}
+
+/// Datagram asking for a register's value
+pub fn read_request(slave: u8, register: u8) -> [u8; READ_REQUEST_LENGTH] {
+    let mut datagram = [SYNC, slave, register & !WRITE, 0];
+    datagram[READ_REQUEST_LENGTH - 1] = crc8(&datagram[..READ_REQUEST_LENGTH - 1]);
+
+    datagram
+}
+
+/// Check a reply to a read request, returning its register and value
+pub fn parse_reply(reply: &[u8; READ_REPLY_LENGTH]) -> Result<(u8, u32), Error> {
+    if reply[0] & 0x0f != SYNC || reply[1] != MASTER_ADDRESS {
+        return Err(Error::Parse);
+    }
+    if crc8(&reply[..READ_REPLY_LENGTH - 1]) != reply[READ_REPLY_LENGTH - 1] {
+        return Err(Error::Checksum);
+    }
+
+    let value = u32::from_be_bytes([reply[3], reply[4], reply[5], reply[6]]);
+    Ok((reply[2], value))
+}
+
+/// How the driver should run the motor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriverConfig {
+    /// Microsteps per full step, a power of two up to 256
+    pub microsteps: u16,
+    /// Current while moving, 0-31 in 32nds of full scale
+    pub run_current: u8,
+    /// Current at standstill, 0-31 in 32nds of full scale
+    pub hold_current: u8,
+    /// Quiet StealthChop rather than SpreadCycle, needed for StallGuard
+    pub stealth_chop: bool,
+    /// StallGuard sensitivity, higher stalls more easily
+    pub stallguard_threshold: u8,
+}
+
+impl Default for DriverConfig {
+    fn default() -> Self {
+        Self {
+            microsteps: DEFAULT_MICROSTEPPING.divisor() as u16,
+            run_current: 16,
+            hold_current: 8,
+            stealth_chop: true,
+            stallguard_threshold: 0,
+        }
+    }
+}
+
+impl DriverConfig {
+    pub fn gconf(&self) -> u32 {
+        let mut gconf = GCONF_I_SCALE_ANALOG
+            | GCONF_PDN_DISABLE
+            | GCONF_MSTEP_REG_SELECT
+            | GCONF_MULTISTEP_FILT;
+        if !self.stealth_chop {
+            gconf |= GCONF_EN_SPREAD_CYCLE;
+        }
+
+        gconf
+    }
+
+    pub fn chopconf(&self) -> Result<u32, Error> {
+        Ok(CHOPCONF_BASE | microstep_resolution(self.microsteps)? << CHOPCONF_MRES_SHIFT)
+    }
+
+    pub fn ihold_irun(&self) -> Result<u32, Error> {
+        if self.run_current > 31 || self.hold_current > 31 {
+            return Err(Error::InvalidSetting);
+        }
+
+        Ok(self.hold_current as u32 | (self.run_current as u32) << 8 | IHOLDDELAY << 16)
+    }
+}
+
+/// The MRES field for a number of microsteps: 0 for 256 down to 8 for full
+/// steps
+pub fn microstep_resolution(microsteps: u16) -> Result<u32, Error> {
+    if !microsteps.is_power_of_two() || microsteps > 256 {
+        return Err(Error::InvalidSetting);
+    }
+
+    Ok(8 - microsteps.trailing_zeros())
+}
+
+/// The DRV_STATUS register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriverStatus(pub u32);
+
+impl DriverStatus {
+    pub fn over_temperature_warning(&self) -> bool {
+        self.0 & 1 << 0 != 0
+    }
+
+    pub fn over_temperature(&self) -> bool {
+        self.0 & 1 << 1 != 0
+    }
+
+    /// Short to ground on either coil
+    pub fn short_to_ground(&self) -> bool {
+        self.0 & 0b11 << 2 != 0
+    }
+
+    /// Short across either coil's low side MOSFETs
+    pub fn short_to_supply(&self) -> bool {
+        self.0 & 0b11 << 4 != 0
+    }
+
+    /// Open load on either coil
+    pub fn open_load(&self) -> bool {
+        self.0 & 0b11 << 6 != 0
+    }
+
+    /// Actual motor current, 0-31 in 32nds of full scale
+    pub fn current(&self) -> u8 {
+        (self.0 >> 16 & 0x1f) as u8
+    }
+
+    pub fn stealth_chop(&self) -> bool {
+        self.0 & 1 << 30 != 0
+    }
+
+    pub fn standstill(&self) -> bool {
+        self.0 & 1 << 31 != 0
+    }
+
+    /// Whether anything needs the driver shutting down
+    pub fn is_fault(&self) -> bool {
+        self.over_temperature() || self.short_to_ground() || self.short_to_supply()
+    }
+}
+
+pub type DriverSerial = Serial<pac::UART5, (gpioc::PC12<AF5<PushPull>>, gpiod::PD2<AF5<PushPull>>)>;
+
+/*
+ * Pinout:
+ *   PC12 -> UART5 TX, through 1k to PDN_UART
+ *   PD2 -> UART5 RX, direct to PDN_UART
+ *
+ * Everything we send is echoed back on RX, so it's read and discarded before
+ * any reply.
+ */
+pub struct Tmc2209 {
+    serial: DriverSerial,
+    slave: u8,
+    config: DriverConfig,
+}
+
+impl Tmc2209 {
+    pub fn new<Pc12Mode, Pd2Mode>(
+        pc12: gpioc::PC12<Pc12Mode>,
+        pd2: gpiod::PD2<Pd2Mode>,
+        gpioc_moder: &mut gpioc::MODER,
+        gpioc_otyper: &mut gpioc::OTYPER,
+        gpioc_afh: &mut gpioc::AFRH,
+        gpiod_moder: &mut gpiod::MODER,
+        gpiod_otyper: &mut gpiod::OTYPER,
+        gpiod_afl: &mut gpiod::AFRL,
+        uart5: pac::UART5,
+        clocks: rcc::Clocks,
+        advanced_periph_bus: &mut rcc::APB1,
+    ) -> Result<Self, Error> {
+        let tx = pc12.into_af_push_pull(gpioc_moder, gpioc_otyper, gpioc_afh);
+        let rx = pd2.into_af_push_pull(gpiod_moder, gpiod_otyper, gpiod_afl);
+        let serial = Serial::new(uart5, (tx, rx), BAUD_RATE.Bd(), clocks, advanced_periph_bus);
+
+        Ok(Self {
+            serial,
+            slave: DEFAULT_SLAVE_ADDRESS,
+            config: DriverConfig::default(),
+        })
+    }
+
+    /// Apply a configuration, checking the driver is there by reading back
+    /// the interface transmission counter
+    pub fn configure(&mut self, config: &DriverConfig) -> Result<(), Error> {
+        let count = self.read_register(register::IFCNT)?;
+
+        self.write_register(register::GCONF, config.gconf())?;
+        self.write_register(register::CHOPCONF, config.chopconf()?)?;
+        self.write_register(register::IHOLD_IRUN, config.ihold_irun()?)?;
+        self.write_register(register::TPWMTHRS, 0)?;
+        self.write_register(register::TCOOLTHRS, TCOOLTHRS_MAX)?;
+        self.write_register(register::SGTHRS, config.stallguard_threshold as u32)?;
+
+        // Each successful write bumps the 8 bit counter
+        if self.read_register(register::IFCNT)? != (count + 6) & 0xff {
+            return Err(Error::Hardware);
+        }
+
+        self.config = *config;
+        Ok(())
+    }
+
+    pub fn config(&self) -> &DriverConfig {
+        &self.config
+    }
+
+    /// Change the microstep resolution, leaving the rest of the
+    /// configuration as it is
+    pub fn set_microsteps(&mut self, microsteps: u16) -> Result<(), Error> {
+        let config = DriverConfig {
+            microsteps,
+            ..self.config
+        };
+        self.write_register(register::CHOPCONF, config.chopconf()?)?;
+
+        self.config = config;
+        Ok(())
+    }
+
+    pub fn status(&mut self) -> Result<DriverStatus, Error> {
+        self.read_register(register::DRV_STATUS).map(DriverStatus)
+    }
+
+    /// StallGuard load measurement, lower is closer to stalling
+    pub fn stallguard(&mut self) -> Result<u16, Error> {
+        Ok(self.read_register(register::SG_RESULT)? as u16 & 0x3ff)
+    }
+
+    pub fn write_register(&mut self, register: u8, value: u32) -> Result<(), Error> {
+        let datagram = write_datagram(self.slave, register, value);
+        self.send(&datagram)
+    }
+
+    pub fn read_register(&mut self, register: u8) -> Result<u32, Error> {
+        self.send(&read_request(self.slave, register))?;
+
+        let mut reply = [0; READ_REPLY_LENGTH];
+        for byte in reply.iter_mut() {
+            *byte = self.receive()?;
+        }
+
+        match parse_reply(&reply)? {
+            (replied, value) if replied == register => Ok(value),
+            _ => Err(Error::Parse),
+        }
+    }
+
+    /// Send a datagram and consume its echo
+    fn send(&mut self, datagram: &[u8]) -> Result<(), Error> {
+        // Drop anything left over from an earlier exchange
+        while self.serial.read().is_ok() {}
+
+        for &byte in datagram {
+            nb::block!(self.serial.write(byte)).map_err(|_| Error::Hardware)?;
+        }
+        for &byte in datagram {
+            if self.receive()? != byte {
+                return Err(Error::Hardware);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<u8, Error> {
+        for _ in 0..READ_TIMEOUT_POLLS {
+            match self.serial.read() {
+                Ok(byte) => return Ok(byte),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(_)) => return Err(Error::Hardware),
+            }
+        }
+
+        Err(Error::Hardware)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reply from the driver, with the CRC it would send
+    fn reply(register: u8, value: u32) -> [u8; READ_REPLY_LENGTH] {
+        let value = value.to_be_bytes();
+        let mut reply = [
+            SYNC,
+            MASTER_ADDRESS,
+            register,
+            value[0],
+            value[1],
+            value[2],
+            value[3],
+            0,
+        ];
+        reply[READ_REPLY_LENGTH - 1] = crc8(&reply[..READ_REPLY_LENGTH - 1]);
+
+        reply
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc8(&[]), 0);
+        // The GCONF read request from the datasheet
+        assert_eq!(crc8(&[0x05, 0x00, 0x00]), 0x48);
+        assert_eq!(crc8(&[0x05, 0x00, 0x02]), 0x8f);
+        assert_eq!(crc8(&[0x05, 0xff, 0x02, 0x00, 0x00, 0x00, 0x07]), 0xe2);
+    }
+
+    #[test]
+    fn write_datagrams() {
+        assert_eq!(
+            write_datagram(0, register::CHOPCONF, 0x1000_0053),
+            [0x05, 0x00, 0xec, 0x10, 0x00, 0x00, 0x53, 0x9c]
+        );
+        assert_eq!(
+            write_datagram(0, register::IHOLD_IRUN, 0x0008_1008),
+            [0x05, 0x00, 0x90, 0x00, 0x08, 0x10, 0x08, 0x2a]
+        );
+
+        let datagram = write_datagram(3, register::SGTHRS, 0x1234_5678);
+        assert_eq!(datagram[1], 3);
+        assert_eq!(datagram[2], register::SGTHRS | WRITE);
+        assert_eq!(datagram[3..7], [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(datagram[7], crc8(&datagram[..7]));
+    }
+
+    #[test]
+    fn read_requests() {
+        assert_eq!(read_request(0, register::GCONF), [0x05, 0x00, 0x00, 0x48]);
+        assert_eq!(read_request(0, register::IFCNT), [0x05, 0x00, 0x02, 0x8f]);
+        // A read never has the write bit set
+        assert_eq!(read_request(0, register::IFCNT | WRITE)[2], register::IFCNT);
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(
+            parse_reply(&[0x05, 0xff, 0x02, 0x00, 0x00, 0x00, 0x07, 0xe2]).unwrap(),
+            (register::IFCNT, 7)
+        );
+        assert_eq!(
+            parse_reply(&reply(register::DRV_STATUS, 0xc01f_0002)).unwrap(),
+            (register::DRV_STATUS, 0xc01f_0002)
+        );
+    }
+
+    #[test]
+    fn bad_replies() {
+        let mut corrupt = reply(register::IFCNT, 7);
+        corrupt[6] ^= 1;
+        assert!(matches!(parse_reply(&corrupt), Err(Error::Checksum)));
+
+        let mut no_sync = reply(register::IFCNT, 7);
+        no_sync[0] = 0x0a;
+        no_sync[7] = crc8(&no_sync[..7]);
+        assert!(matches!(parse_reply(&no_sync), Err(Error::Parse)));
+
+        // Our own read request echoed back isn't a reply
+        let mut echo = reply(register::IFCNT, 7);
+        echo[1] = DEFAULT_SLAVE_ADDRESS;
+        echo[7] = crc8(&echo[..7]);
+        assert!(matches!(parse_reply(&echo), Err(Error::Parse)));
+    }
+
+    #[test]
+    fn general_configuration() {
+        let config = DriverConfig::default();
+        assert_eq!(config.gconf(), 0x1c1);
+
+        let spread_cycle = DriverConfig {
+            stealth_chop: false,
+            ..config
+        };
+        assert_eq!(spread_cycle.gconf(), 0x1c5);
+    }
+
+    #[test]
+    fn microstep_resolutions() {
+        assert_eq!(microstep_resolution(256).unwrap(), 0);
+        assert_eq!(microstep_resolution(16).unwrap(), 4);
+        assert_eq!(microstep_resolution(2).unwrap(), 7);
+        assert_eq!(microstep_resolution(1).unwrap(), 8);
+        for microsteps in [0, 3, 12, 512] {
+            assert!(matches!(
+                microstep_resolution(microsteps),
+                Err(Error::InvalidSetting)
+            ));
+        }
+    }
+
+    #[test]
+    fn chopper_configuration() {
+        let config = |microsteps| DriverConfig {
+            microsteps,
+            ..DriverConfig::default()
+        };
+        assert_eq!(config(256).chopconf().unwrap(), 0x1000_0053);
+        assert_eq!(config(16).chopconf().unwrap(), 0x1400_0053);
+        assert_eq!(config(1).chopconf().unwrap(), 0x1800_0053);
+        assert!(matches!(config(6).chopconf(), Err(Error::InvalidSetting)));
+    }
+
+    #[test]
+    fn currents() {
+        assert_eq!(DriverConfig::default().ihold_irun().unwrap(), 0x0008_1008);
+
+        let config = DriverConfig {
+            run_current: 31,
+            hold_current: 0,
+            ..DriverConfig::default()
+        };
+        assert_eq!(config.ihold_irun().unwrap(), 0x0008_1f00);
+
+        for (run_current, hold_current) in [(32, 8), (16, 32)] {
+            let config = DriverConfig {
+                run_current,
+                hold_current,
+                ..DriverConfig::default()
+            };
+            assert!(matches!(config.ihold_irun(), Err(Error::InvalidSetting)));
+        }
+    }
+
+    #[test]
+    fn status_flags() {
+        let healthy = DriverStatus(0x8010_0000);
+        assert!(!healthy.is_fault());
+        assert!(healthy.standstill());
+        assert!(!healthy.stealth_chop());
+        assert_eq!(healthy.current(), 16);
+
+        let warm = DriverStatus(1);
+        assert!(warm.over_temperature_warning());
+        assert!(!warm.over_temperature());
+        assert!(!warm.is_fault());
+
+        assert!(DriverStatus(1 << 1).over_temperature());
+        assert!(DriverStatus(1 << 1).is_fault());
+        for bit in [2, 3] {
+            assert!(DriverStatus(1 << bit).short_to_ground());
+            assert!(DriverStatus(1 << bit).is_fault());
+        }
+        for bit in [4, 5] {
+            assert!(DriverStatus(1 << bit).short_to_supply());
+            assert!(DriverStatus(1 << bit).is_fault());
+        }
+        // A disconnected coil is worth reporting but needn't stop the driver
+        for bit in [6, 7] {
+            assert!(DriverStatus(1 << bit).open_load());
+            assert!(!DriverStatus(1 << bit).is_fault());
+        }
+
+        let running = DriverStatus(0x401f_0000);
+        assert!(running.stealth_chop());
+        assert!(!running.standstill());
+        assert_eq!(running.current(), 31);
+    }
+}
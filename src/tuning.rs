@@ -0,0 +1,280 @@
+/// Tuning of the azimuth controller's gains from relay feedback, after
+/// Åström and Hägglund.  Switching the stepper fully one way then the other
+/// each time the heading crosses the target makes the turntable oscillate at
+/// the frequency its lag puts it half a turn out of phase, from which the
+/// ultimate gain and period follow.
+#[allow(unused_imports)]
+use num_traits::float::Float;
+
+use crate::geo::Angle;
+use crate::stepper::CircularDirection;
+
+/// Full steps per second the relay switches between either way
+pub const TUNING_RATE: u32 = 100;
+
+/// Degrees of error the relay ignores either side of the target, to keep
+/// compass noise from switching it
+pub const TUNING_HYSTERESIS: f32 = 2.0;
+
+/// Oscillations left to settle before measuring, and how many to measure
+pub const TUNING_SKIP: u32 = 2;
+pub const TUNING_CYCLES: u32 = 4;
+
+/// Longest to oscillate for before giving up
+pub const TUNING_TIMEOUT_MS: u64 = 120_000;
+
+/// Degrees either side of the target the oscillation may swing before
+/// giving up, as the turntable is too slow to stop for the relay
+pub const TUNING_MAX_AMPLITUDE: f32 = 90.0;
+
+/// Gains of a PID controller from heading error in degrees to step rate in
+/// full steps per second
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gains {
+    pub kp: f32,
+    /// Per second of integrated error
+    pub ki: f32,
+    /// Per degree per second of change in error
+    pub kd: f32,
+}
+
+impl Gains {
+    /// The Ziegler-Nichols rules for a PID controller, given the ultimate
+    /// gain and its period in seconds
+    pub fn ziegler_nichols(ultimate_gain: f32, ultimate_period: f32) -> Self {
+        let kp = 0.6 * ultimate_gain;
+
+        Self {
+            kp,
+            ki: kp * 2.0 / ultimate_period,
+            kd: kp * ultimate_period / 8.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TuningAction {
+    /// Run the motor this way at this many full steps per second
+    Run(CircularDirection, u32),
+    /// Stop, these are the gains
+    Done(Gains),
+    /// Stop, the turntable didn't oscillate steadily around the target
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Oscillating,
+    Done(Gains),
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelayTuning {
+    state: State,
+    setpoint: Angle,
+    /// Which way the motor turns to correct a positive error
+    positive: CircularDirection,
+    started: u64,
+    /// Whether the relay is correcting a positive error
+    correcting_positive: bool,
+    /// When the relay last switched to correcting a positive error, which
+    /// starts each oscillation
+    cycle_start: Option<u64>,
+    cycles: u32,
+    /// Most and least error seen in the current oscillation
+    high: f32,
+    low: f32,
+    /// Sums of the measured oscillations' amplitudes and periods
+    amplitudes: f32,
+    periods: u64,
+}
+
+impl RelayTuning {
+    /// Oscillate around the setpoint, with `positive` the way the motor
+    /// turns to correct a positive error
+    pub fn new(now_ms: u64, setpoint: Angle, positive: CircularDirection) -> Self {
+        Self {
+            state: State::Oscillating,
+            setpoint,
+            positive,
+            started: now_ms,
+            correcting_positive: true,
+            cycle_start: None,
+            cycles: 0,
+            high: 0.0,
+            low: 0.0,
+            amplitudes: 0.0,
+            periods: 0,
+        }
+    }
+
+    /// Move on given the time and heading, in the same frame as the setpoint
+    pub fn update(&mut self, now_ms: u64, heading: Angle) -> TuningAction {
+        let error = heading.difference(self.setpoint).degrees();
+        self.high = self.high.max(error);
+        self.low = self.low.min(error);
+
+        if self.correcting_positive && error < -TUNING_HYSTERESIS {
+            self.correcting_positive = false;
+        } else if !self.correcting_positive && error > TUNING_HYSTERESIS {
+            self.correcting_positive = true;
+
+            if let Some(start) = self.cycle_start {
+                self.cycles += 1;
+                if self.cycles > TUNING_SKIP {
+                    self.amplitudes += (self.high - self.low) / 2.0;
+                    self.periods += now_ms.saturating_sub(start);
+                }
+            }
+            self.cycle_start = Some(now_ms);
+            self.high = error;
+            self.low = error;
+        }
+
+        if self.state == State::Oscillating {
+            if self.cycles >= TUNING_SKIP + TUNING_CYCLES {
+                self.state = self.result();
+            } else if error.abs() > TUNING_MAX_AMPLITUDE
+                || now_ms.saturating_sub(self.started) > TUNING_TIMEOUT_MS
+            {
+                self.state = State::Failed;
+            }
+        }
+
+        match self.state {
+            State::Oscillating if self.correcting_positive => {
+                TuningAction::Run(self.positive, TUNING_RATE)
+            }
+            State::Oscillating => TuningAction::Run(self.positive.reverse(), TUNING_RATE),
+            State::Done(gains) => TuningAction::Done(gains),
+            State::Failed => TuningAction::Failed,
+        }
+    }
+
+    /// The describing function of a relay with hysteresis gives the
+    /// ultimate gain from the amplitude of the oscillation
+    fn result(&self) -> State {
+        let amplitude = self.amplitudes / TUNING_CYCLES as f32;
+        let period = self.periods as f32 / TUNING_CYCLES as f32 / 1000.0;
+        if amplitude <= TUNING_HYSTERESIS || period <= 0.0 {
+            return State::Failed;
+        }
+
+        let swing = (amplitude * amplitude - TUNING_HYSTERESIS * TUNING_HYSTERESIS).sqrt();
+        let ultimate_gain = 4.0 * TUNING_RATE as f32 / (core::f32::consts::PI * swing);
+        State::Done(Gains::ziegler_nichols(ultimate_gain, period))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP_MS: u64 = 10;
+
+    /// Run a tuning against a turntable turning `gain` degrees per second per
+    /// full step per second, seen through a compass lagging `lag_ms` behind
+    fn tune(gain: f32, lag_ms: u64, positive: CircularDirection) -> TuningAction {
+        let setpoint = Angle::from_degrees(100.0);
+        let mut heading = 90.0;
+        let mut history = [heading; 256];
+        let lag = (lag_ms / STEP_MS) as usize;
+        assert!(lag < history.len());
+
+        let mut tuning = RelayTuning::new(0, setpoint, positive);
+        for i in 0..(TUNING_TIMEOUT_MS * 2 / STEP_MS) as usize {
+            let seen = history[(i + history.len() - lag) % history.len()];
+            let action = tuning.update(i as u64 * STEP_MS, Angle::from_degrees(seen));
+            let rate = match action {
+                TuningAction::Run(direction, rate) if direction == positive => rate as f32,
+                TuningAction::Run(_, rate) => -(rate as f32),
+                result => return result,
+            };
+            heading += gain * rate * STEP_MS as f32 / 1000.0;
+            history[(i + 1) % history.len()] = heading;
+        }
+
+        panic!("tuning didn't finish");
+    }
+
+    /// The gains the relay oscillation of an integrator with dead time gives
+    fn expected(gain: f32, lag_ms: u64) -> Gains {
+        let speed = gain * TUNING_RATE as f32;
+        let amplitude = TUNING_HYSTERESIS + speed * lag_ms as f32 / 1000.0;
+        let period = 4.0 * amplitude / speed;
+        let swing = (amplitude * amplitude - TUNING_HYSTERESIS * TUNING_HYSTERESIS).sqrt();
+        let ultimate_gain = 4.0 * TUNING_RATE as f32 / (core::f32::consts::PI * swing);
+
+        Gains::ziegler_nichols(ultimate_gain, period)
+    }
+
+    fn assert_close(gains: Gains, expected: Gains) {
+        for (actual, expected) in [
+            (gains.kp, expected.kp),
+            (gains.ki, expected.ki),
+            (gains.kd, expected.kd),
+        ] {
+            assert!(
+                (actual - expected).abs() < 0.05 * expected,
+                "{:?} {:?}",
+                gains,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn ziegler_nichols() {
+        let gains = Gains::ziegler_nichols(10.0, 4.0);
+        assert!((gains.kp - 6.0).abs() < 1e-6);
+        assert!((gains.ki - 3.0).abs() < 1e-6);
+        assert!((gains.kd - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tunes_a_lagging_turntable() {
+        for (gain, lag_ms) in [(0.1, 500), (0.05, 1_000), (0.2, 300)] {
+            match tune(gain, lag_ms, CircularDirection::CW) {
+                TuningAction::Done(gains) => assert_close(gains, expected(gain, lag_ms)),
+                result => panic!("{:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn either_direction() {
+        match tune(0.1, 500, CircularDirection::CCW) {
+            TuningAction::Done(gains) => assert_close(gains, expected(0.1, 500)),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn slower_turntables_get_higher_gains() {
+        let gains = |gain| match tune(gain, 500, CircularDirection::CW) {
+            TuningAction::Done(gains) => gains,
+            result => panic!("{:?}", result),
+        };
+        assert!(gains(0.05).kp > gains(0.1).kp);
+    }
+
+    #[test]
+    fn fails_without_moving() {
+        assert_eq!(tune(0.0, 500, CircularDirection::CW), TuningAction::Failed);
+    }
+
+    #[test]
+    fn fails_swinging_too_far() {
+        // Lagging so much it overshoots past the limit
+        assert_eq!(
+            tune(0.5, 2_500, CircularDirection::CW),
+            TuningAction::Failed
+        );
+    }
+
+    #[test]
+    fn fails_turning_the_wrong_way() {
+        assert_eq!(tune(-0.1, 500, CircularDirection::CW), TuningAction::Failed);
+    }
+}
@@ -0,0 +1,419 @@
+/// Struct for unipolar stepper (28BYJ-48 through a ULN2003 board)
+/// configuration and control
+use stm32f3xx_hal::gpio::gpiod::{PD4, PD5, PD6, PD7};
+use stm32f3xx_hal::gpio::{gpiod, Output, PXx, PushPull};
+use stm32f3xx_hal::pac;
+use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::rcc;
+use stm32f3xx_hal::time::duration::Microseconds;
+use stm32f3xx_hal::timer::{Event, Timer};
+
+use crate::stepper::{CircularDirection, Microstepping, StepperDriver};
+use crate::Error;
+
+/// Full steps per revolution of a 28BYJ-48's output shaft, through its
+/// gearbox
+pub const FULL_STEPS_PER_REVOLUTION: u32 = 2048;
+
+/// Full steps per second a 28BYJ-48 manages reliably
+pub const DEFAULT_RATE: u32 = 250;
+
+/// Most full steps per second a 28BYJ-48 follows at 5V, about 15 rpm.  Any
+/// faster and it stalls and just buzzes.
+pub const MAX_RATE: u32 = 500;
+
+const WAVE: [u8; 4] = [0b0001, 0b0010, 0b0100, 0b1000];
+const FULL: [u8; 4] = [0b0011, 0b0110, 0b1100, 0b1001];
+const HALF: [u8; 8] = [
+    0b0001, 0b0011, 0b0010, 0b0110, 0b0100, 0b1100, 0b1000, 0b1001,
+];
+
+/// How the coils are energised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepMode {
+    /// One coil at a time, for the least power and torque
+    Wave,
+    /// Two coils at a time, for the most torque
+    Full,
+    /// Alternating one and two coils, for twice the resolution
+    Half,
+}
+
+impl StepMode {
+    /// The coils energised at each step, from bit 0 for IN1 to bit 3 for IN4
+    pub fn sequence(&self) -> &'static [u8] {
+        match self {
+            StepMode::Wave => &WAVE,
+            StepMode::Full => &FULL,
+            StepMode::Half => &HALF,
+        }
+    }
+
+    /// Steps through the sequence per full step
+    pub fn divisor(&self) -> u32 {
+        match self {
+            StepMode::Wave | StepMode::Full => 1,
+            StepMode::Half => 2,
+        }
+    }
+
+    /// Where a step of the sequence is in the half step sequence.  Wave steps
+    /// are its single coil steps and full steps the two coil ones.
+    fn half_step(&self, position: usize) -> usize {
+        match self {
+            StepMode::Wave => position * 2,
+            StepMode::Full => position * 2 + 1,
+            StepMode::Half => position,
+        }
+    }
+
+    /// The step of the sequence nearest a half step.  Between two steps, it's
+    /// the one that keeps the half step's coil energised.
+    fn step_nearest(&self, half_step: usize) -> usize {
+        match self {
+            StepMode::Wave => half_step / 2,
+            StepMode::Full => (half_step + HALF.len() - 1) / 2 % FULL.len(),
+            StepMode::Half => half_step,
+        }
+    }
+}
+
+/// Steps through a coil sequence in either direction.  Clockwise runs
+/// forward through the sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoilSequencer {
+    mode: StepMode,
+    position: usize,
+    direction: CircularDirection,
+}
+
+impl CoilSequencer {
+    pub fn new(mode: StepMode) -> Self {
+        Self {
+            mode,
+            position: 0,
+            direction: CircularDirection::CW,
+        }
+    }
+
+    pub fn mode(&self) -> StepMode {
+        self.mode
+    }
+
+    /// Change mode, keeping as close as we can to the current rotor position
+    pub fn set_mode(&mut self, mode: StepMode) {
+        self.position = mode.step_nearest(self.mode.half_step(self.position));
+        self.mode = mode;
+    }
+
+    pub fn direction(&self) -> CircularDirection {
+        self.direction
+    }
+
+    pub fn set_direction(&mut self, direction: CircularDirection) {
+        self.direction = direction;
+    }
+
+    /// The coils energised at the current step
+    pub fn coils(&self) -> u8 {
+        self.mode.sequence()[self.position]
+    }
+
+    /// Move to the next step in the current direction, returning the coils
+    /// to energise
+    pub fn step(&mut self) -> u8 {
+        let length = self.mode.sequence().len();
+        self.position = match self.direction {
+            CircularDirection::CW => (self.position + 1) % length,
+            CircularDirection::CCW => (self.position + length - 1) % length,
+        };
+
+        self.coils()
+    }
+}
+
+/*
+ * Pinout:
+ *   PD4 -> IN1
+ *   PD5 -> IN2
+ *   PD6 -> IN3
+ *   PD7 -> IN4
+ */
+pub struct UnipolarStepper {
+    coils: [PXx<Output<PushPull>>; 4],
+    timer: Timer<pac::TIM7>,
+    sequencer: CoilSequencer,
+    rate: u32,
+    enabled: bool,
+}
+
+impl UnipolarStepper {
+    pub fn new<Pd4Mode, Pd5Mode, Pd6Mode, Pd7Mode>(
+        pd4: PD4<Pd4Mode>,
+        pd5: PD5<Pd5Mode>,
+        pd6: PD6<Pd6Mode>,
+        pd7: PD7<Pd7Mode>,
+        moder: &mut gpiod::MODER,
+        otyper: &mut gpiod::OTYPER,
+        tim7: pac::TIM7,
+        clocks: rcc::Clocks,
+        advanced_periph_bus: &mut rcc::APB1,
+    ) -> Result<Self, Error> {
+        let coils = [
+            pd4.into_push_pull_output(moder, otyper)
+                .downgrade()
+                .downgrade(),
+            pd5.into_push_pull_output(moder, otyper)
+                .downgrade()
+                .downgrade(),
+            pd6.into_push_pull_output(moder, otyper)
+                .downgrade()
+                .downgrade(),
+            pd7.into_push_pull_output(moder, otyper)
+                .downgrade()
+                .downgrade(),
+        ];
+        let mut timer = Timer::new(tim7, clocks, advanced_periph_bus);
+        timer.enable_interrupt(Event::Update);
+
+        Ok(Self {
+            coils,
+            timer,
+            sequencer: CoilSequencer::new(StepMode::Half),
+            rate: DEFAULT_RATE,
+            enabled: false,
+        })
+    }
+
+    pub fn set_mode(&mut self, mode: StepMode) {
+        self.sequencer.set_mode(mode);
+        if self.enabled {
+            self.start_timer();
+        }
+    }
+
+    /// Move on one step.  Intended to be called from the TIM7 interrupt.
+    pub fn step(&mut self) {
+        self.timer.clear_event(Event::Update);
+
+        if self.enabled {
+            let coils = self.sequencer.step();
+            self.energise(coils);
+        }
+    }
+
+    fn energise(&mut self, coils: u8) {
+        for (i, pin) in self.coils.iter_mut().enumerate() {
+            if coils & (1 << i) != 0 {
+                pin.set_high().ok();
+            } else {
+                pin.set_low().ok();
+            }
+        }
+    }
+
+    fn start_timer(&mut self) {
+        let steps_per_second = self.rate * self.sequencer.mode().divisor();
+        self.timer.start(Microseconds(1_000_000 / steps_per_second));
+    }
+}
+
+impl StepperDriver for UnipolarStepper {
+    fn enable(&mut self) {
+        if !self.enabled {
+            self.enabled = true;
+            self.energise(self.sequencer.coils());
+            self.start_timer();
+        }
+    }
+
+    /// Stops and de-energises the coils.  The gearbox holds the position and
+    /// the motor doesn't sit there getting hot.
+    fn disable(&mut self) {
+        self.enabled = false;
+        self.timer.stop();
+        self.energise(0);
+    }
+
+    fn set_direction(&mut self, dir: CircularDirection) {
+        self.sequencer.set_direction(dir);
+    }
+
+    fn toggle_direction(&mut self) {
+        self.sequencer
+            .set_direction(match self.sequencer.direction() {
+                CircularDirection::CW => CircularDirection::CCW,
+                CircularDirection::CCW => CircularDirection::CW,
+            });
+    }
+
+    fn set_rate(&mut self, steps_per_second: u32) {
+        self.rate = steps_per_second.clamp(1, MAX_RATE);
+        if self.enabled {
+            self.start_timer();
+        }
+    }
+
+    fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn speed(&self) -> f32 {
+        self.rate as f32 * 360.0 / FULL_STEPS_PER_REVOLUTION as f32
+    }
+
+    fn full_steps_per_revolution(&self) -> u32 {
+        FULL_STEPS_PER_REVOLUTION
+    }
+
+    /// Half steps for anything finer than full steps, as that's as fine as
+    /// the coils can be sequenced
+    fn set_microstepping(&mut self, microstepping: Microstepping) -> Result<(), Error> {
+        let mode = match microstepping {
+            Microstepping::Full => StepMode::Full,
+            _ => StepMode::Half,
+        };
+        if mode != self.sequencer.mode() {
+            self.set_mode(mode);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coils_energised(coils: u8) -> u32 {
+        coils.count_ones()
+    }
+
+    #[test]
+    fn sequences_move_one_coil_at_a_time() {
+        for mode in [StepMode::Wave, StepMode::Full, StepMode::Half] {
+            let sequence = mode.sequence();
+            for (i, &coils) in sequence.iter().enumerate() {
+                let next = sequence[(i + 1) % sequence.len()];
+                assert!(coils & 0b1111 == coils);
+                // Each step moves the field on to the neighbouring coil
+                let turned = ((coils << 1) | (coils >> 3)) & 0b1111;
+                match mode {
+                    StepMode::Half => assert_ne!(coils & next, 0, "step {}", i),
+                    _ => assert_eq!(next, turned, "{:?} step {}", mode, i),
+                }
+                match mode {
+                    StepMode::Wave => assert_eq!(coils_energised(coils), 1),
+                    StepMode::Full => assert_eq!(coils_energised(coils), 2),
+                    StepMode::Half => {
+                        assert_eq!(coils_energised(coils), 1 + i as u32 % 2)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn half_steps_interleave_wave_and_full_steps() {
+        for i in 0..4 {
+            assert_eq!(HALF[2 * i], WAVE[i]);
+            assert_eq!(HALF[2 * i + 1], FULL[i]);
+        }
+    }
+
+    #[test]
+    fn steps_either_way() {
+        let mut sequencer = CoilSequencer::new(StepMode::Half);
+        assert_eq!(sequencer.coils(), 0b0001);
+        assert_eq!(sequencer.step(), 0b0011);
+        assert_eq!(sequencer.step(), 0b0010);
+
+        sequencer.set_direction(CircularDirection::CCW);
+        assert_eq!(sequencer.step(), 0b0011);
+        assert_eq!(sequencer.step(), 0b0001);
+        // Wraps round to the end of the sequence
+        assert_eq!(sequencer.step(), 0b1001);
+
+        sequencer.set_direction(CircularDirection::CW);
+        assert_eq!(sequencer.step(), 0b0001);
+    }
+
+    #[test]
+    fn whole_sequence_returns_to_the_start() {
+        for mode in [StepMode::Wave, StepMode::Full, StepMode::Half] {
+            for direction in [CircularDirection::CW, CircularDirection::CCW] {
+                let mut sequencer = CoilSequencer::new(mode);
+                sequencer.set_direction(direction);
+                let start = sequencer.coils();
+                for _ in 0..mode.sequence().len() {
+                    sequencer.step();
+                }
+                assert_eq!(sequencer.coils(), start);
+            }
+        }
+    }
+
+    #[test]
+    fn full_steps_keep_their_coils_in_half_steps() {
+        let mut sequencer = CoilSequencer::new(StepMode::Full);
+        for _ in 0..FULL.len() {
+            let coils = sequencer.coils();
+            let mut half = sequencer;
+            half.set_mode(StepMode::Half);
+            assert_eq!(half.coils(), coils);
+
+            // And back again
+            half.set_mode(StepMode::Full);
+            assert_eq!(half.coils(), coils);
+            sequencer.step();
+        }
+    }
+
+    #[test]
+    fn wave_steps_keep_their_coil_in_half_steps() {
+        let mut sequencer = CoilSequencer::new(StepMode::Wave);
+        for _ in 0..WAVE.len() {
+            let coils = sequencer.coils();
+            let mut half = sequencer;
+            half.set_mode(StepMode::Half);
+            assert_eq!(half.coils(), coils);
+
+            half.set_mode(StepMode::Wave);
+            assert_eq!(half.coils(), coils);
+            sequencer.step();
+        }
+    }
+
+    #[test]
+    fn mode_changes_keep_an_energised_coil() {
+        let modes = [StepMode::Wave, StepMode::Full, StepMode::Half];
+        for from in modes {
+            for to in modes {
+                let mut sequencer = CoilSequencer::new(from);
+                for _ in 0..from.sequence().len() {
+                    let coils = sequencer.coils();
+                    let mut changed = sequencer;
+                    changed.set_mode(to);
+                    assert_eq!(changed.mode(), to);
+                    // The rotor moves at most half a step
+                    assert_ne!(changed.coils() & coils, 0, "{:?} to {:?}", from, to);
+                    sequencer.step();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mode_changes_keep_the_direction() {
+        let mut sequencer = CoilSequencer::new(StepMode::Half);
+        sequencer.set_direction(CircularDirection::CCW);
+        sequencer.set_mode(StepMode::Full);
+        assert_eq!(sequencer.direction(), CircularDirection::CCW);
+        let coils = sequencer.coils();
+        let position = FULL.iter().position(|&c| c == coils).unwrap();
+        assert_eq!(
+            sequencer.step(),
+            FULL[(position + FULL.len() - 1) % FULL.len()]
+        );
+    }
+}